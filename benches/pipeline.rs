@@ -0,0 +1,124 @@
+//! Benchmarks for the path a notification takes from DBus to the screen: parsing its hints, loading
+//! its images, and building its window. The ones that touch GTK need a display.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use ninomiya::config::Config;
+use ninomiya::demo;
+use ninomiya::gui::Gui;
+use ninomiya::hints::{Hints, ImageRef};
+use ninomiya::image::demo_image_url;
+use ninomiya::server::CloseReason;
+use ninomiya::timer::GlibClock;
+use std::rc::Rc;
+use std::sync::mpsc;
+
+/// The sizes of the raw images to try, in pixels on a side. Chat avatars are at the small end;
+/// screenshots are at the large end.
+const IMAGE_SIZES: &[i32] = &[64, 512, 2048];
+
+/// An opaque gray square, like the raw images apps send in the `image-data` hint.
+fn raw_image(size: i32) -> ImageRef {
+    ImageRef::Image {
+        width: size,
+        height: size,
+        has_alpha: true,
+        bits_per_sample: 8,
+        image_data: vec![0x80; (size * size * 4) as usize],
+    }
+}
+
+/// A GUI that can show windows without the main loop running. Nothing closes on its own, since
+/// timeouts only fire from the main loop.
+fn gui() -> Rc<Gui> {
+    gtk::init().expect("failed to initialize gtk");
+    let (tx, _rx) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+    let (signal_tx, _signal_rx) = mpsc::channel();
+    let gui = Gui::new(Config::default(), Rc::new(GlibClock::new()), tx, signal_tx);
+    gui.register().expect("failed to register the application");
+    gui
+}
+
+fn hint_parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hint parsing");
+    group.bench_function("no image", |b| {
+        b.iter_batched(
+            || {
+                Hints {
+                    desktop_entry: Some("galax".to_owned()),
+                    category: Some("im.received".to_owned()),
+                    ..Hints::new()
+                }
+                .into_dbus()
+            },
+            |map| Hints::from_dbus(black_box(map)).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    for &size in IMAGE_SIZES {
+        group.bench_with_input(BenchmarkId::new("image data", size), &size, |b, &size| {
+            b.iter_batched(
+                || {
+                    Hints {
+                        image: Some(raw_image(size)),
+                        ..Hints::new()
+                    }
+                    .into_dbus()
+                },
+                |map| Hints::from_dbus(black_box(map)).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn image_loading(c: &mut Criterion) {
+    let gui = gui();
+    let fg = gdk::RGBA {
+        red: 0.0,
+        green: 0.0,
+        blue: 0.0,
+        alpha: 1.0,
+    };
+    let mut group = c.benchmark_group("image loading");
+    group.bench_function("file", |b| {
+        b.iter(|| {
+            gui.imageref_to_pixbuf(ImageRef::Url(demo_image_url()), 64, 64, &fg)
+                .unwrap()
+        })
+    });
+    for &size in IMAGE_SIZES {
+        group.bench_with_input(BenchmarkId::new("image data", size), &size, |b, &size| {
+            b.iter_batched(
+                || raw_image(size),
+                |image| gui.imageref_to_pixbuf(image, 64, 64, &fg).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn window_construction(c: &mut Criterion) {
+    let gui = gui();
+    let mut group = c.benchmark_group("window construction");
+    let count = demo::demo_notifications().len();
+    for index in 0..count {
+        let summary = demo::demo_notifications().remove(index).summary;
+        group.bench_function(summary, |b| {
+            b.iter_batched(
+                || demo::demo_notifications().remove(index),
+                |notification| {
+                    let id = notification.id;
+                    gui.notification_window(notification);
+                    gui.close_notification(id, CloseReason::Closed);
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, hint_parsing, image_loading, window_construction);
+criterion_main!(benches);
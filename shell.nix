@@ -16,6 +16,8 @@ stdenv.mkDerivation rec {
     dbus
     pkgconfig
     gtk3
+    # Lets the GTK tests run without a display.
+    xorg.xorgserver
   ];
   RUST_BACKTRACE = 1;
   LD_LIBRARY_PATH = "${lib.makeLibraryPath buildInputs}";
@@ -0,0 +1,106 @@
+//! Picks an accent color for a notification out of its image or icon, so that notifications from
+//! different contacts and apps are told apart by color without any configuration.
+//!
+//! The colors are bucketed coarsely and the most common bucket wins. Transparent, washed-out, and
+//! nearly black or white pixels are skipped, since they're usually background rather than what the
+//! image is of.
+
+use crate::config::AccentFromImage;
+use gdk_pixbuf::Pixbuf;
+use std::collections::HashMap;
+
+/// Pixels more transparent than this are ignored.
+const MIN_ALPHA: u8 = 128;
+/// Pixels whose channels are all within this much of each other are gray, and ignored.
+const MIN_CHROMA: u8 = 48;
+/// Pixels darker or lighter than these are ignored too.
+const MIN_BRIGHTNESS: u8 = 32;
+const MAX_BRIGHTNESS: u8 = 240;
+/// How many of the high bits of each channel go into the bucket.
+const BITS: u32 = 3;
+
+/// The color there's most of in `pixels` (as RGBA), ignoring background-ish ones. It's the average
+/// of the pixels in the most common bucket, so it's a color that's actually in the image. `None`
+/// if there's nothing colorful.
+fn dominant(pixels: impl Iterator<Item = [u8; 4]>) -> Option<[u8; 3]> {
+    let mut buckets: HashMap<[u8; 3], (u32, [u32; 3])> = HashMap::new();
+    for [r, g, b, a] in pixels {
+        let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+        if a < MIN_ALPHA || max - min < MIN_CHROMA || max < MIN_BRIGHTNESS || min > MAX_BRIGHTNESS {
+            continue;
+        }
+        let key = [r >> (8 - BITS), g >> (8 - BITS), b >> (8 - BITS)];
+        let (count, sums) = buckets.entry(key).or_default();
+        *count += 1;
+        sums[0] += u32::from(r);
+        sums[1] += u32::from(g);
+        sums[2] += u32::from(b);
+    }
+    // Ties go to the bucket with the lowest key, so the answer doesn't depend on hashing.
+    let (_, (count, sums)) = buckets
+        .into_iter()
+        .max_by_key(|(key, (count, _))| (*count, std::cmp::Reverse(*key)))?;
+    Some([
+        (sums[0] / count) as u8,
+        (sums[1] / count) as u8,
+        (sums[2] / count) as u8,
+    ])
+}
+
+/// The dominant color of `pixbuf`, if it has one.
+pub fn dominant_color(pixbuf: &Pixbuf) -> Option<[u8; 3]> {
+    let channels = pixbuf.get_n_channels() as usize;
+    let rowstride = pixbuf.get_rowstride() as usize;
+    let (width, height) = (pixbuf.get_width() as usize, pixbuf.get_height() as usize);
+    // Safe because we don't hold onto the slice or modify the pixbuf while we're reading it.
+    let bytes: &[u8] = unsafe { pixbuf.get_pixels() };
+    dominant((0..height).flat_map(|y| {
+        (0..width).map(move |x| {
+            let pixel = &bytes[y * rowstride + x * channels..][..channels];
+            let alpha = if channels == 4 { pixel[3] } else { 255 };
+            [pixel[0], pixel[1], pixel[2], alpha]
+        })
+    }))
+}
+
+/// The CSS that applies `color` to a notification window, the way `accent` asks for.
+pub fn css(accent: AccentFromImage, [r, g, b]: [u8; 3]) -> Option<String> {
+    let (property, width) = match accent {
+        AccentFromImage::Off | AccentFromImage::Color => return None,
+        AccentFromImage::Border => ("border", 2),
+        AccentFromImage::Bar => ("border-left", 4),
+    };
+    Some(format!(
+        "window {{ {}: {}px solid rgb({}, {}, {}); }}",
+        property, width, r, g, b
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_dominant_color() {
+        let red = [200, 30, 40, 255];
+        let blue = [20, 40, 210, 255];
+        let white = [255, 255, 255, 255];
+        let clear = [0, 200, 0, 0];
+        // Mostly white and transparent background, then more red than blue.
+        let mut pixels = vec![white; 50];
+        pixels.extend(vec![clear; 50]);
+        pixels.extend(vec![red; 10]);
+        pixels.extend(vec![[204, 26, 44, 255]; 10]);
+        pixels.extend(vec![blue; 15]);
+        assert_eq!(dominant(pixels.into_iter()), Some([202, 28, 42]));
+        assert_eq!(
+            dominant(vec![white, [128, 128, 128, 255]].into_iter()),
+            None
+        );
+        assert_eq!(
+            css(AccentFromImage::Bar, [205, 32, 42]).as_deref(),
+            Some("window { border-left: 4px solid rgb(205, 32, 42); }")
+        );
+        assert_eq!(css(AccentFromImage::Off, [205, 32, 42]), None);
+    }
+}
@@ -0,0 +1,203 @@
+//! Runs shell commands when actions are invoked, as configured by `[[action_commands]]`. This is
+//! for notifications from clients that exit right after sending them, like `notify-send` in a
+//! script, which aren't around to hear about the action.
+//!
+//! The command is run with `sh -c`, and gets the notification's fields in environment variables
+//! rather than pasted into it, so that a notification can't inject its own commands.
+
+use crate::app_filter::{self, glob_matches};
+use crate::config::ActionCommand;
+use crate::router::SignalSink;
+use crate::server::{Notification, Signal};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::thread;
+use tracing::{debug, info, warn};
+
+/// What we need to run the commands for a notification on screen.
+#[derive(Debug)]
+struct Pending {
+    /// The commands for each of its action keys that has one.
+    commands: HashMap<String, String>,
+    env: Vec<(&'static str, String)>,
+}
+
+#[derive(Debug)]
+pub struct ActionCommands {
+    rules: Vec<ActionCommand>,
+    pending: HashMap<u32, Pending>,
+}
+
+/// The environment variables describing `notification`. Unset fields are set to empty strings.
+fn environment(notification: &Notification) -> Vec<(&'static str, String)> {
+    let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+    vec![
+        ("NINOMIYA_ID", notification.id.to_string()),
+        (
+            "NINOMIYA_APP_NAME",
+            optional(&notification.application_name),
+        ),
+        ("NINOMIYA_SUMMARY", notification.summary.clone()),
+        ("NINOMIYA_BODY", optional(&notification.body)),
+        ("NINOMIYA_CATEGORY", optional(&notification.hints.category)),
+        (
+            "NINOMIYA_DESKTOP_ENTRY",
+            optional(&notification.hints.desktop_entry),
+        ),
+        (
+            "NINOMIYA_URGENCY",
+            notification.hints.urgency.name().to_owned(),
+        ),
+    ]
+}
+
+impl ActionCommands {
+    pub fn new(rules: Vec<ActionCommand>) -> Self {
+        ActionCommands {
+            rules,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// The command for the action `key` on `notification`, if a rule covers it. The first matching
+    /// rule wins.
+    fn command_for(&self, notification: &Notification, key: &str) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|rule| {
+                glob_matches(&rule.key, key)
+                    && (rule.apps.is_empty()
+                        || app_filter::matches_app(
+                            &rule.apps,
+                            notification.application_name.as_deref(),
+                            notification.hints.desktop_entry.as_deref(),
+                        ))
+            })
+            .map(|rule| rule.command.as_str())
+    }
+
+    /// Remembers what to run for `notification`'s actions, if anything.
+    pub fn add(&mut self, notification: &Notification) {
+        let commands: HashMap<String, String> = notification
+            .actions
+            .iter()
+            .filter_map(|action| {
+                self.command_for(notification, &action.key)
+                    .map(|command| (action.key.clone(), command.to_owned()))
+            })
+            .collect();
+        if commands.is_empty() {
+            // It might be replacing one that had commands.
+            self.pending.remove(&notification.id);
+            return;
+        }
+        self.pending.insert(
+            notification.id,
+            Pending {
+                commands,
+                env: environment(notification),
+            },
+        );
+    }
+
+    /// Runs the command for `key` on notification `id`, if there is one, without waiting for it.
+    pub fn invoke(&self, id: u32, key: &str) {
+        let pending = match self.pending.get(&id) {
+            Some(pending) => pending,
+            None => return,
+        };
+        let command = match pending.commands.get(key) {
+            Some(command) => command,
+            None => return,
+        };
+        info!("Running the command for {} on notification {}", key, id);
+        debug!("Command: {}", command);
+        match Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(pending.env.iter().cloned())
+            .env("NINOMIYA_ACTION_KEY", key)
+            .stdin(Stdio::null())
+            .spawn()
+        {
+            Ok(mut child) => {
+                let key = key.to_owned();
+                thread::spawn(move || match child.wait() {
+                    Ok(status) if !status.success() => {
+                        warn!(
+                            "The command for {} on notification {} failed: {}",
+                            key, id, status
+                        )
+                    }
+                    Ok(_) => {}
+                    Err(err) => warn!("Failed to wait for the command for {}: {:?}", key, err),
+                });
+            }
+            Err(err) => warn!("Failed to run the command for {}: {:?}", key, err),
+        }
+    }
+
+    /// Forgets about notification `id` once it's closed.
+    pub fn remove(&mut self, id: u32) {
+        self.pending.remove(&id);
+    }
+}
+
+impl SignalSink for RefCell<ActionCommands> {
+    fn handle(&self, signal: &Signal) {
+        match signal {
+            Signal::ActionInvoked { id, key } => self.borrow().invoke(*id, key),
+            Signal::NotificationClosed { id, .. } => self.borrow_mut().remove(*id),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(apps: &[&str], key: &str, command: &str) -> ActionCommand {
+        ActionCommand {
+            apps: apps.iter().map(|app| (*app).to_owned()).collect(),
+            key: key.to_owned(),
+            command: command.to_owned(),
+        }
+    }
+
+    #[test]
+    fn matches_rules() {
+        let mut commands = ActionCommands::new(vec![
+            rule(&["backup*"], "open", "xdg-open \"$NINOMIYA_BODY\""),
+            rule(&[], "default", "echo clicked"),
+        ]);
+        let notification = Notification::builder()
+            .id(3)
+            .application_name("backup-script")
+            .summary("Backup done")
+            .body("/mnt/backup")
+            .action("open", "Open")
+            .action("default", "")
+            .action("retry", "Retry")
+            .build();
+        commands.add(&notification);
+        let pending = &commands.pending[&3];
+        assert_eq!(pending.commands.len(), 2);
+        assert_eq!(pending.commands["open"], "xdg-open \"$NINOMIYA_BODY\"");
+        assert!(pending
+            .env
+            .contains(&("NINOMIYA_BODY", "/mnt/backup".to_owned())));
+
+        let other = Notification::builder()
+            .id(4)
+            .application_name("mail")
+            .action("open", "Open")
+            .build();
+        commands.add(&other);
+        assert!(!commands.pending.contains_key(&4));
+
+        commands.remove(3);
+        assert!(commands.pending.is_empty());
+    }
+}
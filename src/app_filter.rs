@@ -0,0 +1,142 @@
+//! Decides which applications are allowed to show notifications at all, based on the `blocked_apps`
+//! and `allowed_apps` lists in the config, and with `strict_allowlist`, which of them get popups.
+
+/// Matches `text` against a glob `pattern`, where `*` matches any run of characters and `?`
+/// matches exactly one. Case doesn't matter, since apps aren't consistent about it.
+pub(crate) fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and where in the text we started matching it against.
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Backtrack, letting the `*` swallow one more character.
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether any of `patterns` matches the app name or the desktop entry.
+pub fn matches_app(
+    patterns: &[String],
+    app_name: Option<&str>,
+    desktop_entry: Option<&str>,
+) -> bool {
+    patterns.iter().any(|pattern| {
+        app_name
+            .into_iter()
+            .chain(desktop_entry)
+            .any(|name| glob_matches(pattern, name))
+    })
+}
+
+#[derive(Debug, Default)]
+pub struct AppFilter {
+    blocked: Vec<String>,
+    allowed: Vec<String>,
+    strict: bool,
+}
+
+impl AppFilter {
+    /// An empty `allowed` list allows every app that isn't blocked.
+    pub fn new(blocked: Vec<String>, allowed: Vec<String>) -> Self {
+        AppFilter {
+            blocked,
+            allowed,
+            strict: false,
+        }
+    }
+
+    /// Only the apps on the allowlist get popups, even if it's empty. The rest aren't dropped, just
+    /// kept out of sight.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Whether a notification from the app with the given name and desktop entry should be taken at
+    /// all. A pattern matches if it matches either of them, and the blocklist wins over the
+    /// allowlist.
+    pub fn allows(&self, app_name: Option<&str>, desktop_entry: Option<&str>) -> bool {
+        if matches_app(&self.blocked, app_name, desktop_entry) {
+            return false;
+        }
+        self.strict
+            || self.allowed.is_empty()
+            || matches_app(&self.allowed, app_name, desktop_entry)
+    }
+
+    /// Whether a notification from the app should pop up, once it's been allowed.
+    pub fn pops_up(&self, app_name: Option<&str>, desktop_entry: Option<&str>) -> bool {
+        !self.strict || matches_app(&self.allowed, app_name, desktop_entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("slack", "Slack"));
+        assert!(glob_matches("org.gnome.*", "org.gnome.Evolution"));
+        assert!(glob_matches("*chat*", "rocketchat-desktop"));
+        assert!(glob_matches("app?", "app1"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("app?", "app"));
+        assert!(!glob_matches("org.gnome.*", "org.kde.kmail"));
+        assert!(!glob_matches("*.desktop", "firefox.desktop.bak"));
+    }
+
+    #[test]
+    fn filters_apps() {
+        let filter = AppFilter::new(vec!["discord".into()], vec![]);
+        assert!(!filter.allows(Some("discord"), None));
+        assert!(!filter.allows(Some("Vesktop"), Some("discord")));
+        assert!(filter.allows(Some("Thunderbird"), None));
+        assert!(filter.allows(None, None));
+
+        let filter = AppFilter::new(
+            vec!["org.gnome.Software".into()],
+            vec!["org.gnome.*".into()],
+        );
+        assert!(filter.allows(None, Some("org.gnome.Evolution")));
+        assert!(!filter.allows(None, Some("org.gnome.Software")));
+        assert!(!filter.allows(Some("notify-send"), None));
+        assert!(filter.pops_up(None, Some("org.gnome.Evolution")));
+
+        let filter = AppFilter::new(vec!["discord".into()], vec!["thunderbird".into()]).strict();
+        assert!(filter.allows(Some("notify-send"), None));
+        assert!(!filter.pops_up(Some("notify-send"), None));
+        assert!(filter.pops_up(Some("Thunderbird"), None));
+        assert!(!filter.allows(Some("discord"), None));
+        assert!(!AppFilter::new(vec![], vec![])
+            .strict()
+            .pops_up(Some("Thunderbird"), None));
+    }
+
+    #[test]
+    fn matches_apps() {
+        let patterns = vec!["signal".to_owned(), "im.riot.*".to_owned()];
+        assert!(matches_app(&patterns, Some("Signal"), None));
+        assert!(matches_app(
+            &patterns,
+            Some("Element"),
+            Some("im.riot.Riot")
+        ));
+        assert!(!matches_app(&patterns, Some("Thunderbird"), None));
+        assert!(!matches_app(&[], Some("Signal"), None));
+    }
+}
@@ -0,0 +1,146 @@
+//! Figures out an icon for notifications that don't come with one.
+//!
+//! Plenty of clients (`notify-send`, scripts, apps using libnotify directly) never set `app_icon`
+//! or the `desktop-entry` hint. For those, we ask the bus which process sent the notification and
+//! try to match it up with a desktop file, whose icon we then use.
+
+use crate::bus;
+use crate::hints::ImageRef;
+use anyhow::Result;
+use dbus::blocking::Connection;
+use gio::prelude::*;
+use std::fs;
+use tracing::debug;
+
+/// Looks up the icon of the desktop entry with the given ID (without the `.desktop` suffix).
+pub fn desktop_entry_icon(desktop_entry: &str) -> Option<ImageRef> {
+    let info = gio::DesktopAppInfo::new(&format!("{}.desktop", desktop_entry))?;
+    let icon = gio::IconExt::to_string(&info.get_icon()?)?;
+    match icon.parse() {
+        Ok(image_ref) => Some(image_ref),
+        Err(err) => {
+            debug!(
+                "Desktop entry {} has unusable icon {}: {:?}",
+                desktop_entry, icon, err
+            );
+            None
+        }
+    }
+}
+
+/// A generic themed icon for the given notification category, for when we can't find anything
+/// more specific.
+pub fn category_icon(category: &str) -> Option<&'static str> {
+    // Categories are of the form `class` or `class.specific`; the classes are from the spec.
+    let icon = match category.split('.').next()? {
+        "call" => "call-start",
+        "device" => "drive-removable-media",
+        "email" => "mail-unread",
+        "im" => "mail-message-new",
+        "network" => "network-wired",
+        "presence" => "user-available",
+        "transfer" => "document-send",
+        _ => return None,
+    };
+    Some(icon)
+}
+
+pub struct IconInferrer {
+    connection: Connection,
+}
+
+impl IconInferrer {
+    /// Connects to the session (or system) bus, which should be the one we're serving on.
+    pub fn new(system: bool) -> Result<Self> {
+        Ok(IconInferrer {
+            connection: bus::connect(system)?,
+        })
+    }
+
+    /// Guesses the icon for a notification sent by the connection with the given unique name.
+    pub fn infer(&self, sender: &str) -> Option<ImageRef> {
+        let pid = bus::sender_pid(&self.connection, sender)?;
+        let candidates = candidate_app_ids(pid);
+        debug!("Candidate app IDs for {} are {:?}", sender, candidates);
+        candidates.iter().find_map(|id| desktop_entry_icon(id))
+    }
+}
+
+/// Desktop file IDs that the process might correspond to, most likely first.
+fn candidate_app_ids(pid: u32) -> Vec<String> {
+    let mut candidates = vec![];
+    if let Ok(cgroup) = fs::read_to_string(format!("/proc/{}/cgroup", pid)) {
+        candidates.extend(app_ids_from_cgroup(&cgroup));
+    }
+    if let Ok(comm) = fs::read_to_string(format!("/proc/{}/comm", pid)) {
+        candidates.push(comm.trim().to_owned());
+    }
+    candidates
+}
+
+/// Desktops that follow systemd's conventions launch each app in its own unit, named
+/// `app[-<launcher>]-<app id>-<random>.scope` or `app[-<launcher>]-<app id>[@<random>].service`.
+/// Since we can't tell whether the first component is a launcher, we return both possibilities.
+fn app_ids_from_cgroup(cgroup: &str) -> Vec<String> {
+    let unit = match cgroup
+        .lines()
+        .filter_map(|line| line.rsplit('/').next())
+        .find(|unit| unit.starts_with("app-"))
+    {
+        Some(unit) => &unit["app-".len()..],
+        None => return vec![],
+    };
+    let name = if let Some(name) = unit.strip_suffix(".scope") {
+        match name.rfind('-') {
+            Some(index) => &name[..index],
+            None => return vec![],
+        }
+    } else if let Some(name) = unit.strip_suffix(".service") {
+        name.split('@').next().unwrap_or(name)
+    } else {
+        return vec![];
+    };
+    // systemd escapes dashes inside the app ID.
+    let unescape = |s: &str| s.replace("\\x2d", "-");
+    let mut ids = vec![];
+    if let Some(index) = name.find('-') {
+        ids.push(unescape(&name[index + 1..]));
+    }
+    ids.push(unescape(name));
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn category_icons() {
+        assert_eq!(category_icon("email.arrived"), Some("mail-unread"));
+        assert_eq!(category_icon("im"), Some("mail-message-new"));
+        assert_eq!(category_icon("x-vendor.thing"), None);
+    }
+
+    #[test]
+    fn cgroup_scope_with_launcher() {
+        let cgroup = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/\
+                      app-gnome-org.gnome.Terminal-4242.scope\n";
+        assert_eq!(
+            app_ids_from_cgroup(cgroup),
+            vec!["org.gnome.Terminal", "gnome-org.gnome.Terminal"]
+        );
+    }
+
+    #[test]
+    fn cgroup_service_with_escapes() {
+        let cgroup = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/\
+                      app-telegram\\x2ddesktop@abc123.service\n";
+        assert_eq!(app_ids_from_cgroup(cgroup), vec!["telegram-desktop"]);
+    }
+
+    #[test]
+    fn cgroup_without_app_unit() {
+        let cgroup = "0::/user.slice/user-1000.slice/session-2.scope\n";
+        assert!(app_ids_from_cgroup(cgroup).is_empty());
+    }
+}
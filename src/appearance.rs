@@ -0,0 +1,69 @@
+//! Follows the desktop's dark mode setting through the settings portal, so that we can switch
+//! themes to match.
+
+use anyhow::{Context, Result};
+use dbus::arg::{RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+use dbus::Message;
+use std::time::Duration;
+use tracing::{debug, info};
+
+const PORTAL_NAME: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const SETTINGS_INTERFACE: &str = "org.freedesktop.portal.Settings";
+const APPEARANCE_NAMESPACE: &str = "org.freedesktop.appearance";
+const COLOR_SCHEME_KEY: &str = "color-scheme";
+/// The `color-scheme` value meaning the user prefers dark themes. 0 means no preference and 2
+/// means light.
+const PREFER_DARK: u32 = 1;
+
+/// Digs a number out of a value, which older portals wrap in an extra variant.
+fn as_u32(value: &dyn RefArg) -> Option<u32> {
+    match value.as_u64() {
+        Some(n) => Some(n as u32),
+        None => as_u32(value.as_iter()?.next()?),
+    }
+}
+
+fn read_prefers_dark(connection: &Connection) -> Result<bool> {
+    let (value,): (Variant<Box<dyn RefArg>>,) = connection
+        .with_proxy(PORTAL_NAME, PORTAL_PATH, Duration::from_millis(1000))
+        .method_call(
+            SETTINGS_INTERFACE,
+            "Read",
+            (APPEARANCE_NAMESPACE, COLOR_SCHEME_KEY),
+        )
+        .context("couldn't read the color scheme")?;
+    Ok(as_u32(&value) == Some(PREFER_DARK))
+}
+
+/// Calls `on_change` with whether the user prefers dark themes, and then again every time that
+/// changes. Runs forever, unless the portal goes away.
+pub fn watch_color_scheme<F: FnMut(bool) + Send + 'static>(mut on_change: F) -> Result<()> {
+    let mut connection =
+        Connection::new_session().context("couldn't connect to the session bus")?;
+    let prefers_dark = read_prefers_dark(&connection)?;
+    info!("Color scheme prefers dark: {}", prefers_dark);
+    on_change(prefers_dark);
+    connection
+        .add_match(
+            MatchRule::new_signal(SETTINGS_INTERFACE, "SettingChanged"),
+            move |(): (), _, message: &Message| {
+                if let Ok((namespace, key, value)) =
+                    message.read3::<&str, &str, Variant<Box<dyn RefArg>>>()
+                {
+                    if namespace == APPEARANCE_NAMESPACE && key == COLOR_SCHEME_KEY {
+                        let prefers_dark = as_u32(&value) == Some(PREFER_DARK);
+                        debug!("Color scheme changed; prefers dark: {}", prefers_dark);
+                        on_change(prefers_dark);
+                    }
+                }
+                true
+            },
+        )
+        .context("couldn't listen for color scheme changes")?;
+    loop {
+        connection.process(Duration::from_secs(60))?;
+    }
+}
@@ -0,0 +1,115 @@
+//! Marks the window of the app a notification came from as wanting attention, for the apps in
+//! `attention_apps`, so that taskbars flash it the way they would for the app's own urgency hint.
+//! Only windows that aren't focused are marked, minimized ones included.
+//!
+//! We set EWMH's `_NET_WM_STATE_DEMANDS_ATTENTION` with `wmctrl` rather than the ICCCM urgency
+//! hint, because the window manager clears it once the window is focused. The urgency hint belongs
+//! to the app, which wouldn't know to clear one we set. Windows are found the same way as in
+//! `raise`, so this only works on X11.
+
+use crate::app_filter;
+use crate::bus;
+use crate::focus;
+use crate::raise::{self, Source};
+use crate::server::Notification;
+use anyhow::{bail, Context, Result};
+use dbus::blocking::Connection;
+use std::process::{Command, Stdio};
+use std::thread;
+use tracing::{debug, warn};
+
+/// The focused window's ID, if any.
+fn active_window() -> Result<Option<u64>> {
+    let output = Command::new("xprop")
+        .args(&["-root", "_NET_ACTIVE_WINDOW"])
+        .stderr(Stdio::null())
+        .output()
+        .context("failed to run xprop")?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    Ok(focus::parse_active_window(output.trim()).and_then(parse_window_id))
+}
+
+/// Parses a window ID like `0x03a00003`. `wmctrl` pads them with zeroes and `xprop` doesn't, so
+/// they have to be compared as numbers.
+fn parse_window_id(id: &str) -> Option<u64> {
+    u64::from_str_radix(id.trim_start_matches("0x"), 16).ok()
+}
+
+/// Marks `source`'s window as wanting attention, unless it's focused.
+fn demand_attention(source: &Source) -> Result<()> {
+    let output = raise::list_windows()?;
+    let windows = raise::parse_windows(&output);
+    let window = match raise::find_window(&windows, source) {
+        Some(window) => window,
+        // It might have quit, or only have a tray icon.
+        None => return Ok(()),
+    };
+    if parse_window_id(window) == active_window()? {
+        debug!("Window {} is focused; not marking it", window);
+        return Ok(());
+    }
+    debug!("Marking window {} as wanting attention", window);
+    let status = Command::new("wmctrl")
+        .args(&["-i", "-r", window, "-b", "add,demands_attention"])
+        .status()
+        .context("failed to run wmctrl")?;
+    if !status.success() {
+        bail!("wmctrl failed to mark {}: {}", window, status);
+    }
+    Ok(())
+}
+
+pub struct AttentionMarker {
+    connection: Connection,
+    /// Which apps to mark, matched like `blocked_apps`.
+    apps: Vec<String>,
+}
+
+impl AttentionMarker {
+    /// Looks up senders on the system bus if `system` is set, and the session bus otherwise.
+    pub fn new(apps: Vec<String>, system: bool) -> Result<Self> {
+        Ok(AttentionMarker {
+            connection: bus::connect(system)?,
+            apps,
+        })
+    }
+
+    /// Marks the window of the app that sent `notification` (from the unique bus name `sender`),
+    /// if it's one of our apps, without waiting.
+    pub fn notify(&self, notification: &Notification, sender: Option<&str>) {
+        if notification.internal
+            || !app_filter::matches_app(
+                &self.apps,
+                notification.application_name.as_deref(),
+                notification.hints.desktop_entry.as_deref(),
+            )
+        {
+            return;
+        }
+        let source = Source {
+            pid: sender.and_then(|sender| bus::sender_pid(&self.connection, sender)),
+            desktop_entry: notification.hints.desktop_entry.clone(),
+        };
+        let id = notification.id;
+        thread::spawn(move || {
+            if let Err(err) = demand_attention(&source) {
+                warn!(
+                    "Couldn't mark the window for notification {}: {:?}",
+                    id, err
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_window_ids() {
+        assert_eq!(parse_window_id("0x03a00003"), parse_window_id("0x3a00003"));
+        assert_eq!(parse_window_id("0x2a00003"), Some(0x2a00003));
+        assert_eq!(parse_window_id("window"), None);
+    }
+}
@@ -0,0 +1,38 @@
+//! Helpers for talking to the bus we serve on, shared by the parts of the server that need to know
+//! more about who sent a notification.
+
+use anyhow::{Context, Result};
+use dbus::blocking::Connection;
+use std::time::Duration;
+use tracing::debug;
+
+/// How long to wait for the bus to tell us a sender's PID. This blocks the server thread.
+const DBUS_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Connects to the session bus, or the system bus if `system` is set.
+pub fn connect(system: bool) -> Result<Connection> {
+    if system {
+        Connection::new_system().context("couldn't connect to the system bus")
+    } else {
+        Connection::new_session().context("couldn't connect to the session bus")
+    }
+}
+
+/// Asks the bus for the PID of the connection with the unique name `sender`.
+pub fn sender_pid(connection: &Connection, sender: &str) -> Option<u32> {
+    let result: Result<(u32,), dbus::Error> = connection
+        .with_proxy(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            DBUS_TIMEOUT,
+        )
+        .method_call(
+            "org.freedesktop.DBus",
+            "GetConnectionUnixProcessID",
+            (sender,),
+        );
+    result
+        .map(|(pid,)| pid)
+        .map_err(|err| debug!("Couldn't get the PID of {}: {:?}", sender, err))
+        .ok()
+}
@@ -0,0 +1,465 @@
+//! Reads the user's calendar, so that do-not-disturb can be turned on automatically during events
+//! they're busy for. The calendar is an iCalendar (`.ics`) feed, which most calendar services can
+//! publish and Evolution, Thunderbird and the like can export, fetched every so often with `curl`
+//! (so `file://` URLs work too).
+//!
+//! This is nowhere near a full iCalendar implementation. Events marked as free
+//! (`TRANSP:TRANSPARENT`) or cancelled are skipped, and of recurring events, only daily and weekly
+//! ones are understood.
+
+use crate::curl;
+use anyhow::{anyhow, bail, Context, Result};
+use glib::translate::{from_glib_full, ToGlibPtr};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+const DAY: i64 = 24 * 60 * 60;
+const WEEK: i64 = 7 * DAY;
+
+/// How a busy event repeats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Repeat {
+    /// In seconds.
+    every: i64,
+    /// The last time an occurrence can start, as a Unix timestamp.
+    until: Option<i64>,
+    count: Option<i64>,
+}
+
+/// A stretch of time (or a series of them) the user is busy for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Busy {
+    /// As a Unix timestamp.
+    start: i64,
+    /// In seconds.
+    length: i64,
+    repeat: Option<Repeat>,
+}
+
+impl Busy {
+    /// Whether the user is busy at `now`, a Unix timestamp.
+    pub fn covers(&self, now: i64) -> bool {
+        self.occurrence_at(now).is_some()
+    }
+
+    /// When the occurrence going on at `now` started, if there is one.
+    fn occurrence_at(&self, now: i64) -> Option<i64> {
+        if now < self.start {
+            return None;
+        }
+        let repeat = match self.repeat {
+            Some(repeat) => repeat,
+            None if now < self.start + self.length => return Some(self.start),
+            None => return None,
+        };
+        let occurrence = (now - self.start) / repeat.every;
+        let start = self.start + occurrence * repeat.every;
+        if repeat.count.map_or(false, |count| occurrence >= count)
+            || repeat.until.map_or(false, |until| start > until)
+            || now >= start + self.length
+        {
+            return None;
+        }
+        Some(start)
+    }
+}
+
+/// Joins folded lines back up: a line starting with a space or tab continues the one before.
+fn unfold(ics: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in ics.lines() {
+        let line = line.trim_end_matches('\r');
+        match lines.last_mut() {
+            Some(last) if line.starts_with(' ') || line.starts_with('\t') => {
+                last.push_str(&line[1..])
+            }
+            _ => lines.push(line.to_owned()),
+        }
+    }
+    lines
+}
+
+/// A content line's parameters, as names and values.
+type Params<'a> = Vec<(&'a str, &'a str)>;
+
+/// Splits a content line like `DTSTART;TZID=Europe/Berlin:20240105T100000` into its name, its
+/// parameters, and its value.
+fn split_line(line: &str) -> Option<(&str, Params<'_>, &str)> {
+    let colon = line.find(':')?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let mut parts = head.split(';');
+    let name = parts.next()?;
+    let params = parts
+        .filter_map(|param| {
+            let mut kv = param.splitn(2, '=');
+            Some((kv.next()?, kv.next()?))
+        })
+        .collect();
+    Some((name, params, value))
+}
+
+/// Parses a date or date-time, which is in UTC if it ends with `Z`, in `tzid` if that's given, and
+/// in local time otherwise. Dates are taken as midnight. Returns a Unix timestamp.
+fn parse_time(value: &str, tzid: Option<&str>) -> Result<i64> {
+    let digits = |range: std::ops::Range<usize>| -> Result<i32> {
+        value
+            .get(range)
+            .and_then(|digits| digits.parse().ok())
+            .with_context(|| format!("bad date {:?}", value))
+    };
+    let (year, month, day) = (digits(0..4)?, digits(4..6)?, digits(6..8)?);
+    let (hour, minute, second) = if value.get(8..9) == Some("T") {
+        (digits(9..11)?, digits(11..13)?, digits(13..15)?)
+    } else {
+        (0, 0, 0)
+    };
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        bail!("bad date {:?}", value);
+    }
+    let tz = if value.ends_with('Z') {
+        glib::TimeZone::new_utc()
+    } else {
+        match tzid {
+            Some(tzid) => glib::TimeZone::new(Some(tzid)),
+            None => glib::TimeZone::new_local(),
+        }
+    };
+    // `glib::DateTime::new` panics when GLib can't make the time (say, because it's out of range
+    // once the time zone is applied), so we check for that ourselves.
+    let time: Option<glib::DateTime> = unsafe {
+        let ptr = glib_sys::g_date_time_new(
+            tz.to_glib_none().0,
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            f64::from(second),
+        );
+        if ptr.is_null() {
+            None
+        } else {
+            Some(from_glib_full(ptr))
+        }
+    };
+    time.map(|time| time.to_unix())
+        .ok_or_else(|| anyhow!("date {:?} is out of range", value))
+}
+
+fn days_in_month(year: i32, month: i32) -> i32 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+/// Parses a duration like `PT1H30M` or `P1D`, in seconds.
+fn parse_duration(value: &str) -> Result<i64> {
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in value.trim_start_matches('+').chars() {
+        match c {
+            'P' | 'T' => {}
+            '0'..='9' => number.push(c),
+            _ => {
+                let n: i64 = number
+                    .parse()
+                    .with_context(|| format!("bad duration {:?}", value))?;
+                number.clear();
+                seconds += n * match c {
+                    'W' => WEEK,
+                    'D' => DAY,
+                    'H' => 60 * 60,
+                    'M' => 60,
+                    'S' => 1,
+                    _ => bail!("bad duration {:?}", value),
+                };
+            }
+        }
+    }
+    Ok(seconds)
+}
+
+/// The day of the week of a Unix timestamp in local time, counting from Monday as 0.
+fn weekday(time: i64) -> i64 {
+    glib::DateTime::new_from_unix_local(time).get_day_of_week() as i64 - 1
+}
+
+/// Turns an `RRULE` for `busy` into the series it makes. Only daily and weekly rules are
+/// understood; anything else counts as happening once.
+fn parse_rule(rule: &str, busy: Busy) -> Vec<Busy> {
+    let mut freq = None;
+    let mut interval = 1;
+    let mut until = None;
+    let mut count = None;
+    let mut days = vec![];
+    for part in rule.split(';') {
+        let mut kv = part.splitn(2, '=');
+        let (key, value) = (kv.next().unwrap_or_default(), kv.next().unwrap_or_default());
+        match key {
+            "FREQ" => freq = Some(value),
+            "INTERVAL" => interval = value.parse().unwrap_or(1),
+            "UNTIL" => until = parse_time(value, None).ok(),
+            "COUNT" => count = value.parse().ok(),
+            "BYDAY" => {
+                days = value
+                    .split(',')
+                    .filter_map(|day| {
+                        let names = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+                        names.iter().position(|name| day.ends_with(name))
+                    })
+                    .collect()
+            }
+            _ => {}
+        }
+    }
+    // A zero or negative interval would never move on to the next occurrence.
+    let every = match freq {
+        _ if interval <= 0 => return vec![busy],
+        Some("DAILY") => DAY * interval,
+        Some("WEEKLY") => WEEK * interval,
+        _ => return vec![busy],
+    };
+    if days.is_empty() || freq != Some("WEEKLY") {
+        return vec![Busy {
+            repeat: Some(Repeat {
+                every,
+                until,
+                count,
+            }),
+            ..busy
+        }];
+    }
+    // A weekly event on several days is a weekly series for each day. The count is shared between
+    // them, so each gets its share.
+    let start_day = weekday(busy.start);
+    let per_day = count.map(|count| (count + days.len() as i64 - 1) / days.len() as i64);
+    days.into_iter()
+        .map(|day| Busy {
+            start: busy.start + (day as i64 - start_day).rem_euclid(7) * DAY,
+            repeat: Some(Repeat {
+                every,
+                until,
+                count: per_day,
+            }),
+            ..busy
+        })
+        .collect()
+}
+
+/// Gets the busy times out of an iCalendar file. Events that can't be read are skipped.
+pub fn parse(ics: &str) -> Vec<Busy> {
+    let mut busy = vec![];
+    let mut event: Option<Vec<String>> = None;
+    for line in unfold(ics) {
+        match line.as_str() {
+            "BEGIN:VEVENT" => event = Some(vec![]),
+            "END:VEVENT" => {
+                if let Some(lines) = event.take() {
+                    match parse_event(&lines) {
+                        Ok(times) => busy.extend(times),
+                        Err(err) => debug!("Skipping calendar event: {:?}", err),
+                    }
+                }
+            }
+            _ => {
+                if let Some(lines) = &mut event {
+                    lines.push(line);
+                }
+            }
+        }
+    }
+    busy
+}
+
+fn parse_event(lines: &[String]) -> Result<Vec<Busy>> {
+    let mut start = None;
+    let mut end = None;
+    let mut duration = None;
+    let mut rule = None;
+    for line in lines {
+        let (name, params, value) = match split_line(line) {
+            Some(split) => split,
+            None => continue,
+        };
+        let tzid = params
+            .iter()
+            .find(|(key, _)| *key == "TZID")
+            .map(|(_, tzid)| *tzid);
+        match (name, value) {
+            ("TRANSP", "TRANSPARENT")
+            | ("STATUS", "CANCELLED")
+            | ("X-MICROSOFT-CDO-BUSYSTATUS", "FREE") => return Ok(vec![]),
+            ("DTSTART", _) => {
+                let all_day = params.contains(&("VALUE", "DATE"));
+                start = Some((parse_time(value, tzid)?, all_day));
+            }
+            ("DTEND", _) => end = Some(parse_time(value, tzid)?),
+            ("DURATION", _) => duration = Some(parse_duration(value)?),
+            ("RRULE", _) => rule = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+    let (start, all_day) = start.context("event has no start")?;
+    let length = match (end, duration) {
+        (Some(end), _) => end - start,
+        (None, Some(duration)) => duration,
+        (None, None) if all_day => DAY,
+        (None, None) => 0,
+    };
+    let busy = Busy {
+        start,
+        length,
+        repeat: None,
+    };
+    Ok(match rule {
+        Some(rule) => parse_rule(&rule, busy),
+        None => vec![busy],
+    })
+}
+
+fn fetch(url: &str) -> Result<Vec<Busy>> {
+    // Feed URLs often have a secret in them, which `curl::Options` keeps off the command line.
+    let output = curl::Options::new()
+        .flag("silent")
+        .flag("show-error")
+        .flag("fail")
+        .flag("location")
+        .option("url", url)
+        .spawn()?
+        .wait_with_output()
+        .context("failed to run curl")?;
+    if !output.status.success() {
+        bail!(
+            "couldn't fetch the calendar: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+pub struct CalendarWatcher {
+    rx: Receiver<Vec<Busy>>,
+    /// The busy times as of the last fetch that worked.
+    busy: Vec<Busy>,
+}
+
+impl CalendarWatcher {
+    /// Starts fetching the calendar at `url` in the background, every `refresh`.
+    pub fn new(url: String, refresh: Duration) -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || loop {
+            match fetch(&url) {
+                Ok(busy) => {
+                    debug!("Read {} busy times from the calendar", busy.len());
+                    if tx.send(busy).is_err() {
+                        return;
+                    }
+                }
+                // Keep going with what we had; it might be a blip in the network.
+                Err(err) => warn!("Failed to read the calendar: {:?}", err),
+            }
+            thread::sleep(refresh);
+        });
+        CalendarWatcher { rx, busy: vec![] }
+    }
+
+    /// When the busy event going on right now started, according to the latest version of the
+    /// calendar, or `None` if the user isn't busy. If events overlap, this is the earliest one.
+    pub fn current_event(&mut self) -> Option<i64> {
+        if let Some(busy) = self.rx.try_iter().last() {
+            self.busy = busy;
+        }
+        let now = now();
+        self.busy
+            .iter()
+            .filter_map(|busy| busy.occurrence_at(now))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_busy_times() {
+        let ics = "BEGIN:VCALENDAR\r
+BEGIN:VEVENT\r
+SUMMARY:Standup\r
+DTSTART:20240101T090000Z\r
+DTEND:20240101T091500Z\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,WE;COUNT=4\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Lunch (free)\r
+DTSTART:20240101T120000Z\r
+DURATION:PT1H\r
+TRANSP:TRANSPARENT\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Review with a very long\r
+  folded summary\r
+DTSTART:20240102T150000Z\r
+DURATION:PT1H30M\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Broken feed\r
+DTSTART:20240104T180000Z\r
+DURATION:PT1H\r
+RRULE:FREQ=DAILY;INTERVAL=0\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+        let busy = parse(ics);
+        let at = |time: &str| parse_time(time, None).unwrap();
+        let busy_at = |time: &str| busy.iter().any(|busy| busy.covers(at(time)));
+        // 2024-01-01 was a Monday.
+        assert!(busy_at("20240101T091000Z"));
+        assert!(!busy_at("20240101T092000Z"));
+        assert!(busy_at("20240103T090500Z"));
+        assert!(busy_at("20240108T090500Z"));
+        assert!(!busy_at("20240109T090500Z"));
+        // Four occurrences, two each on Monday and Wednesday, so it's over by the third week.
+        assert!(!busy_at("20240115T090500Z"));
+        assert!(!busy_at("20240101T123000Z"));
+        assert!(busy_at("20240102T162000Z"));
+        assert!(!busy_at("20240102T163000Z"));
+        // A rule that never moves on counts as happening once.
+        assert!(busy_at("20240104T183000Z"));
+        assert!(!busy_at("20240105T183000Z"));
+        assert_eq!(
+            busy[1].occurrence_at(at("20240103T090500Z")),
+            Some(at("20240103T090000Z"))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_times() {
+        assert_eq!(
+            parse_time("20240229T120000Z", None).unwrap(),
+            parse_time("20240301T000000Z", None).unwrap() - 12 * 60 * 60
+        );
+        assert!(parse_time("20230229", None).is_err());
+        assert!(parse_time("20241301", None).is_err());
+        assert!(parse_time("20240101T250000Z", None).is_err());
+        assert!(parse_time("20240101T1200", None).is_err());
+        assert!(parse_time("2024010é1200", None).is_err());
+        assert!(parse_time("00000101", None).is_err());
+    }
+}
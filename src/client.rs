@@ -1,12 +1,21 @@
-//! This file implements the `notify` subcommand, which is used to send notifications.
+//! This file implements the subcommands that talk to a running daemon: `notify`, which is used to
+//! send notifications, `select-action`, `undo-close`, `pause` and `mute`, `progress` and
+//! `complete-progress`, `theme`, `history`, and `ping`.
 
+use crate::bus::connect;
+use crate::config::Config;
+use crate::control::{CONTROL_INTERFACE, CONTROL_PATH};
 use crate::dbus_codegen::client::OrgFreedesktopNotifications;
-use crate::hints::{Hints, ImageRef};
+use crate::emoji;
+use crate::hints::{expand_path, Hints, ImageRef};
+use crate::history::DbusEntry;
 use crate::server::Action;
-use anyhow::{anyhow, ensure, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use clap::arg_enum;
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
 use dbus::blocking::{Connection, Proxy};
-use std::path::PathBuf;
+use std::convert::TryFrom;
+use std::io::BufRead;
 use std::time::Duration;
 use structopt::StructOpt;
 
@@ -37,10 +46,11 @@ pub struct NotifyOpt {
     app_name: Option<String>,
     /// The name of the icon to display, or a path to it. Paths are interpreted as relative to
     /// the current directory, and should contain a '.' or a '/' to disambiguate from icon
-    /// names.
+    /// names. `~` and environment variables are expanded.
     #[structopt(short = "c", long)]
     icon: Option<String>,
-    /// The path to the image to display. Paths are interpreted as relative to the current directory.
+    /// The path to the image to display. Paths are interpreted as relative to the current
+    /// directory, and `~` and environment variables are expanded.
     #[structopt(short = "m", long)]
     image: Option<String>,
     /// The summary of the notification.
@@ -49,17 +59,39 @@ pub struct NotifyOpt {
     /// Valid actions to take. Each action separates the key from the label by a colon.
     #[structopt(long, parse(try_from_str = parse_action))]
     action: Vec<Action>,
+    /// Keep the notification open after an action is invoked.
+    #[structopt(long)]
+    resident: bool,
     /// The body of the notification.
     #[structopt(short, long)]
     body: Option<String>,
+    /// Turn emoji shortcodes like `:tada:` in the summary and body into emoji.
+    #[structopt(long)]
+    expand_emoji: bool,
+    /// The DBus name of the daemon to send the notification to. Overrides `--testing`.
+    #[structopt(long)]
+    dbus_name: Option<String>,
+    /// Send the notification over the system bus instead of the session bus.
+    #[structopt(long)]
+    system: bool,
+    /// Read percentages (like `42` or `42%`, or `42:message`) from stdin, one per line, and keep
+    /// one progress notification up to date with them. Once stdin closes, it's replaced with the
+    /// body, or "<summary>: done". Lines without a percentage are ignored, and so is anything
+    /// after the first percentage on a line, so `rsync --info=progress2` output works.
+    #[structopt(long, conflicts_with = "action")]
+    progress_from_stdin: bool,
     /// DEBUG: Whether to send the image as a path or as bytes.
     #[structopt(long, possible_values = &ImageAs::variants(), case_insensitive = true, default_value = "path", hidden_short_help = true)]
     image_as: ImageAs,
 }
 pub fn notify(dbus_name: &str, options: NotifyOpt) -> Result<()> {
-    let c = Connection::new_session()?;
+    let c = connect(options.system)?;
+    if options.progress_from_stdin {
+        let dbus_name = options.dbus_name.as_deref().unwrap_or(dbus_name);
+        return progress_from_stdin(&c, dbus_name, &options, std::io::stdin().lock());
+    }
     let proxy = Proxy::new(
-        dbus_name,
+        options.dbus_name.as_deref().unwrap_or(dbus_name),
         "/org/freedesktop/Notifications",
         Duration::from_millis(1000),
         &c,
@@ -72,6 +104,13 @@ pub fn notify(dbus_name: &str, options: NotifyOpt) -> Result<()> {
         .map(|act| vec![act.key.as_str(), act.label.as_str()].into_iter())
         .flatten()
         .collect();
+    let expand = |text: &str| {
+        if options.expand_emoji {
+            emoji::expand_shortcodes(text)
+        } else {
+            text.to_owned()
+        }
+    };
 
     proxy
         .notify(
@@ -81,8 +120,8 @@ pub fn notify(dbus_name: &str, options: NotifyOpt) -> Result<()> {
             0,
             &format_icon(&options.icon)
                 .with_context(|| format!("loading icon from {:?}", options.icon))?,
-            &options.summary,
-            options.body.as_deref().unwrap_or(""),
+            &expand(&options.summary),
+            &expand(options.body.as_deref().unwrap_or("")),
             actions,
             hints.into_dbus(),
             -1, // expiration timeout
@@ -91,10 +130,326 @@ pub fn notify(dbus_name: &str, options: NotifyOpt) -> Result<()> {
     return Ok(());
 }
 
+/// Tells the daemon to let the user pick an action on the newest notification by pressing its
+/// number. Meant to be bound to a key chord in the window manager.
+pub fn select_action(dbus_name: &str, system: bool) -> Result<()> {
+    let c = connect(system)?;
+    c.with_proxy(dbus_name, CONTROL_PATH, Duration::from_millis(1000))
+        .method_call(CONTROL_INTERFACE, "SelectAction", ())
+        .context("failed to ask the daemon to select an action")
+}
+
+/// Tells the daemon to show the notification the user last dismissed again, for when it was an
+/// accident. Also meant to be bound to a key chord.
+pub fn undo_close(dbus_name: &str, system: bool) -> Result<()> {
+    let c = connect(system)?;
+    c.with_proxy(dbus_name, CONTROL_PATH, Duration::from_millis(1000))
+        .method_call(CONTROL_INTERFACE, "UndoClose", ())
+        .context("failed to ask the daemon to undo the close")
+}
+
+#[derive(Debug, StructOpt)]
+pub struct PauseOpt {
+    /// How long for, like `30m`, `1h` or `90s`; a bare number is minutes. Without it, it lasts
+    /// until it's turned off.
+    #[structopt(long = "for", parse(try_from_str = parse_duration))]
+    duration: Option<Duration>,
+}
+
+fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("{:?} doesn't start with a number", s))?;
+    let seconds = match unit.trim() {
+        "s" => 1,
+        "" | "m" => 60,
+        "h" => 60 * 60,
+        unit => bail!("unknown unit {:?}; use s, m or h", unit),
+    };
+    Ok(Duration::from_secs(number * seconds))
+}
+
+/// Turns on the control interface's boolean `property`, or calls `method` to turn it on for just
+/// `duration`.
+fn silence(
+    dbus_name: &str,
+    system: bool,
+    property: &str,
+    method: &str,
+    duration: Option<Duration>,
+) -> Result<()> {
+    let c = connect(system)?;
+    let proxy = c.with_proxy(dbus_name, CONTROL_PATH, Duration::from_millis(1000));
+    match duration {
+        Some(duration) => {
+            let seconds = u32::try_from(duration.as_secs()).context("that's too long")?;
+            proxy
+                .method_call(CONTROL_INTERFACE, method, (seconds,))
+                .with_context(|| format!("failed to call {} on the daemon", method))
+        }
+        None => proxy
+            .set(CONTROL_INTERFACE, property, true)
+            .with_context(|| format!("failed to set {} on the daemon", property)),
+    }
+}
+
+/// Pauses notifications, for a while if there's a duration, after which the daemon shows what
+/// arrived in the meantime.
+pub fn pause(dbus_name: &str, system: bool, options: PauseOpt) -> Result<()> {
+    silence(dbus_name, system, "Paused", "PauseFor", options.duration)
+}
+
+/// Turns on do-not-disturb, for a while if there's a duration, after which the daemon turns it back
+/// off by itself.
+pub fn mute(dbus_name: &str, system: bool, options: PauseOpt) -> Result<()> {
+    silence(
+        dbus_name,
+        system,
+        "DoNotDisturb",
+        "DoNotDisturbFor",
+        options.duration,
+    )
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ThemeOpt {
+    /// Switches the daemon to another theme until it's restarted.
+    Set {
+        /// The path to a theme file, or the name of one in the config directory (like `dark` for
+        /// `dark.css`).
+        theme: String,
+    },
+    /// Lists the themes in the config directory.
+    List,
+}
+
+/// Lists the themes that `theme set` knows by name, or tells the daemon to switch to one.
+pub fn theme(dbus_name: &str, system: bool, options: ThemeOpt) -> Result<()> {
+    match options {
+        ThemeOpt::Set { theme } => {
+            let path = Config::find_theme(&theme)?;
+            let path = path
+                .to_str()
+                .context("the theme's path isn't valid UTF-8")?;
+            let c = connect(system)?;
+            c.with_proxy(dbus_name, CONTROL_PATH, Duration::from_millis(1000))
+                .method_call(CONTROL_INTERFACE, "SetTheme", (path,))
+                .context("failed to ask the daemon to switch themes")
+        }
+        ThemeOpt::List => {
+            for (name, path) in Config::themes()? {
+                println!("{}\t{}", name, path.display());
+            }
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ProgressOpt {
+    /// The ID printed by the last call, to update that notification. Leave it out to start a new
+    /// one.
+    #[structopt(long, default_value = "0")]
+    id: u32,
+    /// How far along it is, from 0 to 100.
+    percent: u32,
+    /// What's in progress, like "Copying photos".
+    text: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct CompleteProgressOpt {
+    /// The ID printed by `progress`.
+    id: u32,
+    /// What to say now that it's done, like "Copied 200 photos".
+    summary: String,
+}
+
+fn control_call<A: dbus::arg::AppendAll>(
+    c: &Connection,
+    dbus_name: &str,
+    method: &str,
+    args: A,
+) -> Result<u32> {
+    let (id,): (u32,) = c
+        .with_proxy(dbus_name, CONTROL_PATH, Duration::from_millis(1000))
+        .method_call(CONTROL_INTERFACE, method, args)
+        .with_context(|| format!("failed to call {} on the daemon", method))?;
+    Ok(id)
+}
+
+/// Shows or updates a progress notification, printing its ID so that scripts can update it.
+pub fn progress(dbus_name: &str, system: bool, options: ProgressOpt) -> Result<()> {
+    let c = connect(system)?;
+    let id = control_call(
+        &c,
+        dbus_name,
+        "UpdateProgress",
+        (options.id, options.percent, options.text),
+    )?;
+    println!("{}", id);
+    Ok(())
+}
+
+/// Replaces a progress notification with one saying it's done.
+pub fn complete_progress(
+    dbus_name: &str,
+    system: bool,
+    options: CompleteProgressOpt,
+) -> Result<()> {
+    let c = connect(system)?;
+    control_call(
+        &c,
+        dbus_name,
+        "CompleteProgress",
+        (options.id, options.summary),
+    )?;
+    Ok(())
+}
+
+#[derive(Debug, StructOpt)]
+pub struct PingOpt {
+    /// Print a JSON object instead of `<version> <uptime in seconds>`.
+    #[structopt(long)]
+    json: bool,
+    /// How many milliseconds to wait for the daemon to answer.
+    #[structopt(long, default_value = "1000")]
+    timeout: u64,
+}
+
+/// Checks that the daemon is up and answering, printing its version and uptime. Fails if it
+/// isn't, so scripts can just check the exit status.
+pub fn ping(dbus_name: &str, system: bool, options: PingOpt) -> Result<()> {
+    let c = connect(system)?;
+    let (uptime, version): (u64, String) = c
+        .with_proxy(
+            dbus_name,
+            CONTROL_PATH,
+            Duration::from_millis(options.timeout),
+        )
+        .method_call(CONTROL_INTERFACE, "Ping", ())
+        .with_context(|| format!("{} didn't answer", dbus_name))?;
+    if options.json {
+        println!(
+            "{}",
+            serde_json::json!({ "version": version, "uptime": uptime })
+        );
+    } else {
+        println!("{} {}", version, uptime);
+    }
+    Ok(())
+}
+
+#[derive(Debug, StructOpt)]
+pub struct HistoryOpt {
+    /// Print a JSON array instead of one line per notification.
+    #[structopt(long)]
+    json: bool,
+}
+
+/// Prints what's in the daemon's notification center, oldest first.
+pub fn history(dbus_name: &str, system: bool, options: HistoryOpt) -> Result<()> {
+    let c = connect(system)?;
+    let (history,): (Vec<DbusEntry>,) = c
+        .with_proxy(dbus_name, CONTROL_PATH, Duration::from_millis(1000))
+        .method_call(CONTROL_INTERFACE, "GetHistory", ())
+        .context("failed to get the history from the daemon")?;
+    if options.json {
+        let history: Vec<serde_json::Value> = history
+            .into_iter()
+            .map(|(id, app_name, summary, body, received)| {
+                serde_json::json!({
+                    "id": id,
+                    "app_name": app_name,
+                    "summary": summary,
+                    "body": body,
+                    "received": received,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::Value::Array(history));
+        return Ok(());
+    }
+    for (_, app_name, summary, body, received) in history {
+        let time = glib::DateTime::new_from_unix_local(received as i64)
+            .format("%H:%M")
+            .map_or_else(String::new, |time| time.to_string());
+        let body = body.replace('\n', " ");
+        match (app_name.is_empty(), body.is_empty()) {
+            (true, true) => println!("{} {}", time, summary),
+            (true, false) => println!("{} {}: {}", time, summary, body),
+            (false, true) => println!("{} [{}] {}", time, app_name, summary),
+            (false, false) => println!("{} [{}] {}: {}", time, app_name, summary, body),
+        }
+    }
+    Ok(())
+}
+
+/// Reads a percentage from a line of progress output: a number (optionally followed by `%`) and
+/// maybe `:message`, or failing that, the first word that's a percentage.
+fn parse_progress_line(line: &str) -> Option<(u32, Option<&str>)> {
+    let parse_percent = |text: &str| text.trim().trim_end_matches('%').parse::<u32>().ok();
+    let line = line.trim();
+    if let Some(colon) = line.find(':') {
+        if let Some(percent) = parse_percent(&line[..colon]) {
+            let message = line[colon + 1..].trim();
+            return Some((percent.min(100), Some(message).filter(|m| !m.is_empty())));
+        }
+    }
+    line.split_whitespace()
+        .filter(|word| word.ends_with('%') || *word == line)
+        .find_map(parse_percent)
+        .map(|percent| (percent.min(100), None))
+}
+
+/// Drives one progress notification from `input`, finishing it once `input` runs out. Lines can
+/// end in `\r` as well as `\n`, since that's how tools redraw their progress.
+fn progress_from_stdin(
+    c: &Connection,
+    dbus_name: &str,
+    options: &NotifyOpt,
+    input: impl BufRead,
+) -> Result<()> {
+    let mut id = 0;
+    let mut last: Option<(u32, String)> = None;
+    let mut line = Vec::new();
+    for byte in input.bytes().chain(std::iter::once(Ok(b'\n'))) {
+        let byte = byte.context("couldn't read stdin")?;
+        if byte != b'\r' && byte != b'\n' {
+            line.push(byte);
+            continue;
+        }
+        let text = String::from_utf8_lossy(&line).into_owned();
+        line.clear();
+        let (percent, message) = match parse_progress_line(&text) {
+            Some(progress) => progress,
+            None => continue,
+        };
+        let text = match message {
+            Some(message) => format!("{}: {}", options.summary, message),
+            None => options.summary.clone(),
+        };
+        // Tools like rsync redraw far more often than anything changes.
+        if last.as_ref() == Some(&(percent, text.clone())) {
+            continue;
+        }
+        id = control_call(c, dbus_name, "UpdateProgress", (id, percent, text.as_str()))?;
+        last = Some((percent, text));
+    }
+    let done = options
+        .body
+        .clone()
+        .unwrap_or_else(|| format!("{}: done", options.summary));
+    control_call(c, dbus_name, "CompleteProgress", (id, done))?;
+    Ok(())
+}
+
 fn format_icon(icon: &Option<String>) -> Result<String> {
     if let Some(icon) = icon {
         if icon.contains(".") || icon.contains("/") {
-            let path = PathBuf::from(icon).canonicalize()?;
+            let path = expand_path(icon)?.canonicalize()?;
             let url = url::Url::from_file_path(&path)
                 .map_err(|_| anyhow!("cannot convert path {:?} to URL", path))?;
             Ok(url.into_string())
@@ -112,7 +467,7 @@ fn fill_hints(options: &NotifyOpt) -> Result<Hints> {
         match options.image_as {
             ImageAs::Path => hints.image = Some(image_path.parse()?),
             ImageAs::Bytes => {
-                let pixbuf = gdk_pixbuf::Pixbuf::new_from_file(image_path)?;
+                let pixbuf = gdk_pixbuf::Pixbuf::new_from_file(expand_path(image_path)?)?;
                 let bytes = unsafe { pixbuf.get_pixels().to_owned() };
                 hints.image = Some(ImageRef::Image {
                     width: pixbuf.get_width(),
@@ -124,5 +479,39 @@ fn fill_hints(options: &NotifyOpt) -> Result<Hints> {
             }
         }
     }
+    hints.resident = options.resident;
     Ok(hints)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_progress_lines() {
+        assert_eq!(parse_progress_line("42"), Some((42, None)));
+        assert_eq!(parse_progress_line(" 7% "), Some((7, None)));
+        assert_eq!(
+            parse_progress_line("60:copying photos"),
+            Some((60, Some("copying photos")))
+        );
+        assert_eq!(parse_progress_line("150"), Some((100, None)));
+        assert_eq!(
+            parse_progress_line("    524,288,000  45%   12.34MB/s    0:00:40 (xfr#3, to-chk=7/12)"),
+            Some((45, None))
+        );
+        assert_eq!(parse_progress_line("sending incremental file list"), None);
+        assert_eq!(parse_progress_line(""), None);
+    }
+
+    #[test]
+    fn parses_durations() -> Result<()> {
+        assert_eq!(parse_duration("30m")?, Duration::from_secs(30 * 60));
+        assert_eq!(parse_duration("45")?, Duration::from_secs(45 * 60));
+        assert_eq!(parse_duration("1h")?, Duration::from_secs(60 * 60));
+        assert_eq!(parse_duration("90s")?, Duration::from_secs(90));
+        assert!(parse_duration("1d").is_err());
+        assert!(parse_duration("h").is_err());
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
-use anyhow::{anyhow, Error, Result};
-use log::info;
+use crate::app_filter::{self, glob_matches};
+use crate::hints::{Hints, Urgency};
+use anyhow::{anyhow, bail, Context, Error, Result};
 use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
+use tracing::info;
 
 // A custom deserializer that just deserializes an f32. We do this because the default serde
 // implementation uses a {seconds, nanoseconds} tuple, which is good for exactness but bad for
@@ -11,14 +14,434 @@ fn deserialize_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Du
     Ok(Duration::from_secs_f32(f32::deserialize(deserializer)?))
 }
 
+// Like `deserialize_duration`, but for optional fields.
+fn deserialize_optional_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Ok(Option::<f32>::deserialize(deserializer)?.map(Duration::from_secs_f32))
+}
+
+/// The width of notification windows: either a number of pixels, or a string like `"25%"` for a
+/// percentage of the monitor's width.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Width {
+    Pixels(i32),
+    Percent(f64),
+}
+
+impl<'de> Deserialize<'de> for Width {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Width, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Pixels(i32),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Pixels(pixels) => Ok(Width::Pixels(pixels)),
+            Raw::Text(text) => text
+                .strip_suffix('%')
+                .and_then(|percent| percent.trim().parse().ok())
+                .map(Width::Percent)
+                .ok_or_else(|| {
+                    serde::de::Error::custom(format!(
+                        "width should be a number of pixels or a percentage, not {:?}",
+                        text
+                    ))
+                }),
+        }
+    }
+}
+
+/// Configures where logs go and how verbose they are.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Per-module filter directives, in the same syntax as `RUST_LOG`; for example,
+    /// `ninomiya::gui=debug`. Ignored if `RUST_LOG` is set.
+    pub filter: Option<String>,
+    /// Whether to also send logs to the systemd journal.
+    pub journald: bool,
+    /// If set, logs are also appended to this file. Interpreted as relative to the configuration
+    /// file.
+    pub file: Option<PathBuf>,
+}
+
+/// Mirroring notifications to ninomiya on another machine, under the `[mirror]` table. Paths are
+/// interpreted as relative to the configuration file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MirrorConfig {
+    /// The `host:port` to mirror notifications to.
+    pub send_to: Option<String>,
+    /// The address to listen for mirrored notifications on, like `0.0.0.0:7370`.
+    pub listen: Option<String>,
+    /// The secret shared by both sides. Required for either one.
+    pub token: Option<String>,
+    /// The PEM certificate to serve when listening.
+    pub certificate: Option<PathBuf>,
+    /// The PEM (PKCS #8) private key for `certificate`.
+    pub key: Option<PathBuf>,
+    /// An extra PEM certificate to trust when sending, for self-signed certificates.
+    pub ca_certificate: Option<PathBuf>,
+    /// Image data bigger than this many bytes is left out when mirroring.
+    pub max_image_size: usize,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        MirrorConfig {
+            send_to: None,
+            listen: None,
+            token: None,
+            certificate: None,
+            key: None,
+            ca_certificate: None,
+            max_image_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Where to push notifications while you're away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PushService {
+    Ntfy,
+    Gotify,
+}
+
+/// Pushing notifications to your phone while you're away, under the `[push]` table.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PushConfig {
+    pub service: PushService,
+    /// For ntfy, the topic URL (like `https://ntfy.sh/my-topic`); for Gotify, the server's URL.
+    /// Nothing is pushed unless this is set.
+    pub url: Option<String>,
+    /// An access token for ntfy, or an application token for Gotify.
+    pub token: Option<String>,
+    /// How many seconds the session has to be idle or locked before notifications get pushed.
+    pub away_after: u64,
+    /// If nonempty, only notifications from these apps are pushed. Matched the same way as
+    /// `blocked_apps`.
+    pub apps: Vec<String>,
+    /// Less urgent notifications aren't pushed.
+    pub min_urgency: Urgency,
+}
+
+impl Default for PushConfig {
+    fn default() -> Self {
+        PushConfig {
+            service: PushService::Ntfy,
+            url: None,
+            token: None,
+            away_after: 5 * 60,
+            apps: vec![],
+            min_urgency: Urgency::Normal,
+        }
+    }
+}
+
+/// Turning on do-not-disturb during busy calendar events, under the `[calendar]` table.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CalendarConfig {
+    /// The URL of an iCalendar feed; `file://` URLs work too. Nothing is read unless this is set.
+    pub url: Option<String>,
+    /// How many minutes to wait between fetches of the calendar.
+    pub refresh: u64,
+}
+
+impl Default for CalendarConfig {
+    fn default() -> Self {
+        CalendarConfig {
+            url: None,
+            refresh: 15,
+        }
+    }
+}
+
+/// A shell command to run when an action is invoked, configured with `[[action_commands]]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActionCommand {
+    /// If nonempty, only notifications from these apps are covered. Matched the same way as
+    /// `blocked_apps`.
+    #[serde(default)]
+    pub apps: Vec<String>,
+    /// The action key to run the command for. `*` and `?` work as in app patterns.
+    pub key: String,
+    /// The command, run with `sh -c`. The notification's fields are in `NINOMIYA_*` environment
+    /// variables.
+    pub command: String,
+}
+
+/// A sound for notifications from some apps or in some categories, configured with `[[sounds]]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SoundRule {
+    /// If nonempty, only notifications from these apps get the sound. Matched the same way as
+    /// `blocked_apps`.
+    #[serde(default)]
+    pub apps: Vec<String>,
+    /// If nonempty, only notifications in these categories get the sound. `*` and `?` work as in
+    /// app patterns, so `email.*` covers every email category.
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// A name from the sound theme or a path to a file. Empty to play nothing.
+    pub sound: String,
+}
+
+/// Notifications to collect into a periodic digest instead of showing one by one, configured with
+/// `[[digests]]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DigestRule {
+    /// What to call the notifications in the digest, like `"CI results"`.
+    pub name: String,
+    /// If nonempty, only notifications from these apps are collected. Matched the same way as
+    /// `blocked_apps`.
+    #[serde(default)]
+    pub apps: Vec<String>,
+    /// If set, only notifications whose summary matches this are collected. `*` and `?` work as
+    /// in app patterns.
+    pub summary: Option<String>,
+    /// How many minutes to collect notifications for, starting from the first one, before showing
+    /// the digest.
+    #[serde(default = "default_digest_minutes")]
+    pub every: u64,
+    /// Extra counts for the digest's summary, keyed by what to call them and matched against the
+    /// summaries of the notifications; for example, `passed = "*passed*"`.
+    #[serde(default)]
+    pub count: BTreeMap<String, String>,
+}
+
+fn default_digest_minutes() -> u64 {
+    30
+}
+
+/// The shape notification images are clipped to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageShape {
+    /// Leave the image as it is.
+    Square,
+    /// Round off the corners, using `image_corner_radius`.
+    Rounded,
+    /// Clip to the largest circle that fits. Good for chat avatars.
+    Circle,
+}
+
+impl ImageShape {
+    /// The CSS class given to clipped images, so themes can style them differently.
+    pub fn css_class(self) -> Option<&'static str> {
+        match self {
+            ImageShape::Square => None,
+            ImageShape::Rounded => Some("rounded"),
+            ImageShape::Circle => Some("circle"),
+        }
+    }
+}
+
+/// Whether to color notifications after their image or icon, and how.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccentFromImage {
+    Off,
+    /// Just `@ninomiya_accent`, for the theme to use however it likes.
+    Color,
+    /// A border all the way around.
+    Border,
+    /// A thick bar down the left side.
+    Bar,
+}
+
+/// Which parts of a notification to show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    /// Everything.
+    Full,
+    /// Just the summary, application name, and icon.
+    Compact,
+}
+
+/// A part of the screen that notifications stack up in. Notifications in the top and center zones
+/// stack downwards; ones in the bottom zones stack upwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Zone {
+    TopLeft,
+    TopCenter,
+    TopRight,
+    Center,
+    BottomLeft,
+    BottomCenter,
+    BottomRight,
+}
+
+impl Zone {
+    /// The x coordinate of a window `width` pixels wide in this zone.
+    pub fn x(self, screen_width: i32, width: i32, padding_x: i32) -> i32 {
+        match self {
+            Zone::TopLeft | Zone::BottomLeft => padding_x,
+            Zone::TopCenter | Zone::Center | Zone::BottomCenter => (screen_width - width) / 2,
+            Zone::TopRight | Zone::BottomRight => screen_width - width - padding_x,
+        }
+    }
+
+    /// Whether later notifications go below earlier ones.
+    pub fn stacks_down(self) -> bool {
+        !matches!(
+            self,
+            Zone::BottomLeft | Zone::BottomCenter | Zone::BottomRight
+        )
+    }
+
+    /// The y coordinate of a window `height` pixels tall when it's the only one in this zone.
+    pub fn first_y(self, screen_height: i32, height: i32, padding_y: i32) -> i32 {
+        match self {
+            Zone::TopLeft | Zone::TopCenter | Zone::TopRight => padding_y,
+            Zone::Center => (screen_height - height) / 2,
+            Zone::BottomLeft | Zone::BottomCenter | Zone::BottomRight => {
+                screen_height - height - padding_y
+            }
+        }
+    }
+
+    /// The zone's name in the config.
+    pub fn name(self) -> &'static str {
+        match self {
+            Zone::TopLeft => "top_left",
+            Zone::TopCenter => "top_center",
+            Zone::TopRight => "top_right",
+            Zone::Center => "center",
+            Zone::BottomLeft => "bottom_left",
+            Zone::BottomCenter => "bottom_center",
+            Zone::BottomRight => "bottom_right",
+        }
+    }
+}
+
+impl Default for Zone {
+    fn default() -> Self {
+        Zone::TopRight
+    }
+}
+
+/// Where dragged notifications' anchors are saved, relative to the config directory. It's a drop-in
+/// that sorts last, so it wins over the rest of the config.
+const ANCHORS_FILE: &str = "conf.d/99-anchors.toml";
+
+/// How a notification is presented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Style {
+    /// A small window that stacks with the others and closes on its own.
+    Banner,
+    /// A bigger window in the middle of the screen that stays until it's dismissed, for things
+    /// that can't be missed.
+    Center,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style::Banner
+    }
+}
+
+/// A setting for each urgency, configured as a table with `low`, `normal`, and `critical` keys.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PerUrgency<T: Default> {
+    pub low: T,
+    pub normal: T,
+    pub critical: T,
+}
+
+impl<T: Default + Copy> PerUrgency<T> {
+    pub fn for_urgency(&self, urgency: Urgency) -> T {
+        match urgency {
+            Urgency::Low => self.low,
+            Urgency::Normal => self.normal,
+            Urgency::Critical => self.critical,
+        }
+    }
+}
+
+/// What to do with notifications while the screen is being shared or recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreenShareMode {
+    /// Nothing; don't even watch for screen sharing.
+    Off,
+    /// Hold notifications until sharing stops, as if the server were paused.
+    Pause,
+    /// Show notifications, but without their contents.
+    Redact,
+}
+
+/// When to go into low-power mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LowPowerMode {
+    Off,
+    On,
+    /// Whenever UPower says we're running on battery.
+    OnBattery,
+}
+
+/// What clicking on a notification does, besides closing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClickAction {
+    /// A single click fires the default action.
+    Single,
+    /// Only a double click fires the default action; a single click just dismisses.
+    Double,
+    /// Clicking only dismisses. Actions can still be picked with their buttons.
+    Dismiss,
+}
+
+/// Presentation settings for a category of notifications, configured under
+/// `[templates."<category>"]`. Anything left unset falls back to the global setting.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Template {
+    pub layout: Option<Layout>,
+    /// An icon name or path to use instead of the one the notification came with.
+    pub icon: Option<String>,
+    /// An extra CSS class for the window, so themes can give the category an accent.
+    pub class: Option<String>,
+    /// How many seconds to show the notification for.
+    #[serde(deserialize_with = "deserialize_optional_duration")]
+    pub duration: Option<Duration>,
+    /// A sound to play: either a name from the sound theme or a path to a file.
+    pub sound: Option<String>,
+    /// Overrides the style from `styles`.
+    pub style: Option<Style>,
+}
+
 /// Configures how the GUI is rendered.
 #[derive(Debug, Deserialize)]
 #[serde(default, deny_unknown_fields)]
 pub struct Config {
-    /// Width of notification windows.
-    pub width: i32,
+    /// Width of notification windows, in pixels or as a percentage of the monitor's width.
+    pub width: Width,
+    /// If set, notifications are at least this many pixels wide, whatever `width` says.
+    pub min_width: Option<i32>,
+    /// If set, notifications are at most this many pixels wide, whatever `width` says.
+    pub max_width: Option<i32>,
     /// Height of the notification's embedded image (if present).
     pub image_height: i32,
+    /// The shape to clip the notification's embedded image to: `square`, `rounded`, or `circle`.
+    pub image_shape: ImageShape,
+    /// The corner radius, in pixels, when `image_shape` is `rounded`.
+    pub image_corner_radius: f64,
+    /// Gives each notification a border or bar in the main color of its image (or failing that,
+    /// its icon): `off`, `color`, `border`, or `bar`. `color` only defines `@ninomiya_accent`
+    /// for the theme.
+    pub accent_from_image: AccentFromImage,
     /// How much space to add in the x direction between the notification and the screen border.
     pub padding_x: i32,
     /// How much space to add in the y direction between the notification and the screen border.
@@ -28,50 +451,353 @@ pub struct Config {
     pub duration: Duration,
     /// How much verticla space to put between notifications.
     pub notification_spacing: i32,
+    /// If set, each zone's notifications are stacked like a deck of cards: the newest in front,
+    /// and each older one peeking out this many pixels further behind it. Hovering over the deck
+    /// fans it out.
+    pub stack_overlap: Option<i32>,
+    /// How many seconds a notification takes to grow or shrink when it's replaced by one of a
+    /// different size. 0 snaps it straight to the new size.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub resize_animation: Duration,
+    /// If set, bodies longer than this many lines are cut short, and grow to show the rest while
+    /// the pointer is over the notification.
+    pub body_lines: Option<i32>,
+    /// Where on the screen notifications of each urgency go. Each zone stacks its notifications
+    /// separately.
+    pub zones: PerUrgency<Zone>,
+    /// How notifications of each urgency are presented: as a `banner` or in the `center`.
+    pub styles: PerUrgency<Style>,
     /// Height of the icon displayed on the left of notifications.
     pub icon_height: i32,
     /// Path to the theme file. Interpreted as relative to the configuration file. Defaults to
     /// If the path doesn't exist, then a warning is printed in the configuration log.
     pub theme_path: PathBuf,
+    /// If set, this theme is used instead of `theme_path` while the desktop is in dark mode.
+    /// Interpreted as relative to the configuration file.
+    pub theme_path_dark: Option<PathBuf>,
+    /// Values for `var(--name)` in the stylesheets, keyed by name (without the `--`).
+    pub css_variables: HashMap<String, String>,
+    /// The font family for all text in notifications, like `"Inter"`.
+    pub font_family: Option<String>,
+    /// The size of the summary, in points.
+    pub summary_size: Option<f64>,
+    /// The size of the body, in points.
+    pub body_size: Option<f64>,
+    /// If set, every notification and how it was resolved is appended to this file as JSON lines.
+    /// Interpreted as relative to the configuration file.
+    pub json_log: Option<PathBuf>,
+    /// Once the JSON log grows past this many bytes, it's moved aside to `<json_log>.1`.
+    pub json_log_max_size: u64,
+    /// Whether to save the notifications on screen and held while paused when ninomiya is stopped
+    /// (with SIGTERM or SIGINT), and bring them back the next time it starts.
+    pub restore_notifications: bool,
+    /// Logging configuration, under the `[log]` table.
+    pub log: LogConfig,
+    /// Mirroring configuration, under the `[mirror]` table.
+    pub mirror: MirrorConfig,
+    /// Away-mode push configuration, under the `[push]` table.
+    pub push: PushConfig,
+    /// Calendar configuration, under the `[calendar]` table.
+    pub calendar: CalendarConfig,
+    /// If a notification comes without an icon, try to find one based on the process that sent
+    /// it.
+    pub infer_app_icons: bool,
+    /// If set, this image is shown for notifications whose icon can't be found. Interpreted as
+    /// relative to the configuration file.
+    pub placeholder_icon: Option<PathBuf>,
+    /// A Rhai script whose hooks are run on every notification. Interpreted as relative to the
+    /// configuration file.
+    pub script: Option<PathBuf>,
+    /// Shell commands to run when actions are invoked. The first rule that matches wins.
+    pub action_commands: Vec<ActionCommand>,
+    /// Apps (matched like `blocked_apps`) whose window is raised, or which are launched if they
+    /// have none, when the default action on one of their notifications is invoked.
+    pub raise_apps: Vec<String>,
+    /// Apps (matched like `blocked_apps`) whose window is marked as wanting attention when one of
+    /// their notifications arrives while it isn't focused.
+    pub attention_apps: Vec<String>,
+    /// The name of the icon theme to load icons from. If unset, uses the GTK default.
+    pub icon_theme: Option<String>,
+    /// Per-category presentation, keyed by category. Keys can be exact (`email.arrived`) or end in
+    /// `*` to match every category with that prefix (`device.*`).
+    pub templates: HashMap<String, Template>,
+    /// Sounds to play for notifications from some apps or in some categories. The first rule that
+    /// matches wins, over both templates and the sounds notifications ask for.
+    pub sounds: Vec<SoundRule>,
+    /// Whether to watch the battery through UPower and notify when it's low or the charger is
+    /// plugged in or out.
+    pub battery_notifications: bool,
+    /// When to skip sounds, body images and animations, and poll less often, to save power:
+    /// `off`, `on`, or `on_battery`.
+    pub low_power: LowPowerMode,
+    /// What to do with notifications while an app is sharing the screen through the desktop
+    /// portal: `off`, `pause`, or `redact`.
+    pub screen_share: ScreenShareMode,
+    /// Do-not-disturb is turned on while a window whose `WM_CLASS` matches one of these is
+    /// focused. `*` and `?` work as in shell globs. Needs X11 and `xprop`.
+    pub dnd_when_focused: Vec<String>,
+    /// Whether to show a digest of the notifications dropped by do-not-disturb once it's turned
+    /// off, instead of just dropping them.
+    pub dnd_digest: bool,
+    /// Whether a timed pause (`ninomiya pause --for`) ends with a digest of the notifications that
+    /// arrived during it, instead of showing them all.
+    pub pause_digest: bool,
+    /// Notifications to collect into a periodic digest instead of showing them. The first rule that
+    /// matches wins, and critical notifications are always shown.
+    pub digests: Vec<DigestRule>,
+    /// Whether a notification sent right after its sender closed one replaces the closed one, so
+    /// that apps that update notifications that way don't make them flicker.
+    pub coalesce_close_notify: bool,
+    /// Whether `GetCapabilities` lists ninomiya's extensions to the spec, like
+    /// `x-ninomiya-progress`.
+    pub advertise_extensions: bool,
+    /// How many bytes the notifications held for the digest or while paused can take up. Past
+    /// this, their images are dropped, and then the oldest low-urgency ones.
+    pub queue_memory_limit: usize,
+    /// Notifications from these apps are dropped. Matched against the app name and desktop entry;
+    /// `*` and `?` work as in shell globs.
+    pub blocked_apps: Vec<String>,
+    /// If nonempty, only these apps can show notifications. Matched the same way as
+    /// `blocked_apps`, which takes priority.
+    pub allowed_apps: Vec<String>,
+    /// Only the apps in `allowed_apps` get popups, and if it's empty, none do. Notifications from
+    /// other apps (that aren't blocked) still go in the JSON log, but expire straight away.
+    pub strict_allowlist: bool,
+    /// Notifications from these apps show "New message" instead of their body, both on screen and
+    /// in the JSON log. Matched the same way as `blocked_apps`.
+    pub redact_body: Vec<String>,
+    /// The DBus names of external processors to run notifications through before they're shown,
+    /// in order. See the `processor` module for the interface they implement.
+    pub processors: Vec<String>,
+    /// How many milliseconds to wait for each processor before giving up on it.
+    pub processor_timeout: u64,
+    /// How many milliseconds the GUI thread can go without handling events before it's reported
+    /// as hung. 0 turns the check off.
+    pub gui_watchdog_timeout: u64,
+    /// Whether to exit if the GUI thread stays hung for three times `gui_watchdog_timeout`, so
+    /// that systemd or DBus activation can start ninomiya again.
+    pub gui_watchdog_exit: bool,
+    /// If set, once more than this many notifications from one app arrive within `group_window`,
+    /// they're rolled up into a single notification.
+    pub group_threshold: Option<usize>,
+    /// How many seconds back to look when counting notifications for `group_threshold`.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub group_window: Duration,
+    /// If set, once more than this many notifications from one app arrive within `spam_window`,
+    /// the app is muted for `spam_mute`.
+    pub spam_threshold: Option<usize>,
+    /// How many seconds back to look when counting notifications for `spam_threshold`.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub spam_window: Duration,
+    /// How many seconds an app that tripped `spam_threshold` stays muted for.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub spam_mute: Duration,
+    /// Whether clicking a notification fires its default action: `single` (on any click),
+    /// `double` (only on a double click), or `dismiss` (never).
+    pub click_action: ClickAction,
+    /// How many seconds after dismissing a notification `ninomiya undo-close` can still bring it
+    /// back. 0 turns undoing off.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub undo_close_timeout: Duration,
+    /// Whether to hold off on closing a notification while the pointer is over it.
+    pub pause_on_hover: bool,
+    /// Whether to number action buttons, so that they can be picked with the keyboard after
+    /// calling `SelectAction` on the control interface.
+    pub action_shortcuts: bool,
+    /// The opacity of notification windows, from 0 to 1. Needs a compositor.
+    pub opacity: f64,
+    /// If set, the window background is the theme's background color with this alpha, from 0 to
+    /// 1. Unlike `opacity`, this leaves the text and images opaque. Needs a compositor; without
+    /// one, the background is opaque.
+    pub background_alpha: Option<f64>,
+    /// Whether to ask the compositor to blur what's behind notifications, which looks nice with
+    /// `background_alpha`.
+    pub blur: bool,
+    /// Whether to look for dock windows (panels and bars) and keep notifications from covering
+    /// them, for window managers that don't leave them out of the work area. Needs X11, `xprop`
+    /// and `xwininfo`.
+    pub avoid_docks: bool,
+    /// Whether to ask the window manager to keep notifications above other windows, including
+    /// fullscreen ones.
+    pub keep_above: bool,
+    /// Whether to ask the window manager to show notifications on every workspace.
+    pub sticky: bool,
+    /// Whether notifications are ordinary windows that the window manager places, so that tiling
+    /// window managers can move and tile them, instead of popups that ninomiya places itself.
+    pub managed_windows: bool,
+    /// Whether notifications can be dragged somewhere else, instead of swiped away. Later
+    /// notifications in the same zone stack from wherever one was dropped.
+    pub draggable: bool,
+    /// Whether where notifications were dragged to is saved to `conf.d/99-anchors.toml`, so that
+    /// it sticks across restarts.
+    pub remember_drag: bool,
+    /// Where each zone's notifications start from instead of its corner: the left edge, and the
+    /// top edge for zones that stack down or the bottom edge for ones that stack up.
+    pub anchors: HashMap<Zone, (i32, i32)>,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
-            width: 300,
+            width: Width::Pixels(300),
+            min_width: None,
+            max_width: None,
             image_height: 64,
+            image_shape: ImageShape::Square,
+            image_corner_radius: 8.0,
+            accent_from_image: AccentFromImage::Off,
             padding_x: 0,
             padding_y: 0,
             duration: Duration::from_millis(3000),
             notification_spacing: 10,
+            stack_overlap: None,
+            resize_animation: Duration::from_millis(200),
+            body_lines: None,
+            zones: PerUrgency::default(),
+            styles: PerUrgency::default(),
             icon_height: 64,
             theme_path: PathBuf::from("style.css"),
+            theme_path_dark: None,
+            css_variables: HashMap::new(),
+            font_family: None,
+            summary_size: None,
+            body_size: None,
+            json_log: None,
+            json_log_max_size: 10 * 1024 * 1024,
+            restore_notifications: true,
+            log: LogConfig::default(),
+            mirror: MirrorConfig::default(),
+            push: PushConfig::default(),
+            calendar: CalendarConfig::default(),
+            infer_app_icons: true,
+            placeholder_icon: None,
+            script: None,
+            action_commands: vec![],
+            raise_apps: vec![],
+            attention_apps: vec![],
+            icon_theme: None,
+            templates: HashMap::new(),
+            sounds: vec![],
+            battery_notifications: false,
+            low_power: LowPowerMode::Off,
+            screen_share: ScreenShareMode::Off,
+            dnd_when_focused: vec![],
+            dnd_digest: false,
+            pause_digest: false,
+            digests: vec![],
+            coalesce_close_notify: false,
+            advertise_extensions: true,
+            queue_memory_limit: 64 * 1024 * 1024,
+            blocked_apps: vec![],
+            allowed_apps: vec![],
+            strict_allowlist: false,
+            redact_body: vec![],
+            processors: vec![],
+            processor_timeout: 250,
+            gui_watchdog_timeout: 10_000,
+            gui_watchdog_exit: false,
+            group_threshold: None,
+            group_window: Duration::from_secs(60),
+            spam_threshold: None,
+            spam_window: Duration::from_secs(60),
+            spam_mute: Duration::from_secs(10 * 60),
+            click_action: ClickAction::Single,
+            undo_close_timeout: Duration::from_secs(10),
+            pause_on_hover: true,
+            action_shortcuts: false,
+            opacity: 1.0,
+            background_alpha: None,
+            blur: false,
+            avoid_docks: false,
+            keep_above: true,
+            sticky: true,
+            managed_windows: false,
+            draggable: false,
+            remember_drag: false,
+            anchors: HashMap::new(),
         }
     }
 }
 
 impl Config {
-    /// Loads the configuration file from the on-disk config path.
+    /// Loads the configuration file from the on-disk config path. Not having one is fine; that's
+    /// the defaults (plus any drop-ins).
     ///
     /// This uses the OS-appropriate path; for example, ~/.config on Linux.
     pub fn load() -> Result<Config, Error> {
-        Config::load_from(Config::config_dir()?.join("config.toml"))
+        Config::load_files(&Config::file()?, false)
+    }
+
+    /// Where `load` looks for the configuration file.
+    pub fn file() -> Result<PathBuf, Error> {
+        Ok(Config::config_dir()?.join("config.toml"))
     }
 
-    /// Loads the configuration file from the given path.
+    /// Loads the configuration file from the given path, followed by every `.toml` file in the
+    /// `conf.d` directory next to it in alphabetical order. Later files override earlier ones.
     pub fn load_from<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
-        let path = path.as_ref().to_str().ok_or(anyhow!(
-            "Failed to convert path '{:?}' to Unicode",
-            path.as_ref().to_string_lossy()
-        ))?;
-        info!("Attempting to load config from {}", path);
-        let file = config::File::new(path, config::FileFormat::Toml);
+        Config::load_files(path.as_ref(), true)
+    }
+
+    /// `load_from`, but with a missing file at `path` treated like an empty one if it isn't
+    /// `required`.
+    fn load_files(path: &Path, required: bool) -> Result<Config, Error> {
         let mut config = config::Config::new();
-        config.merge(file)?;
+        let drop_ins = match path.parent() {
+            Some(dir) => Config::drop_ins(&dir.join("conf.d"))?,
+            None => vec![],
+        };
+        let main = std::iter::once((path.to_owned(), required));
+        for (path, required) in main.chain(drop_ins.into_iter().map(|path| (path, true))) {
+            let path = path.to_str().ok_or(anyhow!(
+                "Failed to convert path '{:?}' to Unicode",
+                path.to_string_lossy()
+            ))?;
+            info!("Attempting to load config from {}", path);
+            config.merge(config::File::new(path, config::FileFormat::Toml).required(required))?;
+        }
         let config = config.try_into()?;
         Ok(config)
     }
 
+    /// The `.toml` files in a drop-in directory, sorted by name. A missing directory has none.
+    fn drop_ins(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        if !dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut paths = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "toml") && path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Saves `anchors` to a drop-in in the config directory, so that they're used next time.
+    pub fn save_anchors(anchors: &HashMap<Zone, (i32, i32)>) -> Result<()> {
+        Config::save_anchors_in(&Config::config_dir()?, anchors)
+    }
+
+    fn save_anchors_in(dir: &Path, anchors: &HashMap<Zone, (i32, i32)>) -> Result<()> {
+        let path = dir.join(ANCHORS_FILE);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut zones: Vec<_> = anchors.iter().collect();
+        zones.sort_by_key(|(zone, _)| zone.name());
+        let mut toml = String::from(
+            "# Written by ninomiya when notifications are dragged. Delete it to put them back.\n\
+             [anchors]\n",
+        );
+        for (zone, (x, y)) in zones {
+            toml.push_str(&format!("{} = [{}, {}]\n", zone.name(), x, y));
+        }
+        std::fs::write(&path, toml).with_context(|| format!("failed to write {:?}", path))
+    }
+
     /// The directory that all the configuration files are stored in.
     pub fn config_dir() -> Result<PathBuf, Error> {
         Ok(
@@ -82,10 +808,183 @@ impl Config {
         )
     }
 
+    /// Where the notifications on screen are saved for `restore_notifications`. Each DBus name gets
+    /// its own file, so that a `--testing` daemon doesn't pick up the real one's.
+    pub fn state_path(dbus_name: &str) -> Result<PathBuf, Error> {
+        Ok(
+            directories::ProjectDirs::from("ai", "deifactor", "ninomiya")
+                .ok_or(anyhow!("Failed to compute cache directory path"))?
+                .cache_dir()
+                .join(format!("state-{}.json", dbus_name)),
+        )
+    }
+
+    /// Where whether the user paused notifications or turned on do-not-disturb is saved. Like the
+    /// state, each DBus name gets its own.
+    pub fn mute_path(dbus_name: &str) -> Result<PathBuf, Error> {
+        Ok(
+            directories::ProjectDirs::from("ai", "deifactor", "ninomiya")
+                .ok_or(anyhow!("Failed to compute cache directory path"))?
+                .cache_dir()
+                .join(format!("mute-{}.json", dbus_name)),
+        )
+    }
+
     /// The path to the selected theme file.
     pub fn full_theme_path(&self) -> Result<PathBuf, Error> {
         Ok(Config::config_dir()?.join(&self.theme_path))
     }
+
+    /// The width of notifications on a monitor that's `monitor_width` pixels wide.
+    pub fn window_width(&self, monitor_width: i32) -> i32 {
+        let width = match self.width {
+            Width::Pixels(pixels) => pixels,
+            Width::Percent(percent) => (f64::from(monitor_width) * percent / 100.0).round() as i32,
+        };
+        let width = self.min_width.map_or(width, |min| width.max(min));
+        self.max_width.map_or(width, |max| width.min(max))
+    }
+
+    /// The themes in the config directory, by name: every `.css` file there, named after the file
+    /// without its extension.
+    pub fn themes() -> Result<Vec<(String, PathBuf)>, Error> {
+        Config::themes_in(&Config::config_dir()?)
+    }
+
+    fn themes_in(dir: &Path) -> Result<Vec<(String, PathBuf)>, Error> {
+        if !dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut themes = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "css") && path.is_file() {
+                if let Some(name) = path.file_stem() {
+                    themes.push((name.to_string_lossy().into_owned(), path));
+                }
+            }
+        }
+        themes.sort();
+        Ok(themes)
+    }
+
+    /// The theme file `theme` refers to: either a path to one (relative to the working directory),
+    /// or the name of one in the config directory.
+    pub fn find_theme(theme: &str) -> Result<PathBuf, Error> {
+        Config::find_theme_in(&Config::config_dir()?, theme)
+    }
+
+    fn find_theme_in(dir: &Path, theme: &str) -> Result<PathBuf, Error> {
+        let path = Path::new(theme);
+        if path.is_file() {
+            return Ok(std::env::current_dir()?.join(path));
+        }
+        match Config::themes_in(dir)?
+            .into_iter()
+            .find(|(name, _)| name == theme)
+        {
+            Some((_, path)) => Ok(path),
+            None => bail!(
+                "{:?} isn't a theme file or the name of a theme in {:?}",
+                theme,
+                dir
+            ),
+        }
+    }
+
+    /// The path to the dark theme file, if one is configured.
+    pub fn full_theme_path_dark(&self) -> Result<Option<PathBuf>, Error> {
+        match &self.theme_path_dark {
+            Some(path) => Ok(Some(Config::config_dir()?.join(path))),
+            None => Ok(None),
+        }
+    }
+
+    /// The style for a notification, from its template if it has one and its urgency otherwise.
+    pub fn style_for(&self, urgency: Urgency, category: Option<&str>) -> Style {
+        self.template_for(category)
+            .and_then(|template| template.style)
+            .unwrap_or_else(|| self.styles.for_urgency(urgency))
+    }
+
+    /// The sound to play for a notification from `app_name` with `hints`, if any: the one from the
+    /// first `[[sounds]]` rule that matches, or else its template's, or else the one its hints ask
+    /// for. Notifications that ask for no sound don't get one.
+    pub fn sound_for(&self, app_name: Option<&str>, hints: &Hints) -> Option<String> {
+        if hints.suppress_sound {
+            return None;
+        }
+        let category = hints.category.as_deref();
+        let rule = self.sounds.iter().find(|rule| {
+            let app_matches = rule.apps.is_empty()
+                || app_filter::matches_app(&rule.apps, app_name, hints.desktop_entry.as_deref());
+            let category_matches = rule.categories.is_empty()
+                || category.map_or(false, |category| {
+                    rule.categories
+                        .iter()
+                        .any(|pattern| glob_matches(pattern, category))
+                });
+            app_matches && category_matches
+        });
+        let sound = match rule {
+            Some(rule) => Some(rule.sound.clone()),
+            None => self
+                .template_for(category)
+                .and_then(|template| template.sound.clone())
+                .or_else(|| hints.sound_name.clone())
+                .or_else(|| {
+                    hints
+                        .sound_file
+                        .as_ref()
+                        .map(|path| path.to_string_lossy().into_owned())
+                }),
+        };
+        sound.filter(|sound| !sound.is_empty())
+    }
+
+    /// The template for the given category. Exact matches win, then the longest matching prefix.
+    pub fn template_for(&self, category: Option<&str>) -> Option<&Template> {
+        let category = category?;
+        if let Some(template) = self.templates.get(category) {
+            return Some(template);
+        }
+        self.templates
+            .iter()
+            .filter_map(|(key, template)| {
+                let prefix = key.strip_suffix('*')?;
+                if category.starts_with(prefix) {
+                    Some((prefix.len(), template))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, template)| template)
+    }
+
+    /// The path to the placeholder icon, if one is configured.
+    pub fn full_placeholder_icon_path(&self) -> Result<Option<PathBuf>, Error> {
+        match &self.placeholder_icon {
+            Some(path) => Ok(Some(Config::config_dir()?.join(path))),
+            None => Ok(None),
+        }
+    }
+
+    /// The path to the script, if one is configured.
+    pub fn full_script_path(&self) -> Result<Option<PathBuf>, Error> {
+        match &self.script {
+            Some(path) => Ok(Some(Config::config_dir()?.join(path))),
+            None => Ok(None),
+        }
+    }
+
+    /// The path to the JSON log, if one is configured.
+    pub fn full_json_log_path(&self) -> Result<Option<PathBuf>, Error> {
+        match &self.json_log {
+            Some(path) => Ok(Some(Config::config_dir()?.join(path))),
+            None => Ok(None),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -111,6 +1010,231 @@ mod test {
         assert!(Config::load_from("/i/do/not/exist").is_err());
     }
 
+    #[test]
+    fn optional_config_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("conf.d"))?;
+        std::fs::write(
+            dir.path().join("conf.d/10-size.toml"),
+            "json_log_max_size = 2\n",
+        )?;
+        let config = Config::load_files(&dir.path().join("config.toml"), false)?;
+        assert_eq!(config.json_log_max_size, 2);
+        assert!(!config.draggable);
+        Ok(())
+    }
+
+    #[test]
+    fn drop_ins() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(
+            dir.path().join("config.toml"),
+            "json_log_max_size = 1\ninfer_app_icons = false\n",
+        )?;
+        std::fs::create_dir(dir.path().join("conf.d"))?;
+        std::fs::write(
+            dir.path().join("conf.d/20-laptop.toml"),
+            "json_log_max_size = 3\n",
+        )?;
+        std::fs::write(
+            dir.path().join("conf.d/10-theme.toml"),
+            "json_log_max_size = 2\nimage_shape = \"circle\"\n",
+        )?;
+        std::fs::write(dir.path().join("conf.d/README"), "not toml")?;
+        let config = Config::load_from(dir.path().join("config.toml"))?;
+        assert_eq!(config.json_log_max_size, 3);
+        assert_eq!(config.image_shape, ImageShape::Circle);
+        assert!(!config.infer_app_icons);
+        Ok(())
+    }
+
+    #[test]
+    fn saves_anchors() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("config.toml"), "draggable = true\n")?;
+        let mut anchors = HashMap::new();
+        anchors.insert(Zone::TopRight, (1500, 40));
+        anchors.insert(Zone::BottomLeft, (10, 1000));
+        Config::save_anchors_in(dir.path(), &anchors)?;
+        let config = Config::load_from(dir.path().join("config.toml"))?;
+        assert!(config.draggable);
+        assert_eq!(config.anchors, anchors);
+        Ok(())
+    }
+
+    #[test]
+    fn finds_themes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("style.css"), "")?;
+        std::fs::write(dir.path().join("dark.css"), "")?;
+        std::fs::write(dir.path().join("config.toml"), "")?;
+        let names: Vec<String> = Config::themes_in(dir.path())?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["dark", "style"]);
+        assert_eq!(
+            Config::find_theme_in(dir.path(), "dark")?,
+            dir.path().join("dark.css")
+        );
+        let path = dir.path().join("style.css");
+        assert_eq!(
+            Config::find_theme_in(dir.path(), path.to_str().unwrap())?,
+            path
+        );
+        assert!(Config::find_theme_in(dir.path(), "light").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn image_shape() -> Result<()> {
+        assert_eq!(config_from_string("")?.image_shape, ImageShape::Square);
+        assert_eq!(
+            config_from_string("image_shape = \"circle\"")?.image_shape,
+            ImageShape::Circle
+        );
+        assert!(config_from_string("image_shape = \"hexagon\"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn width() -> Result<()> {
+        assert_eq!(config_from_string("")?.window_width(1920), 300);
+        let config = config_from_string("width = \"25%\"\nmin_width = 350\nmax_width = 600")?;
+        assert_eq!(config.width, Width::Percent(25.0));
+        assert_eq!(config.window_width(1920), 480);
+        assert_eq!(config.window_width(1280), 350);
+        assert_eq!(config.window_width(3440), 600);
+        assert!(config_from_string("width = \"wide\"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn templates() -> Result<()> {
+        let config = config_from_string(
+            r#"
+            [templates."email.arrived"]
+            class = "mail"
+            duration = 10
+            [templates."email.*"]
+            class = "any-mail"
+            [templates."*"]
+            layout = "compact"
+            "#,
+        )?;
+        let class = |category| {
+            config
+                .template_for(Some(category))
+                .and_then(|t| t.class.clone())
+        };
+        assert_eq!(class("email.arrived"), Some("mail".to_owned()));
+        assert_eq!(class("email.bounced"), Some("any-mail".to_owned()));
+        assert_eq!(
+            config.template_for(Some("im")).and_then(|t| t.layout),
+            Some(Layout::Compact)
+        );
+        assert_eq!(
+            config.template_for(Some("email.arrived")).unwrap().duration,
+            Some(Duration::from_secs(10))
+        );
+        assert!(config.template_for(None).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn sounds() -> Result<()> {
+        let config = config_from_string(
+            r#"
+            [[sounds]]
+            apps = ["slack"]
+            sound = "message-new-instant"
+            [[sounds]]
+            categories = ["email.*"]
+            sound = "~/sounds/mail.oga"
+            [[sounds]]
+            apps = ["spotify"]
+            sound = ""
+            [templates."device.*"]
+            sound = "device-added"
+            "#,
+        )?;
+        let hints = |category: Option<&str>, sound_name: Option<&str>| Hints {
+            category: category.map(String::from),
+            sound_name: sound_name.map(String::from),
+            ..Hints::new()
+        };
+        assert_eq!(
+            config.sound_for(Some("Slack"), &hints(None, Some("bell"))),
+            Some("message-new-instant".to_owned())
+        );
+        assert_eq!(
+            config.sound_for(Some("thunderbird"), &hints(Some("email.arrived"), None)),
+            Some("~/sounds/mail.oga".to_owned())
+        );
+        assert_eq!(
+            config.sound_for(Some("spotify"), &hints(None, Some("bell"))),
+            None
+        );
+        assert_eq!(
+            config.sound_for(Some("udiskie"), &hints(Some("device.added"), Some("bell"))),
+            Some("device-added".to_owned())
+        );
+        assert_eq!(
+            config.sound_for(Some("app"), &hints(None, Some("bell"))),
+            Some("bell".to_owned())
+        );
+        let suppressed = Hints {
+            suppress_sound: true,
+            ..hints(None, None)
+        };
+        assert_eq!(config.sound_for(Some("Slack"), &suppressed), None);
+        Ok(())
+    }
+
+    #[test]
+    fn zones() -> Result<()> {
+        let config = config_from_string(
+            r#"
+            [zones]
+            critical = "center"
+            low = "bottom_right"
+            "#,
+        )?;
+        assert_eq!(config.zones.for_urgency(Urgency::Critical), Zone::Center);
+        assert_eq!(config.zones.for_urgency(Urgency::Normal), Zone::TopRight);
+        assert_eq!(config.zones.for_urgency(Urgency::Low), Zone::BottomRight);
+        assert_eq!(Zone::Center.x(1920, 300, 10), 810);
+        assert_eq!(Zone::BottomRight.x(1920, 300, 10), 1610);
+        assert_eq!(Zone::BottomRight.first_y(1080, 80, 10), 990);
+        assert!(config_from_string("[zones]\nnormal = \"left\"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn styles() -> Result<()> {
+        let config = config_from_string(
+            r#"
+            [styles]
+            critical = "center"
+            [templates."device.error"]
+            style = "center"
+            [templates."im.*"]
+            style = "banner"
+            "#,
+        )?;
+        assert_eq!(config.style_for(Urgency::Normal, None), Style::Banner);
+        assert_eq!(config.style_for(Urgency::Critical, None), Style::Center);
+        assert_eq!(
+            config.style_for(Urgency::Normal, Some("device.error")),
+            Style::Center
+        );
+        assert_eq!(
+            config.style_for(Urgency::Critical, Some("im.received")),
+            Style::Banner
+        );
+        Ok(())
+    }
+
     #[test]
     fn config_file_does_not_parse() {
         assert!(config_from_string("asldkfjaldskjf'!@#").is_err());
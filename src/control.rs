@@ -0,0 +1,275 @@
+//! The ninomiya-specific control interface, which lets other programs (status bars, scripts, the
+//! CLI) inspect and change the daemon's state. It lives on the same bus name as the notification
+//! server, at `CONTROL_PATH`.
+
+use crate::history::{DbusEntry, Entry};
+use crate::image;
+use crate::server::TData;
+use dbus::arg;
+use dbus::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use dbus::tree::{self, Access};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const CONTROL_PATH: &str = "/ai/deifactor/Ninomiya";
+pub const CONTROL_INTERFACE: &str = "ai.deifactor.Ninomiya";
+
+/// A snapshot of the properties on the control interface. The server compares these between turns
+/// of its loop to figure out when to send `PropertiesChanged`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Properties {
+    /// If true, incoming notifications are held until we're unpaused.
+    pub paused: bool,
+    /// If true, incoming notifications aren't displayed at all.
+    pub do_not_disturb: bool,
+    /// How many notifications are on screen.
+    pub displayed_count: u32,
+    /// How many notifications are being held because we're paused.
+    pub waiting_count: u32,
+    /// How many notifications from each app are on screen or held, by app name.
+    pub unread_counts: HashMap<String, u32>,
+    /// False while the GUI thread seems to be hung, so notifications aren't being shown.
+    pub gui_responsive: bool,
+}
+
+fn variant<T: arg::RefArg + 'static>(value: T) -> arg::Variant<Box<dyn arg::RefArg>> {
+    arg::Variant(Box::new(value) as Box<dyn arg::RefArg>)
+}
+
+impl Properties {
+    /// Builds a `PropertiesChanged` signal for every property that differs from `old`. Returns
+    /// `None` if nothing changed.
+    pub fn changed_since(&self, old: &Properties) -> Option<PropertiesPropertiesChanged> {
+        let mut changed = HashMap::new();
+        if self.paused != old.paused {
+            changed.insert("Paused".to_owned(), variant(self.paused));
+        }
+        if self.do_not_disturb != old.do_not_disturb {
+            changed.insert("DoNotDisturb".to_owned(), variant(self.do_not_disturb));
+        }
+        if self.displayed_count != old.displayed_count {
+            changed.insert("DisplayedCount".to_owned(), variant(self.displayed_count));
+        }
+        if self.waiting_count != old.waiting_count {
+            changed.insert("WaitingCount".to_owned(), variant(self.waiting_count));
+        }
+        if self.unread_counts != old.unread_counts {
+            changed.insert(
+                "UnreadCounts".to_owned(),
+                variant(self.unread_counts.clone()),
+            );
+        }
+        if self.gui_responsive != old.gui_responsive {
+            changed.insert("GuiResponsive".to_owned(), variant(self.gui_responsive));
+        }
+        if changed.is_empty() {
+            None
+        } else {
+            Some(PropertiesPropertiesChanged {
+                interface_name: CONTROL_INTERFACE.to_owned(),
+                changed_properties: changed,
+                invalidated_properties: vec![],
+            })
+        }
+    }
+}
+
+/// Sent when a notification's window is shown.
+#[derive(Debug)]
+pub struct NotificationDisplayed {
+    pub id: u32,
+}
+
+impl arg::AppendAll for NotificationDisplayed {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.id, i);
+    }
+}
+
+impl dbus::message::SignalArgs for NotificationDisplayed {
+    const NAME: &'static str = "NotificationDisplayed";
+    const INTERFACE: &'static str = CONTROL_INTERFACE;
+}
+
+/// Sent when a notification is held because we're paused.
+#[derive(Debug)]
+pub struct NotificationQueued {
+    pub id: u32,
+}
+
+impl arg::AppendAll for NotificationQueued {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.id, i);
+    }
+}
+
+impl dbus::message::SignalArgs for NotificationQueued {
+    const NAME: &'static str = "NotificationQueued";
+    const INTERFACE: &'static str = CONTROL_INTERFACE;
+}
+
+/// Builds the control interface. All the state lives in the `NotifyServer` that's the tree's
+/// data.
+pub fn control_interface(
+    f: &tree::Factory<tree::MTFn<TData>, TData>,
+) -> tree::Interface<tree::MTFn<TData>, TData> {
+    // We send PropertiesChanged ourselves, since most of these change without anyone calling Set.
+    f.interface(CONTROL_INTERFACE, ())
+        .add_p(
+            f.property::<bool, _>("Paused", ())
+                .access(Access::ReadWrite)
+                .auto_emit_on_set(false)
+                .on_get(|iter, pinfo| {
+                    iter.append(pinfo.tree.get_data().properties().paused);
+                    Ok(())
+                })
+                .on_set(|iter, pinfo| {
+                    pinfo.tree.get_data().set_paused(iter.read()?);
+                    Ok(())
+                }),
+        )
+        .add_p(
+            f.property::<bool, _>("DoNotDisturb", ())
+                .access(Access::ReadWrite)
+                .auto_emit_on_set(false)
+                .on_get(|iter, pinfo| {
+                    iter.append(pinfo.tree.get_data().properties().do_not_disturb);
+                    Ok(())
+                })
+                .on_set(|iter, pinfo| {
+                    pinfo.tree.get_data().set_do_not_disturb(iter.read()?);
+                    Ok(())
+                }),
+        )
+        .add_p(
+            f.property::<u32, _>("DisplayedCount", ())
+                .on_get(|iter, pinfo| {
+                    iter.append(pinfo.tree.get_data().properties().displayed_count);
+                    Ok(())
+                }),
+        )
+        .add_p(
+            f.property::<u32, _>("WaitingCount", ())
+                .on_get(|iter, pinfo| {
+                    iter.append(pinfo.tree.get_data().properties().waiting_count);
+                    Ok(())
+                }),
+        )
+        .add_p(
+            f.property::<HashMap<String, u32>, _>("UnreadCounts", ())
+                .on_get(|iter, pinfo| {
+                    iter.append(pinfo.tree.get_data().properties().unread_counts);
+                    Ok(())
+                }),
+        )
+        .add_p(
+            f.property::<bool, _>("GuiResponsive", ())
+                .on_get(|iter, pinfo| {
+                    iter.append(pinfo.tree.get_data().properties().gui_responsive);
+                    Ok(())
+                }),
+        )
+        .add_m(f.method("SelectAction", (), |m| {
+            m.tree.get_data().select_action();
+            Ok(vec![m.msg.method_return()])
+        }))
+        .add_m(f.method("UndoClose", (), |m| {
+            m.tree.get_data().undo_close();
+            Ok(vec![m.msg.method_return()])
+        }))
+        .add_m(
+            f.method("PauseFor", (), |m| {
+                let seconds: u32 = m.msg.read1()?;
+                m.tree
+                    .get_data()
+                    .set_paused_for(Duration::from_secs(u64::from(seconds)));
+                Ok(vec![m.msg.method_return()])
+            })
+            .inarg::<u32, _>("seconds"),
+        )
+        .add_m(
+            f.method("DoNotDisturbFor", (), |m| {
+                let seconds: u32 = m.msg.read1()?;
+                m.tree
+                    .get_data()
+                    .set_do_not_disturb_for(Duration::from_secs(u64::from(seconds)));
+                Ok(vec![m.msg.method_return()])
+            })
+            .inarg::<u32, _>("seconds"),
+        )
+        .add_m(
+            f.method("SetTheme", (), |m| {
+                let path: &str = m.msg.read1()?;
+                m.tree.get_data().set_theme(PathBuf::from(path))?;
+                Ok(vec![m.msg.method_return()])
+            })
+            .inarg::<&str, _>("path"),
+        )
+        .add_m(
+            f.method("UpdateProgress", (), |m| {
+                let (id, percent, text): (u32, u32, &str) = m.msg.read3()?;
+                let sender = m.msg.sender().map(|sender| sender.to_string());
+                let id = m
+                    .tree
+                    .get_data()
+                    .update_progress(sender, id, percent, text)?;
+                Ok(vec![m.msg.method_return().append1(id)])
+            })
+            .inarg::<u32, _>("id")
+            .inarg::<u32, _>("percent")
+            .inarg::<&str, _>("text")
+            .outarg::<u32, _>("id"),
+        )
+        .add_m(
+            f.method("CompleteProgress", (), |m| {
+                let (id, summary): (u32, &str) = m.msg.read2()?;
+                let sender = m.msg.sender().map(|sender| sender.to_string());
+                let id = m.tree.get_data().complete_progress(sender, id, summary)?;
+                Ok(vec![m.msg.method_return().append1(id)])
+            })
+            .inarg::<u32, _>("id")
+            .inarg::<&str, _>("summary")
+            .outarg::<u32, _>("id"),
+        )
+        .add_m(
+            f.method("ListAssets", (), |m| {
+                let urls: Vec<String> = image::ASSETS
+                    .iter()
+                    .map(|asset| asset.url().to_string())
+                    .collect();
+                Ok(vec![m.msg.method_return().append1(urls)])
+            })
+            .outarg::<Vec<String>, _>("urls"),
+        )
+        .add_m(
+            f.method("Ping", (), |m| {
+                let (uptime, version) = m.tree.get_data().ping();
+                Ok(vec![m.msg.method_return().append2(uptime, version)])
+            })
+            .outarg::<u64, _>("uptime")
+            .outarg::<&str, _>("version"),
+        )
+        .add_m(
+            f.method("GetHistory", (), |m| {
+                let history: Vec<DbusEntry> = m
+                    .tree
+                    .get_data()
+                    .history()
+                    .iter()
+                    .map(Entry::to_dbus)
+                    .collect();
+                Ok(vec![m.msg.method_return().append1(history)])
+            })
+            .outarg::<Vec<(u32, &str, &str, &str, u64)>, _>("notifications"),
+        )
+        .add_m(
+            f.method("GetStats", (), |m| {
+                let stats = m.tree.get_data().stats();
+                Ok(vec![m.msg.method_return().append1(stats)])
+            })
+            .outarg::<HashMap<String, f64>, _>("stats"),
+        )
+        .add_s(f.signal("NotificationDisplayed", ()).sarg::<u32, _>("id"))
+        .add_s(f.signal("NotificationQueued", ()).sarg::<u32, _>("id"))
+}
@@ -0,0 +1,305 @@
+//! Builds the CSS for the appearance settings in the config file, so that simple tweaks don't need
+//! a theme. It's loaded after the default stylesheet but before the user's theme, so themes can
+//! still override it (and use the colors it defines).
+//!
+//! This is also where the user's CSS variables get filled in.
+
+use crate::config::Config;
+use crate::hints::Urgency;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use tracing::warn;
+use url::Url;
+
+/// Generates the stylesheet. `composited` is whether a compositor is running; without one,
+/// translucent backgrounds would come out black, so we make them opaque.
+pub fn generate(config: &Config, composited: bool) -> String {
+    let mut css = String::new();
+    let background_alpha = if composited {
+        config.background_alpha.unwrap_or(1.0)
+    } else {
+        1.0
+    };
+    // Defined even if it isn't used, so that themes can refer to it.
+    writeln!(
+        css,
+        "@define-color ninomiya_background alpha(@theme_bg_color, {});",
+        background_alpha
+    )
+    .unwrap();
+    if config.background_alpha.is_some() {
+        css.push_str("window { background-color: @ninomiya_background; }\n");
+    }
+    if let Some(font_family) = &config.font_family {
+        writeln!(
+            css,
+            "window {{ font-family: \"{}\"; }}",
+            font_family.replace('"', "")
+        )
+        .unwrap();
+    }
+    if let Some(size) = config.summary_size {
+        writeln!(css, "#summary {{ font-size: {}pt; }}", size).unwrap();
+    }
+    if let Some(size) = config.body_size {
+        writeln!(css, "#body {{ font-size: {}pt; }}", size).unwrap();
+    }
+    css
+}
+
+/// Turns an arbitrary name into something usable in a CSS class: lowercase, with runs of anything
+/// that isn't a letter or digit replaced by a single `-`.
+fn sanitize(name: &str) -> String {
+    let mut sanitized = String::with_capacity(name.len());
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            sanitized.push(c);
+        } else if !sanitized.is_empty() && !sanitized.ends_with('-') {
+            sanitized.push('-');
+        }
+    }
+    sanitized.trim_end_matches('-').to_owned()
+}
+
+/// The CSS classes for a notification, so that themes can style apps, urgencies, and categories
+/// differently. A category like `email.arrived` gets both `category-email` and
+/// `category-email-arrived`. Clients can also ask for a class of their own with the
+/// `x-ninomiya-style-class` hint.
+pub fn notification_classes(
+    app_name: Option<&str>,
+    urgency: Urgency,
+    category: Option<&str>,
+    style_class: Option<&str>,
+) -> Vec<String> {
+    let mut classes = vec![format!("urgency-{}", urgency.name())];
+    if let Some(app_name) = app_name.map(sanitize).filter(|name| !name.is_empty()) {
+        classes.push(format!("app-{}", app_name));
+    }
+    if let Some(category) = category {
+        if let Some(dot) = category.find('.') {
+            classes.push(format!("category-{}", sanitize(&category[..dot])));
+        }
+        classes.push(format!("category-{}", sanitize(category)));
+    }
+    if let Some(style_class) = style_class.map(sanitize).filter(|class| !class.is_empty()) {
+        classes.push(style_class);
+    }
+    classes
+}
+
+/// Colors for a single notification, defined for its window so that themes can use them:
+/// `@ninomiya_urgency`, which comes from the `urgency-<urgency>` CSS variable if there is one, and
+/// `@ninomiya_accent`, the main color of its image or icon (or the urgency color if it has none).
+pub fn notification_colors(
+    urgency: Urgency,
+    accent: Option<[u8; 3]>,
+    variables: &HashMap<String, String>,
+) -> String {
+    let default = match urgency {
+        Urgency::Low => "#888888",
+        Urgency::Normal => "@theme_selected_bg_color",
+        Urgency::Critical => "#cc3333",
+    };
+    let mut css = String::new();
+    writeln!(
+        css,
+        "@define-color ninomiya_urgency var(--urgency-{}, {});",
+        urgency.name(),
+        default
+    )
+    .unwrap();
+    match accent {
+        Some([r, g, b]) => writeln!(
+            css,
+            "@define-color ninomiya_accent rgb({}, {}, {});",
+            r, g, b
+        )
+        .unwrap(),
+        None => css.push_str("@define-color ninomiya_accent @ninomiya_urgency;\n"),
+    }
+    substitute_variables(&css, variables)
+}
+
+/// The index of the parenthesis that closes the one just before `s`, if it's closed.
+fn closing_paren(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(index),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Replaces `var(--name)` in a stylesheet with the value of `name` from `variables`. GTK doesn't
+/// understand CSS variables, so we do it ourselves before loading it. `var(--name, fallback)` uses
+/// the fallback if the variable isn't defined; it can have parentheses (and variables) of its own.
+pub fn substitute_variables(css: &str, variables: &HashMap<String, String>) -> String {
+    let mut output = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("var(--") {
+        let inner = start + "var(--".len();
+        let end = match closing_paren(&rest[inner..]) {
+            Some(index) => inner + index,
+            None => break,
+        };
+        output.push_str(&rest[..start]);
+        let mut parts = rest[inner..end].splitn(2, ',');
+        let name = parts.next().unwrap_or_default().trim();
+        let fallback = parts.next().map(str::trim);
+        match (variables.get(name), fallback) {
+            (Some(value), _) => output.push_str(value),
+            (None, Some(fallback)) => output.push_str(&substitute_variables(fallback, variables)),
+            (None, None) => {
+                warn!("CSS variable --{} isn't defined", name);
+                output.push_str(&rest[start..=end]);
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Makes relative `url()`s in a stylesheet absolute, relative to `dir`. GTK resolves them relative
+/// to the file a stylesheet was loaded from, but we load stylesheets from strings once their
+/// variables are filled in, so we do it ourselves.
+pub fn rebase_urls(css: &str, dir: &Path) -> String {
+    let mut output = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("url(") {
+        let inner = start + "url(".len();
+        let end = match rest[inner..].find(')') {
+            Some(index) => inner + index,
+            None => break,
+        };
+        output.push_str(&rest[..inner]);
+        let target = rest[inner..end].trim();
+        let unquoted = target.trim_matches(|c| c == '"' || c == '\'');
+        // Anything with a scheme (like `file:`, `resource:` or `data:`) is already absolute.
+        let relative =
+            !unquoted.is_empty() && !unquoted.starts_with('/') && Url::parse(unquoted).is_err();
+        match Url::from_file_path(dir.join(unquoted)) {
+            Ok(url) if relative => write!(output, "\"{}\"", url).unwrap(),
+            _ => output.push_str(target),
+        }
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn background_alpha() {
+        let config = Config {
+            background_alpha: Some(0.8),
+            ..Config::default()
+        };
+        assert_eq!(
+            generate(&config, true),
+            "@define-color ninomiya_background alpha(@theme_bg_color, 0.8);\n\
+             window { background-color: @ninomiya_background; }\n"
+        );
+        assert!(generate(&config, false)
+            .starts_with("@define-color ninomiya_background alpha(@theme_bg_color, 1);\n"));
+        assert!(!generate(&Config::default(), true).contains("window"));
+    }
+
+    #[test]
+    fn fonts() {
+        let config = Config {
+            font_family: Some("Inter".to_owned()),
+            summary_size: Some(13.0),
+            body_size: Some(10.5),
+            ..Config::default()
+        };
+        let css = generate(&config, true);
+        assert!(css.contains("window { font-family: \"Inter\"; }\n"));
+        assert!(css.contains("#summary { font-size: 13pt; }\n"));
+        assert!(css.contains("#body { font-size: 10.5pt; }\n"));
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(
+            notification_classes(
+                Some("Mozilla Firefox"),
+                Urgency::Critical,
+                Some("email.arrived"),
+                None
+            ),
+            vec![
+                "urgency-critical",
+                "app-mozilla-firefox",
+                "category-email",
+                "category-email-arrived"
+            ]
+        );
+        assert_eq!(
+            notification_classes(Some("--"), Urgency::Low, Some("im"), Some("Build Failed")),
+            vec!["urgency-low", "category-im", "build-failed"]
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let mut variables = HashMap::new();
+        variables.insert("urgency-critical".to_owned(), "#ff0000".to_owned());
+        assert_eq!(
+            notification_colors(Urgency::Critical, Some([10, 20, 30]), &variables),
+            "@define-color ninomiya_urgency #ff0000;\n\
+             @define-color ninomiya_accent rgb(10, 20, 30);\n"
+        );
+        assert_eq!(
+            notification_colors(Urgency::Normal, None, &variables),
+            "@define-color ninomiya_urgency @theme_selected_bg_color;\n\
+             @define-color ninomiya_accent @ninomiya_urgency;\n"
+        );
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let mut variables = HashMap::new();
+        variables.insert("accent".to_owned(), "#ff8800".to_owned());
+        variables.insert("radius".to_owned(), "6px".to_owned());
+        assert_eq!(
+            substitute_variables(
+                "window { border-radius: var(--radius); color: var(--accent ); }\n\
+                 #summary { font-family: var(--font, sans-serif); color: var(--missing); }",
+                &variables
+            ),
+            "window { border-radius: 6px; color: #ff8800; }\n\
+             #summary { font-family: sans-serif; color: var(--missing); }"
+        );
+        assert_eq!(
+            substitute_variables(
+                "color: var(--shade, alpha(var(--accent), 0.5)); margin: var(--gap, 2px);",
+                &variables
+            ),
+            "color: alpha(#ff8800, 0.5); margin: 2px;"
+        );
+    }
+
+    #[test]
+    fn rebases_urls() {
+        assert_eq!(
+            rebase_urls(
+                "a { background-image: url(\"bg.png\"); }\n\
+                 b { background-image: url('icons/x.svg'); }\n\
+                 c { background-image: url(/abs.png), url(data:image/png;base64,AAAA); }",
+                Path::new("/home/me/.config/ninomiya")
+            ),
+            "a { background-image: url(\"file:///home/me/.config/ninomiya/bg.png\"); }\n\
+             b { background-image: url(\"file:///home/me/.config/ninomiya/icons/x.svg\"); }\n\
+             c { background-image: url(/abs.png), url(data:image/png;base64,AAAA); }"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Runs `curl`, which already knows how to do HTTPS, proxies and so on. Its options go in as a
+//! config file on stdin rather than on the command line, since URLs, tokens and notification
+//! bodies shouldn't be visible to every user on the machine.
+
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+
+/// Quotes `value` for a curl config file.
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Builds a curl config file out of options and their values. Options without a value are flags.
+#[derive(Default)]
+pub struct Options(String);
+
+impl Options {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn flag(mut self, name: &str) -> Self {
+        self.0.push_str(name);
+        self.0.push('\n');
+        self
+    }
+
+    pub fn option(mut self, name: &str, value: &str) -> Self {
+        self.0.push_str(&format!("{} = {}\n", name, quote(value)));
+        self
+    }
+
+    /// The config file, as it'll be passed to curl.
+    #[cfg(test)]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Starts curl with these options, with its stdout and stderr piped.
+    pub fn spawn(&self) -> Result<Child> {
+        let mut child = Command::new("curl")
+            .args(&["--config", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("failed to run curl")?;
+        let written = child
+            .stdin
+            .take()
+            .expect("curl's stdin is piped")
+            .write_all(self.0.as_bytes());
+        if let Err(err) = written {
+            let _ = child.wait();
+            return Err(err).context("couldn't pass options to curl");
+        }
+        Ok(child)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_values() {
+        assert_eq!(
+            quote("https://example.com/a\"b\\c\nd"),
+            r#""https://example.com/a\"b\\c\nd""#
+        );
+        assert_eq!(
+            Options::new()
+                .flag("silent")
+                .option("url", "https://example.com/")
+                .as_str(),
+            "silent\nurl = \"https://example.com/\"\n"
+        );
+    }
+}
@@ -1,12 +1,29 @@
 //! Implements the `demo` subcommand.
 //!
 //! The `demo` subcommand sends a series of notifications intended to capture a variety of
-//! possibilities: icon present/absent, image present/absent, etc.
+//! possibilities: icon present/absent, image present/absent, etc. With `--stress`, it sends a lot
+//! of them at once and prints how long they took to show up.
 
-use crate::hints::{Hints, ImageRef};
+use crate::hints::{ImageRef, Urgency};
 use crate::image::{demo_icon_url, demo_image_url};
-use crate::server::{Action, NinomiyaEvent, Notification};
+use crate::latency::{self, Latencies};
+use crate::lifecycle::Lifecycle;
+use crate::server::{NinomiyaEvent, Notification};
 use anyhow::Result;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+/// How often to check whether every stress test notification has been shown.
+const STRESS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, StructOpt)]
+pub struct DemoOpt {
+    /// Sends this many notifications as fast as possible, then prints the p50 and p95 latencies
+    /// from sending them to their windows being shown.
+    #[structopt(long)]
+    pub stress: Option<u32>,
+}
 
 /// Sends all demo notifications
 pub fn send_notifications(tx: glib::Sender<NinomiyaEvent>) -> Result<()> {
@@ -16,77 +33,77 @@ pub fn send_notifications(tx: glib::Sender<NinomiyaEvent>) -> Result<()> {
     Ok(())
 }
 
+/// Sends `count` demo notifications at once, timing each from when it's sent.
+pub fn send_stress(tx: glib::Sender<NinomiyaEvent>, count: u32) -> Result<()> {
+    let notifications = std::iter::repeat_with(demo_notifications).flatten();
+    for (id, mut notification) in (1..=count).zip(notifications) {
+        notification.id = id;
+        notification.lifecycle = Lifecycle::received(Instant::now());
+        tx.send(NinomiyaEvent::Notification(notification))?;
+    }
+    Ok(())
+}
+
+/// Once `count` notifications have been shown, prints their latencies. Needs the main loop to be
+/// running.
+pub fn report_stress(latencies: Arc<Latencies>, count: u32) {
+    // Only so many samples are kept.
+    let count = (count as usize).min(latency::MAX_SAMPLES);
+    glib::timeout_add_local(STRESS_POLL_INTERVAL.as_millis() as u32, move || {
+        let summary = latencies.summary();
+        if summary.samples < count {
+            return glib::Continue(true);
+        }
+        println!("{}", summary);
+        glib::Continue(false)
+    });
+}
+
 /// The list of notifications to send for demo purposes.
-fn demo_notifications() -> Vec<Notification> {
-    let base = || Notification {
-        id: 0,
-        icon: None,
-        actions: vec![],
-        application_name: Some("galax".into()),
-        summary: "placeholder".into(),
-        body: None,
-        hints: Hints::new(),
-    };
+pub fn demo_notifications() -> Vec<Notification> {
+    let base = |id| Notification::builder().id(id).application_name("galax");
 
     let demo_icon = ImageRef::Url(demo_icon_url());
     let demo_image = ImageRef::Url(demo_image_url());
-    let no_icon_no_image = Notification {
-        id: 1,
-        summary: "no image or icon".into(),
-        body: Some("we are not alone / yowaku te tsuyoi hitori hitori da".into()),
-        ..base()
-    };
-    let icon_no_image = Notification {
-        id: 2,
-        icon: Some(demo_icon.clone()),
-        summary: "icon, no image".into(),
-        body: Some("load_galax: let's upgrade the world!".into()),
-        ..base()
-    };
-    let no_icon_image = Notification {
-        id: 3,
-        summary: "image, no icon".into(),
-        body: Some("load_galax: gatchaman crowds is a good anime".into()),
-        hints: Hints {
-            image: Some(demo_image.clone()),
-        },
-        ..base()
-    };
-    let image_icon = Notification {
-        id: 4,
-        icon: Some(demo_icon.clone()),
-        summary: "image and icon".into(),
-        body: Some("load_galax: some weird alien gave me this book".into()),
-        hints: Hints {
-            image: Some(demo_image.clone()),
-        },
-        ..base()
-    };
-    let image_and_actions = Notification {
-        id: 5,
-        icon: Some(demo_icon.clone()),
-        summary: "image and actions".into(),
-        body: Some("load_galax: what will you do?".into()),
-        hints: Hints {
-            image: Some(demo_image.clone()),
-        },
-        actions: vec![
-            Action {
-                key: "key-1".into(),
-                label: "fight".into(),
-            },
-            Action {
-                key: "key-2".into(),
-                label: "perish like a MESS".into(),
-            },
-        ],
-        ..base()
-    };
+    let no_icon_no_image = base(1)
+        .summary("no image or icon")
+        .body("we are not alone / yowaku te tsuyoi hitori hitori da")
+        .build();
+    let icon_no_image = base(2)
+        .icon(demo_icon.clone())
+        .summary("icon, no image")
+        .body("load_galax: let's upgrade the world!")
+        .build();
+    let no_icon_image = base(3)
+        .summary("image, no icon")
+        .body("load_galax: gatchaman crowds is a good anime")
+        .image(demo_image.clone())
+        .build();
+    let image_icon = base(4)
+        .icon(demo_icon.clone())
+        .summary("image and icon")
+        .body("load_galax: some weird alien gave me this book")
+        .image(demo_image.clone())
+        .build();
+    let image_and_actions = base(5)
+        .icon(demo_icon)
+        .summary("image and actions")
+        .body("load_galax: what will you do?")
+        .image(demo_image)
+        .action("key-1", "fight")
+        .action("key-2", "perish like a MESS")
+        .build();
+    let critical = base(6)
+        .summary("critical")
+        .body("load_galax: this one is important")
+        .urgency(Urgency::Critical)
+        .build();
     vec![
         no_icon_no_image,
         icon_no_image,
         no_icon_image,
         image_icon,
         image_and_actions,
+        critical,
     ]
 }
@@ -0,0 +1,245 @@
+//! Collects notifications matching the `[[digests]]` rules instead of showing them, and every so
+//! often sums them up in a single notification, like "14 CI results, 12 passed". Clicking the
+//! digest shows the notifications themselves.
+
+use crate::app_filter::{self, glob_matches};
+use crate::config::DigestRule;
+use crate::hints::Urgency;
+use crate::server::{Action, Notification};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// How many summaries a digest lists in its body.
+const LISTED: usize = 5;
+
+/// A digest that's due.
+#[derive(Debug)]
+pub struct Digest {
+    /// The previous digest for the same rule, if it's still around. Its notifications are folded
+    /// into this one, so it should be closed.
+    pub close: Option<u32>,
+    pub notification: Notification,
+}
+
+#[derive(Debug, Default)]
+struct Collected {
+    /// When the first notification since the last digest was collected.
+    since: Option<Instant>,
+    pending: Vec<Notification>,
+    /// The ID of the rule's digest that's on screen, along with the notifications it's summing up.
+    shown: Option<(u32, Vec<Notification>)>,
+}
+
+#[derive(Debug)]
+pub struct Digester {
+    rules: Vec<(DigestRule, Collected)>,
+}
+
+impl Digester {
+    pub fn new(rules: Vec<DigestRule>) -> Self {
+        Digester {
+            rules: rules
+                .into_iter()
+                .map(|rule| (rule, Collected::default()))
+                .collect(),
+        }
+    }
+
+    /// Collects `notification`, which arrived at `now`, if a rule matches it. Otherwise it's
+    /// handed back to be shown as usual. Critical notifications are never collected.
+    pub fn add(&mut self, notification: Notification, now: Instant) -> Option<Notification> {
+        if notification.hints.urgency == Urgency::Critical {
+            return Some(notification);
+        }
+        let (rule, collected) = match self
+            .rules
+            .iter_mut()
+            .find(|(rule, _)| matches(rule, &notification))
+        {
+            Some(found) => found,
+            None => return Some(notification),
+        };
+        debug!(
+            "Collecting notification {} for the {:?} digest",
+            notification.id, rule.name
+        );
+        collected.since.get_or_insert(now);
+        collected.pending.push(notification);
+        None
+    }
+
+    /// Builds the digests whose rules have been collecting for long enough by `now`.
+    pub fn due(&mut self, now: Instant) -> Vec<Digest> {
+        let mut digests = vec![];
+        for (rule, collected) in &mut self.rules {
+            match collected.since {
+                Some(since)
+                    if now.duration_since(since) >= Duration::from_secs(rule.every * 60) => {}
+                _ => continue,
+            }
+            collected.since = None;
+            let (close, mut held) = match collected.shown.take() {
+                Some((id, held)) => (Some(id), held),
+                None => (None, vec![]),
+            };
+            held.append(&mut collected.pending);
+            let notification = digest_notification(rule, &held);
+            collected.shown = Some((notification.id, held));
+            digests.push(Digest {
+                close,
+                notification,
+            });
+        }
+        digests
+    }
+
+    /// Takes the notification with the given ID out of whichever digest it's been collected for,
+    /// say because its app closed it. Returns whether it found it.
+    pub fn remove(&mut self, id: u32) -> bool {
+        let mut found = false;
+        for (_, collected) in &mut self.rules {
+            let shown = collected.shown.iter_mut().map(|(_, held)| held);
+            for held in std::iter::once(&mut collected.pending).chain(shown) {
+                let before = held.len();
+                held.retain(|notification| notification.id != id);
+                found |= held.len() != before;
+            }
+        }
+        found
+    }
+
+    /// Ends the digest with the given ID, returning the notifications it was summing up. Returns
+    /// None if it isn't a digest (or isn't the latest one for its rule).
+    pub fn take(&mut self, id: u32) -> Option<Vec<Notification>> {
+        let collected = self
+            .rules
+            .iter_mut()
+            .map(|(_, collected)| collected)
+            .find(|collected| collected.shown.as_ref().map(|(shown, _)| *shown) == Some(id))?;
+        collected.shown.take().map(|(_, held)| held)
+    }
+
+    /// The notifications that have been collected, whether or not their digest is out yet.
+    pub fn held(&self) -> impl Iterator<Item = &Notification> {
+        self.rules.iter().flat_map(|(_, collected)| {
+            let shown = collected.shown.iter().flat_map(|(_, held)| held.iter());
+            collected.pending.iter().chain(shown)
+        })
+    }
+}
+
+fn matches(rule: &DigestRule, notification: &Notification) -> bool {
+    let app_matches = rule.apps.is_empty()
+        || app_filter::matches_app(
+            &rule.apps,
+            notification.application_name.as_deref(),
+            notification.hints.desktop_entry.as_deref(),
+        );
+    app_matches
+        && rule
+            .summary
+            .as_ref()
+            .map_or(true, |pattern| glob_matches(pattern, &notification.summary))
+}
+
+/// Builds the notification showing a digest of `held`, which isn't empty.
+fn digest_notification(rule: &DigestRule, held: &[Notification]) -> Notification {
+    let (summary, body) = digest_text(rule, held);
+    // Clicking on it shows the notifications it's summing up.
+    let mut notification = Notification::summing_up(held, &summary, &body);
+    notification.actions.push(Action {
+        key: "show".to_owned(),
+        label: "Show".to_owned(),
+    });
+    notification
+}
+
+/// Sums up a digest as the number of notifications and each of the rule's counts, with the latest
+/// few summaries in the body.
+fn digest_text(rule: &DigestRule, held: &[Notification]) -> (String, String) {
+    let mut summary = format!("{} {}", held.len(), rule.name);
+    for (label, pattern) in &rule.count {
+        let count = held
+            .iter()
+            .filter(|notification| glob_matches(pattern, &notification.summary))
+            .count();
+        summary.push_str(&format!(", {} {}", count, label));
+    }
+    let mut lines: Vec<String> = held
+        .iter()
+        .rev()
+        .take(LISTED)
+        .map(|notification| notification.summary.clone())
+        .collect();
+    if held.len() > LISTED {
+        lines.push(format!("and {} more", held.len() - LISTED));
+    }
+    (summary, lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_and_sums_up() {
+        let mut count = std::collections::BTreeMap::new();
+        count.insert("passed".to_owned(), "*passed*".to_owned());
+        let mut digester = Digester::new(vec![DigestRule {
+            name: "CI results".to_owned(),
+            apps: vec!["ci".to_owned()],
+            summary: None,
+            every: 10,
+            count,
+        }]);
+        let start = Instant::now();
+        assert!(digester
+            .add(Notification::from_app("ci", "Build passed"), start)
+            .is_none());
+        assert!(digester
+            .add(Notification::from_app("ci", "Build failed"), start)
+            .is_none());
+        assert!(digester
+            .add(Notification::from_app("ci", "Tests passed"), start)
+            .is_none());
+        assert!(digester
+            .add(Notification::from_app("mail", "Build passed"), start)
+            .is_some());
+        let mut critical = Notification::from_app("ci", "Deploy failed");
+        critical.hints.urgency = Urgency::Critical;
+        assert!(digester.add(critical, start).is_some());
+
+        assert!(digester.due(start + Duration::from_secs(5 * 60)).is_empty());
+        let digests = digester.due(start + Duration::from_secs(10 * 60));
+        assert_eq!(digests.len(), 1);
+        assert_eq!(digests[0].close, None);
+        let first = &digests[0].notification;
+        assert_eq!(first.summary, "3 CI results, 2 passed");
+        assert_eq!(
+            first.body.as_deref(),
+            Some("Tests passed\nBuild failed\nBuild passed")
+        );
+
+        // The next digest takes in the last one, if it hasn't been looked at, minus the ones their
+        // apps closed.
+        let later = start + Duration::from_secs(20 * 60);
+        assert!(digester
+            .add(Notification::from_app("ci", "Build passed"), later)
+            .is_none());
+        let closed = Notification::from_app("ci", "Build passed");
+        let closed_id = closed.id;
+        assert!(digester.add(closed, later).is_none());
+        assert!(digester.remove(closed_id));
+        assert!(!digester.remove(closed_id));
+        let digests = digester.due(later + Duration::from_secs(10 * 60));
+        assert_eq!(digests[0].close, Some(first.id));
+        assert_eq!(digests[0].notification.summary, "4 CI results, 3 passed");
+        assert!(digester.take(first.id).is_none());
+        assert_eq!(
+            digester
+                .take(digests[0].notification.id)
+                .map(|held| held.len()),
+            Some(4)
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Finds dock windows (panels and bars) so that notifications can be kept from covering them, for
+//! window managers that don't report them properly. Docks are the windows in the EWMH
+//! `_NET_CLIENT_LIST` whose `_NET_WM_WINDOW_TYPE` is `_NET_WM_WINDOW_TYPE_DOCK`, so this only works
+//! on X11.
+//!
+//! As elsewhere, we shell out (to `xprop` and `xwininfo`) rather than talking to the X server
+//! ourselves.
+
+use anyhow::{Context, Result};
+use std::cell::Cell;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        self.x <= x && x < self.x + self.width && self.y <= y && y < self.y + self.height
+    }
+
+    /// The smallest rectangle containing both.
+    pub fn union(&self, other: &Rect) -> Rect {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+
+    fn overlaps(&self, other: &Rect) -> bool {
+        self.x < other.x + other.width
+            && other.x < self.x + self.width
+            && self.y < other.y + other.height
+            && other.y < self.y + self.height
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .with_context(|| format!("failed to run {}", program))?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Gets the window IDs out of `xprop -root _NET_CLIENT_LIST` output, which looks like
+/// `_NET_CLIENT_LIST(WINDOW): window id # 0x1e00003, 0x2200007`.
+fn parse_client_list(output: &str) -> Vec<&str> {
+    match output.trim().splitn(2, "# ").nth(1) {
+        Some(ids) => ids
+            .split(',')
+            .map(str::trim)
+            .filter(|id| id.starts_with("0x"))
+            .collect(),
+        None => vec![],
+    }
+}
+
+/// Gets the geometry of a window out of `xwininfo -id` output, if it's on screen.
+fn parse_geometry(output: &str) -> Option<Rect> {
+    let field = |name: &str| -> Option<&str> {
+        output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(name))
+            .map(|value| value.trim_start_matches(':').trim())
+    };
+    if field("Map State") != Some("IsViewable") {
+        return None;
+    }
+    Some(Rect {
+        x: field("Absolute upper-left X")?.parse().ok()?,
+        y: field("Absolute upper-left Y")?.parse().ok()?,
+        width: field("Width")?.parse().ok()?,
+        height: field("Height")?.parse().ok()?,
+    })
+}
+
+/// Lists where the dock windows on screen are.
+pub fn probe() -> Result<Vec<Rect>> {
+    let clients = run("xprop", &["-root", "_NET_CLIENT_LIST"])?;
+    let mut docks = vec![];
+    for id in parse_client_list(&clients) {
+        let window_type = run("xprop", &["-id", id, "_NET_WM_WINDOW_TYPE"])?;
+        if !window_type.contains("_NET_WM_WINDOW_TYPE_DOCK") {
+            continue;
+        }
+        docks.extend(parse_geometry(&run("xwininfo", &["-id", id])?));
+    }
+    Ok(docks)
+}
+
+/// Keeps track of where the dock windows are. Looking takes a few processes per window, so it's
+/// done on a thread of its own, and until the first look is done there aren't any docks.
+#[derive(Debug, Default)]
+pub struct DockWatcher {
+    docks: Arc<Mutex<Vec<Rect>>>,
+    probed_at: Cell<Option<Instant>>,
+    probing: Arc<AtomicBool>,
+}
+
+impl DockWatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Where the docks were last seen. If that was more than `interval` ago, looks again in the
+    /// background, and calls `changed` from there if they've moved.
+    pub fn docks<F: FnOnce() + Send + 'static>(&self, interval: Duration, changed: F) -> Vec<Rect> {
+        let stale = self
+            .probed_at
+            .get()
+            .map_or(true, |probed_at| probed_at.elapsed() >= interval);
+        if stale && !self.probing.swap(true, Ordering::SeqCst) {
+            self.probed_at.set(Some(Instant::now()));
+            let (docks, probing) = (Arc::clone(&self.docks), Arc::clone(&self.probing));
+            thread::spawn(move || {
+                let probed = probe().unwrap_or_else(|err| {
+                    warn!("Failed to look for dock windows: {:?}", err);
+                    vec![]
+                });
+                debug!("Found dock windows at {:?}", probed);
+                let moved = {
+                    let mut docks = docks.lock().unwrap();
+                    let moved = *docks != probed;
+                    *docks = probed;
+                    moved
+                };
+                probing.store(false, Ordering::SeqCst);
+                if moved {
+                    changed();
+                }
+            });
+        }
+        self.docks.lock().unwrap().clone()
+    }
+}
+
+/// Moves `window` off any of `docks` it overlaps, leaving the padding between them. Docks along the
+/// top or bottom of the screen push it down or up, and ones along the sides push it sideways.
+pub fn avoid(
+    mut window: Rect,
+    docks: &[Rect],
+    screen_width: i32,
+    screen_height: i32,
+    padding_x: i32,
+    padding_y: i32,
+) -> Rect {
+    // Getting off one dock might put it on another. Docks that overlap each other could push it
+    // back and forth forever, so give up after trying each once.
+    for _ in 0..docks.len() {
+        let dock = match docks.iter().find(|dock| dock.overlaps(&window)) {
+            Some(dock) => dock,
+            None => break,
+        };
+        if dock.width >= dock.height {
+            if dock.y + dock.height / 2 < screen_height / 2 {
+                window.y = dock.y + dock.height + padding_y;
+            } else {
+                window.y = dock.y - window.height - padding_y;
+            }
+        } else if dock.x + dock.width / 2 < screen_width / 2 {
+            window.x = dock.x + dock.width + padding_x;
+        } else {
+            window.x = dock.x - window.width - padding_x;
+        }
+    }
+    window
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_off_docks() {
+        assert_eq!(
+            parse_client_list("_NET_CLIENT_LIST(WINDOW): window id # 0x1e00003, 0x2200007\n"),
+            vec!["0x1e00003", "0x2200007"]
+        );
+        let xwininfo = "
+xwininfo: Window id: 0x1e00003 \"polybar-top\"
+
+  Absolute upper-left X:  0
+  Absolute upper-left Y:  0
+  Relative upper-left X:  0
+  Relative upper-left Y:  0
+  Width: 1920
+  Height: 30
+  Map State: IsViewable
+";
+        let top_bar = parse_geometry(xwininfo).unwrap();
+        assert_eq!(
+            top_bar,
+            Rect {
+                x: 0,
+                y: 0,
+                width: 1920,
+                height: 30
+            }
+        );
+        assert_eq!(
+            parse_geometry(&xwininfo.replace("IsViewable", "IsUnMapped")),
+            None
+        );
+
+        let right_dock = Rect {
+            x: 1872,
+            y: 0,
+            width: 48,
+            height: 1080,
+        };
+        let window = Rect {
+            x: 1620,
+            y: 0,
+            width: 300,
+            height: 80,
+        };
+        assert_eq!(
+            avoid(window, &[top_bar, right_dock], 1920, 1080, 0, 10),
+            Rect {
+                x: 1572,
+                y: 40,
+                ..window
+            }
+        );
+        // Windows that don't overlap anything stay put.
+        let bottom = Rect { y: 900, ..window };
+        assert_eq!(avoid(bottom, &[top_bar], 1920, 1080, 0, 10), bottom);
+    }
+}
@@ -0,0 +1,376 @@
+//! `ninomiya doctor`, which checks the environment for the things that most often stop ninomiya
+//! from working: someone else owning the notification name, no compositor, missing icons, and so
+//! on. Each check prints what it found and, if something's off, what to do about it.
+
+use crate::config::Config;
+use crate::theme_lint;
+use anyhow::{bail, Result};
+use dbus::blocking::Connection;
+use gtk::prelude::*;
+use std::fmt;
+use std::time::Duration;
+
+const DBUS_TIMEOUT: Duration = Duration::from_millis(1000);
+/// An icon every icon theme should have.
+const PROBE_ICON: &str = "dialog-information";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    /// Something that works, but maybe not the way you'd like.
+    Warning,
+    /// Something that will stop ninomiya from working.
+    Problem,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Status::Ok => "  ok   ",
+            Status::Warning => "warning",
+            Status::Problem => "problem",
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Finding {
+    status: Status,
+    check: &'static str,
+    detail: String,
+    /// What to do about it, if it isn't ok.
+    advice: Option<String>,
+}
+
+impl Finding {
+    fn ok(check: &'static str, detail: impl Into<String>) -> Self {
+        Finding {
+            status: Status::Ok,
+            check,
+            detail: detail.into(),
+            advice: None,
+        }
+    }
+
+    fn warning(check: &'static str, detail: impl Into<String>, advice: impl Into<String>) -> Self {
+        Finding {
+            status: Status::Warning,
+            check,
+            detail: detail.into(),
+            advice: Some(advice.into()),
+        }
+    }
+
+    fn problem(check: &'static str, detail: impl Into<String>, advice: impl Into<String>) -> Self {
+        Finding {
+            status: Status::Problem,
+            check,
+            detail: detail.into(),
+            advice: Some(advice.into()),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.status, self.check, self.detail)?;
+        if let Some(advice) = &self.advice {
+            write!(f, "\n          {}", advice)?;
+        }
+        Ok(())
+    }
+}
+
+/// The name of the process with the given PID, from `/proc`.
+fn process_name(pid: u32) -> Option<String> {
+    std::fs::read_to_string(format!("/proc/{}/comm", pid))
+        .ok()
+        .map(|name| name.trim().to_owned())
+}
+
+/// Who owns `dbus_name`, if anyone: the process name if we can find it, or the unique name.
+fn name_owner(connection: &Connection, dbus_name: &str) -> Result<Option<String>> {
+    let bus = connection.with_proxy(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        DBUS_TIMEOUT,
+    );
+    let (has_owner,): (bool,) =
+        bus.method_call("org.freedesktop.DBus", "NameHasOwner", (dbus_name,))?;
+    if !has_owner {
+        return Ok(None);
+    }
+    let (owner,): (String,) =
+        bus.method_call("org.freedesktop.DBus", "GetNameOwner", (dbus_name,))?;
+    let pid: Result<(u32,), _> = bus.method_call(
+        "org.freedesktop.DBus",
+        "GetConnectionUnixProcessID",
+        (&owner,),
+    );
+    Ok(Some(
+        match pid
+            .ok()
+            .and_then(|(pid,)| process_name(pid).map(|name| (pid, name)))
+        {
+            Some((pid, name)) => format!("{} (PID {})", name, pid),
+            None => owner,
+        },
+    ))
+}
+
+fn check_dbus(dbus_name: &str, system: bool) -> Finding {
+    const CHECK: &str = "DBus";
+    let bus = if system { "system" } else { "session" };
+    let connection = match if system {
+        Connection::new_system()
+    } else {
+        Connection::new_session()
+    } {
+        Ok(connection) => connection,
+        Err(err) => {
+            return Finding::problem(
+                CHECK,
+                format!("can't connect to the {} bus: {}", bus, err),
+                if system {
+                    "Make sure the system bus (dbus-daemon or dbus-broker) is running."
+                } else {
+                    "Start your session with dbus-run-session, or check DBUS_SESSION_BUS_ADDRESS."
+                },
+            )
+        }
+    };
+    match name_owner(&connection, dbus_name) {
+        Ok(None) => Finding::ok(CHECK, format!("{} is free on the {} bus", dbus_name, bus)),
+        Ok(Some(owner)) if owner.starts_with("ninomiya") => Finding::ok(
+            CHECK,
+            format!(
+                "{} is owned by {}, which is running already",
+                dbus_name, owner
+            ),
+        ),
+        Ok(Some(owner)) => Finding::problem(
+            CHECK,
+            format!("{} is owned by {}", dbus_name, owner),
+            "Only one notification daemon can run at a time. Stop that one (and keep it from \
+             being started again), or try ninomiya alongside it with --testing.",
+        ),
+        Err(err) => Finding::warning(
+            CHECK,
+            format!("couldn't check who owns {}: {}", dbus_name, err),
+            "The bus might be restricting introspection; this is usually harmless.",
+        ),
+    }
+}
+
+/// Checks everything that needs a display. Returns a single problem if we can't get one.
+fn check_display() -> Vec<Finding> {
+    if let Err(err) = gtk::init() {
+        return vec![Finding::problem(
+            "Display",
+            format!("GTK couldn't open a display: {}", err),
+            "Run ninomiya from inside your graphical session, with DISPLAY or WAYLAND_DISPLAY set.",
+        )];
+    }
+    let mut findings = vec![];
+    let display = gdk::Display::get_default();
+    let backend = display
+        .as_ref()
+        .map_or_else(|| "unknown".to_owned(), |display| display.get_type().name());
+    findings.push(if backend.contains("Wayland") {
+        Finding::warning(
+            "Backend",
+            "running as a native Wayland client",
+            "Notifications are positioned by moving their windows, which Wayland doesn't allow, so \
+             they'll end up wherever the compositor puts them. Set GDK_BACKEND=x11 to go through \
+             XWayland instead.",
+        )
+    } else if backend.contains("X11") {
+        Finding::ok("Backend", "X11")
+    } else {
+        Finding::warning(
+            "Backend",
+            backend,
+            "This GDK backend hasn't been tested with ninomiya.",
+        )
+    });
+
+    let screen = match gdk::Screen::get_default() {
+        Some(screen) => screen,
+        None => {
+            findings.push(Finding::problem(
+                "Screen",
+                "GDK has no default screen",
+                "This usually means the display connection is broken.",
+            ));
+            return findings;
+        }
+    };
+    findings.push(if screen.is_composited() {
+        Finding::ok("Compositor", "a compositor is running")
+    } else {
+        Finding::warning(
+            "Compositor",
+            "no compositor is running",
+            "Notifications will be opaque, without rounded corners or shadows, and opacity and \
+             background_alpha won't do anything. Start one (e.g. picom) if you want those.",
+        )
+    });
+    findings.push(if screen.get_rgba_visual().is_some() {
+        Finding::ok("RGBA visual", "available")
+    } else {
+        Finding::warning(
+            "RGBA visual",
+            "the screen has no RGBA visual",
+            "Transparency won't work even with a compositor. Check your X server's color depth \
+             (it should be 24 or 32 bits).",
+        )
+    });
+    findings
+}
+
+fn check_icons(config: Option<&Config>) -> Finding {
+    const CHECK: &str = "Icons";
+    let theme_name = config.and_then(|config| config.icon_theme.clone());
+    let theme = match &theme_name {
+        Some(name) => {
+            let theme = gtk::IconTheme::new();
+            theme.set_custom_theme(Some(name.as_str()));
+            Some(theme)
+        }
+        None => gtk::IconTheme::get_default(),
+    };
+    let described = theme_name.as_deref().unwrap_or("the default icon theme");
+    match theme {
+        None => Finding::problem(
+            CHECK,
+            "couldn't get an icon theme",
+            "Apps that send icon names will show up without icons. Install an icon theme like \
+             adwaita-icon-theme.",
+        ),
+        Some(theme) => {
+            if theme
+                .lookup_icon(PROBE_ICON, 32, gtk::IconLookupFlags::empty())
+                .is_some()
+            {
+                Finding::ok(CHECK, format!("{} has {}", described, PROBE_ICON))
+            } else {
+                Finding::problem(
+                    CHECK,
+                    format!("{} doesn't have {}", described, PROBE_ICON),
+                    "Install an icon theme (like adwaita-icon-theme or hicolor-icon-theme), or \
+                     fix icon_theme in the config.",
+                )
+            }
+        }
+    }
+}
+
+fn check_config(config: &Result<Config>) -> Vec<Finding> {
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            return vec![Finding::problem(
+                "Config",
+                format!("{:#}", err),
+                "Fix the config file; until then, ninomiya falls back to the defaults.",
+            )]
+        }
+    };
+    // Running on the defaults is fine.
+    let loaded = match Config::file() {
+        Ok(file) if !file.exists() => format!("{:?} doesn't exist, so the defaults are used", file),
+        _ => "loaded".to_owned(),
+    };
+    let mut findings = vec![Finding::ok("Config", loaded)];
+    let themes = match config.full_theme_path().and_then(|light| {
+        Ok(std::iter::once(light)
+            .chain(config.full_theme_path_dark()?)
+            .collect::<Vec<_>>())
+    }) {
+        Ok(themes) => themes,
+        Err(err) => {
+            findings.push(Finding::problem(
+                "Theme",
+                format!("couldn't find the themes: {:#}", err),
+                "Check that $HOME or $XDG_CONFIG_HOME is set.",
+            ));
+            return findings;
+        }
+    };
+    for theme_path in themes {
+        if !theme_path.exists() {
+            findings.push(Finding::ok(
+                "Theme",
+                format!(
+                    "{:?} doesn't exist, so the default look is used",
+                    theme_path
+                ),
+            ));
+            continue;
+        }
+        findings.push(match theme_lint::lint_file(&theme_path) {
+            Ok(warnings) if warnings.is_empty() => {
+                Finding::ok("Theme", format!("{:?} looks fine", theme_path))
+            }
+            Ok(warnings) => Finding::warning(
+                "Theme",
+                format!("{:?} has selectors that never match", theme_path),
+                warnings.join("\n          "),
+            ),
+            Err(err) => Finding::problem(
+                "Theme",
+                format!("{:#}", err),
+                "Check that the theme file is readable.",
+            ),
+        });
+    }
+    findings
+}
+
+/// Runs every check and prints the results. Fails if anything would stop ninomiya from working.
+pub fn run(dbus_name: &str, system: bool, config: &Result<Config>) -> Result<()> {
+    let mut findings = vec![check_dbus(dbus_name, system)];
+    let display = check_display();
+    let have_display = display
+        .first()
+        .map_or(false, |finding| finding.check != "Display");
+    findings.extend(display);
+    if have_display {
+        findings.push(check_icons(config.as_ref().ok()));
+    }
+    findings.extend(check_config(config));
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    let problems = findings
+        .iter()
+        .filter(|finding| finding.status == Status::Problem)
+        .count();
+    if problems > 0 {
+        bail!(
+            "found {} problem(s); ninomiya probably won't work until they're fixed",
+            problems
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prints_findings() {
+        assert_eq!(
+            Finding::ok("Config", "loaded").to_string(),
+            "[  ok   ] Config: loaded"
+        );
+        assert_eq!(
+            Finding::problem("Icons", "no icons", "Install some.").to_string(),
+            "[problem] Icons: no icons\n          Install some."
+        );
+        let findings = check_config(&Err(anyhow::anyhow!("bad toml")));
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].status, Status::Problem);
+    }
+}
@@ -0,0 +1,129 @@
+//! Expands `:tada:`-style emoji shortcodes, for `ninomiya notify --expand-emoji`. Only the common
+//! ones are here; they use the same names as GitHub and Slack.
+
+/// The emoji for a shortcode (without the colons), if we know it.
+fn lookup(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "+1" | "thumbsup" => "👍",
+        "-1" | "thumbsdown" => "👎",
+        "100" => "💯",
+        "alarm_clock" => "⏰",
+        "bell" => "🔔",
+        "bomb" => "💣",
+        "book" => "📖",
+        "bug" => "🐛",
+        "calendar" => "📆",
+        "check" | "heavy_check_mark" => "✔️",
+        "white_check_mark" => "✅",
+        "clap" => "👏",
+        "clock" | "clock3" => "🕒",
+        "cloud" => "☁️",
+        "coffee" => "☕",
+        "computer" => "💻",
+        "construction" => "🚧",
+        "cry" => "😢",
+        "email" | "envelope" => "✉️",
+        "eyes" => "👀",
+        "fire" => "🔥",
+        "floppy_disk" => "💾",
+        "gear" => "⚙️",
+        "ghost" => "👻",
+        "grin" => "😁",
+        "hammer" => "🔨",
+        "heart" => "❤️",
+        "hourglass" => "⌛",
+        "inbox_tray" => "📥",
+        "information_source" => "ℹ️",
+        "joy" => "😂",
+        "key" => "🔑",
+        "laughing" => "😆",
+        "lock" => "🔒",
+        "mag" => "🔍",
+        "memo" => "📝",
+        "moon" => "🌙",
+        "muscle" => "💪",
+        "musical_note" => "🎵",
+        "no_entry" => "⛔",
+        "ok_hand" => "👌",
+        "outbox_tray" => "📤",
+        "package" => "📦",
+        "party_popper" | "tada" => "🎉",
+        "pray" => "🙏",
+        "question" => "❓",
+        "rage" => "😡",
+        "recycle" => "♻️",
+        "rocket" => "🚀",
+        "rotating_light" => "🚨",
+        "skull" => "💀",
+        "sleeping" => "😴",
+        "smile" => "😄",
+        "smiley" => "😃",
+        "snowflake" => "❄️",
+        "sob" => "😭",
+        "sparkles" => "✨",
+        "star" => "⭐",
+        "sunny" => "☀️",
+        "sweat_smile" => "😅",
+        "thinking" => "🤔",
+        "trophy" => "🏆",
+        "umbrella" => "☔",
+        "unlock" => "🔓",
+        "warning" => "⚠️",
+        "wave" => "👋",
+        "white_flag" => "🏳️",
+        "wink" => "😉",
+        "wrench" => "🔧",
+        "x" => "❌",
+        "zap" => "⚡",
+        "zzz" => "💤",
+        _ => return None,
+    })
+}
+
+fn is_shortcode_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '+' || c == '-'
+}
+
+/// Replaces every shortcode we know in `text` with its emoji. Anything else between colons (like
+/// the time in "at 10:30:00") is left alone.
+pub fn expand_shortcodes(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(':') {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let end = after.find(|c| !is_shortcode_char(c));
+        match end
+            .filter(|&end| after[end..].starts_with(':'))
+            .and_then(|end| lookup(&after[..end]).map(|emoji| (end, emoji)))
+        {
+            Some((end, emoji)) => {
+                output.push_str(emoji);
+                rest = &after[end + 1..];
+            }
+            None => {
+                // The closing colon might start the next shortcode, so only skip this one.
+                output.push(':');
+                rest = after;
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_shortcodes() {
+        assert_eq!(expand_shortcodes("build passed :tada:"), "build passed 🎉");
+        assert_eq!(expand_shortcodes(":+1::rocket:"), "👍🚀");
+        assert_eq!(
+            expand_shortcodes("at 10:30:00, :nope: :fire"),
+            "at 10:30:00, :nope: :fire"
+        );
+        assert_eq!(expand_shortcodes("ratio 1:2 :x:"), "ratio 1:2 ❌");
+    }
+}
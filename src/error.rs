@@ -0,0 +1,114 @@
+//! The errors the notification server can send back to clients. Everything else in ninomiya uses
+//! `anyhow`; these exist so that clients get a DBus error name they can act on instead of a generic
+//! failure.
+
+use dbus::tree;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum NotifyError {
+    /// One of the image hints couldn't be understood.
+    InvalidImage(anyhow::Error),
+    /// The action list had an odd length, so it can't be split into keys and labels. Holds the
+    /// length.
+    InvalidActionList(usize),
+    /// Some other hint had the wrong type or an unknown value.
+    HintParse(anyhow::Error),
+    /// The theme `SetTheme` was asked to switch to couldn't be loaded.
+    InvalidTheme(anyhow::Error),
+    /// Something went wrong on our end.
+    Internal(anyhow::Error),
+}
+
+impl NotifyError {
+    /// Classifies an error from parsing hints. Errors that are already `NotifyError`s (such as
+    /// invalid images) are kept as they are.
+    pub fn from_hints(err: anyhow::Error) -> Self {
+        match err.downcast::<NotifyError>() {
+            Ok(err) => err,
+            Err(err) => NotifyError::HintParse(err),
+        }
+    }
+
+    /// The name of the DBus error sent back to the client.
+    pub fn dbus_name(&self) -> &'static str {
+        match self {
+            NotifyError::InvalidImage(_) => "ai.deifactor.Ninomiya.Error.InvalidImage",
+            NotifyError::InvalidActionList(_) => "ai.deifactor.Ninomiya.Error.InvalidActionList",
+            NotifyError::HintParse(_) => "ai.deifactor.Ninomiya.Error.HintParse",
+            NotifyError::InvalidTheme(_) => "ai.deifactor.Ninomiya.Error.InvalidTheme",
+            NotifyError::Internal(_) => "org.freedesktop.DBus.Error.Failed",
+        }
+    }
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NotifyError::InvalidImage(err) => write!(f, "invalid image: {:#}", err),
+            NotifyError::InvalidActionList(len) => {
+                write!(f, "action list length {} must be a multiple of 2", len)
+            }
+            NotifyError::HintParse(err) => write!(f, "invalid hints: {:#}", err),
+            NotifyError::InvalidTheme(err) => write!(f, "invalid theme: {:#}", err),
+            NotifyError::Internal(err) => write!(f, "internal error: {:#}", err),
+        }
+    }
+}
+
+impl Error for NotifyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NotifyError::InvalidImage(err)
+            | NotifyError::HintParse(err)
+            | NotifyError::InvalidTheme(err)
+            | NotifyError::Internal(err) => Some(err.as_ref()),
+            NotifyError::InvalidActionList(_) => None,
+        }
+    }
+}
+
+impl From<NotifyError> for tree::MethodErr {
+    fn from(err: NotifyError) -> Self {
+        (err.dbus_name(), err.to_string()).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn classifies_hint_errors() {
+        let err: anyhow::Error = NotifyError::InvalidImage(anyhow!("bad signature")).into();
+        let err = NotifyError::from_hints(err);
+        assert_eq!(err.dbus_name(), "ai.deifactor.Ninomiya.Error.InvalidImage");
+        assert_eq!(err.to_string(), "invalid image: bad signature");
+
+        let err = NotifyError::from_hints(
+            Err::<(), _>(anyhow!("unknown urgency 7"))
+                .context("couldn't read hints")
+                .unwrap_err(),
+        );
+        assert_eq!(err.dbus_name(), "ai.deifactor.Ninomiya.Error.HintParse");
+        assert_eq!(
+            err.to_string(),
+            "invalid hints: couldn't read hints: unknown urgency 7"
+        );
+
+        let method_err = tree::MethodErr::from(NotifyError::InvalidActionList(3));
+        assert_eq!(
+            &**method_err.errorname(),
+            "ai.deifactor.Ninomiya.Error.InvalidActionList"
+        );
+
+        let method_err = tree::MethodErr::from(NotifyError::Internal(anyhow!("GUI hung")));
+        assert_eq!(
+            &**method_err.errorname(),
+            "org.freedesktop.DBus.Error.Failed"
+        );
+        assert_eq!(method_err.description(), "internal error: GUI hung");
+    }
+}
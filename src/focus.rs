@@ -0,0 +1,135 @@
+//! Keeps track of which window has focus, so that do-not-disturb can be turned on automatically
+//! while certain apps are in front. This follows the EWMH `_NET_ACTIVE_WINDOW` property on the root
+//! window, so it only works on X11 (including XWayland apps, with some compositors).
+//!
+//! We shell out to `xprop`, which can already wait for property changes and read `WM_CLASS`, rather
+//! than talking to the X server ourselves.
+
+use anyhow::{Context, Result};
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use tracing::{debug, warn};
+
+/// Gets the window ID out of a line of `xprop -spy -root _NET_ACTIVE_WINDOW` output, which looks
+/// like `_NET_ACTIVE_WINDOW(WINDOW): window id # 0x2a00003`. Returns `None` if no window has focus.
+pub(crate) fn parse_active_window(line: &str) -> Option<&str> {
+    let id = line.rsplit("# ").next()?.trim();
+    if id.starts_with("0x") && id != "0x0" {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// Gets the instance and class names out of `xprop WM_CLASS` output, which looks like
+/// `WM_CLASS(STRING) = "Navigator", "firefox"`.
+fn parse_wm_class(output: &str) -> Vec<String> {
+    match output.splitn(2, " = ").nth(1) {
+        Some(names) => names
+            .split(',')
+            .map(|name| name.trim().trim_matches('"').to_owned())
+            .filter(|name| !name.is_empty())
+            .collect(),
+        None => vec![],
+    }
+}
+
+fn wm_class(window: &str) -> Result<Vec<String>> {
+    let output = Command::new("xprop")
+        .args(&["-id", window, "WM_CLASS"])
+        .stderr(Stdio::null())
+        .output()
+        .context("failed to run xprop")?;
+    Ok(parse_wm_class(&String::from_utf8_lossy(&output.stdout)))
+}
+
+pub struct FocusWatcher {
+    rx: Receiver<Vec<String>>,
+    /// The `WM_CLASS` names of the focused window, as of the last poll.
+    classes: Vec<String>,
+}
+
+impl FocusWatcher {
+    /// Starts watching for focus changes in the background.
+    pub fn new() -> Result<Self> {
+        let mut child = Command::new("xprop")
+            .args(&["-spy", "-root", "_NET_ACTIVE_WINDOW"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("failed to run xprop to watch the focused window")?;
+        let stdout = child.stdout.take().expect("xprop's stdout wasn't piped");
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => {
+                        warn!("Failed to read from xprop: {:?}", err);
+                        break;
+                    }
+                };
+                let classes = match parse_active_window(&line).map(wm_class) {
+                    Some(Ok(classes)) => classes,
+                    Some(Err(err)) => {
+                        warn!("Couldn't get the focused window's class: {:?}", err);
+                        vec![]
+                    }
+                    None => vec![],
+                };
+                debug!("Focused window has classes {:?}", classes);
+                if tx.send(classes).is_err() {
+                    break;
+                }
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+        });
+        Ok(FocusWatcher {
+            rx,
+            classes: vec![],
+        })
+    }
+
+    /// Catches up on focus changes without blocking, and returns the `WM_CLASS` names of the
+    /// focused window.
+    pub fn poll(&mut self) -> &[String] {
+        loop {
+            match self.rx.try_recv() {
+                Ok(classes) => self.classes = classes,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // xprop died, so we can't tell what's focused anymore.
+                    self.classes.clear();
+                    break;
+                }
+            }
+        }
+        &self.classes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_xprop_output() {
+        assert_eq!(
+            parse_active_window("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x2a00003"),
+            Some("0x2a00003")
+        );
+        assert_eq!(
+            parse_active_window("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x0"),
+            None
+        );
+        assert_eq!(
+            parse_wm_class("WM_CLASS(STRING) = \"Navigator\", \"firefox\"\n"),
+            vec!["Navigator", "firefox"]
+        );
+        assert!(parse_wm_class("WM_CLASS:  not found.\n").is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+//! Implements tee mode, where every notification we display is also re-sent to another daemon.
+//!
+//! This is useful when migrating from another daemon, or when running alongside something like
+//! GNOME Shell that insists on handling notifications itself.
+
+use crate::bus;
+use crate::dbus_codegen::client::OrgFreedesktopNotifications;
+use crate::router::SignalSink;
+use crate::server::Signal;
+use crate::state::SavedNotification;
+use anyhow::Result;
+use dbus::blocking::{Connection, Proxy};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// How long to wait for the other daemon to reply. This happens on the forwarder's own thread, but
+/// everything queued behind it waits too.
+const FORWARD_TIMEOUT: Duration = Duration::from_millis(500);
+/// How many notifications can be waiting to be forwarded before we start dropping them.
+const QUEUE_LENGTH: usize = 256;
+
+enum Message {
+    /// Boxed, so that closes waiting in the queue don't each take up as much room as a
+    /// notification.
+    Notify(Box<SavedNotification>, i32),
+    /// Closes the forwarded copy.
+    Close(u32),
+    /// Our copy is gone, so there's nothing left to replace or close.
+    Forget(u32),
+}
+
+/// Forwards notifications from a thread of its own, so that a slow or stuck daemon doesn't hold up
+/// the server.
+pub struct Forwarder {
+    messages: SyncSender<Message>,
+}
+
+impl Forwarder {
+    /// Connects to the session (or system) bus in order to forward to the daemon owning
+    /// `dbus_name`.
+    pub fn new(dbus_name: String, system: bool) -> Result<Self> {
+        let worker = Worker {
+            connection: bus::connect(system)?,
+            dbus_name,
+            ids: HashMap::new(),
+        };
+        let (messages, rx) = mpsc::sync_channel(QUEUE_LENGTH);
+        thread::spawn(move || worker.run(rx));
+        Ok(Forwarder { messages })
+    }
+
+    fn send(&self, message: Message) {
+        match self.messages.try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("Too many notifications waiting to be forwarded"),
+            Err(TrySendError::Disconnected(_)) => warn!("The forwarding thread is gone"),
+        }
+    }
+
+    /// Re-sends a notification, as it is once it's been through scripts, processors and redaction.
+    pub fn notify(&self, notification: &SavedNotification, expire_timeout: i32) {
+        self.send(Message::Notify(
+            Box::new(notification.clone()),
+            expire_timeout,
+        ));
+    }
+
+    /// Closes the forwarded copy of notification `id`, if there is one.
+    pub fn close_notification(&self, id: u32) {
+        self.send(Message::Close(id));
+    }
+}
+
+impl SignalSink for Forwarder {
+    fn handle(&self, signal: &Signal) {
+        if let Signal::NotificationClosed { id, .. } = signal {
+            self.send(Message::Forget(*id));
+        }
+    }
+}
+
+/// The forwarder's thread, which owns the connection.
+struct Worker {
+    connection: Connection,
+    /// The DBus name of the daemon to forward to.
+    dbus_name: String,
+    /// Maps our notification IDs to the IDs the other daemon assigned to its copies, so that
+    /// replacements and closes go to the right place.
+    ids: HashMap<u32, u32>,
+}
+
+impl Worker {
+    fn run(mut self, rx: Receiver<Message>) {
+        for message in rx {
+            match message {
+                Message::Notify(notification, expire_timeout) => {
+                    self.notify(&notification, expire_timeout)
+                }
+                Message::Close(id) => self.close_notification(id),
+                Message::Forget(id) => {
+                    self.ids.remove(&id);
+                }
+            }
+        }
+    }
+
+    fn proxy(&self) -> Proxy<'_, &Connection> {
+        Proxy::new(
+            self.dbus_name.as_str(),
+            "/org/freedesktop/Notifications",
+            FORWARD_TIMEOUT,
+            &self.connection,
+        )
+    }
+
+    /// Failures are logged rather than returned, since the other daemon going away shouldn't stop
+    /// us from displaying anything.
+    fn notify(&mut self, notification: &SavedNotification, expire_timeout: i32) {
+        let id = notification.id;
+        let replaces_id = self.ids.get(&id).copied().unwrap_or(0);
+        match self.proxy().notify(
+            &notification.app_name,
+            replaces_id,
+            &notification.app_icon,
+            &notification.summary,
+            &notification.body,
+            notification.actions.iter().map(String::as_str).collect(),
+            notification.hints.to_dbus(),
+            expire_timeout,
+        ) {
+            Ok(their_id) => {
+                debug!(
+                    "Forwarded notification {} to {} as {}",
+                    id, self.dbus_name, their_id
+                );
+                self.ids.insert(id, their_id);
+            }
+            Err(err) => warn!(
+                "Failed to forward notification {} to {}: {}",
+                id, self.dbus_name, err
+            ),
+        }
+    }
+
+    fn close_notification(&mut self, id: u32) {
+        if let Some(their_id) = self.ids.remove(&id) {
+            if let Err(err) = self.proxy().close_notification(their_id) {
+                warn!(
+                    "Failed to close forwarded notification {} on {}: {}",
+                    their_id, self.dbus_name, err
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Implements the `generate` subcommand, which prints files for integrating ninomiya with the rest
+//! of the system. Since they're generated by the binary itself, they always match its CLI.
+
+use anyhow::{Context, Result};
+use clap::{App, Shell};
+use std::io;
+use structopt::StructOpt;
+
+/// A systemd user unit, with `{bus_name}` and `{exec_start}` left to be filled in.
+static SYSTEMD_UNIT: &str = include_str!("../data/ninomiya.service");
+
+#[derive(Debug, StructOpt)]
+pub enum GenerateOpt {
+    /// Prints a completion script for the given shell.
+    Completions {
+        #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+        shell: Shell,
+    },
+    /// Prints a systemd user unit that starts the daemon. Global options like `--testing`,
+    /// `--system`, `--forward-to`, `--log-file` and `--verbose` are passed through to the daemon.
+    SystemdUnit,
+}
+
+/// Prints the requested file to stdout. `app` is the top-level CLI, `dbus_name` is the name the
+/// daemon will own, and `daemon_args` are the arguments the daemon should be started with.
+pub fn generate(
+    mut app: App,
+    dbus_name: &str,
+    daemon_args: &[String],
+    options: GenerateOpt,
+) -> Result<()> {
+    match options {
+        GenerateOpt::Completions { shell } => {
+            app.gen_completions_to("ninomiya", shell, &mut io::stdout())
+        }
+        GenerateOpt::SystemdUnit => print!("{}", systemd_unit(dbus_name, daemon_args)?),
+    }
+    Ok(())
+}
+
+fn systemd_unit(dbus_name: &str, daemon_args: &[String]) -> Result<String> {
+    let exe = std::env::current_exe().context("couldn't find the path to ninomiya")?;
+    let mut exec_start = quote_exec_arg(&exe.to_string_lossy());
+    for arg in daemon_args {
+        exec_start.push(' ');
+        exec_start.push_str(&quote_exec_arg(arg));
+    }
+    Ok(SYSTEMD_UNIT
+        .replace("{bus_name}", dbus_name)
+        .replace("{exec_start}", &exec_start))
+}
+
+/// Quotes `arg` for a systemd `ExecStart=` line, which would otherwise split it on spaces and
+/// expand `%` specifiers and `$` variables in it.
+fn quote_exec_arg(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '%' => quoted.push_str("%%"),
+            '$' => quoted.push_str("$$"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_exec_args() {
+        assert_eq!(
+            quote_exec_arg("/home/me/my apps/ninomiya"),
+            "\"/home/me/my apps/ninomiya\""
+        );
+        assert_eq!(
+            quote_exec_arg(r#"100% "$HOME"\"#),
+            r#""100%% \"$$HOME\"\\""#
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! Rolls bursts of notifications from one app up into a single notification, so that a busy chat
+//! doesn't bury the screen in popups.
+
+use crate::server::Notification;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// What to do with a notification after grouping it.
+#[derive(Debug)]
+pub enum Grouped {
+    /// Show it as usual.
+    Show(Notification),
+    /// It was folded into a rollup. The given windows (the individual notifications, and any
+    /// earlier version of the rollup) should be closed, and the rollup shown instead.
+    Rollup {
+        close: Vec<u32>,
+        rollup: Notification,
+    },
+}
+
+/// A rollup that's on screen.
+#[derive(Debug)]
+struct Rollup {
+    /// The ID of the notification showing it. This changes every time it's updated.
+    id: u32,
+    app_name: String,
+    summaries: Vec<String>,
+    /// Notifications that were folded in without being shown.
+    held: Vec<Notification>,
+}
+
+#[derive(Debug, Default)]
+struct AppGroup {
+    /// When each recent notification arrived, along with its ID and summary.
+    recent: VecDeque<(Instant, u32, String)>,
+    rollup: Option<Rollup>,
+}
+
+#[derive(Debug)]
+pub struct Grouper {
+    /// Rolls up once more than this many notifications arrive within `window`.
+    threshold: usize,
+    window: Duration,
+    /// Keyed by desktop entry, or by app name if there isn't one.
+    apps: HashMap<String, AppGroup>,
+}
+
+impl Grouper {
+    pub fn new(threshold: usize, window: Duration) -> Self {
+        Grouper {
+            threshold,
+            window,
+            apps: HashMap::new(),
+        }
+    }
+
+    /// Decides whether `notification`, which arrived at `now`, should be shown on its own or
+    /// rolled up with the ones before it.
+    pub fn add(&mut self, notification: Notification, now: Instant) -> Grouped {
+        let key = match notification
+            .hints
+            .desktop_entry
+            .as_ref()
+            .or(notification.application_name.as_ref())
+        {
+            Some(key) => key.clone(),
+            None => return Grouped::Show(notification),
+        };
+        let group = self.apps.entry(key.clone()).or_default();
+        if let Some(rollup) = &mut group.rollup {
+            let close = vec![rollup.id];
+            rollup.summaries.push(notification.summary.clone());
+            rollup.held.push(notification);
+            return Grouped::Rollup {
+                close,
+                rollup: rollup_notification(rollup),
+            };
+        }
+
+        let window = self.window;
+        while let Some(&(arrived, _, _)) = group.recent.front() {
+            if now.duration_since(arrived) <= window {
+                break;
+            }
+            group.recent.pop_front();
+        }
+        group
+            .recent
+            .push_back((now, notification.id, notification.summary.clone()));
+        if group.recent.len() <= self.threshold {
+            return Grouped::Show(notification);
+        }
+
+        let recent: Vec<(Instant, u32, String)> = group.recent.drain(..).collect();
+        let close = recent
+            .iter()
+            .map(|(_, id, _)| *id)
+            .filter(|&id| id != notification.id)
+            .collect();
+        let mut rollup = Rollup {
+            id: 0,
+            app_name: notification.application_name.clone().unwrap_or(key),
+            summaries: recent.into_iter().map(|(_, _, summary)| summary).collect(),
+            held: vec![notification],
+        };
+        let notification = rollup_notification(&mut rollup);
+        group.rollup = Some(rollup);
+        Grouped::Rollup {
+            close,
+            rollup: notification,
+        }
+    }
+
+    /// Ends the rollup with the given ID, returning the notifications it was holding. Returns None
+    /// if it isn't a rollup (or isn't the latest version of one).
+    pub fn take(&mut self, id: u32) -> Option<Vec<Notification>> {
+        let group = self
+            .apps
+            .values_mut()
+            .find(|group| group.rollup.as_ref().map(|rollup| rollup.id) == Some(id))?;
+        group.rollup.take().map(|rollup| rollup.held)
+    }
+
+    /// The notifications folded into rollups, which haven't been shown on their own.
+    pub fn held(&self) -> impl Iterator<Item = &Notification> {
+        self.apps
+            .values()
+            .filter_map(|group| group.rollup.as_ref())
+            .flat_map(|rollup| rollup.held.iter())
+    }
+}
+
+/// Builds the notification showing `rollup`, giving it a fresh ID.
+fn rollup_notification(rollup: &mut Rollup) -> Notification {
+    let (summary, body) = rollup_text(&rollup.app_name, &rollup.summaries);
+    // Clicking on it expands it into the notifications it's holding.
+    let mut notification = Notification::summing_up(&rollup.held, &summary, &body);
+    notification.application_name = Some(rollup.app_name.clone());
+    rollup.id = notification.id;
+    notification
+}
+
+/// Summarizes a burst of notifications, listing the most common summaries first. For a chat app,
+/// the summary is usually the channel or sender, so this comes out like "12 new notifications from
+/// Slack" / "#general and 3 others".
+fn rollup_text(app_name: &str, summaries: &[String]) -> (String, String) {
+    let mut counts: Vec<(&str, usize)> = vec![];
+    for summary in summaries {
+        match counts.iter_mut().find(|(s, _)| s == summary) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((summary, 1)),
+        }
+    }
+    // Stable, so ties stay in the order they arrived.
+    counts.sort_by(|a, b| b.1.cmp(&a.1));
+    let body = match counts.as_slice() {
+        [] => String::new(),
+        [(only, _)] => only.to_string(),
+        [(first, _), (second, _)] => format!("{} and {}", first, second),
+        [(first, _), rest @ ..] => format!("{} and {} others", first, rest.len()),
+    };
+    (
+        format!("{} new notifications from {}", summaries.len(), app_name),
+        body,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes() {
+        let summaries: Vec<String> = vec!["#random", "#general", "#general", "#dev", "#ops"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            rollup_text("Slack", &summaries),
+            (
+                "5 new notifications from Slack".to_owned(),
+                "#general and 3 others".to_owned()
+            )
+        );
+        assert_eq!(
+            rollup_text("Slack", &summaries[..2]).1,
+            "#random and #general"
+        );
+    }
+
+    #[test]
+    fn rolls_up_bursts() {
+        let mut grouper = Grouper::new(2, Duration::from_secs(10));
+        let start = Instant::now();
+        let first = Notification::from_app("chat", "a");
+        let first_id = first.id;
+        assert!(matches!(grouper.add(first, start), Grouped::Show(_)));
+        // Other apps are counted separately.
+        assert!(matches!(
+            grouper.add(Notification::from_app("mail", "x"), start),
+            Grouped::Show(_)
+        ));
+        let second = Notification::from_app("chat", "b");
+        let second_id = second.id;
+        assert!(matches!(grouper.add(second, start), Grouped::Show(_)));
+
+        let rollup_id = match grouper.add(Notification::from_app("chat", "c"), start) {
+            Grouped::Rollup { close, rollup } => {
+                assert_eq!(close, vec![first_id, second_id]);
+                assert_eq!(rollup.summary, "3 new notifications from chat");
+                rollup.id
+            }
+            grouped => panic!("expected a rollup, got {:?}", grouped),
+        };
+        // Later ones update the rollup, replacing the old one.
+        let new_rollup_id = match grouper.add(Notification::from_app("chat", "d"), start) {
+            Grouped::Rollup { close, rollup } => {
+                assert_eq!(close, vec![rollup_id]);
+                rollup.id
+            }
+            grouped => panic!("expected a rollup, got {:?}", grouped),
+        };
+        assert!(grouper.take(rollup_id).is_none());
+        let held = grouper.take(new_rollup_id).unwrap();
+        assert_eq!(
+            held.iter().map(|n| n.summary.as_str()).collect::<Vec<_>>(),
+            vec!["c", "d"]
+        );
+    }
+
+    #[test]
+    fn forgets_old_notifications() {
+        let mut grouper = Grouper::new(1, Duration::from_secs(10));
+        let start = Instant::now();
+        assert!(matches!(
+            grouper.add(Notification::from_app("chat", "a"), start),
+            Grouped::Show(_)
+        ));
+        assert!(matches!(
+            grouper.add(
+                Notification::from_app("chat", "b"),
+                start + Duration::from_secs(11)
+            ),
+            Grouped::Show(_)
+        ));
+    }
+}
@@ -1,7 +1,12 @@
 use lazy_static::lazy_static;
 use std::any::Any;
+use std::env;
 use std::panic::{catch_unwind, UnwindSafe};
+use std::path::Path;
+use std::process::{Command, Stdio};
 use std::sync::{mpsc, mpsc::Sender, Mutex};
+use std::thread;
+use std::time::Duration;
 
 // A task for the test runner, and a channel to use to send the result back to the test thread.
 struct TestTask {
@@ -13,6 +18,7 @@ lazy_static! {
     static ref RUNNER: Mutex<Sender<TestTask>> = {
         let (tx, rx) = mpsc::channel::<TestTask>();
         std::thread::spawn(move || loop {
+            ensure_display();
             gtk::init().expect("failed to initialize gtk");
             if let Ok(task) = rx.recv() {
                 let result = catch_unwind(task.function);
@@ -27,6 +33,39 @@ lazy_static! {
     };
 }
 
+// If there's no display to run the tests against (say, on CI), starts a virtual one with Xvfb. It
+// exits by itself once the test binary disconnects from it.
+fn ensure_display() {
+    if env::var_os("DISPLAY").is_some() || env::var_os("WAYLAND_DISPLAY").is_some() {
+        return;
+    }
+    let display = (99..200)
+        .find(|n| !Path::new(&format!("/tmp/.X11-unix/X{}", n)).exists())
+        .expect("couldn't find a free X display number");
+    Command::new("Xvfb")
+        .arg(format!(":{}", display))
+        .args(&[
+            "-screen",
+            "0",
+            "1920x1080x24",
+            "-nolisten",
+            "tcp",
+            "-terminate",
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("there's no display, and starting Xvfb failed");
+    let socket = format!("/tmp/.X11-unix/X{}", display);
+    for _ in 0..50 {
+        if Path::new(&socket).exists() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    env::set_var("DISPLAY", format!(":{}", display));
+}
+
 // Panics using a dynamically-typed value, trying to make it look good.
 //
 // Without this function, any panic from an inner test would just be reported as Box<Any>, which is
@@ -1,34 +1,243 @@
-use crate::config::Config;
-use crate::hints::ImageRef;
+use crate::accent;
+use crate::app_icon;
+use crate::appearance;
+use crate::config::{
+    AccentFromImage, ClickAction, Config, ImageShape, Layout, LowPowerMode, Style, Zone,
+};
+use crate::css;
+use crate::docks::{self, DockWatcher, Rect};
+use crate::hints::{ImageRef, Urgency};
 use crate::image;
-use crate::server::{Action, NinomiyaEvent, Notification, Signal};
-use anyhow::{Context, Result};
+use crate::latency::Latencies;
+use crate::low_power::LowPower;
+use crate::mpris;
+use crate::server::{Action, CloseReason, NinomiyaEvent, Notification, Signal};
+use crate::sound;
+use crate::theme_lint;
+use crate::timer::{Clock, CloseTimer};
+use crate::watchdog::{self, Heartbeat};
+use anyhow::{bail, Context, Result};
+use gdk::prelude::GdkContextExt;
 use gdk_pixbuf::Pixbuf;
 use gio::prelude::*;
 use glib::{clone, object::WeakRef};
 use gtk::prelude::*;
-use log::{debug, error, info};
-use std::collections::HashMap;
-use std::path::Path;
+use gtk::SettingsExt;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::PI;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::{mpsc, Mutex};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, debug_span, error, info, warn};
+use url::Url;
+
+/// A notification's numbered action buttons, along with when they were added.
+type ShortcutButtons = (u64, Vec<WeakRef<gtk::Button>>);
 
 pub struct Gui {
     app: gtk::Application,
     loader: image::Loader,
-    config: Config,
+    /// Swapped out when the config is reloaded.
+    config: RefCell<Rc<Config>>,
+    /// Schedules the timeouts that close notifications.
+    clock: Rc<dyn Clock>,
     /// Used to send notifications on a delay.
     tx: glib::Sender<NinomiyaEvent>,
     signal_tx: mpsc::Sender<Signal>,
-    windows: Mutex<HashMap<u32, WeakRef<gtk::ApplicationWindow>>>,
+    windows: Mutex<HashMap<u32, NotificationWindow>>,
+    /// How many images or icons in a row have failed to load.
+    image_failures: Cell<u32>,
+    /// Shown when we can't find any other icon for a notification.
+    placeholder_icon: Option<ImageRef>,
+    /// Used to add media controls to notifications from music players.
+    mpris: Option<Rc<mpris::Mpris>>,
+    /// The numbered action buttons of each notification, when `action_shortcuts` is on. When they
+    /// were added counts up.
+    shortcut_buttons: RefCell<HashMap<u32, ShortcutButtons>>,
+    /// What the next entry in `shortcut_buttons` is numbered.
+    next_shortcut_serial: Cell<u64>,
+    /// What the next notification window is numbered.
+    next_window_serial: Cell<u64>,
+    /// The CSS provider for the user's theme, kept so that we can swap it out when dark mode is
+    /// toggled.
+    theme_provider: RefCell<Option<gtk::CssProvider>>,
+    /// The theme picked with `ninomiya theme set`, which wins over the config's until we restart.
+    theme_override: RefCell<Option<PathBuf>>,
+    /// The CSS provider for the config's appearance settings, swapped out when it's reloaded.
+    config_provider: RefCell<Option<gtk::CssProvider>>,
+    /// Whether the settings portal says the user prefers dark themes.
+    portal_prefers_dark: Cell<bool>,
+    /// How long notifications take to be dispatched and shown.
+    latencies: Arc<Latencies>,
+    /// Beaten from the main loop, so that the server can tell if we're hung.
+    heartbeat: Arc<Heartbeat>,
+    low_power: Arc<LowPower>,
+    /// GTK's animation setting from before low-power mode turned animations off.
+    saved_animations: Cell<Option<bool>>,
+    /// Where the dock windows are, if `avoid_docks` is on.
+    docks: DockWatcher,
+    /// The last notification the user dismissed, and when, for `undo_close`.
+    dismissed: RefCell<Option<(SystemTime, Notification)>>,
+    /// Where notifications brought back with `undo_close` go: back to the server, so that
+    /// do-not-disturb and pausing apply to them like anything else. Without it, as in demos,
+    /// they're shown right away.
+    restore_tx: RefCell<Option<mpsc::Sender<Notification>>>,
+    /// Where each zone's notifications go: the config's `anchors`, updated as notifications are
+    /// dragged. The whole map is saved, so that dragging one zone doesn't forget the others.
+    anchors: Rc<RefCell<HashMap<Zone, (i32, i32)>>>,
+    /// With `stack_overlap`, the zones whose decks are fanned out because the pointer went over
+    /// them, the area each one covers once it's fanned out, and the ones we're waiting for the
+    /// pointer to leave.
+    fanned: Rc<RefCell<HashSet<Zone>>>,
+    fanned_areas: Rc<RefCell<HashMap<Zone, Rect>>>,
+    fan_in_checks: Rc<RefCell<HashSet<Zone>>>,
+}
+
+/// A notification's window, along with the timer that will close it.
+struct NotificationWindow {
+    window: WeakRef<gtk::ApplicationWindow>,
+    /// `None` if it stays open until it's dismissed.
+    timer: Option<Rc<RefCell<CloseTimer>>>,
+    /// The part of the screen it's stacked in.
+    zone: Zone,
+    /// Which zone it goes in depends on this, so it's kept in case the config changes.
+    urgency: Urgency,
+    /// The notification itself, so that it can be brought back if it's dismissed by accident. Only
+    /// kept when `undo_close_timeout` is on.
+    notification: Option<Notification>,
+    /// Its body, if `body_lines` can cut it short.
+    body: Option<WeakRef<gtk::Label>>,
+    /// Its colors, for widgets that are added to it later.
+    provider: gtk::CssProvider,
+    /// Counts up as windows are shown, so that the newest can be put in front. A window that
+    /// replaces another keeps its number.
+    serial: u64,
+}
+
+/// Where the window a notification replaced was, so that the new one can take its place.
+struct Replaced {
+    zone: Zone,
+    y: i32,
+    height: i32,
+    /// Where what's inside the window went, inside its border and padding.
+    content: Option<gtk::Allocation>,
+    serial: u64,
+}
+
+impl NotificationWindow {
+    fn cancel_timer(&self) {
+        if let Some(timer) = &self.timer {
+            timer.borrow_mut().cancel();
+        }
+    }
+}
+
+/// Tells the server and the GUI what the user did with a notification's window, making sure it's
+/// resolved once: however fast they click, the app hears about one action and the window is closed
+/// once. Resident notifications stay up after an action, so theirs can be invoked again, just not
+/// twice within the double-click time.
+struct Resolver {
+    id: u32,
+    resident: bool,
+    signal_tx: mpsc::Sender<Signal>,
+    tx: glib::Sender<NinomiyaEvent>,
+    debounce: Duration,
+    last_action: Cell<Option<Instant>>,
+    dismissed: Cell<bool>,
+}
+
+impl Resolver {
+    fn new(
+        id: u32,
+        resident: bool,
+        signal_tx: mpsc::Sender<Signal>,
+        tx: glib::Sender<NinomiyaEvent>,
+    ) -> Rc<Self> {
+        Rc::new(Resolver {
+            id,
+            resident,
+            signal_tx,
+            tx,
+            debounce: Duration::from_millis(double_click_time().into()),
+            last_action: Cell::new(None),
+            dismissed: Cell::new(false),
+        })
+    }
+
+    fn invoke(&self, key: &str) {
+        let repeated = self.last_action.get().map_or(false, |last| {
+            !self.resident || last.elapsed() < self.debounce
+        });
+        if self.dismissed.get() || repeated {
+            debug!(
+                "Ignoring repeated action {} on notification {}",
+                key, self.id
+            );
+            return;
+        }
+        self.last_action.set(Some(Instant::now()));
+        let res = self.signal_tx.send(Signal::ActionInvoked {
+            id: self.id,
+            key: key.to_owned(),
+        });
+        if let Err(err) = res {
+            error!("Failed sending signal to GUI thread: {:?}", err);
+        }
+    }
+
+    fn dismiss(&self) {
+        if self.dismissed.replace(true) {
+            return;
+        }
+        let res = self.tx.send(NinomiyaEvent::CloseNotification(
+            self.id,
+            CloseReason::Dismissed,
+        ));
+        if let Err(err) = res {
+            error!(
+                "Failed to send close notification for {}: {:?}",
+                self.id, err
+            );
+        }
+    }
 }
 
 /// This is the 'default' action key; if present, clicking an action will fire it.
 const DEFAULT_KEY: &str = "default";
 
+/// How long to wait for the user to press a number after `SelectAction`, in milliseconds.
+const ACTION_SELECTION_TIMEOUT_MS: u32 = 5000;
+/// Added to a notification's window while we're waiting for the user to pick an action.
+const SELECTING_ACTION_CLASS: &str = "selecting-action";
+/// Added to windows drawn by `draw_opaque`, for themes that want to look different without a
+/// compositor.
+const OPAQUE_CLASS: &str = "opaque";
+
+/// Once a window has been swiped this many pixels, releasing it won't count as a click.
+const SWIPE_START_DISTANCE: f64 = 10.0;
+/// Swiping a window further than this fraction of its width dismisses it.
+const SWIPE_DISMISS_FRACTION: f64 = 0.4;
+/// Converts touchpad scroll deltas to pixels.
+const SCROLL_SWIPE_SCALE: f64 = 10.0;
+
+/// After this many images fail to load in a row, we tell the user that something's wrong.
+const IMAGE_FAILURE_THRESHOLD: u32 = 5;
+/// How long to trust where we last saw the dock windows, with `avoid_docks`.
+const DOCK_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+/// With `stack_overlap`, how long after the pointer leaves a fanned-out deck it stacks back up.
+const FAN_IN_DELAY: Duration = Duration::from_millis(300);
+
+/// The signals we handle. These are the same everywhere GTK runs.
+const SIGHUP: i32 = 1;
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+
 impl Gui {
     pub fn new(
         config: Config,
+        clock: Rc<dyn Clock>,
         tx: glib::Sender<NinomiyaEvent>,
         signal_tx: mpsc::Sender<Signal>,
     ) -> Rc<Self> {
@@ -39,15 +248,56 @@ impl Gui {
             gio::ApplicationFlags::NON_UNIQUE,
         )
         .expect("failed to construct application");
-        let loader = image::Loader::new();
+        let loader = match &config.icon_theme {
+            Some(theme_name) => image::Loader::new_with_theme_name(theme_name),
+            None => image::Loader::new(),
+        };
+        let placeholder_icon = match config.full_placeholder_icon_path() {
+            Ok(path) => path.and_then(|path| Url::from_file_path(path).ok().map(ImageRef::Url)),
+            Err(err) => {
+                warn!("Couldn't figure out the placeholder icon path: {:?}", err);
+                None
+            }
+        };
+        let mpris = match mpris::Mpris::new() {
+            Ok(mpris) => Some(Rc::new(mpris)),
+            Err(err) => {
+                warn!("Media controls won't be available: {:?}", err);
+                None
+            }
+        };
+        let low_power = Arc::new(LowPower::new(config.low_power == LowPowerMode::On));
+        let anchors = Rc::new(RefCell::new(config.anchors.clone()));
         debug!("Application constructed.");
         Rc::new(Gui {
             app,
             loader,
-            config,
+            config: RefCell::new(Rc::new(config)),
+            clock,
             tx,
             signal_tx,
             windows: Mutex::new(HashMap::new()),
+            image_failures: Cell::new(0),
+            placeholder_icon,
+            mpris,
+            shortcut_buttons: RefCell::new(HashMap::new()),
+            next_shortcut_serial: Cell::new(0),
+            next_window_serial: Cell::new(0),
+            theme_provider: RefCell::new(None),
+            theme_override: RefCell::new(None),
+            config_provider: RefCell::new(None),
+            portal_prefers_dark: Cell::new(false),
+            latencies: Arc::new(Latencies::new()),
+            heartbeat: Arc::new(Heartbeat::new()),
+            low_power,
+            saved_animations: Cell::new(None),
+            docks: DockWatcher::new(),
+            dismissed: RefCell::new(None),
+            restore_tx: RefCell::new(None),
+            anchors,
+            fanned: Rc::new(RefCell::new(HashSet::new())),
+            fanned_areas: Rc::new(RefCell::new(HashMap::new())),
+            fan_in_checks: Rc::new(RefCell::new(HashSet::new())),
         })
     }
 
@@ -59,14 +309,46 @@ impl Gui {
             move |event| {
                 debug!("Got event {:?}", event);
                 match event {
-                    NinomiyaEvent::Notification(notification) =>
-                        this.notification_window(notification),
-                    NinomiyaEvent::CloseNotification(id) =>
-                        this.close_notification(id)
+                    NinomiyaEvent::Notification(notification) => {
+                        this.latencies.dispatched(&notification.lifecycle);
+                        this.notification_window(notification)
+                    }
+                    NinomiyaEvent::CloseNotification(id, reason) =>
+                        this.close_notification(id, reason),
+                    NinomiyaEvent::SelectAction => this.select_action(),
+                    NinomiyaEvent::UndoClose => this.undo_close(),
+                    NinomiyaEvent::SetTheme(path, reply) => {
+                        let result = this.set_theme(path);
+                        if let Err(err) = &result {
+                            error!("Failed to switch themes: {:?}", err);
+                        }
+                        let _ = reply.send(result.map_err(|err| format!("{:#}", err)));
+                    }
+                    NinomiyaEvent::Relayout => this.relayout(),
+                    NinomiyaEvent::ExpandBody(id, expanded) => this.expand_body(id, expanded),
                 }
                 glib::Continue(true)
             }),
         );
+        let heartbeat = Arc::clone(&self.heartbeat);
+        glib::timeout_add_local(watchdog::HEARTBEAT_INTERVAL.as_millis() as u32, move || {
+            heartbeat.beat();
+            glib::Continue(true)
+        });
+        // Quit cleanly, so that main can save what's on screen.
+        for &signal in &[SIGINT, SIGTERM] {
+            let app = self.app.clone();
+            glib::unix_signal_add_local(signal, move || {
+                info!("Got signal {}; quitting", signal);
+                app.quit();
+                glib::Continue(true)
+            });
+        }
+        let this = self.clone();
+        glib::unix_signal_add_local(SIGHUP, move || {
+            this.reload();
+            glib::Continue(true)
+        });
         // Not actually necessary, but shuts up GTK.
         self.app.connect_activate(|_app| {
             debug!("Activated.");
@@ -75,53 +357,530 @@ impl Gui {
         self.app.run(argv)
     }
 
-    fn notification_window(&self, notification: Notification) {
-        let screen = gdk::Screen::get_default().expect("couldn't get screen");
-        let window = gtk::ApplicationWindowBuilder::new()
-            .accept_focus(false)
-            .application(&self.app)
-            .width_request(self.config.width)
-            // Automatically sets up override redirect, so the window manager won't touch our
-            // windows at all.
-            .type_(gtk::WindowType::Popup)
-            .type_hint(gdk::WindowTypeHint::Notification)
-            .build();
-        // Necessary to get transparent backgrounds working.
-        let visual = screen.get_rgba_visual();
-        window.set_visual(visual.as_ref());
+    /// The latencies of the notifications we've shown, for the server to report.
+    pub fn latencies(&self) -> Arc<Latencies> {
+        Arc::clone(&self.latencies)
+    }
+
+    /// The heartbeat of our main loop, for the server's watchdog.
+    pub fn heartbeat(&self) -> Arc<Heartbeat> {
+        Arc::clone(&self.heartbeat)
+    }
+
+    /// Whether we're in low-power mode, for the server and the battery watcher to share.
+    pub fn low_power(&self) -> Arc<LowPower> {
+        Arc::clone(&self.low_power)
+    }
+
+    /// Turns GTK's animations (like CSS transitions in the theme) off while low-power mode is on,
+    /// and back to how they were once it's off.
+    fn apply_low_power(&self, on: bool) {
+        let settings = match gtk::Settings::get_default() {
+            Some(settings) => settings,
+            None => return,
+        };
+        if on && self.saved_animations.get().is_none() {
+            self.saved_animations
+                .set(Some(settings.get_property_gtk_enable_animations()));
+            settings.set_property_gtk_enable_animations(false);
+        } else if !on {
+            if let Some(enabled) = self.saved_animations.take() {
+                settings.set_property_gtk_enable_animations(enabled);
+            }
+        }
+    }
+
+    /// The current config. It's only ever swapped out as a whole, so hang on to this rather than
+    /// calling it over and over if you need it to stay consistent.
+    fn config(&self) -> Rc<Config> {
+        self.config.borrow().clone()
+    }
+
+    /// Loads the CSS generated from the appearance settings in the config, replacing the one loaded
+    /// before.
+    pub fn add_config_css(&self) -> Result<()> {
+        let config = self.config();
+        let composited = gdk::Screen::get_default().map_or(false, |screen| screen.is_composited());
+        if !composited && (config.opacity < 1.0 || config.background_alpha.is_some()) {
+            warn!("No compositor is running, so notifications will be opaque");
+        }
+        let provider = add_css_data(&css::substitute_variables(
+            &css::generate(&config, composited),
+            &config.css_variables,
+        ))?;
+        if let Some(old) = self.config_provider.replace(Some(provider)) {
+            if let Some(screen) = gdk::Screen::get_default() {
+                gtk::StyleContext::remove_provider_for_screen(&screen, &old);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reloads the config and the theme, and applies them to the windows on screen as well as to
+    /// new ones. Only the appearance is reloaded; the server (and so everything about which
+    /// notifications are shown, and how they're handled) keeps the config it started with.
+    pub fn reload(&self) {
+        info!("Reloading the config and theme");
+        match Config::load() {
+            Ok(config) => {
+                self.anchors.replace(config.anchors.clone());
+                self.config.replace(Rc::new(config));
+            }
+            Err(err) => error!(
+                "Failed to reload the config, so keeping the old one: {:?}",
+                err
+            ),
+        }
+        if let Err(err) = self.add_config_css() {
+            error!("Failed to reload the config's CSS: {:?}", err);
+        }
+        if let Err(err) = self.load_theme() {
+            error!("Failed to reload the theme: {:?}", err);
+        }
+        self.relayout();
+    }
+
+    /// Resizes and restacks the windows on screen to match the config, and whatever height the
+    /// theme gives them now. Each zone keeps its windows in the order they're stacked in.
+    fn relayout(&self) {
+        let screen = match gdk::Screen::get_default() {
+            Some(screen) => screen,
+            None => return,
+        };
+        let config = self.config();
+        let banner_width = self.window_width(&screen);
+        let mut stacks: HashMap<Zone, Vec<(u64, i32, gtk::ApplicationWindow)>> = HashMap::new();
+        for entry in self.windows.lock().unwrap().values_mut() {
+            let window = match entry.window.upgrade() {
+                Some(window) => window,
+                None => continue,
+            };
+            if entry.zone != Zone::Center {
+                entry.zone = config.zones.for_urgency(entry.urgency);
+            }
+            let y = window.get_position().1;
+            stacks
+                .entry(entry.zone)
+                .or_default()
+                .push((entry.serial, y, window));
+        }
+        for (zone, mut stack) in stacks {
+            let width = match zone {
+                Zone::Center => banner_width * 3 / 2,
+                _ => banner_width,
+            };
+            let deck = config
+                .stack_overlap
+                .filter(|_| !config.managed_windows && zone != Zone::Center);
+            if let Some(overlap) = deck {
+                if !self.fanned.borrow().contains(&zone) {
+                    self.layout_deck(&screen, zone, width, overlap, stack);
+                    continue;
+                }
+                // Fanned out, the front of the deck stays nearest the edge.
+                stack.sort_by_key(|&(serial, _, _)| std::cmp::Reverse(serial));
+            } else {
+                // Nearest the edge the zone stacks from first.
+                stack.sort_by_key(|&(_, y, _)| if zone.stacks_down() { y } else { -y });
+            }
+            let mut edge = None;
+            let mut area: Option<Rect> = None;
+            for (_, _, window) in stack {
+                window.set_size_request(width, -1);
+                window.resize(width, config.image_height);
+                window.set_opacity(config.opacity);
+                let height = window.get_preferred_height_for_width(width).1;
+                let (x, first_y) = self.origin(&screen, zone, width, height);
+                let y = match edge {
+                    None => first_y,
+                    Some(edge) if zone.stacks_down() => edge + config.notification_spacing,
+                    Some(edge) => edge - config.notification_spacing - height,
+                };
+                edge = Some(if zone.stacks_down() { y + height } else { y });
+                if !config.managed_windows {
+                    window.move_(x, y);
+                }
+                let rect = Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                };
+                area = Some(area.map_or(rect, |area| area.union(&rect)));
+            }
+            if let (Some(_), Some(area)) = (deck, area) {
+                self.fanned_areas.borrow_mut().insert(zone, area);
+            }
+        }
+    }
+
+    /// Stacks the windows in `stack` like a deck of cards: the newest one where the first window in
+    /// `zone` would go, and each older one behind it, sticking out `overlap` pixels further than
+    /// the one in front.
+    fn layout_deck(
+        &self,
+        screen: &gdk::Screen,
+        zone: Zone,
+        width: i32,
+        overlap: i32,
+        mut stack: Vec<(u64, i32, gtk::ApplicationWindow)>,
+    ) {
+        let config = self.config();
+        stack.sort_by_key(|&(serial, _, _)| std::cmp::Reverse(serial));
+        let mut front: Option<(i32, i32)> = None;
+        for (depth, (_, _, window)) in stack.iter().enumerate() {
+            window.set_size_request(width, -1);
+            window.resize(width, config.image_height);
+            window.set_opacity(config.opacity);
+            let height = window.get_preferred_height_for_width(width).1;
+            let (x, first_y) = self.origin(screen, zone, width, height);
+            let offset = depth as i32 * overlap;
+            // Lined up with the far edge of the front window, so that taller windows behind it
+            // still only peek out by the overlap, and never out past its near edge.
+            let y = match front {
+                None => first_y,
+                Some((top, bottom)) if zone.stacks_down() => (bottom + offset - height).max(top),
+                Some((top, bottom)) => (top - offset).min(bottom - height),
+            };
+            if front.is_none() {
+                front = Some((y, y + height));
+            }
+            window.move_(x, y);
+        }
+        // Raising them oldest first leaves the newest on top.
+        for (_, _, window) in stack.iter().rev() {
+            if let Some(gdk_window) = window.get_window() {
+                gdk::WindowExt::raise(&gdk_window);
+            }
+        }
+    }
+
+    /// Fans out `zone`'s deck once the pointer is over `window`, and stacks it back up once the
+    /// pointer has left the area the fanned-out deck covers. Fanning out moves windows out from
+    /// under the pointer, so leaving one window doesn't mean the pointer has left the deck.
+    fn add_hover_to_fan_out(&self, window: &gtk::ApplicationWindow, zone: Zone) {
+        let fanned = Rc::clone(&self.fanned);
+        let areas = Rc::clone(&self.fanned_areas);
+        let checks = Rc::clone(&self.fan_in_checks);
+        let tx = self.tx.clone();
+        window.add_events(gdk::EventMask::ENTER_NOTIFY_MASK | gdk::EventMask::LEAVE_NOTIFY_MASK);
+        window.connect_enter_notify_event(clone!(@strong fanned, @strong tx => move |_, _| {
+            if fanned.borrow_mut().insert(zone) {
+                let _ = tx.send(NinomiyaEvent::Relayout);
+            }
+            gtk::Inhibit(false)
+        }));
+        window.connect_leave_notify_event(move |_, event| {
+            // Moving onto one of the window's children doesn't count as leaving.
+            if event.get_detail() == gdk::NotifyType::Inferior {
+                return gtk::Inhibit(false);
+            }
+            // The pointer might be in a gap between windows, where there are no more crossing
+            // events, so we keep checking until it's gone.
+            if !checks.borrow_mut().insert(zone) {
+                return gtk::Inhibit(false);
+            }
+            glib::timeout_add_local(
+                FAN_IN_DELAY.as_millis() as u32,
+                clone!(@strong fanned, @strong areas, @strong checks, @strong tx => move || {
+                    let over_deck = match (areas.borrow().get(&zone), pointer_position()) {
+                        (Some(area), Some((x, y))) => area.contains(x, y),
+                        _ => false,
+                    };
+                    if over_deck && fanned.borrow().contains(&zone) {
+                        return glib::Continue(true);
+                    }
+                    checks.borrow_mut().remove(&zone);
+                    areas.borrow_mut().remove(&zone);
+                    if fanned.borrow_mut().remove(&zone) {
+                        let _ = tx.send(NinomiyaEvent::Relayout);
+                    }
+                    glib::Continue(false)
+                }),
+            );
+            gtk::Inhibit(false)
+        });
+    }
+
+    /// Loads a stylesheet, filling in the CSS variables from the config.
+    pub fn add_css_file<P: AsRef<Path>>(&self, path: P) -> Result<gtk::CssProvider> {
+        let path = path.as_ref();
+        // we don't use ? here because if the path doesn't exist canonicalize() returns an Err
+        info!("Attempting to load CSS from {:?}", path.canonicalize());
+        let css = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read CSS from {:?}", path))?;
+        let css = css::substitute_variables(&css, &self.config().css_variables);
+        // Relative URLs are relative to the file, wherever we were started from.
+        let dir = std::env::current_dir()?.join(path.parent().unwrap_or_else(|| Path::new("")));
+        add_css_data(&css::rebase_urls(&css, &dir))
+    }
+
+    /// Loads the user's theme, replacing the one loaded before. Uses the one picked with
+    /// `set_theme` if there is one, and otherwise the dark theme if there is one and the desktop is
+    /// in dark mode.
+    pub fn load_theme(&self) -> Result<()> {
+        if let Some(path) = self.theme_override.borrow().clone() {
+            return self.load_theme_file(path);
+        }
+        let dark_path = self
+            .config()
+            .full_theme_path_dark()?
+            .filter(|_| self.prefers_dark());
+        let theme_path = match dark_path {
+            Some(path) => path,
+            None => self.config().full_theme_path()?,
+        };
+        self.load_theme_file(theme_path)
+    }
+
+    fn load_theme_file(&self, theme_path: PathBuf) -> Result<()> {
+        let screen = gdk::Screen::get_default().context("couldn't get the screen")?;
+        // The old one stays if the new one can't be loaded.
+        let provider = if theme_path.exists() {
+            if let Ok(warnings) = theme_lint::lint_file(&theme_path) {
+                for warning in warnings {
+                    warn!("In theme {:?}, {}", theme_path, warning);
+                }
+            }
+            Some(self.add_css_file(theme_path)?)
+        } else {
+            warn!("Theme path {:?} doesn't exist, not loading it", theme_path);
+            None
+        };
+        if let Some(old) = self.theme_provider.replace(provider) {
+            gtk::StyleContext::remove_provider_for_screen(&screen, &old);
+        }
+        Ok(())
+    }
+
+    /// Switches to the theme at `path` right away, for the windows on screen too. It sticks through
+    /// reloads and dark mode changes, but not restarts. If it can't be loaded, nothing changes.
+    pub fn set_theme(&self, path: PathBuf) -> Result<()> {
+        if !path.is_file() {
+            bail!("there's no theme at {:?}", path);
+        }
+        self.load_theme_file(path.clone())?;
+        self.theme_override.replace(Some(path));
+        self.relayout();
+        Ok(())
+    }
+
+    /// Whether the desktop is in dark mode, according to either GTK or the settings portal.
+    fn prefers_dark(&self) -> bool {
+        self.portal_prefers_dark.get()
+            || gtk::Settings::get_default().map_or(false, |settings| {
+                settings.get_property_gtk_application_prefer_dark_theme()
+            })
+    }
+
+    /// Reloads the theme whenever dark mode is toggled, if there's a separate dark theme.
+    pub fn watch_color_scheme(self: &Rc<Self>) {
+        if self.config().theme_path_dark.is_none() {
+            return;
+        }
+        let this = self.clone();
+        let reload = move || {
+            if let Err(err) = this.load_theme() {
+                error!("Failed to reload the theme: {:?}", err);
+            }
+            // The new theme might make them taller or shorter.
+            this.relayout();
+        };
+        if let Some(settings) = gtk::Settings::get_default() {
+            settings.connect_property_gtk_application_prefer_dark_theme_notify(
+                clone!(@strong reload => move |_| reload()),
+            );
+        }
+        // The portal is blocking DBus, so it gets its own thread.
+        let (tx, rx) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        std::thread::spawn(move || {
+            let result = appearance::watch_color_scheme(move |prefers_dark| {
+                if tx.send(prefers_dark).is_err() {
+                    error!("Failed to send the color scheme to the GUI thread");
+                }
+            });
+            if let Err(err) = result {
+                info!("Not following the portal's color scheme: {:?}", err);
+            }
+        });
+        let this = self.clone();
+        rx.attach(None, move |prefers_dark| {
+            this.portal_prefers_dark.set(prefers_dark);
+            reload();
+            glib::Continue(true)
+        });
+    }
 
-        window.move_(
-            screen.get_width() - self.config.width - self.config.padding_x,
-            self.next_y(),
+    /// Registers the application, which `run` normally takes care of. Windows can't be shown
+    /// before this, so it's only needed to show notifications without running the main loop, like
+    /// in tests and benchmarks.
+    pub fn register(&self) -> Result<()> {
+        self.app
+            .register(None::<&gio::Cancellable>)
+            .context("failed to register the application")
+    }
+
+    /// Shows a window for the notification. If there's already one with the same ID, it's replaced.
+    pub fn notification_window(&self, notification: Notification) {
+        let _span = debug_span!("notification_window", id = notification.id).entered();
+        let lifecycle = notification.lifecycle.clone();
+        let urgency = notification.hints.urgency;
+        let low_power = self.low_power.is_on();
+        self.apply_low_power(low_power);
+        let screen = gdk::Screen::get_default().expect("couldn't get screen");
+        let config = self.config();
+        let template = config.template_for(notification.hints.category.as_deref());
+        let style = config.style_for(
+            notification.hints.urgency,
+            notification.hints.category.as_deref(),
+        );
+        let duration = template.and_then(|t| t.duration).unwrap_or(config.duration);
+        let sound = config.sound_for(
+            notification.application_name.as_deref(),
+            &notification.hints,
         );
+        let mut kept = if config.undo_close_timeout > Duration::default() {
+            Some(notification.clone())
+        } else {
+            None
+        };
+        let shown_for = notification.lifecycle.shown_for();
+        if style == Style::Banner && shown_for > Duration::default() && shown_for >= duration {
+            // It was restored after a restart, and would have closed in the meantime.
+            info!(
+                "Not restoring notification {}, which has expired",
+                notification.id
+            );
+            let id = notification.id;
+            let reason = CloseReason::Expired;
+            if let Err(err) = self
+                .signal_tx
+                .send(Signal::NotificationClosed { id, reason })
+            {
+                error!("Failed sending signal to GUI thread: {:?}", err);
+            }
+            return;
+        }
+        let (width, zone) = match style {
+            Style::Banner => (
+                self.window_width(&screen),
+                config.zones.for_urgency(notification.hints.urgency),
+            ),
+            Style::Center => (self.window_width(&screen) * 3 / 2, Zone::Center),
+        };
+        // A notification that replaces one on screen takes its place, unless its urgency moves it
+        // somewhere else.
+        let replaced = self
+            .remove_replaced_window(notification.replaces.unwrap_or(notification.id))
+            .filter(|replaced| replaced.zone == zone);
+        let replaced_y = replaced.as_ref().map(|replaced| replaced.y);
+        let window = if config.managed_windows {
+            // An ordinary window, which the window manager places and tiles like any other. The
+            // hints keep it out of taskbars and pagers, and let rules pick it out by its role.
+            gtk::ApplicationWindowBuilder::new()
+                .accept_focus(false)
+                .application(&self.app)
+                .width_request(width)
+                .type_(gtk::WindowType::Toplevel)
+                .type_hint(gdk::WindowTypeHint::Utility)
+                .decorated(false)
+                .skip_taskbar_hint(true)
+                .skip_pager_hint(true)
+                .title(&notification.summary)
+                .role("notification")
+                .build()
+        } else {
+            gtk::ApplicationWindowBuilder::new()
+                .accept_focus(false)
+                .application(&self.app)
+                .width_request(width)
+                // Automatically sets up override redirect, so the window manager won't touch our
+                // windows at all.
+                .type_(gtk::WindowType::Popup)
+                .type_hint(gdk::WindowTypeHint::Notification)
+                .build()
+        };
+        // Necessary to get transparent backgrounds working. Without a compositor, nothing would
+        // blend the window with what's behind it, so we stick with the default visual and draw the
+        // window ourselves.
+        if screen.is_composited() {
+            let visual = screen.get_rgba_visual();
+            window.set_visual(visual.as_ref());
+        } else {
+            window.get_style_context().add_class(OPAQUE_CLASS);
+            window.connect_draw(draw_opaque);
+        }
+        window.set_opacity(config.opacity);
+        // Window managers are supposed to leave popups alone, but not all of them do, so we spell
+        // out what we want.
+        window.set_keep_above(config.keep_above);
+        if config.sticky {
+            window.stick();
+        }
+        if config.blur {
+            window.connect_realize(|window| {
+                if let Some(gdk_window) = window.get_window() {
+                    request_blur(&gdk_window);
+                }
+            });
+        }
+
+        let compact = template.and_then(|t| t.layout) == Some(Layout::Compact);
+        if let Some(class) = template.and_then(|t| t.class.as_deref()) {
+            window.get_style_context().add_class(class);
+        }
+        if style == Style::Center {
+            window.get_style_context().add_class("center");
+        }
+        for class in css::notification_classes(
+            notification.application_name.as_deref(),
+            notification.hints.urgency,
+            notification.hints.category.as_deref(),
+            notification.hints.style_class.as_deref(),
+        ) {
+            window.get_style_context().add_class(&class);
+        }
+
+        // The notification's own colors. It's filled in once we know them, but widgets that are
+        // added later, like media controls, need it too.
+        let provider = gtk::CssProvider::new();
 
         // Contains the icon, text, and image.
         let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 0);
         hbox.set_widget_name("container");
 
-        notification
+        let image = notification
             .hints
             .image
+            .filter(|_| !compact)
             .and_then(|image_ref| {
                 let pixbuf = self.imageref_to_pixbuf(
                     image_ref,
-                    self.config.image_height,
-                    self.config.image_height,
+                    config.image_height,
+                    config.image_height,
+                    &foreground_color(&hbox),
                 );
                 if let Err(ref err) = pixbuf {
                     info!("Failed to load image: {}", err);
                 }
+                self.record_image_load(&pixbuf);
                 pixbuf.ok()
-            })
-            .map(|image| {
-                hbox.add(
-                    &gtk::ImageBuilder::new()
-                        .name("image")
-                        .valign(gtk::Align::Start)
-                        .pixbuf(&image)
-                        .build(),
-                )
             });
+        // The image the accent color comes from: the notification's own, or failing that its
+        // icon.
+        let mut accent_source = image.clone();
+        if let Some(image) = image {
+            let image = clip_pixbuf(image, config.image_shape, config.image_corner_radius);
+            let widget = gtk::ImageBuilder::new()
+                .name("image")
+                .valign(gtk::Align::Start)
+                .pixbuf(&image)
+                .build();
+            if let Some(class) = config.image_shape.css_class() {
+                widget.get_style_context().add_class(class);
+            }
+            hbox.add(&widget)
+        }
 
         // Important: all the labels *must* set wrap to true, so that we can actually set the
         // window's width properly.
@@ -130,30 +889,84 @@ impl Gui {
             .name("text")
             .hexpand(true)
             .build();
+        // Long text can get clipped, so the full text goes in tooltips. The body isn't shown at all
+        // in compact mode, so it goes in the summary's tooltip instead.
+        let summary_tooltip = match notification.body.as_ref().filter(|_| compact) {
+            Some(body) => format!(
+                "{}\n{}",
+                glib::markup_escape_text(&notification.summary),
+                body
+            ),
+            None => glib::markup_escape_text(&notification.summary).to_string(),
+        };
         notification_text_container.add(
             &gtk::LabelBuilder::new()
                 .label(&notification.summary)
+                .tooltip_markup(&summary_tooltip)
+                .selectable(true)
                 .name("summary")
                 .xalign(0.0)
                 .wrap(true)
                 .halign(gtk::Align::Start)
                 .build(),
         );
-        if let Some(body) = &notification.body {
+        let mut collapsible = None;
+        if let Some(body) = notification.body.as_ref().filter(|_| !compact) {
+            let label = gtk::LabelBuilder::new()
+                .label(body)
+                .use_markup(true)
+                .tooltip_markup(body)
+                .selectable(true)
+                .name("body")
+                .xalign(0.0)
+                .wrap(true)
+                .halign(gtk::Align::Start)
+                .build();
+            if let Some(lines) = config.body_lines {
+                label.set_lines(lines);
+                label.set_ellipsize(pango::EllipsizeMode::End);
+                collapsible = Some(label.downgrade());
+            }
+            notification_text_container.add(&label);
+        }
+        // Progress notifications send how far along they are in the `value` hint.
+        if let Some(value) = notification.hints.value {
             notification_text_container.add(
-                &gtk::LabelBuilder::new()
-                    .label(body)
-                    .use_markup(true)
-                    .name("body")
-                    .xalign(0.0)
-                    .wrap(true)
-                    .halign(gtk::Align::Start)
+                &gtk::ProgressBarBuilder::new()
+                    .name("progress")
+                    .fraction(f64::from(value.max(0).min(100)) / 100.0)
                     .build(),
             );
         }
 
-        self.action_buttons(notification.id, &notification.actions)
-            .map(|buttons| notification_text_container.add(&buttons));
+        // Decoding and scaling big images is the most expensive thing we do.
+        if !compact && !low_power {
+            if let Some(images) = self.body_images(notification.body_images, width) {
+                notification_text_container.add(&images);
+            }
+        }
+
+        let resolver = Resolver::new(
+            notification.id,
+            notification.hints.resident,
+            self.signal_tx.clone(),
+            self.tx.clone(),
+        );
+        self.action_buttons(
+            notification.id,
+            &notification.actions,
+            style == Style::Center,
+            &resolver,
+        )
+        .map(|buttons| notification_text_container.add(&buttons));
+
+        if let Some(controls) = self.media_controls(
+            notification.hints.desktop_entry.as_deref(),
+            notification.hints.category.as_deref(),
+            &provider,
+        ) {
+            notification_text_container.add(&controls);
+        }
 
         hbox.add(&notification_text_container);
 
@@ -167,140 +980,1023 @@ impl Gui {
                 &gtk::LabelBuilder::new()
                     .name("application-name")
                     .label(&app_name)
+                    .tooltip_text(&app_name)
                     .max_width_chars(15)
                     .build(),
             )
         };
 
-        notification
-            .icon
-            .and_then(|image_ref| {
-                let pixbuf = self.imageref_to_pixbuf(
-                    image_ref,
-                    self.config.icon_height,
-                    self.config.icon_height,
-                );
-                if let Err(ref err) = pixbuf {
-                    info!("Failed to load icon: {}", err);
-                }
-                pixbuf.ok()
-            })
-            .map(|pixbuf| {
-                icon_and_name.add(
-                    &gtk::ImageBuilder::new()
-                        .name("icon")
-                        .pixbuf(&pixbuf)
-                        .valign(gtk::Align::Start)
-                        .build(),
-                )
-            });
+        let icon = match template.and_then(|t| t.icon.as_deref()) {
+            Some(icon) => icon
+                .parse()
+                .map_err(|err| info!("Couldn't parse template icon {}: {:?}", icon, err))
+                .ok()
+                .or(notification.icon),
+            None => notification.icon,
+        };
+        if let Some(pixbuf) = self.load_icon(
+            icon,
+            notification.hints.desktop_entry.as_deref(),
+            notification.hints.category.as_deref(),
+            &foreground_color(&icon_and_name),
+        ) {
+            icon_and_name.add(
+                &gtk::ImageBuilder::new()
+                    .name("icon")
+                    .pixbuf(&pixbuf)
+                    .valign(gtk::Align::Start)
+                    .build(),
+            );
+            accent_source.get_or_insert(pixbuf);
+        }
 
         notification_text_container.add(&icon_and_name);
 
+        // Colors themes can use for this notification in particular, plus the accent border if
+        // `accent_from_image` asks for one. Finding the accent means going over every pixel, so
+        // it's only done when it's asked for.
+        let accent = accent_source
+            .as_ref()
+            .filter(|_| config.accent_from_image != AccentFromImage::Off && !low_power)
+            .and_then(accent::dominant_color);
+        let mut window_css = css::notification_colors(urgency, accent, &config.css_variables);
+        if let Some(css) = accent.and_then(|color| accent::css(config.accent_from_image, color)) {
+            window_css.push_str(&css);
+            window.get_style_context().add_class("accented");
+        }
+
         let id = notification.id;
         let has_default = notification
             .actions
             .iter()
             .any(|act| act.key == DEFAULT_KEY);
-        // On click, close the notification.
-        window.connect_button_press_event(
-            clone!(@strong self.tx as tx, @strong self.signal_tx as signal_tx => move |_, _| {
-                debug!("Clicked on notification {}", id);
-                if has_default {
-                        let res = signal_tx.send(Signal::ActionInvoked { id, key: DEFAULT_KEY.into() });
-                        if let Err(err) = res {
-                            error!("Failed sending signal to GUI thread: {:?}", err);
-                        }
-                }
-                if let Err(err) = tx.send(NinomiyaEvent::CloseNotification(id)) {
-                    error!("Failed to send close notification for {}: {:?}", id, err);
+        // Centered notifications have to be dismissed with their button, not by accident.
+        let must_dismiss = style == Style::Center;
+        let swiped = if must_dismiss {
+            Rc::new(Cell::new(false))
+        } else {
+            self.add_swipe_to_dismiss(&window, id, width, Rc::clone(&resolver))
+        };
+        if config.draggable && !config.managed_windows {
+            self.add_drag_to_move(&window, zone, Rc::clone(&swiped));
+        }
+        self.add_click_handler(&window, id, has_default, !must_dismiss, swiped, resolver);
+
+        if let Some((_, buttons)) = self.shortcut_buttons.borrow().get(&id) {
+            // We only get key presses while `select_action` has the keyboard grabbed.
+            let buttons = buttons.clone();
+            window.add_events(gdk::EventMask::KEY_PRESS_MASK);
+            window.connect_key_press_event(move |window, event| {
+                let number =
+                    gdk::keyval_to_unicode(event.get_keyval()).and_then(|c| c.to_digit(10));
+                if let Some(button) = number
+                    .filter(|&number| number >= 1)
+                    .and_then(|number| buttons.get(number as usize - 1))
+                    .and_then(|button| button.upgrade())
+                {
+                    button.clicked();
                 }
-                gtk::Inhibit(false)
-            }),
-        );
+                // Any other key cancels.
+                end_action_selection(window);
+                gtk::Inhibit(true)
+            });
+        }
 
+        // A replacement that's a different size grows or shrinks to it, so its content is clipped
+        // to the size in between.
+        let resize_from = replaced
+            .as_ref()
+            .and_then(|replaced| replaced.content)
+            .filter(|_| self.animates_resizes(low_power))
+            .map(|content| {
+                (
+                    content.height,
+                    hbox.get_preferred_height_for_width(content.width).1,
+                )
+            })
+            .filter(|(from, to)| from != to);
         window.add(&hbox);
+        if let Err(err) = provider.load_from_data(window_css.as_bytes()) {
+            warn!("Failed to load the notification's CSS: {:?}", err);
+        }
+        add_provider_to_all(window.upcast_ref(), &provider);
+        let clip =
+            resize_from.map(|(from, _)| wrap_in_clip(&window, hbox.upcast_ref(), from, &provider));
         // Necessary to actually properly enforce the size. Otherwise long summaries/bodies will
         // just run off the side of the screen.
-        window.resize(self.config.width, self.config.image_height);
+        window.resize(width, config.image_height);
+        // Bottom zones stack upwards, so we need to know how tall the window will be up front.
+        let height = window.get_preferred_height_for_width(width).1;
+        if !config.managed_windows {
+            let (x, first_y) = self.origin(&screen, zone, width, height);
+            window.move_(
+                x,
+                replaced_y.unwrap_or_else(|| self.next_y(zone, height, first_y)),
+            );
+        }
         window.show_all();
-
-        let mut windows = self.windows.lock().unwrap();
-        if windows.insert(id, window.downgrade()).is_some() {
-            error!("Got duplicate notifications for id {}", id);
+        if let (Some(clip), Some(replaced), Some((from, to))) = (clip, &replaced, resize_from) {
+            animate_height(
+                &window,
+                clip,
+                Resize {
+                    from,
+                    to,
+                    frame: replaced.height - from,
+                    y: replaced.y,
+                    stacks_down: zone.stacks_down(),
+                },
+                self.windows_behind(zone, replaced.y),
+                config.resize_animation,
+                self.tx.clone(),
+            );
         }
-        // Register a timeout to close this window in the future.
-        glib::timeout_add(
-            self.config.duration.as_millis() as u32,
-            clone!(@strong self.tx as tx => move || {
-                info!("Automatically closing window for notification {}", id);
-                if let Err(err) = tx.send(NinomiyaEvent::CloseNotification(id)) {
-                    error!("Failed to send close notification for {}: {:?}", id, err);
-                }
-                Continue(false)
-            }),
-        );
-    }
-
-    // Builds a box that contains the buttons for the given notification. Returns None if there
-    // shouldn't be a button bar, which can occur if there are no actions *or* if the only action
-    // is a default action with an empty label.
-    fn action_buttons(&self, id: u32, actions: &Vec<Action>) -> Option<gtk::Box> {
-        if actions.is_empty() {
-            return None;
+        self.latencies.shown(&lifecycle);
+        if let Some(sound) = sound.filter(|_| !low_power) {
+            sound::play(&sound);
         }
-        let buttons = gtk::BoxBuilder::new().name("buttons").build();
-        assert!(!actions.is_empty());
-        // Some programs (such as Telegram) send a default action with an empty label, assuming
-        // that clicking on the notification is how users will interact with it. So we avoid
-        // displaying empty buttons in that case.
-        for action in actions
-            .iter()
-            .filter(|act| !(act.key == DEFAULT_KEY && act.label.is_empty()))
-        {
-            let button = gtk::ButtonBuilder::new().label(&action.label).build();
-            button.connect_clicked(
-                clone!(@strong action.key as key, @strong self.signal_tx as signal_tx => move |_| {
-                    debug!("Clicked key {} on notification id {}", key, id);
-                    let res = signal_tx.send(Signal::ActionInvoked { id, key: key.clone() });
-                    if let Err(err) = res {
-                        error!("Failed sending signal to GUI thread: {:?}", err);
+
+        // Close this window in the future, unless it has to be dismissed.
+        let timer = match style {
+            Style::Banner => Some(CloseTimer::start(
+                self.clock.clone(),
+                duration - shown_for,
+                clone!(@strong self.tx as tx => move || {
+                    info!("Automatically closing window for notification {}", id);
+                    if let Err(err) = tx.send(NinomiyaEvent::CloseNotification(id, CloseReason::Expired)) {
+                        error!("Failed to send close notification for {}: {:?}", id, err);
                     }
                 }),
-            );
-            buttons.add(&button);
+            )),
+            Style::Center => None,
+        };
+        if let Some(timer) = timer.as_ref().filter(|_| config.pause_on_hover) {
+            window
+                .add_events(gdk::EventMask::ENTER_NOTIFY_MASK | gdk::EventMask::LEAVE_NOTIFY_MASK);
+            window.connect_enter_notify_event(clone!(@strong timer => move |_, _| {
+                timer.borrow_mut().pause();
+                gtk::Inhibit(false)
+            }));
+            window.connect_leave_notify_event(clone!(@strong timer => move |_, event| {
+                // Moving onto one of the window's children doesn't count as leaving.
+                if event.get_detail() != gdk::NotifyType::Inferior {
+                    CloseTimer::resume(&timer);
+                }
+                gtk::Inhibit(false)
+            }));
+        }
+        if collapsible.is_some() {
+            self.add_hover_to_expand(&window, id);
+        }
+        let deck =
+            config.stack_overlap.is_some() && !config.managed_windows && zone != Zone::Center;
+        if deck {
+            self.add_hover_to_fan_out(&window, zone);
         }
-        Some(buttons)
-    }
 
-    fn close_notification(&self, id: u32) {
+        if let Some(kept) = &mut kept {
+            kept.lifecycle.shown_at.get_or_insert_with(SystemTime::now);
+        }
+        let serial = replaced.as_ref().map_or_else(
+            || {
+                let serial = self.next_window_serial.get();
+                self.next_window_serial.set(serial + 1);
+                serial
+            },
+            |replaced| replaced.serial,
+        );
         let mut windows = self.windows.lock().unwrap();
-        if let Some(window) = windows.remove(&id).and_then(|weak| weak.upgrade()) {
-            window.close();
-        } else {
-            error!("Couldn't grab window for notification {}", id);
+        let entry = NotificationWindow {
+            window: window.downgrade(),
+            timer,
+            zone,
+            urgency,
+            notification: kept,
+            body: collapsible,
+            provider,
+            serial,
+        };
+        if let Some(old) = windows.insert(id, entry) {
+            error!("Got duplicate notifications for id {}", id);
+            old.cancel_timer();
+        }
+        drop(windows);
+        if deck {
+            // It goes on top of the deck, and the rest shift back.
+            self.relayout();
+        }
+        if let Err(err) = self.signal_tx.send(Signal::NotificationDisplayed { id }) {
+            error!("Failed sending signal to GUI thread: {:?}", err);
         }
     }
 
-    /// Returns the y-coordinate of the lowest window.
-    fn next_y(&self) -> i32 {
+    /// If there's a window for `id`, closes it without telling anyone and cancels its timeout, so
+    /// that a new version of the notification can be shown instead. Returns where the window was.
+    fn remove_replaced_window(&self, id: u32) -> Option<Replaced> {
+        let entry = self.windows.lock().unwrap().remove(&id)?;
+        entry.cancel_timer();
+        let window = entry.window.upgrade()?;
+        debug!("Replacing the window for notification {}", id);
+        self.shortcut_buttons.borrow_mut().remove(&id);
+        let replaced = Replaced {
+            zone: entry.zone,
+            y: window.get_position().1,
+            height: window.get_allocated_height(),
+            content: window.get_child().map(|child| child.get_allocation()),
+            serial: entry.serial,
+        };
+        window.close();
+        Some(replaced)
+    }
+
+    /// The windows in `zone` stacked after the one at `y`, which move along with its far edge when
+    /// it grows or shrinks.
+    fn windows_behind(&self, zone: Zone, y: i32) -> Vec<WeakRef<gtk::ApplicationWindow>> {
         self.windows
             .lock()
             .unwrap()
             .values()
-            .filter_map(|weak| weak.upgrade())
-            .map(|win| win.get_size().1 + win.get_position().1)
-            .max()
-            .map_or(self.config.padding_y, |bottom| {
-                bottom + self.config.notification_spacing
+            .filter(|entry| entry.zone == zone)
+            .filter_map(|entry| entry.window.upgrade())
+            .filter(|window| {
+                let other = window.get_position().1;
+                if zone.stacks_down() {
+                    other > y
+                } else {
+                    other < y
+                }
             })
+            .map(|window| window.downgrade())
+            .collect()
+    }
+
+    /// Shows the whole body while the pointer is over `window`, when `body_lines` cuts it short.
+    fn add_hover_to_expand(&self, window: &gtk::ApplicationWindow, id: u32) {
+        let tx = self.tx.clone();
+        window.add_events(gdk::EventMask::ENTER_NOTIFY_MASK | gdk::EventMask::LEAVE_NOTIFY_MASK);
+        window.connect_enter_notify_event(clone!(@strong tx => move |_, _| {
+            let _ = tx.send(NinomiyaEvent::ExpandBody(id, true));
+            gtk::Inhibit(false)
+        }));
+        window.connect_leave_notify_event(move |_, event| {
+            // Moving onto one of the window's children doesn't count as leaving.
+            if event.get_detail() != gdk::NotifyType::Inferior {
+                let _ = tx.send(NinomiyaEvent::ExpandBody(id, false));
+            }
+            gtk::Inhibit(false)
+        });
+    }
+
+    /// Shows the whole body of notification `id`, or cuts it back down to `body_lines`. Its window
+    /// grows or shrinks to match the way a replacement's does, and the rest of the stack moves
+    /// along.
+    fn expand_body(&self, id: u32, expanded: bool) {
+        let config = self.config();
+        let lines = match config.body_lines {
+            Some(_) if expanded => -1,
+            Some(lines) => lines,
+            None => return,
+        };
+        let (window, body, zone, provider) = {
+            let windows = self.windows.lock().unwrap();
+            let entry = match windows.get(&id) {
+                Some(entry) => entry,
+                None => return,
+            };
+            let body = entry.body.as_ref().and_then(|body| body.upgrade());
+            match (entry.window.upgrade(), body) {
+                (Some(window), Some(body)) => (window, body, entry.zone, entry.provider.clone()),
+                _ => return,
+            }
+        };
+        if body.get_lines() == lines {
+            return;
+        }
+        body.set_lines(lines);
+        // In the middle of an animation, the content's already in a clip; it settles into its new
+        // size once that's done.
+        let content = window
+            .get_child()
+            .filter(|child| !child.is::<gtk::ScrolledWindow>());
+        let content = match content {
+            Some(content) if self.animates_resizes(self.low_power.is_on()) => content,
+            _ => return self.relayout(),
+        };
+        let allocation = content.get_allocation();
+        let to = content.get_preferred_height_for_width(allocation.width).1;
+        if to == allocation.height {
+            return;
+        }
+        let y = window.get_position().1;
+        let clip = wrap_in_clip(&window, &content, allocation.height, &provider);
+        animate_height(
+            &window,
+            clip,
+            Resize {
+                from: allocation.height,
+                to,
+                frame: window.get_allocated_height() - allocation.height,
+                y,
+                stacks_down: zone.stacks_down(),
+            },
+            self.windows_behind(zone, y),
+            config.resize_animation,
+            self.tx.clone(),
+        );
+    }
+
+    /// Whether replacements should grow or shrink to their new size rather than snap to it. Decks
+    /// are restacked whole, and the window manager sizes managed windows itself.
+    fn animates_resizes(&self, low_power: bool) -> bool {
+        let config = self.config();
+        let animations = gtk::Settings::get_default().map_or(true, |settings| {
+            settings.get_property_gtk_enable_animations()
+        });
+        config.resize_animation > Duration::default()
+            && animations
+            && !low_power
+            && !config.managed_windows
+            && config.stack_overlap.is_none()
+    }
+
+    /// On click, closes the notification (unless `clicks_dismiss` is false), firing its default
+    /// action (if any) as `click_action` says. We wait for the release so that the press can start
+    /// a swipe instead.
+    fn add_click_handler(
+        &self,
+        window: &gtk::ApplicationWindow,
+        id: u32,
+        has_default: bool,
+        clicks_dismiss: bool,
+        swiped: Rc<Cell<bool>>,
+        resolver: Rc<Resolver>,
+    ) {
+        let click_action = self.config().click_action;
+        let invoke_default = clone!(@strong resolver => move || resolver.invoke(DEFAULT_KEY));
+        let dismiss = move || {
+            if clicks_dismiss {
+                resolver.dismiss();
+            }
+        };
+        // In double-click mode, a single click only dismisses once it's clear no second click is
+        // coming.
+        let pending_dismiss: Rc<RefCell<Option<glib::SourceId>>> = Rc::new(RefCell::new(None));
+        // Set between the second press of a double click and its release, which shouldn't count
+        // as a click of its own.
+        let double_clicked = Rc::new(Cell::new(false));
+        let double_click_time = double_click_time();
+
+        window.add_events(gdk::EventMask::BUTTON_PRESS_MASK | gdk::EventMask::BUTTON_RELEASE_MASK);
+        window.connect_button_press_event(
+            clone!(@strong invoke_default, @strong dismiss, @strong pending_dismiss,
+                   @strong double_clicked => move |window, event| {
+                if !on_background(window, event) {
+                    return gtk::Inhibit(false);
+                }
+                if click_action == ClickAction::Double
+                    && has_default
+                    && event.get_event_type() == gdk::EventType::DoubleButtonPress
+                {
+                    debug!("Double-clicked on notification {}", id);
+                    if let Some(source) = pending_dismiss.borrow_mut().take() {
+                        glib::source_remove(source);
+                    }
+                    double_clicked.set(true);
+                    invoke_default();
+                    dismiss();
+                }
+                gtk::Inhibit(false)
+            }),
+        );
+        window.connect_button_release_event(move |window, event| {
+            if swiped.get() || !on_background(window, event) {
+                return gtk::Inhibit(false);
+            }
+            if double_clicked.replace(false) {
+                return gtk::Inhibit(false);
+            }
+            debug!("Clicked on notification {}", id);
+            match click_action {
+                ClickAction::Single => {
+                    if has_default {
+                        invoke_default();
+                    }
+                    dismiss();
+                }
+                ClickAction::Double if has_default => {
+                    if pending_dismiss.borrow().is_none() {
+                        let source = glib::timeout_add_local(
+                            double_click_time,
+                            clone!(@strong dismiss, @strong pending_dismiss => move || {
+                                pending_dismiss.borrow_mut().take();
+                                dismiss();
+                                Continue(false)
+                            }),
+                        );
+                        *pending_dismiss.borrow_mut() = Some(source);
+                    }
+                }
+                ClickAction::Double | ClickAction::Dismiss => dismiss(),
+            }
+            gtk::Inhibit(false)
+        });
+    }
+
+    /// Lets the user dismiss the window by swiping it sideways, with a finger or mouse (by
+    /// dragging, unless `draggable` is on) or with a touchpad (by scrolling horizontally). The
+    /// window follows along, and snaps back if it isn't swiped far enough. The returned flag is set
+    /// once a swipe has started, so that it isn't also treated as a click.
+    fn add_swipe_to_dismiss(
+        &self,
+        window: &gtk::ApplicationWindow,
+        id: u32,
+        width: i32,
+        resolver: Rc<Resolver>,
+    ) -> Rc<Cell<bool>> {
+        let swiped = Rc::new(Cell::new(false));
+        // Where managed windows go is up to the window manager, so they can't follow the swipe.
+        if self.config().managed_windows {
+            return swiped;
+        }
+        // How far the window has been moved, and where it started.
+        let translation = Rc::new(Cell::new(0.0));
+        let origin = Rc::new(Cell::new((0, 0)));
+        let width = f64::from(width);
+        let opacity = self.config().opacity;
+
+        let move_by = clone!(@strong swiped, @strong translation, @strong origin => move |window: &gtk::ApplicationWindow, dx: f64| {
+            let (x, y) = origin.get();
+            let total = translation.get() + dx;
+            translation.set(total);
+            if total.abs() > SWIPE_START_DISTANCE {
+                swiped.set(true);
+            }
+            window.move_(x + total as i32, y);
+            window.set_opacity(opacity * (1.0 - total.abs() / width).max(0.2));
+        });
+        let begin = clone!(@strong swiped, @strong translation, @strong origin => move |window: &gtk::ApplicationWindow| {
+            swiped.set(false);
+            translation.set(0.0);
+            origin.set(window.get_position());
+        });
+        let finish = clone!(@strong translation, @strong origin => move |window: &gtk::ApplicationWindow| {
+            if translation.get().abs() > width * SWIPE_DISMISS_FRACTION {
+                debug!("Swiped away notification {}", id);
+                resolver.dismiss();
+            } else {
+                let (x, y) = origin.get();
+                window.move_(x, y);
+                window.set_opacity(opacity);
+            }
+            translation.set(0.0);
+        });
+
+        if !self.config().draggable {
+            let drag = gtk::GestureDrag::new(window);
+            drag.connect_drag_begin(
+                clone!(@weak window, @strong begin => move |_, _, _| begin(&window)),
+            );
+            drag.connect_drag_update(
+                clone!(@weak window, @strong translation, @strong move_by => move |_, offset_x, _| {
+                    // The offset is relative to the window, which we've been moving, so we have to
+                    // add back what we've moved it by.
+                    move_by(&window, offset_x - translation.get());
+                }),
+            );
+            drag.connect_drag_end(
+                clone!(@weak window, @strong finish => move |_, _, _| finish(&window)),
+            );
+            // Gestures stop working once they're dropped, so keep it around until the window goes
+            // away.
+            window.connect_destroy(move |_| {
+                let _ = &drag;
+            });
+        }
+
+        window.add_events(gdk::EventMask::SCROLL_MASK | gdk::EventMask::SMOOTH_SCROLL_MASK);
+        let scrolling = Cell::new(false);
+        window.connect_scroll_event(move |window, event| {
+            if event.get_direction() != gdk::ScrollDirection::Smooth {
+                return gtk::Inhibit(false);
+            }
+            if event.get_is_stop() {
+                scrolling.set(false);
+                finish(window);
+            } else {
+                if !scrolling.replace(true) {
+                    begin(window);
+                }
+                move_by(window, event.get_delta().0 * SCROLL_SWIPE_SCALE);
+            }
+            gtk::Inhibit(true)
+        });
+        swiped
+    }
+
+    /// Lets the user drag the window somewhere else, when `draggable` is on. Where it's dropped
+    /// becomes its zone's anchor, so the notifications after it stack from there. `dragged` is set
+    /// once it's moved, so that the drag isn't also treated as a click.
+    fn add_drag_to_move(
+        &self,
+        window: &gtk::ApplicationWindow,
+        zone: Zone,
+        dragged: Rc<Cell<bool>>,
+    ) {
+        let anchors = Rc::clone(&self.anchors);
+        let remember = self.config().remember_drag;
+        // Where the window started, and how far it's been moved.
+        let origin = Rc::new(Cell::new((0, 0)));
+        let translation = Rc::new(Cell::new((0.0, 0.0)));
+
+        let drag = gtk::GestureDrag::new(window);
+        drag.connect_drag_begin(
+            clone!(@weak window, @strong origin, @strong translation, @strong dragged => move |_, _, _| {
+                dragged.set(false);
+                translation.set((0.0, 0.0));
+                origin.set(window.get_position());
+            }),
+        );
+        drag.connect_drag_update(
+            clone!(@weak window, @strong origin, @strong translation, @strong dragged => move |_, offset_x, offset_y| {
+                // As with swiping, the offset is relative to the window, which we've been moving,
+                // so we have to add back what we've moved it by.
+                let (moved_x, moved_y) = translation.get();
+                let (dx, dy) = (offset_x - moved_x, offset_y - moved_y);
+                let total = (moved_x + dx, moved_y + dy);
+                translation.set(total);
+                if total.0.hypot(total.1) > SWIPE_START_DISTANCE {
+                    dragged.set(true);
+                }
+                let (x, y) = origin.get();
+                window.move_(x + total.0 as i32, y + total.1 as i32);
+            }),
+        );
+        drag.connect_drag_end(clone!(@weak window => move |_, _, _| {
+            if !dragged.get() {
+                let (x, y) = origin.get();
+                window.move_(x, y);
+                return;
+            }
+            let (x, y) = window.get_position();
+            let edge = if zone.stacks_down() { y } else { y + window.get_size().1 };
+            debug!("Anchoring the {} zone at ({}, {})", zone.name(), x, edge);
+            let mut anchors = anchors.borrow_mut();
+            anchors.insert(zone, (x, edge));
+            if remember {
+                if let Err(err) = Config::save_anchors(&anchors) {
+                    warn!("Failed to save where notifications were dragged: {:?}", err);
+                }
+            }
+        }));
+        // Gestures stop working once they're dropped, so keep it around until the window goes away.
+        window.connect_destroy(move |_| {
+            let _ = &drag;
+        });
+    }
+
+    /// Builds a box containing the images from the body's `<img>` tags. Returns None if there
+    /// aren't any (or none of them loaded).
+    fn body_images(&self, images: Vec<ImageRef>, width: i32) -> Option<gtk::Box> {
+        let container = gtk::BoxBuilder::new()
+            .orientation(gtk::Orientation::Vertical)
+            .name("body-images")
+            .build();
+        for image_ref in images {
+            let pixbuf = self.imageref_to_pixbuf(
+                image_ref,
+                width,
+                self.config().image_height,
+                &foreground_color(&container),
+            );
+            self.record_image_load(&pixbuf);
+            match pixbuf {
+                Ok(pixbuf) => container.add(
+                    &gtk::ImageBuilder::new()
+                        .name("body-image")
+                        .halign(gtk::Align::Start)
+                        .pixbuf(&pixbuf)
+                        .build(),
+                ),
+                Err(err) => info!("Failed to load body image: {}", err),
+            }
+        }
+        if container.get_children().is_empty() {
+            None
+        } else {
+            Some(container)
+        }
+    }
+
+    /// Builds a box for previous/play-pause/next buttons if the notification might have come from
+    /// a media player. Looking up the player is slow, so the buttons show up once it's found;
+    /// until then (or if there isn't one), the box stays empty.
+    fn media_controls(
+        &self,
+        desktop_entry: Option<&str>,
+        category: Option<&str>,
+        provider: &gtk::CssProvider,
+    ) -> Option<gtk::Box> {
+        let mpris = self.mpris.as_ref()?;
+        let is_media = category.map_or(false, mpris::is_media_category);
+        if desktop_entry.is_none() && !is_media {
+            return None;
+        }
+        let controls = gtk::BoxBuilder::new().name("media-controls").build();
+        let tx = self.tx.clone();
+        mpris.find_player(
+            desktop_entry,
+            is_media,
+            clone!(@weak controls, @strong mpris, @strong provider => move |player| {
+                for &command in &mpris::Command::ALL {
+                    let button = gtk::Button::new_from_icon_name(
+                        Some(command.icon_name()),
+                        gtk::IconSize::Button,
+                    );
+                    button.connect_clicked(clone!(@strong mpris, @strong player => move |_| {
+                        mpris.send(&player, command);
+                    }));
+                    controls.add(&button);
+                }
+                add_provider_to_all(controls.upcast_ref(), &provider);
+                controls.show_all();
+                // The window just got taller.
+                let _ = tx.send(NinomiyaEvent::Relayout);
+            }),
+        );
+        Some(controls)
+    }
+
+    // Builds a box that contains the buttons for the given notification. Returns None if there
+    // shouldn't be a button bar, which can occur if there are no actions *or* if the only action
+    // is a default action with an empty label. Unless the notification is resident, invoking an
+    // action also closes it. If `dismiss_button` is set, there's a Dismiss button at the end.
+    fn action_buttons(
+        &self,
+        id: u32,
+        actions: &Vec<Action>,
+        dismiss_button: bool,
+        resolver: &Rc<Resolver>,
+    ) -> Option<gtk::Box> {
+        if actions.is_empty() && !dismiss_button {
+            return None;
+        }
+        let buttons = gtk::BoxBuilder::new().name("buttons").build();
+        let mut shortcut_buttons = vec![];
+        // Some programs (such as Telegram) send a default action with an empty label, assuming
+        // that clicking on the notification is how users will interact with it. So we avoid
+        // displaying empty buttons in that case.
+        for (index, action) in actions
+            .iter()
+            .filter(|act| !(act.key == DEFAULT_KEY && act.label.is_empty()))
+            .enumerate()
+        {
+            let button = if self.config().action_shortcuts && index < 9 {
+                // Show the number to press as a badge next to the label.
+                let content = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+                content.add(
+                    &gtk::LabelBuilder::new()
+                        .label(&(index + 1).to_string())
+                        .name("action-number")
+                        .build(),
+                );
+                content.add(&gtk::Label::new(Some(&action.label)));
+                let button = gtk::Button::new();
+                button.add(&content);
+                shortcut_buttons.push(button.downgrade());
+                button
+            } else {
+                gtk::ButtonBuilder::new().label(&action.label).build()
+            };
+            button.connect_clicked(
+                clone!(@strong action.key as key, @strong resolver => move |_| {
+                    debug!("Clicked key {} on notification id {}", key, id);
+                    resolver.invoke(&key);
+                    if !resolver.resident {
+                        resolver.dismiss();
+                    }
+                }),
+            );
+            buttons.add(&button);
+        }
+        if dismiss_button {
+            let button = gtk::ButtonBuilder::new().label("Dismiss").build();
+            button.connect_clicked(clone!(@strong resolver => move |_| resolver.dismiss()));
+            buttons.add(&button);
+        }
+        if !shortcut_buttons.is_empty() {
+            let serial = self.next_shortcut_serial.get();
+            self.next_shortcut_serial.set(serial + 1);
+            self.shortcut_buttons
+                .borrow_mut()
+                .insert(id, (serial, shortcut_buttons));
+        }
+        Some(buttons)
+    }
+
+    /// Loads the notification's icon. If it doesn't have one, or it can't be loaded, we try the
+    /// icon of its desktop entry, then a generic icon for its category, then the placeholder.
+    fn load_icon(
+        &self,
+        icon: Option<ImageRef>,
+        desktop_entry: Option<&str>,
+        category: Option<&str>,
+        fg: &gdk::RGBA,
+    ) -> Option<Pixbuf> {
+        let size = self.config().icon_height;
+        if let Some(image_ref) = icon {
+            let pixbuf = self.imageref_to_pixbuf(image_ref, size, size, fg);
+            self.record_image_load(&pixbuf);
+            match pixbuf {
+                Ok(pixbuf) => return Some(pixbuf),
+                Err(err) => info!("Failed to load icon: {}", err),
+            }
+        }
+        let fallbacks = desktop_entry
+            .and_then(app_icon::desktop_entry_icon)
+            .into_iter()
+            .chain(
+                category
+                    .and_then(app_icon::category_icon)
+                    .map(|name| ImageRef::IconName(name.to_owned())),
+            )
+            .chain(self.placeholder_icon.clone());
+        for image_ref in fallbacks {
+            match self.imageref_to_pixbuf(image_ref.clone(), size, size, fg) {
+                Ok(pixbuf) => {
+                    debug!("Falling back to icon {:?}", image_ref);
+                    return Some(pixbuf);
+                }
+                Err(err) => debug!("Failed to load fallback icon {:?}: {}", image_ref, err),
+            }
+        }
+        None
+    }
+
+    /// Keeps track of how many image loads have failed in a row. If it's a lot, something's
+    /// probably wrong with the setup (a broken icon theme, say), so we tell the user.
+    fn record_image_load<T>(&self, result: &Result<T>) {
+        if result.is_ok() {
+            self.image_failures.set(0);
+            return;
+        }
+        let failures = self.image_failures.get() + 1;
+        if failures < IMAGE_FAILURE_THRESHOLD {
+            self.image_failures.set(failures);
+            return;
+        }
+        self.image_failures.set(0);
+        let notification = Notification::internal(
+            "Images are failing to load",
+            &format!(
+                "The last {} images and icons couldn't be loaded. Check the logs for details.",
+                failures
+            ),
+        );
+        if let Err(err) = self.tx.send(NinomiyaEvent::Notification(notification)) {
+            error!("Failed to send image failure notification: {:?}", err);
+        }
+    }
+
+    /// Grabs the keyboard for the newest notification with numbered actions, so that the next key
+    /// press picks one. Gives up after `ACTION_SELECTION_TIMEOUT_MS`. Newest means most recently
+    /// shown: our own notifications count down from the top of the ID space, so the biggest ID
+    /// isn't it.
+    fn select_action(&self) {
+        let newest = self
+            .shortcut_buttons
+            .borrow()
+            .iter()
+            .max_by_key(|(_, (serial, _))| *serial)
+            .map(|(&id, _)| id);
+        let id = match newest {
+            Some(id) => id,
+            None => {
+                info!("No notifications with actions to select from");
+                return;
+            }
+        };
+        let window = match self
+            .windows
+            .lock()
+            .unwrap()
+            .get(&id)
+            .and_then(|entry| entry.window.upgrade())
+        {
+            Some(window) => window,
+            None => return,
+        };
+        let seat = gdk::Display::get_default().and_then(|display| display.get_default_seat());
+        let (gdk_window, seat) = match (window.get_window(), seat) {
+            (Some(gdk_window), Some(seat)) => (gdk_window, seat),
+            _ => {
+                warn!("Can't grab the keyboard without a window and a seat");
+                return;
+            }
+        };
+        let status = seat.grab(
+            &gdk_window,
+            gdk::SeatCapabilities::KEYBOARD,
+            false,
+            None,
+            None,
+            None,
+        );
+        if status != gdk::GrabStatus::Success {
+            warn!("Failed to grab the keyboard: {:?}", status);
+            return;
+        }
+        debug!("Selecting an action on notification {}", id);
+        window.get_style_context().add_class(SELECTING_ACTION_CLASS);
+        glib::timeout_add_local(
+            ACTION_SELECTION_TIMEOUT_MS,
+            clone!(@weak window => @default-return Continue(false), move || {
+                end_action_selection(&window);
+                Continue(false)
+            }),
+        );
+    }
+
+    /// Closes the notification's window, telling the server why.
+    pub fn close_notification(&self, id: u32, reason: CloseReason) {
+        self.shortcut_buttons.borrow_mut().remove(&id);
+        let mut windows = self.windows.lock().unwrap();
+        let entry = windows.remove(&id);
+        if let Some(entry) = &entry {
+            // If it was closed some other way, the timer shouldn't fire later on.
+            entry.cancel_timer();
+        }
+        let entry = match entry {
+            Some(entry) => entry,
+            // Say, its timer ran out just as it was dismissed. It's only closed once.
+            None => {
+                debug!("Notification {} is already closed", id);
+                return;
+            }
+        };
+        if reason == CloseReason::Dismissed {
+            if let Some(mut notification) = entry.notification {
+                notification.lifecycle.resolve(reason);
+                self.dismissed
+                    .replace(Some((SystemTime::now(), notification)));
+            }
+        }
+        if let Some(window) = entry.window.upgrade() {
+            window.close();
+            if let Err(err) = self
+                .signal_tx
+                .send(Signal::NotificationClosed { id, reason })
+            {
+                error!("Failed sending signal to GUI thread: {:?}", err);
+            }
+        } else {
+            error!("Couldn't grab window for notification {}", id);
+        }
+        drop(windows);
+        let config = self.config();
+        if config.stack_overlap.is_some() && !config.managed_windows {
+            // The next one down comes to the front.
+            self.relayout();
+        }
+    }
+
+    /// Shows the notification the user last dismissed again, if that was within
+    /// `undo_close_timeout`. It gets whatever was left of its timeout when it was dismissed.
+    pub fn undo_close(&self) {
+        let (dismissed_at, mut notification) = match self.dismissed.borrow_mut().take() {
+            Some(dismissed) => dismissed,
+            None => {
+                info!("There's no dismissed notification to bring back");
+                return;
+            }
+        };
+        let since = SystemTime::now()
+            .duration_since(dismissed_at)
+            .unwrap_or_default();
+        if since > self.config().undo_close_timeout {
+            info!(
+                "Notification {} was dismissed too long ago to bring back",
+                notification.id
+            );
+            return;
+        }
+        if self.windows.lock().unwrap().contains_key(&notification.id) {
+            // The app sent it again in the meantime.
+            return;
+        }
+        // The time it spent dismissed doesn't count against its timeout.
+        notification.lifecycle.shown_at = notification
+            .lifecycle
+            .shown_at
+            .map(|shown_at| shown_at + since);
+        info!("Bringing back notification {}", notification.id);
+        match &*self.restore_tx.borrow() {
+            Some(restore_tx) => {
+                if let Err(err) = restore_tx.send(notification) {
+                    error!("Failed sending notification to the server: {:?}", err);
+                }
+            }
+            None => self.notification_window(notification),
+        }
+    }
+
+    /// Sends notifications brought back with `undo_close` to `restore_tx`, rather than showing
+    /// them right away.
+    pub fn restore_through(&self, restore_tx: mpsc::Sender<Notification>) {
+        self.restore_tx.replace(Some(restore_tx));
+    }
+
+    /// The width of notification windows, which depends on the monitor they're on: the one at the
+    /// top right of the screen.
+    fn window_width(&self, screen: &gdk::Screen) -> i32 {
+        let monitor = screen.get_monitor_at_point(screen.get_width() - 1, self.config().padding_y);
+        self.config()
+            .window_width(screen.get_monitor_geometry(monitor).width)
+    }
+
+    /// Where the window nearest the edge of `zone` goes, if it's `width` by `height`. With
+    /// `avoid_docks`, that's moved off any docks it would cover.
+    fn origin(&self, screen: &gdk::Screen, zone: Zone, width: i32, height: i32) -> (i32, i32) {
+        let config = self.config();
+        // Wherever the user put notifications is where they want them, docks or not.
+        let anchor = self.anchors.borrow().get(&zone).copied();
+        if let Some((x, edge)) = anchor {
+            return (
+                x,
+                if zone.stacks_down() {
+                    edge
+                } else {
+                    edge - height
+                },
+            );
+        }
+        let (screen_width, screen_height) = (screen.get_width(), screen.get_height());
+        let window = Rect {
+            x: zone.x(screen_width, width, config.padding_x),
+            y: zone.first_y(screen_height, height, config.padding_y),
+            width,
+            height,
+        };
+        // Centered notifications are meant to go over everything.
+        if !config.avoid_docks || zone == Zone::Center {
+            return (window.x, window.y);
+        }
+        let window = docks::avoid(
+            window,
+            &self.docks(),
+            screen_width,
+            screen_height,
+            config.padding_x,
+            config.padding_y,
+        );
+        (window.x, window.y)
+    }
+
+    /// Where the dock windows are. Panels don't move much, so this only looks again every
+    /// `DOCK_PROBE_INTERVAL`, and restacks the windows if they've moved since.
+    fn docks(&self) -> Vec<Rect> {
+        let tx = self.tx.clone();
+        self.docks.docks(DOCK_PROBE_INTERVAL, move || {
+            let _ = tx.send(NinomiyaEvent::Relayout);
+        })
     }
 
-    fn imageref_to_pixbuf(
+    /// Returns the y-coordinate for a new window `height` pixels tall in `zone`: below the lowest
+    /// window already there, or above the highest one for bottom zones. If it's the first one,
+    /// it goes at `first_y`.
+    fn next_y(&self, zone: Zone, height: i32, first_y: i32) -> i32 {
+        let windows = self.windows.lock().unwrap();
+        let extents = windows
+            .values()
+            .filter(|entry| entry.zone == zone)
+            .filter_map(|entry| entry.window.upgrade())
+            .map(|win| {
+                (
+                    win.get_position().1,
+                    win.get_position().1 + win.get_size().1,
+                )
+            });
+        let spacing = self.config().notification_spacing;
+        let next = if zone.stacks_down() {
+            extents
+                .map(|(_, bottom)| bottom)
+                .max()
+                .map(|bottom| bottom + spacing)
+        } else {
+            extents
+                .map(|(top, _)| top)
+                .min()
+                .map(|top| top - spacing - height)
+        };
+        next.unwrap_or(first_y)
+    }
+
+    /// Loads the image. Symbolic icons are drawn in `fg`.
+    pub fn imageref_to_pixbuf(
         &self,
         image_ref: ImageRef,
         max_width: i32,
         max_height: i32,
+        fg: &gdk::RGBA,
     ) -> Result<Pixbuf> {
         match image_ref {
             ImageRef::Url(url) => Ok(resize_pixbuf(
@@ -308,7 +2004,7 @@ impl Gui {
                 max_width,
                 max_height,
             )),
-            ImageRef::IconName(icon_name) => self.loader.load_from_icon(&icon_name, max_height),
+            ImageRef::IconName(icon_name) => self.loader.load_from_icon(&icon_name, max_height, fg),
             ImageRef::Image {
                 width,
                 height,
@@ -332,22 +2028,289 @@ impl Gui {
     }
 }
 
-pub fn add_css<P: AsRef<Path>>(path: P) -> Result<(), anyhow::Error> {
-    // we don't use ? here because if the path doesn't exist canonicalize() returns an Err
-    info!(
-        "Attempting to load CSS from {:?}",
-        &path.as_ref().canonicalize()
+/// Draws a window when there's no compositor. GTK would draw the translucent parts of a theme
+/// (rounded corners, shadows, see-through backgrounds) over black, so we paint an opaque background
+/// first and then a plain border in place of the theme's.
+fn draw_opaque(window: &gtk::ApplicationWindow, cr: &cairo::Context) -> gtk::Inhibit {
+    let style_context = window.get_style_context();
+    let width = f64::from(window.get_allocated_width());
+    let height = f64::from(window.get_allocated_height());
+    let background = style_context
+        .lookup_color("theme_bg_color")
+        .unwrap_or(gdk::RGBA {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+            alpha: 1.0,
+        });
+    cr.set_source_rgb(background.red, background.green, background.blue);
+    cr.paint();
+    gtk::render_background(&style_context, cr, 0.0, 0.0, width, height);
+    if let Some(child) = window.get_child() {
+        window.propagate_draw(&child, cr);
+    }
+    let border = style_context.lookup_color("borders").unwrap_or(gdk::RGBA {
+        red: 0.5,
+        green: 0.5,
+        blue: 0.5,
+        alpha: 1.0,
+    });
+    cr.set_source_rgb(border.red, border.green, border.blue);
+    cr.set_line_width(1.0);
+    // Offset by half a pixel so that the line covers whole pixels.
+    cr.rectangle(0.5, 0.5, width - 1.0, height - 1.0);
+    cr.stroke();
+    gtk::Inhibit(true)
+}
+
+/// Asks the compositor to blur what's behind the window, by setting the property that KWin and
+/// picom look for. An empty region means the whole window. Does nothing on compositors that don't
+/// know about it.
+fn request_blur(window: &gdk::Window) {
+    gdk::property_change(
+        window,
+        &gdk::Atom::intern("_KDE_NET_WM_BLUR_BEHIND_REGION"),
+        &gdk::Atom::intern("CARDINAL"),
+        32,
+        gdk::PropMode::Replace,
+        gdk::ChangeData::ULongs(&[]),
     );
+}
+
+/// How far apart two clicks can be and still count as a double click, in milliseconds.
+fn double_click_time() -> u32 {
+    gtk::Settings::get_default().map_or(400, |settings| {
+        settings.get_property_gtk_double_click_time()
+    }) as u32
+}
+
+/// Where the pointer is on the screen, in the same coordinates we move windows with.
+fn pointer_position() -> Option<(i32, i32)> {
+    let seat = gdk::Display::get_default()?.get_default_seat()?;
+    let (_, x, y) = seat.get_pointer()?.get_position();
+    Some((x, y))
+}
+
+/// Releases the keyboard grab taken by `Gui::select_action`, if `window` still has it.
+fn end_action_selection(window: &gtk::ApplicationWindow) {
+    let style_context = window.get_style_context();
+    if !style_context.has_class(SELECTING_ACTION_CLASS) {
+        return;
+    }
+    style_context.remove_class(SELECTING_ACTION_CLASS);
+    if let Some(seat) = gdk::Display::get_default().and_then(|display| display.get_default_seat()) {
+        seat.ungrab();
+    }
+}
+
+/// Whether a click landed on the window itself, rather than on a widget with its own input window.
+/// Selectable labels have one, so that dragging over them to select text doesn't dismiss the
+/// notification; so do buttons.
+fn on_background(window: &gtk::ApplicationWindow, event: &gdk::EventButton) -> bool {
+    event.get_window() == window.get_window()
+}
+
+/// Loads CSS from a string and applies it to the whole screen.
+fn add_css_data(css: &str) -> Result<gtk::CssProvider, anyhow::Error> {
     let provider = gtk::CssProvider::new();
     provider
-        .load_from_file(&gio::File::new_for_path(path))
+        .load_from_data(css.as_bytes())
         .context("failed to load CSS")?;
     gtk::StyleContext::add_provider_for_screen(
         &gdk::Screen::get_default().context("Error initializing gtk css provider.")?,
         &provider,
         gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
     );
-    Ok(())
+    Ok(provider)
+}
+
+/// Moves `content` out of `window` and into a clip that's `height` pixels tall, however tall it
+/// would like to be, so that the window can grow or shrink to its size bit by bit. The clip only
+/// stays until it's done; see `unwrap_clip`.
+fn wrap_in_clip(
+    window: &gtk::ApplicationWindow,
+    content: &gtk::Widget,
+    height: i32,
+    provider: &gtk::CssProvider,
+) -> gtk::ScrolledWindow {
+    let clip = gtk::ScrolledWindowBuilder::new()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .vscrollbar_policy(gtk::PolicyType::External)
+        .propagate_natural_height(true)
+        .build();
+    if content.get_parent().is_some() {
+        window.remove(content);
+    }
+    clip.add(content);
+    pin_height(&clip, height);
+    // The clip and the viewport GTK puts inside it need the notification's colors too, in case the
+    // theme styles them.
+    let viewport = clip.get_child();
+    for widget in std::iter::once(clip.clone().upcast()).chain(viewport) {
+        widget
+            .get_style_context()
+            .add_provider(provider, gtk::STYLE_PROVIDER_PRIORITY_APPLICATION + 1);
+    }
+    window.add(&clip);
+    clip.show_all();
+    clip
+}
+
+/// Puts what's in `clip` straight back in `window`, so that themes see the same widgets as ever.
+fn unwrap_clip(window: &gtk::ApplicationWindow, clip: &gtk::ScrolledWindow) {
+    let viewport = match clip
+        .get_child()
+        .and_then(|child| child.downcast::<gtk::Viewport>().ok())
+    {
+        Some(viewport) => viewport,
+        None => return,
+    };
+    if let Some(content) = viewport.get_child() {
+        viewport.remove(&content);
+        window.remove(clip);
+        window.add(&content);
+    }
+}
+
+/// Cuts `clip` down (or stretches it) to `height`, however tall what's in it would like to be.
+fn pin_height(clip: &gtk::ScrolledWindow, height: i32) {
+    // GTK won't let the minimum go over the maximum even for a moment, so which one goes first
+    // depends on which way it's going.
+    if height > clip.get_max_content_height() {
+        clip.set_max_content_height(height);
+        clip.set_min_content_height(height);
+    } else {
+        clip.set_min_content_height(height);
+        clip.set_max_content_height(height);
+    }
+}
+
+/// How a window's content is changing size.
+struct Resize {
+    /// How tall its content was.
+    from: i32,
+    /// How tall its content is now.
+    to: i32,
+    /// The window's own border and padding.
+    frame: i32,
+    /// Where the window's top was.
+    y: i32,
+    stacks_down: bool,
+}
+
+/// Grows or shrinks `window`, whose content is in `clip`, over `duration`. The window's edge
+/// nearest the edge of its zone stays put, and the windows `behind` it move along with the far one.
+/// Once it's done, the content goes back in the window and the stack is laid out again, in case
+/// anything changed along the way.
+fn animate_height(
+    window: &gtk::ApplicationWindow,
+    clip: gtk::ScrolledWindow,
+    resize: Resize,
+    behind: Vec<WeakRef<gtk::ApplicationWindow>>,
+    duration: Duration,
+    tx: glib::Sender<NinomiyaEvent>,
+) {
+    let Resize {
+        from,
+        to,
+        frame,
+        y,
+        stacks_down,
+    } = resize;
+    let (old_height, bottom) = (from + frame, y + from + frame);
+    let (x, width) = (window.get_position().0, window.get_size().0);
+    let started = Cell::new(None);
+    // How far the windows behind it have been moved so far.
+    let shifted = Cell::new(0);
+    window.add_tick_callback(move |window, clock| {
+        let now = clock.get_frame_time();
+        let start = started.get().unwrap_or(now);
+        started.set(Some(start));
+        let done = (now - start) as f64 / duration.as_micros() as f64;
+        // Eases out, so that it settles into its new size.
+        let eased = 1.0 - (1.0 - done.min(1.0)).powi(3);
+        let content = from + (f64::from(to - from) * eased).round() as i32;
+        pin_height(&clip, content);
+        let height = content + frame;
+        window.resize(width, height);
+        if !stacks_down {
+            window.move_(x, bottom - height);
+        }
+        let shift = if stacks_down {
+            height - old_height
+        } else {
+            old_height - height
+        };
+        // Only by how much further it's gone since the last frame, so that they keep wherever
+        // they've been moved to in the meantime.
+        let step = shift - shifted.replace(shift);
+        if step != 0 {
+            for other in &behind {
+                if let Some(other) = other.upgrade() {
+                    let (other_x, other_y) = other.get_position();
+                    other.move_(other_x, other_y + step);
+                }
+            }
+        }
+        if done < 1.0 {
+            return glib::Continue(true);
+        }
+        unwrap_clip(window, &clip);
+        let _ = tx.send(NinomiyaEvent::Relayout);
+        glib::Continue(false)
+    });
+}
+
+/// Adds `provider` to `widget` and everything in it. Providers added to a widget don't apply to its
+/// children, but its colors have to be defined wherever the theme uses them. It goes above the
+/// theme, which would otherwise win since it's for the whole screen.
+fn add_provider_to_all(widget: &gtk::Widget, provider: &gtk::CssProvider) {
+    widget
+        .get_style_context()
+        .add_provider(provider, gtk::STYLE_PROVIDER_PRIORITY_APPLICATION + 1);
+    if let Some(container) = widget.downcast_ref::<gtk::Container>() {
+        container.forall(|child| add_provider_to_all(child, provider));
+    }
+}
+
+/// The text color the theme gives the widget, which symbolic icons inside it should match.
+fn foreground_color<W: IsA<gtk::Widget>>(widget: &W) -> gdk::RGBA {
+    widget
+        .get_style_context()
+        .get_color(gtk::StateFlags::NORMAL)
+}
+
+/// Clips the pixbuf to the given shape, making everything outside it transparent.
+fn clip_pixbuf(input: Pixbuf, shape: ImageShape, corner_radius: f64) -> Pixbuf {
+    if shape == ImageShape::Square {
+        return input;
+    }
+    let (width, height) = (input.get_width(), input.get_height());
+    let (w, h) = (width as f64, height as f64);
+    let surface = match cairo::ImageSurface::create(cairo::Format::ARgb32, width, height) {
+        Ok(surface) => surface,
+        Err(err) => {
+            error!("Failed to create surface to clip image: {:?}", err);
+            return input;
+        }
+    };
+    let cr = cairo::Context::new(&surface);
+    if shape == ImageShape::Circle {
+        cr.arc(w / 2.0, h / 2.0, w.min(h) / 2.0, 0.0, 2.0 * PI);
+    } else {
+        let r = corner_radius.min(w / 2.0).min(h / 2.0);
+        cr.new_sub_path();
+        cr.arc(w - r, r, r, -PI / 2.0, 0.0);
+        cr.arc(w - r, h - r, r, 0.0, PI / 2.0);
+        cr.arc(r, h - r, r, PI / 2.0, PI);
+        cr.arc(r, r, r, PI, 3.0 * PI / 2.0);
+        cr.close_path();
+    }
+    cr.clip();
+    cr.set_source_pixbuf(&input, 0.0, 0.0);
+    cr.paint();
+    surface.flush();
+    gdk::pixbuf_get_from_surface(&surface, 0, 0, width, height).unwrap_or(input)
 }
 
 /// Resizes the given pixbuf to fit within the given dimensions. Preserves the aspect ratio.
@@ -371,3 +2334,288 @@ fn resize_pixbuf(input: Pixbuf, max_width: i32, max_height: i32) -> Pixbuf {
             .expect("failed to resize; OOM?")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo;
+    use crate::gtk_test_runner::run_test;
+    use crate::hints::Urgency;
+    use crate::lifecycle::Resolution;
+    use crate::snapshot;
+    use crate::timer::FakeClock;
+
+    // Builds a GUI whose notifications never close on their own, since the clock never moves.
+    fn gui(config: Config) -> Rc<Gui> {
+        let (tx, _rx) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        let (signal_tx, _signal_rx) = mpsc::channel();
+        let gui = Gui::new(config, Rc::new(FakeClock::new()), tx, signal_tx);
+        gui.register().unwrap();
+        gui
+    }
+
+    // Shows `notification` in a fresh GUI, and returns its window once it's laid out.
+    fn show(config: Config, notification: Notification) -> gtk::ApplicationWindow {
+        show_in(&gui(config), notification)
+    }
+
+    fn show_in(gui: &Gui, notification: Notification) -> gtk::ApplicationWindow {
+        let id = notification.id;
+        gui.notification_window(notification);
+        while gtk::events_pending() {
+            gtk::main_iteration();
+        }
+        let window = gui
+            .windows
+            .lock()
+            .unwrap()
+            .get(&id)
+            .and_then(|entry| entry.window.upgrade());
+        window.expect("no window for the notification")
+    }
+
+    // Finds the widget with the given name, searching depth-first from `widget`.
+    fn find(widget: &gtk::Widget, name: &str) -> Option<gtk::Widget> {
+        if widget.get_widget_name().as_deref() == Some(name) {
+            return Some(widget.clone());
+        }
+        widget
+            .downcast_ref::<gtk::Container>()?
+            .get_children()
+            .iter()
+            .find_map(|child| find(child, name))
+    }
+
+    #[test]
+    fn resolves_once() {
+        run_test(|| {
+            let (tx, rx) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+            let (signal_tx, signal_rx) = mpsc::channel();
+            let closes = Rc::new(Cell::new(0));
+            rx.attach(
+                None,
+                clone!(@strong closes => move |_| {
+                    closes.set(closes.get() + 1);
+                    Continue(true)
+                }),
+            );
+            let resolver = Resolver::new(1, false, signal_tx.clone(), tx.clone());
+            for _ in 0..3 {
+                resolver.invoke(DEFAULT_KEY);
+                resolver.dismiss();
+            }
+            // Resident notifications can be acted on again, but not straight away.
+            let resident = Resolver::new(2, true, signal_tx, tx);
+            resident.invoke("reply");
+            resident.invoke("reply");
+            while gtk::events_pending() {
+                gtk::main_iteration();
+            }
+            assert_eq!(signal_rx.try_iter().count(), 2);
+            assert_eq!(closes.get(), 1);
+        });
+    }
+
+    #[test]
+    fn expires_on_its_clock() {
+        run_test(|| {
+            let (tx, rx) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+            let (signal_tx, _signal_rx) = mpsc::channel();
+            let expired = Rc::new(RefCell::new(vec![]));
+            rx.attach(
+                None,
+                clone!(@strong expired => move |event| {
+                    if let NinomiyaEvent::CloseNotification(id, CloseReason::Expired) = event {
+                        expired.borrow_mut().push(id);
+                    }
+                    Continue(true)
+                }),
+            );
+            let clock = Rc::new(FakeClock::new());
+            let config = Config {
+                duration: Duration::from_secs(5),
+                ..Config::default()
+            };
+            let gui = Gui::new(config, clock.clone(), tx, signal_tx);
+            gui.register().unwrap();
+            show_in(&gui, Notification::builder().id(1).summary("soon").build());
+            let run_pending = || {
+                while gtk::events_pending() {
+                    gtk::main_iteration();
+                }
+            };
+            clock.advance(Duration::from_millis(4999));
+            run_pending();
+            assert!(expired.borrow().is_empty());
+            clock.advance(Duration::from_millis(1));
+            run_pending();
+            assert_eq!(*expired.borrow(), vec![1]);
+        });
+    }
+
+    #[test]
+    fn undoes_close() {
+        run_test(|| {
+            let gui = gui(Config::default());
+            let notification = || Notification::builder().id(1).summary("oops").build();
+            show_in(&gui, notification());
+            gui.close_notification(1, CloseReason::Dismissed);
+            assert!(gui.windows.lock().unwrap().is_empty());
+            gui.undo_close();
+            assert!(gui.windows.lock().unwrap().contains_key(&1));
+            // It can only be brought back once, and only if it was dismissed.
+            gui.close_notification(1, CloseReason::Expired);
+            gui.undo_close();
+            assert!(gui.windows.lock().unwrap().is_empty());
+
+            // With a server, it goes back there to be dispatched again.
+            let (restore_tx, restore_rx) = mpsc::channel();
+            gui.restore_through(restore_tx);
+            show_in(&gui, notification());
+            gui.close_notification(1, CloseReason::Dismissed);
+            gui.undo_close();
+            assert!(gui.windows.lock().unwrap().is_empty());
+            let restored = restore_rx.try_recv().unwrap();
+            assert_eq!(restored.lifecycle.resolution, Some(Resolution::Dismissed));
+        });
+    }
+
+    #[test]
+    fn builds_widgets() {
+        run_test(|| {
+            let window = show(
+                Config::default(),
+                Notification::builder()
+                    .id(1)
+                    .application_name("galax")
+                    .summary("the summary")
+                    .body("the body")
+                    .action("default", "")
+                    .action("reply", "Reply")
+                    .action("mute", "Mute")
+                    .build(),
+            );
+            let root = window.upcast_ref::<gtk::Widget>();
+            let summary = find(root, "summary").expect("no summary");
+            assert_eq!(
+                summary
+                    .downcast_ref::<gtk::Label>()
+                    .unwrap()
+                    .get_text()
+                    .unwrap()
+                    .as_str(),
+                "the summary"
+            );
+            assert_eq!(summary.get_tooltip_text().as_deref(), Some("the summary"));
+            assert!(summary
+                .downcast_ref::<gtk::Label>()
+                .unwrap()
+                .get_selectable());
+            assert!(find(root, "body").is_some());
+            let composited = window.get_screen().unwrap().is_composited();
+            assert_eq!(
+                window.get_style_context().has_class(OPAQUE_CLASS),
+                !composited
+            );
+            let app_name = find(root, "application-name").expect("no application name");
+            assert_eq!(app_name.get_tooltip_text().as_deref(), Some("galax"));
+            // The default action doesn't get a button.
+            let buttons = find(root, "buttons").expect("no buttons");
+            let labels: Vec<String> = buttons
+                .downcast_ref::<gtk::Container>()
+                .unwrap()
+                .get_children()
+                .iter()
+                .filter_map(|button| button.downcast_ref::<gtk::Button>()?.get_label())
+                .map(|label| label.to_string())
+                .collect();
+            assert_eq!(labels, vec!["Reply", "Mute"]);
+            window.close();
+        })
+    }
+
+    #[test]
+    fn adds_classes() {
+        run_test(|| {
+            let window = show(
+                Config::default(),
+                Notification::builder()
+                    .id(1)
+                    .application_name("Mozilla Firefox")
+                    .summary("download finished")
+                    .urgency(Urgency::Critical)
+                    .build(),
+            );
+            let style = window.get_style_context();
+            assert!(style.has_class("urgency-critical"));
+            assert!(style.has_class("app-mozilla-firefox"));
+            window.close();
+        })
+    }
+
+    #[test]
+    fn sizes_window() {
+        run_test(|| {
+            let config = Config {
+                width: crate::config::Width::Pixels(420),
+                ..Config::default()
+            };
+            let window = show(
+                config,
+                Notification::builder()
+                    .id(1)
+                    .summary("a summary long enough that it has to wrap onto another line or two")
+                    .build(),
+            );
+            assert_eq!(window.get_size().0, 420);
+            window.close();
+        })
+    }
+
+    #[test]
+    fn places_by_urgency() {
+        run_test(|| {
+            let mut config = Config::default();
+            config.zones.low = Zone::BottomLeft;
+            let gui = gui(config);
+            let low = |id| {
+                Notification::builder()
+                    .id(id)
+                    .summary("low")
+                    .urgency(Urgency::Low)
+                    .build()
+            };
+            let first = show_in(&gui, low(1));
+            let second = show_in(&gui, low(2));
+            let normal = show_in(
+                &gui,
+                Notification::builder().id(3).summary("normal").build(),
+            );
+            let screen_height = gdk::Screen::get_default().unwrap().get_height();
+
+            assert_eq!(first.get_position().0, 0);
+            assert!(first.get_position().1 > screen_height / 2);
+            // Bottom zones stack upwards.
+            assert!(second.get_position().1 + second.get_size().1 <= first.get_position().1);
+            // The normal one isn't stacked under the low ones.
+            assert_eq!(normal.get_position().1, 0);
+            for window in &[first, second, normal] {
+                window.close();
+            }
+        })
+    }
+
+    #[test]
+    fn matches_snapshots() {
+        run_test(|| {
+            let gui = gui(Config::default());
+            gui.add_css_file("data/style.css").unwrap();
+            for notification in demo::demo_notifications() {
+                let name = format!("demo-{}", notification.id);
+                let window = show_in(&gui, notification);
+                snapshot::assert_matches(&name, &snapshot::render(window.upcast_ref()));
+                window.close();
+            }
+        })
+    }
+}
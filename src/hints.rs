@@ -1,11 +1,16 @@
+#![forbid(unsafe_code)]
+
+use crate::error::NotifyError;
 use anyhow::{anyhow, Context, Result};
 use dbus::arg;
+use dbus::arg::messageitem::{MessageItem, MessageItemArray};
 use derivative::Derivative;
-use log::debug;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
 use std::path::PathBuf;
 use std::str::FromStr;
+use tracing::{debug, warn};
 use url::Url;
 
 fn show_pixel_count(image_data: &Vec<u8>, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
@@ -14,19 +19,314 @@ fn show_pixel_count(image_data: &Vec<u8>, f: &mut fmt::Formatter) -> Result<(),
 
 pub type HintMap<'a> = HashMap<&'a str, arg::Variant<Box<dyn arg::RefArg>>>;
 
+/// A raw image as we build it to send: width, height, rowstride, has alpha, bits per sample,
+/// channels, and the image data.
+type RawImage = (i32, i32, i32, bool, i32, i32, Vec<u8>);
+
 static IMAGE_DATA: &str = "image-data";
 static IMAGE_PATH: &str = "image-path";
+// The names from version 1.1 of the spec, which plenty of clients still use.
+static IMAGE_DATA_1_1: &str = "image_data";
+static IMAGE_PATH_1_1: &str = "image_path";
 // Despite the name, this stores the *image*. I guess that's why it's deprecated.
 static ICON_DATA: &str = "icon_data";
+static DESKTOP_ENTRY: &str = "desktop-entry";
+static CATEGORY: &str = "category";
+static URGENCY: &str = "urgency";
+static RESIDENT: &str = "resident";
+static TRANSIENT: &str = "transient";
+static SOUND_FILE: &str = "sound-file";
+static SOUND_NAME: &str = "sound-name";
+static SUPPRESS_SOUND: &str = "suppress-sound";
+static ACTION_ICONS: &str = "action-icons";
+static X: &str = "x";
+static Y: &str = "y";
+// Not in the spec, but lots of clients use it for progress bars and volume popups.
+static VALUE: &str = "value";
+// Our own, which clients can check for with `GetCapabilities`.
+static STYLE_CLASS: &str = "x-ninomiya-style-class";
+
+/// How urgent a notification is. The values are the ones used in the `urgency` hint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Urgency {
+    Low = 0,
+    Normal = 1,
+    Critical = 2,
+}
+
+impl Urgency {
+    pub(crate) fn from_hint(value: u64) -> Result<Self> {
+        match value {
+            0 => Ok(Urgency::Low),
+            1 => Ok(Urgency::Normal),
+            2 => Ok(Urgency::Critical),
+            _ => Err(anyhow!("unknown urgency {}", value)),
+        }
+    }
+
+    /// A lowercase name for the urgency, as used in CSS classes.
+    pub fn name(self) -> &'static str {
+        match self {
+            Urgency::Low => "low",
+            Urgency::Normal => "normal",
+            Urgency::Critical => "critical",
+        }
+    }
+}
+
+/// A hint value that doesn't borrow from the message it came in, so it can be kept around and sent
+/// between threads. Integers of every width are widened, and are sent back as 32-bit integers if
+/// they fit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OwnedValue {
+    Bool(bool),
+    Byte(u8),
+    Int(i64),
+    UInt(u64),
+    Double(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Array(Vec<OwnedValue>),
+    Struct(Vec<OwnedValue>),
+}
+
+impl OwnedValue {
+    /// Copies a value out of a message. Returns `None` for types we don't handle, like dicts.
+    pub fn from_refarg(value: &dyn arg::RefArg) -> Option<Self> {
+        use arg::ArgType;
+        Some(match value.arg_type() {
+            ArgType::Boolean => OwnedValue::Bool(value.as_u64()? != 0),
+            ArgType::Byte => OwnedValue::Byte(value.as_u64()? as u8),
+            ArgType::Int16 | ArgType::Int32 | ArgType::Int64 => OwnedValue::Int(value.as_i64()?),
+            ArgType::UInt16 | ArgType::UInt32 | ArgType::UInt64 => {
+                OwnedValue::UInt(value.as_u64()?)
+            }
+            ArgType::Double => OwnedValue::Double(value.as_f64()?),
+            ArgType::String | ArgType::ObjectPath | ArgType::Signature => {
+                OwnedValue::String(value.as_str()?.to_owned())
+            }
+            ArgType::Array if &*value.signature() == "ay" => OwnedValue::Bytes(
+                value
+                    .as_iter()?
+                    .map(|byte| byte.as_u64().map(|byte| byte as u8))
+                    .collect::<Option<_>>()?,
+            ),
+            ArgType::Array => OwnedValue::Array(
+                value
+                    .as_iter()?
+                    .map(OwnedValue::from_refarg)
+                    .collect::<Option<_>>()?,
+            ),
+            ArgType::Struct => OwnedValue::Struct(
+                value
+                    .as_iter()?
+                    .map(OwnedValue::from_refarg)
+                    .collect::<Option<_>>()?,
+            ),
+            // Unwrap nested variants; the value inside is what anyone cares about.
+            ArgType::Variant => OwnedValue::from_refarg(value.as_iter()?.next()?)?,
+            _ => return None,
+        })
+    }
+
+    /// Converts this back into something that can be put in a message. Structs come out the way
+    /// they would if they'd been read from a message.
+    pub fn to_refarg(&self) -> Box<dyn arg::RefArg> {
+        match self {
+            OwnedValue::Bool(b) => Box::new(*b),
+            OwnedValue::Byte(n) => Box::new(*n),
+            OwnedValue::Int(n) if !self.is_wide() => Box::new(*n as i32),
+            OwnedValue::Int(n) => Box::new(*n),
+            OwnedValue::UInt(n) if !self.is_wide() => Box::new(*n as u32),
+            OwnedValue::UInt(n) => Box::new(*n),
+            OwnedValue::Double(n) => Box::new(*n),
+            OwnedValue::String(s) => Box::new(s.clone()),
+            OwnedValue::Bytes(bytes) => Box::new(bytes.clone()),
+            OwnedValue::Struct(items) => {
+                let fields: Vec<Box<dyn arg::RefArg>> =
+                    items.iter().map(OwnedValue::to_refarg).collect();
+                Box::new(fields)
+            }
+            // There's no owned array of arbitrary values other than `MessageItem`.
+            OwnedValue::Array(_) => Box::new(self.to_message_item(false)),
+        }
+    }
+
+    /// Whether this is an integer that doesn't fit in 32 bits.
+    fn is_wide(&self) -> bool {
+        match *self {
+            OwnedValue::Int(n) => n as i32 as i64 != n,
+            OwnedValue::UInt(n) => n as u32 as u64 != n,
+            _ => false,
+        }
+    }
+
+    /// `wide` forces integers to be 64-bit, since every element of an array has to have the same
+    /// type.
+    fn to_message_item(&self, wide: bool) -> MessageItem {
+        match self {
+            OwnedValue::Bool(b) => MessageItem::Bool(*b),
+            OwnedValue::Byte(n) => MessageItem::Byte(*n),
+            OwnedValue::Int(n) if !wide && !self.is_wide() => MessageItem::Int32(*n as i32),
+            OwnedValue::Int(n) => MessageItem::Int64(*n),
+            OwnedValue::UInt(n) if !wide && !self.is_wide() => MessageItem::UInt32(*n as u32),
+            OwnedValue::UInt(n) => MessageItem::UInt64(*n),
+            OwnedValue::Double(n) => MessageItem::Double(*n),
+            OwnedValue::String(s) => MessageItem::Str(s.clone()),
+            OwnedValue::Bytes(bytes) => MessageItem::from(&bytes[..]),
+            OwnedValue::Array(items) => {
+                let wide = items.iter().any(OwnedValue::is_wide);
+                let items: Vec<_> = items
+                    .iter()
+                    .map(|item| item.to_message_item(wide))
+                    .collect();
+                // Empty arrays don't remember what they were arrays of, so any type will do.
+                let signature = match items.first() {
+                    Some(item) => format!("a{}", item.signature()),
+                    None => "av".to_owned(),
+                };
+                MessageItemArray::new(items, dbus::Signature::new(signature).unwrap())
+                    .map(MessageItem::Array)
+                    .unwrap_or_else(|err| {
+                        debug!("Can't rebuild array {:?}: {:?}", self, err);
+                        MessageItem::Array(
+                            MessageItemArray::new(vec![], dbus::Signature::new("av").unwrap())
+                                .unwrap(),
+                        )
+                    })
+            }
+            OwnedValue::Struct(items) => MessageItem::Struct(
+                items
+                    .iter()
+                    .map(|item| item.to_message_item(false))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            OwnedValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// A copy of a notification's hints that owns all of its data, so that it can be stored (say, in
+/// the history), sent between threads, or serialized. Hints whose values we can't represent are
+/// dropped.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OwnedHints(pub HashMap<String, OwnedValue>);
+
+impl OwnedHints {
+    pub fn from_dbus(map: &HintMap) -> Self {
+        OwnedHints(
+            map.iter()
+                .filter_map(|(key, value)| match OwnedValue::from_refarg(&*value.0) {
+                    Some(value) => Some(((*key).to_owned(), value)),
+                    None => {
+                        debug!("Can't keep hint {} with value {:?}", key, value);
+                        None
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Brings these in line with `hints` once scripts, processors or redaction have changed those:
+    /// the category, the urgency and whether there's an image.
+    pub fn follow(&mut self, hints: &Hints) {
+        match &hints.category {
+            Some(category) => {
+                self.0
+                    .insert(CATEGORY.to_owned(), OwnedValue::String(category.clone()));
+            }
+            None => {
+                self.0.remove(CATEGORY);
+            }
+        }
+        self.0
+            .insert(URGENCY.to_owned(), OwnedValue::Byte(hints.urgency as u8));
+        if hints.image.is_none() {
+            for &key in &[
+                IMAGE_DATA,
+                IMAGE_PATH,
+                IMAGE_DATA_1_1,
+                IMAGE_PATH_1_1,
+                ICON_DATA,
+            ] {
+                self.0.remove(key);
+            }
+        }
+    }
+
+    /// Converts these back into a hint map, borrowing the keys.
+    pub fn to_dbus(&self) -> HintMap<'_> {
+        self.0
+            .iter()
+            .map(|(key, value)| (key.as_str(), arg::Variant(value.to_refarg())))
+            .collect()
+    }
+}
 
 /// Provides convenient access to the standardized hints of a notification.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Hints {
     pub image: Option<ImageRef>,
+    /// The name of the sending application's desktop file, without the `.desktop` suffix.
+    pub desktop_entry: Option<String>,
+    /// The type of notification, such as `email.arrived`.
+    pub category: Option<String>,
+    pub urgency: Urgency,
+    /// If true, the notification stays around after one of its actions is invoked.
+    pub resident: bool,
+    /// If true, the notification shouldn't be kept around once it's closed.
+    pub transient: bool,
+    /// A sound file to play when the notification is shown.
+    pub sound_file: Option<PathBuf>,
+    /// A sound from the freedesktop sound theme to play, like `message-new-instant`.
+    pub sound_name: Option<String>,
+    /// If true, no sound should be played, whatever the sound hints or config say.
+    pub suppress_sound: bool,
+    /// If true, action keys are icon names, and the buttons should show icons instead of labels.
+    pub action_icons: bool,
+    /// Where on the screen the notification should point to.
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+    /// A progress value, from 0 to 100.
+    pub value: Option<i32>,
+    /// A CSS class to add to the notification's window, so that themes can style it.
+    pub style_class: Option<String>,
+    /// Every hint we don't know about, so that rules and scripts can still match on them.
+    pub extra: HashMap<String, OwnedValue>,
+}
+impl Default for Hints {
+    fn default() -> Self {
+        Hints::new()
+    }
 }
+
 impl Hints {
     pub fn new() -> Self {
-        Hints { image: None }
+        Hints {
+            image: None,
+            desktop_entry: None,
+            category: None,
+            urgency: Urgency::Normal,
+            resident: false,
+            transient: false,
+            sound_file: None,
+            sound_name: None,
+            suppress_sound: false,
+            action_icons: false,
+            x: None,
+            y: None,
+            value: None,
+            style_class: None,
+            extra: HashMap::new(),
+        }
     }
 
     /// Builds a new instance of this using the given dbus hint map.
@@ -34,21 +334,66 @@ impl Hints {
         let mut hints = Hints::new();
 
         // We do these in reverse precedence order so we always clear them out from the map.
-        if let Some(icon_data) = map.remove(ICON_DATA) {
-            hints.image = Some(ImageRef::from_variant(icon_data)?);
-        }
-        if let Some(image_path) = map.remove(IMAGE_PATH) {
-            let image_path_str = image_path
-                .0
-                .as_str()
-                .context("`image-path` did not have expected signature")?;
-            hints.image = Some(image_path_str.parse()?);
-        }
-        if let Some(image_bytes) = map.remove(IMAGE_DATA) {
-            hints.image = Some(ImageRef::from_variant(image_bytes)?);
+        for &key in &[
+            ICON_DATA,
+            IMAGE_PATH_1_1,
+            IMAGE_PATH,
+            IMAGE_DATA_1_1,
+            IMAGE_DATA,
+        ] {
+            let value = match map.remove(key) {
+                Some(value) => value,
+                None => continue,
+            };
+            let image = if key == IMAGE_PATH || key == IMAGE_PATH_1_1 {
+                value
+                    .0
+                    .as_str()
+                    .with_context(|| format!("`{}` did not have expected signature", key))
+                    .and_then(str::parse)
+            } else {
+                ImageRef::from_variant(value)
+            };
+            hints.image = Some(image.map_err(NotifyError::InvalidImage)?);
         }
 
-        debug!("Unused hints are {:?}", map);
+        hints.desktop_entry = take_string(&mut map, DESKTOP_ENTRY);
+        hints.category = take_string(&mut map, CATEGORY);
+        hints.urgency = take(&mut map, URGENCY, |value| value.as_u64())
+            .and_then(|value| match Urgency::from_hint(value) {
+                Ok(urgency) => Some(urgency),
+                Err(err) => {
+                    warn!("Ignoring hint `{}`: {}", URGENCY, err);
+                    None
+                }
+            })
+            .unwrap_or(Urgency::Normal);
+        hints.resident = take_bool(&mut map, RESIDENT);
+        hints.transient = take_bool(&mut map, TRANSIENT);
+        hints.sound_file =
+            take_string(&mut map, SOUND_FILE).and_then(|path| match expand_path(&path) {
+                Ok(path) => Some(path),
+                Err(err) => {
+                    warn!("Ignoring hint `{}`: {:#}", SOUND_FILE, err);
+                    None
+                }
+            });
+        hints.sound_name = take_string(&mut map, SOUND_NAME);
+        hints.suppress_sound = take_bool(&mut map, SUPPRESS_SOUND);
+        hints.action_icons = take_bool(&mut map, ACTION_ICONS);
+        hints.x = take_int(&mut map, X);
+        hints.y = take_int(&mut map, Y);
+        hints.value = take_int(&mut map, VALUE);
+        hints.style_class = take_string(&mut map, STYLE_CLASS);
+
+        for (key, value) in map {
+            match OwnedValue::from_refarg(&*value.0) {
+                Some(value) => {
+                    hints.extra.insert(key.to_owned(), value);
+                }
+                None => debug!("Ignoring hint {} with unsupported value {:?}", key, value),
+            }
+        }
 
         Ok(hints)
     }
@@ -67,7 +412,7 @@ impl Hints {
                 } => {
                     let rowstride = width * bits_per_sample;
                     let n_channels = if has_alpha { 4 } else { 3 };
-                    let tuple = (
+                    let tuple: RawImage = (
                         width,
                         height,
                         rowstride,
@@ -95,10 +440,94 @@ impl Hints {
                 }
             }
         }
+        if let Some(desktop_entry) = self.desktop_entry {
+            map.insert(
+                DESKTOP_ENTRY,
+                arg::Variant(Box::new(desktop_entry) as Box<dyn arg::RefArg>),
+            );
+        }
+        map.insert(
+            URGENCY,
+            arg::Variant(Box::new(self.urgency as u8) as Box<dyn arg::RefArg>),
+        );
+        if let Some(category) = self.category {
+            map.insert(
+                CATEGORY,
+                arg::Variant(Box::new(category) as Box<dyn arg::RefArg>),
+            );
+        }
+        for &(key, set) in &[
+            (RESIDENT, self.resident),
+            (TRANSIENT, self.transient),
+            (SUPPRESS_SOUND, self.suppress_sound),
+            (ACTION_ICONS, self.action_icons),
+        ] {
+            if set {
+                map.insert(key, arg::Variant(Box::new(true) as Box<dyn arg::RefArg>));
+            }
+        }
+        if let Some(sound_file) = self.sound_file {
+            map.insert(
+                SOUND_FILE,
+                arg::Variant(
+                    Box::new(sound_file.to_string_lossy().into_owned()) as Box<dyn arg::RefArg>
+                ),
+            );
+        }
+        if let Some(sound_name) = self.sound_name {
+            map.insert(
+                SOUND_NAME,
+                arg::Variant(Box::new(sound_name) as Box<dyn arg::RefArg>),
+            );
+        }
+        if let Some(style_class) = self.style_class {
+            map.insert(
+                STYLE_CLASS,
+                arg::Variant(Box::new(style_class) as Box<dyn arg::RefArg>),
+            );
+        }
+        for &(key, value) in &[(X, self.x), (Y, self.y), (VALUE, self.value)] {
+            if let Some(value) = value {
+                map.insert(key, arg::Variant(Box::new(value) as Box<dyn arg::RefArg>));
+            }
+        }
         map
     }
 }
 
+/// Takes the optional hint `key` out of `map` and converts it. One with the wrong type is logged
+/// and ignored, since a notification is still worth showing without it.
+fn take<T>(
+    map: &mut HintMap,
+    key: &str,
+    convert: impl FnOnce(&dyn arg::RefArg) -> Option<T>,
+) -> Option<T> {
+    let value = map.remove(key)?;
+    let converted = convert(&*value.0);
+    if converted.is_none() {
+        warn!(
+            "Ignoring hint `{}`, which has the wrong type ({:?})",
+            key,
+            value.0.signature()
+        );
+    }
+    converted
+}
+
+fn take_string(map: &mut HintMap, key: &str) -> Option<String> {
+    take(map, key, |value| value.as_str().map(str::to_owned))
+}
+
+/// Booleans are meant to be sent as booleans, but some clients send them as integers, so we accept
+/// both.
+fn take_bool(map: &mut HintMap, key: &str) -> bool {
+    take(map, key, |value| value.as_u64()).map_or(false, |n| n != 0)
+}
+
+fn take_int(map: &mut HintMap, key: &str) -> Option<i32> {
+    take(map, key, |value| value.as_i64()).map(|n| n as i32)
+}
+
 /// Represents an image as it was passed in the hints. Can be converted into a pixbuf.
 #[derive(Clone, Derivative)]
 #[derivative(Debug)]
@@ -117,18 +546,26 @@ pub enum ImageRef {
     IconName(String),
 }
 
-/// The `FromStr` implementation turns URLs and path-like things (anything containing a '.' or a
-/// '/') into `Url`s, and anything else into `IconName`s.
+/// Expands `~` and environment variables (`$HOME`, `${XDG_PICTURES_DIR}`) in a path, the way a
+/// shell would.
+pub fn expand_path(path: &str) -> Result<PathBuf> {
+    let expanded =
+        shellexpand::full(path).with_context(|| format!("failed to expand path {}", path))?;
+    Ok(PathBuf::from(expanded.into_owned()))
+}
+
+/// The `FromStr` implementation turns URLs (including `data:` URLs) and path-like things (anything
+/// containing a '.' or a '/') into `Url`s, and anything else into `IconName`s.
 impl FromStr for ImageRef {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        if s.contains("://") {
+        if s.contains("://") || s.starts_with("data:") {
             // It's definitely a URL.
             Ok(ImageRef::Url(s.parse()?))
         } else if s.contains(".") || s.contains("/") {
             // Probably a path.
-            let path = PathBuf::from(s);
+            let path = expand_path(s)?;
             Ok(ImageRef::Url(
                 Url::from_file_path(path.canonicalize()?)
                     .map_err(|_| anyhow!("failed to parse path as file path"))?,
@@ -149,7 +586,9 @@ impl ImageRef {
         let signature = variant.0.signature();
         if signature != expected_signature {
             return Err(anyhow!(
-                "Unexpected signature when getting image {} (expected {})"
+                "Unexpected signature when getting image {} (expected {})",
+                signature,
+                expected_signature
             ));
         }
         // use an anonymous function so we can use ? to bail out early, then convert the None into an
@@ -162,16 +601,12 @@ impl ImageRef {
             let has_alpha = iter.next()?.as_i64()? != 0;
             let bits_per_sample = iter.next()?.as_i64()? as i32;
             let _channels = iter.next()?.as_i64()?;
-            let cloned = iter.next()?;
-            let bytes = unsafe { refarg_to_bytes(&*cloned) };
             let image = ImageRef::Image {
                 width,
                 height,
                 has_alpha,
                 bits_per_sample,
-                // TODO: we wind up cloning the image data here *twice*. we shouldn't really need to do
-                // that.
-                image_data: bytes.clone(),
+                image_data: image_bytes(&*variant.0)?.clone(),
             };
             Some(image)
         })()
@@ -179,21 +614,216 @@ impl ImageRef {
     }
 }
 
-/// Converts a refarg, which *must* contain a Vec<u8>, into the corresponding Vec<u8>.
-///
-/// This function is necessary because we can't get a `&(dyn arg::RefArg + 'static)`, but we need
-/// that `'static` bound in order to use `arg::cast`.
-unsafe fn refarg_to_bytes<'a>(refarg: &'a dyn arg::RefArg) -> &'a Vec<u8> {
-    assert_eq!(
-        refarg.signature(),
-        dbus::strings::Signature::new("ay").unwrap()
-    );
-    // This *should* be safe. For one, Vec<u8> and dbus-rs's InternalArray type actually don't own
-    // any references, so they're 'static. For another, I *think* lying to the compiler about
-    // lifetimes is safe as long as you don't actually violate those lifetimes. And since the
-    // underlying lifetime in this case is the lifetime of the `raw_image_from_variant` body, and
-    // we're cloning the vec anyway in order to return it... I think we're good.
-    let refarg =
-        std::mem::transmute::<&'a dyn arg::RefArg, &'a (dyn arg::RefArg + 'static)>(refarg);
-    arg::cast(refarg).expect("thought we were getting a Vec<u8>???")
+/// Gets the image data out of a raw image. Structs read from a message are vectors of their fields,
+/// but ones we built ourselves (say, with `into_dbus`) are still tuples.
+fn image_bytes<'a>(image: &'a (dyn arg::RefArg + 'static)) -> Option<&'a Vec<u8>> {
+    if let Some(fields) = arg::cast::<Vec<Box<dyn arg::RefArg>>>(image) {
+        return arg::cast(&**fields.get(6)?);
+    }
+    arg::cast::<RawImage>(image).map(|raw| &raw.6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_paths() -> Result<()> {
+        std::env::set_var("NINOMIYA_TEST_PICTURES", "/pictures");
+        assert_eq!(
+            expand_path("$NINOMIYA_TEST_PICTURES/avatar.png")?,
+            PathBuf::from("/pictures/avatar.png")
+        );
+        assert_eq!(
+            expand_path("/no/expansion.png")?,
+            PathBuf::from("/no/expansion.png")
+        );
+        assert!(expand_path("$NINOMIYA_TEST_DOES_NOT_EXIST/avatar.png").is_err());
+        Ok(())
+    }
+
+    fn image_data(hints: Hints) -> Option<Vec<u8>> {
+        match hints.image {
+            Some(ImageRef::Image { image_data, .. }) => Some(image_data),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn reads_raw_images() -> Result<()> {
+        let hints = Hints {
+            image: Some(ImageRef::Image {
+                width: 1,
+                height: 1,
+                has_alpha: true,
+                bits_per_sample: 8,
+                image_data: vec![1, 2, 3, 4],
+            }),
+            ..Hints::new()
+        };
+        assert_eq!(
+            image_data(Hints::from_dbus(hints.into_dbus())?),
+            Some(vec![1, 2, 3, 4])
+        );
+
+        // This is how it comes out of a message.
+        let fields: Vec<Box<dyn arg::RefArg>> = vec![
+            Box::new(1i32),
+            Box::new(1i32),
+            Box::new(4i32),
+            Box::new(true),
+            Box::new(8i32),
+            Box::new(4i32),
+            Box::new(vec![5u8, 6, 7, 8]),
+        ];
+        let mut map = HashMap::new();
+        map.insert(
+            IMAGE_DATA,
+            arg::Variant(Box::new(fields) as Box<dyn arg::RefArg>),
+        );
+        assert_eq!(image_data(Hints::from_dbus(map)?), Some(vec![5, 6, 7, 8]));
+        Ok(())
+    }
+
+    fn string(s: &str) -> arg::Variant<Box<dyn arg::RefArg>> {
+        arg::Variant(Box::new(s.to_owned()) as Box<dyn arg::RefArg>)
+    }
+
+    fn image_name(hints: Hints) -> Option<String> {
+        match hints.image {
+            Some(ImageRef::IconName(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn accepts_old_image_names() -> Result<()> {
+        let mut map = HashMap::new();
+        map.insert(IMAGE_PATH_1_1, string("old-name"));
+        assert_eq!(
+            image_name(Hints::from_dbus(map)?).as_deref(),
+            Some("old-name")
+        );
+
+        // The current name wins.
+        let mut map = HashMap::new();
+        map.insert(IMAGE_PATH_1_1, string("old-name"));
+        map.insert(IMAGE_PATH, string("new-name"));
+        assert_eq!(
+            image_name(Hints::from_dbus(map)?).as_deref(),
+            Some("new-name")
+        );
+
+        // Raw images win over paths, whatever they're called.
+        let mut map = Hints {
+            image: Some(ImageRef::Image {
+                width: 1,
+                height: 1,
+                has_alpha: true,
+                bits_per_sample: 8,
+                image_data: vec![1, 2, 3, 4],
+            }),
+            ..Hints::new()
+        }
+        .into_dbus();
+        let raw_image = map.remove(IMAGE_DATA).unwrap();
+        map.insert(IMAGE_DATA_1_1, raw_image);
+        map.insert(IMAGE_PATH, string("new-name"));
+        assert_eq!(image_data(Hints::from_dbus(map)?), Some(vec![1, 2, 3, 4]));
+        Ok(())
+    }
+
+    #[test]
+    fn reads_standard_and_extra_hints() -> Result<()> {
+        let mut map = HashMap::new();
+        map.insert(
+            SUPPRESS_SOUND,
+            arg::Variant(Box::new(true) as Box<dyn arg::RefArg>),
+        );
+        // Some clients send booleans as integers.
+        map.insert(
+            TRANSIENT,
+            arg::Variant(Box::new(1u8) as Box<dyn arg::RefArg>),
+        );
+        map.insert(SOUND_NAME, string("message-new-instant"));
+        map.insert(VALUE, arg::Variant(Box::new(42i32) as Box<dyn arg::RefArg>));
+        map.insert("x-kde-origin-name", string("Konversation"));
+        map.insert(STYLE_CLASS, string("build-failed"));
+        // A hint with the wrong type is ignored rather than failing the whole notification.
+        map.insert(
+            CATEGORY,
+            arg::Variant(Box::new(3u32) as Box<dyn arg::RefArg>),
+        );
+        map.insert(Y, string("top"));
+        // So is one with a value we don't understand.
+        map.insert(URGENCY, arg::Variant(Box::new(3u8) as Box<dyn arg::RefArg>));
+        map.insert(
+            SOUND_FILE,
+            string("$NINOMIYA_TEST_DOES_NOT_EXIST/message.wav"),
+        );
+        let hints = Hints::from_dbus(map)?;
+        assert_eq!(hints.category, None);
+        assert_eq!(hints.y, None);
+        assert_eq!(hints.urgency, Urgency::Normal);
+        assert_eq!(hints.sound_file, None);
+        assert_eq!(hints.style_class.as_deref(), Some("build-failed"));
+        assert!(hints.suppress_sound);
+        assert!(hints.transient);
+        assert!(!hints.action_icons);
+        assert_eq!(hints.sound_name.as_deref(), Some("message-new-instant"));
+        assert_eq!(hints.value, Some(42));
+        assert_eq!(hints.x, None);
+        assert_eq!(
+            hints.extra.get("x-kde-origin-name"),
+            Some(&OwnedValue::String("Konversation".to_owned()))
+        );
+        assert_eq!(hints.extra.len(), 1);
+
+        let mut map = HashMap::new();
+        map.insert(URGENCY, string("critical"));
+        assert_eq!(Hints::from_dbus(map)?.urgency, Urgency::Normal);
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips_owned_hints() -> Result<()> {
+        let mut map = Hints {
+            image: Some(ImageRef::Image {
+                width: 1,
+                height: 1,
+                has_alpha: true,
+                bits_per_sample: 8,
+                image_data: vec![1, 2, 3, 4],
+            }),
+            category: Some("im.received".to_owned()),
+            urgency: Urgency::Critical,
+            value: Some(40),
+            ..Hints::new()
+        }
+        .into_dbus();
+        map.insert(
+            "x-nonstandard",
+            arg::Variant(Box::new((1u64 << 40, "big".to_owned())) as Box<dyn arg::RefArg>),
+        );
+        let owned = OwnedHints::from_dbus(&map);
+        assert_eq!(owned.0.len(), map.len());
+        assert_eq!(owned.0[URGENCY], OwnedValue::Byte(2));
+
+        let serialized = serde_json::to_string(&owned)?;
+        let deserialized: OwnedHints = serde_json::from_str(&serialized)?;
+        assert_eq!(deserialized, owned);
+
+        let map = deserialized.to_dbus();
+        for (key, value) in &map {
+            assert_eq!(
+                OwnedValue::from_refarg(&*value.0).as_ref(),
+                owned.0.get(*key),
+                "{} changed",
+                key
+            );
+        }
+        let hints = Hints::from_dbus(map)?;
+        assert_eq!(image_data(hints), Some(vec![1, 2, 3, 4]));
+        Ok(())
+    }
 }
@@ -0,0 +1,101 @@
+//! The notification center: the notifications that came in lately, whether or not they got a popup
+//! of their own, so that the ones rolled up, collected into digests, or kept off screen can still
+//! be read. It's only kept in memory, and is available from the control interface's `GetHistory`
+//! and `ninomiya history`.
+
+use crate::server::Notification;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many notifications to keep.
+pub const HISTORY_LENGTH: usize = 200;
+
+/// An entry as `GetHistory` returns it: its ID, app name, summary, body, and when it arrived.
+pub type DbusEntry = (u32, String, String, String, u64);
+
+/// A notification as the notification center lists it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub id: u32,
+    pub app_name: String,
+    pub summary: String,
+    pub body: String,
+    /// When it arrived, in seconds since the Unix epoch.
+    pub received: u64,
+}
+
+impl Entry {
+    /// The entry as `GetHistory` returns it.
+    pub fn to_dbus(&self) -> DbusEntry {
+        (
+            self.id,
+            self.app_name.clone(),
+            self.summary.clone(),
+            self.body.clone(),
+            self.received,
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    /// Oldest first.
+    entries: VecDeque<Entry>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a notification, in place of any earlier version of it.
+    pub fn add(&mut self, notification: &Notification) {
+        self.entries.retain(|entry| entry.id != notification.id);
+        if self.entries.len() == HISTORY_LENGTH {
+            self.entries.pop_front();
+        }
+        let received = notification
+            .lifecycle
+            .received
+            .unwrap_or_else(SystemTime::now)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.entries.push_back(Entry {
+            id: notification.id,
+            app_name: notification.application_name.clone().unwrap_or_default(),
+            summary: notification.summary.clone(),
+            body: notification.body.clone().unwrap_or_default(),
+            received: received.as_secs(),
+        });
+    }
+
+    /// Oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_recent_notifications() {
+        let mut history = History::new();
+        for id in 0..=HISTORY_LENGTH as u32 {
+            let notification = Notification::builder()
+                .id(id)
+                .summary(&format!("notification {}", id))
+                .build();
+            history.add(&notification);
+        }
+        let updated = Notification::builder().id(5).summary("updated").build();
+        history.add(&updated);
+        let entries: Vec<&Entry> = history.entries().collect();
+        assert_eq!(entries.len(), HISTORY_LENGTH);
+        // The oldest was pushed out, and the update moved to the end.
+        assert_eq!(entries[0].id, 1);
+        assert_eq!(entries.last().unwrap().summary, "updated");
+        assert_eq!(entries.iter().filter(|entry| entry.id == 5).count(), 1);
+    }
+}
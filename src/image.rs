@@ -3,19 +3,69 @@ use anyhow::{anyhow, bail, Context, Result};
 use gdk_pixbuf::{Pixbuf, PixbufLoader, PixbufLoaderExt};
 use gtk::prelude::*;
 use gtk::IconTheme;
-use log::warn;
+use tracing::{info, warn};
 use url::Url;
 
-// XXX: This is kinda hacky, isn't it? But I can't think of a better way to do it.
+/// An image compiled into the binary, which can be loaded as `ninomiya:///<name>`. Themes, tests
+/// and scripts can use these without caring where ninomiya is installed, so don't rename or remove
+/// any.
+pub struct Asset {
+    pub name: &'static str,
+    pub description: &'static str,
+    bytes: &'static [u8],
+}
+
+impl Asset {
+    pub fn url(&self) -> Url {
+        Url::parse(&format!("ninomiya:///{}", self.name)).unwrap()
+    }
+}
+
+macro_rules! asset {
+    ($path:literal, $description:literal) => {
+        Asset {
+            name: $path,
+            description: $description,
+            bytes: include_bytes!(concat!("../data/", $path)),
+        }
+    };
+}
 
-const DEMO_IMAGE: &str = "/demo-image.png";
-const DEMO_ICON: &str = "/demo-icon.png";
+pub const ASSETS: &[Asset] = &[
+    asset!("demo-image.png", "The image used by `ninomiya demo`"),
+    asset!("demo-icon.png", "The icon used by `ninomiya demo`"),
+    asset!("assets/avatar-blue.png", "A placeholder avatar"),
+    asset!("assets/avatar-green.png", "A placeholder avatar"),
+    asset!("assets/avatar-orange.png", "A placeholder avatar"),
+    asset!("assets/avatar-purple.png", "A placeholder avatar"),
+    asset!(
+        "assets/category-email.png",
+        "An icon for the email category"
+    ),
+    asset!("assets/category-im.png", "An icon for the im category"),
+    asset!(
+        "assets/category-device.png",
+        "An icon for the device category"
+    ),
+    asset!(
+        "assets/category-network.png",
+        "An icon for the network category"
+    ),
+    asset!(
+        "assets/category-transfer.png",
+        "An icon for the transfer category"
+    ),
+];
+
+fn asset(name: &str) -> Option<&'static Asset> {
+    ASSETS.iter().find(|asset| asset.name == name)
+}
 
 pub fn demo_image_url() -> Url {
-    Url::parse("ninomiya:///demo-image.png").unwrap()
+    asset("demo-image.png").unwrap().url()
 }
 pub fn demo_icon_url() -> Url {
-    Url::parse("ninomiya:///demo-icon.png").unwrap()
+    asset("demo-icon.png").unwrap().url()
 }
 
 pub struct Loader {
@@ -24,6 +74,12 @@ pub struct Loader {
     icon_theme: Option<gtk::IconTheme>,
 }
 
+impl Default for Loader {
+    fn default() -> Self {
+        Loader::new()
+    }
+}
+
 impl Loader {
     /// Constructs a loader that will use the default GTK icon theme.
     pub fn new() -> Self {
@@ -34,6 +90,15 @@ impl Loader {
         Loader::new_with_icon_theme(theme)
     }
 
+    /// Constructs a loader that will use the icon theme with the given name (e.g. `Papirus`),
+    /// regardless of what GTK is configured to use.
+    pub fn new_with_theme_name(theme_name: &str) -> Self {
+        info!("Using icon theme {}", theme_name);
+        let theme = IconTheme::new();
+        theme.set_custom_theme(Some(theme_name));
+        Loader::new_with_icon_theme(Some(theme))
+    }
+
     /// Constructs an image loader that will use the given icon theme. Passing `None` will result
     /// in using no icon theme.
     pub fn new_with_icon_theme(icon_theme: Option<IconTheme>) -> Self {
@@ -42,42 +107,68 @@ impl Loader {
 
     /// Loads the image from the given URI.
     ///
-    /// It must either be a file:// URI, which will be loaded from disk, or
-    /// one of the special constants `DEMO_ICON_URI` and `DEMO_IMAGE_URI`, which will load images
-    /// that are compiled into the binary.
+    /// It must either be a file:// URI, which will be loaded from disk, a base64-encoded data: URI,
+    /// or a ninomiya:// URI naming one of the `ASSETS` compiled into the binary.
     pub fn load_from_url(&self, url: &Url) -> Result<Pixbuf> {
         match url.scheme() {
             "ninomiya" => self.load_builtin(url.path()),
             "file" => Ok(Pixbuf::new_from_file(url.path())?),
+            "data" => load_data_url(url),
             _ => bail!(
-                "Can't handle URLs {}: invalid schema (must be 'file' or 'ninomiya')",
+                "Can't handle URLs {}: invalid schema (must be 'file', 'data', or 'ninomiya')",
                 url
             ),
         }
     }
 
-    /// Loads the icon with the given name.
-    pub fn load_from_icon(&self, icon_name: &str, size: i32) -> Result<Pixbuf> {
-        self.icon_theme
+    /// Loads the icon with the given name. If the theme gives us a symbolic icon, it's colored
+    /// with `fg` instead of using its own colors, so that it matches the surrounding text.
+    pub fn load_from_icon(&self, icon_name: &str, size: i32, fg: &gdk::RGBA) -> Result<Pixbuf> {
+        let info = self
+            .icon_theme
             .as_ref()
             .context("no icon theme specified")?
-            .load_icon(icon_name, size, gtk::IconLookupFlags::FORCE_SIZE)?
-            .with_context(|| anyhow!("icon {} not found", icon_name))
+            .lookup_icon(icon_name, size, gtk::IconLookupFlags::FORCE_SIZE)
+            .with_context(|| anyhow!("icon {} not found", icon_name))?;
+        if info.is_symbolic() {
+            let (pixbuf, _was_symbolic) = info.load_symbolic(fg, None, None, None)?;
+            Ok(pixbuf)
+        } else {
+            Ok(info.load_icon()?)
+        }
     }
 
     fn load_builtin(&self, path: &str) -> Result<Pixbuf> {
-        let image_bytes: &[u8] = match path {
-            DEMO_IMAGE => include_bytes!("../data/demo-image.png"),
-            DEMO_ICON => include_bytes!("../data/demo-icon.png"),
-            _ => bail!("Unknown builtin image {}", path),
-        };
-        let loader = PixbufLoader::new();
-        loader
-            .write(image_bytes)
-            .context("failed to write in-memory bytes to  loader")?;
-        loader.close().context("failed to close loader")?;
-        loader.get_pixbuf().context("Pixbuf didn't finish loading")
+        match asset(path.trim_start_matches('/')) {
+            Some(asset) => load_bytes(asset.bytes),
+            None => bail!("Unknown builtin image {}", path),
+        }
+    }
+}
+
+/// Decodes an in-memory image in any format gdk-pixbuf understands.
+fn load_bytes(image_bytes: &[u8]) -> Result<Pixbuf> {
+    let loader = PixbufLoader::new();
+    loader
+        .write(image_bytes)
+        .context("failed to write in-memory bytes to  loader")?;
+    loader.close().context("failed to close loader")?;
+    loader.get_pixbuf().context("Pixbuf didn't finish loading")
+}
+
+/// Loads a URL like `data:image/png;base64,iVBOR...`, which some Electron apps send instead of a
+/// path.
+fn load_data_url(url: &Url) -> Result<Pixbuf> {
+    // For data: URLs, the 'path' is everything after the scheme.
+    let (metadata, data) = match url.path().find(',') {
+        Some(index) => (&url.path()[..index], &url.path()[index + 1..]),
+        None => bail!("data URL is missing a ','"),
+    };
+    if !metadata.ends_with(";base64") {
+        bail!("Only base64-encoded data URLs are supported");
     }
+    let bytes = base64::decode(data).context("data URL isn't valid base64")?;
+    load_bytes(&bytes)
 }
 
 #[cfg(test)]
@@ -99,6 +190,12 @@ mod tests {
             .context("failed to load demo image")?;
         assert_eq!(demo_image.get_width(), 200);
         assert_eq!(demo_image.get_height(), 200);
+
+        for asset in ASSETS {
+            loader
+                .load_from_url(&asset.url())
+                .with_context(|| format!("failed to load {}", asset.name))?;
+        }
         Ok(())
     }
 
@@ -120,6 +217,18 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    pub fn load_from_data_url() -> Result<()> {
+        let url = Url::parse(
+            "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAIAAAADCAIAAAA2iEnWAAAAEElEQVR4nG\
+             P4z8AARAwoFABE0AX7pM/egAAAAABJRU5ErkJggg==",
+        )?;
+        let image = Loader::new_with_icon_theme(None).load_from_url(&url)?;
+        assert_eq!(image.get_width(), 2);
+        assert_eq!(image.get_height(), 3);
+        Ok(())
+    }
+
     #[test]
     pub fn load_nonexistent_builtin() -> Result<()> {
         let loader = Loader::new_with_icon_theme(None);
@@ -0,0 +1,195 @@
+//! An append-only log of every notification we receive and how it was resolved, written as one JSON
+//! object per line so that it can be piped into external tooling.
+
+use crate::lifecycle::{Lifecycle, Resolution};
+use crate::server::Notification;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Record<'a> {
+    Received {
+        id: u32,
+        timestamp_ms: u64,
+        app_name: Option<&'a str>,
+        summary: &'a str,
+        body: Option<&'a str>,
+    },
+    Resolved {
+        id: u32,
+        timestamp_ms: u64,
+        resolution: Resolution,
+        /// The key of the action the user invoked, if any.
+        action: Option<&'a str>,
+        /// How long the notification was around for. `None` if we never saw it arrive, which can
+        /// happen if the daemon restarted.
+        displayed_ms: Option<u64>,
+        /// How long it was on screen, counting from before a restart. `None` if it never made it
+        /// there, say because it was queued or dropped.
+        shown_ms: Option<u64>,
+    },
+}
+
+pub struct JsonLog {
+    path: PathBuf,
+    /// Once the file grows past this many bytes, it's moved to `<path>.1` and a fresh one started.
+    max_size: u64,
+    file: File,
+}
+
+fn open_for_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open JSON log {:?}", path))
+}
+
+fn timestamp_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl JsonLog {
+    pub fn open<P: AsRef<Path>>(path: P, max_size: u64) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        Ok(JsonLog {
+            file: open_for_append(&path)?,
+            path,
+            max_size,
+        })
+    }
+
+    /// Records that we received the given notification.
+    pub fn received(&mut self, notification: &Notification) {
+        let received = notification
+            .lifecycle
+            .received
+            .unwrap_or_else(SystemTime::now);
+        self.write_or_warn(&Record::Received {
+            id: notification.id,
+            timestamp_ms: timestamp_ms(received),
+            app_name: notification.application_name.as_deref(),
+            summary: &notification.summary,
+            body: notification.body.as_deref(),
+        });
+    }
+
+    /// Records how the notification with the given ID was resolved. Its lifecycle should have
+    /// been resolved already; if it somehow wasn't, it's logged as closed.
+    pub fn resolved(&mut self, id: u32, lifecycle: &Lifecycle) {
+        let record = Record::Resolved {
+            id,
+            timestamp_ms: timestamp_ms(SystemTime::now()),
+            resolution: lifecycle.resolution.unwrap_or(Resolution::Closed),
+            action: lifecycle.action.as_deref(),
+            displayed_ms: lifecycle.age().map(|age| age.as_millis() as u64),
+            shown_ms: lifecycle
+                .shown_at
+                .map(|_| lifecycle.shown_for().as_millis() as u64),
+        };
+        self.write_or_warn(&record);
+    }
+
+    fn write_or_warn(&mut self, record: &Record) {
+        if let Err(err) = self.write(record) {
+            warn!("Failed to write to JSON log {:?}: {:?}", self.path, err);
+        }
+    }
+
+    fn write(&mut self, record: &Record) -> Result<()> {
+        if self.file.metadata()?.len() >= self.max_size {
+            self.rotate()?;
+        }
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        let mut rotated = OsString::from(&self.path);
+        rotated.push(".1");
+        fs::rename(&self.path, &rotated)
+            .with_context(|| format!("failed to rotate JSON log {:?}", self.path))?;
+        self.file = open_for_append(&self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::CloseReason;
+    use std::time::Instant;
+
+    fn notification(id: u32) -> Notification {
+        Notification::builder()
+            .id(id)
+            .application_name("galax")
+            .summary("summary")
+            .received_at(Instant::now())
+            .build()
+    }
+
+    fn read_lines(path: &Path) -> Vec<serde_json::Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn records_resolution() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("log.jsonl");
+        let mut log = JsonLog::open(&path, u64::max_value())?;
+        let (expired, replied) = (notification(1), notification(2));
+        log.received(&expired);
+        log.received(&replied);
+        let mut lifecycle = expired.lifecycle.clone();
+        lifecycle.shown();
+        lifecycle.resolve(CloseReason::Expired);
+        log.resolved(1, &lifecycle);
+        let mut lifecycle = replied.lifecycle.clone();
+        lifecycle.action_invoked("reply");
+        lifecycle.resolve(CloseReason::Dismissed);
+        log.resolved(2, &lifecycle);
+
+        let lines = read_lines(&path);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["event"], "received");
+        assert_eq!(lines[0]["app_name"], "galax");
+        assert_eq!(lines[2]["event"], "resolved");
+        assert_eq!(lines[2]["resolution"], "expired");
+        assert!(lines[2]["shown_ms"].is_u64());
+        assert!(lines[3]["shown_ms"].is_null());
+        assert_eq!(lines[3]["resolution"], "action");
+        assert_eq!(lines[3]["action"], "reply");
+        Ok(())
+    }
+
+    #[test]
+    fn rotates_when_too_big() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("log.jsonl");
+        let mut log = JsonLog::open(&path, 1)?;
+        log.received(&notification(1));
+        log.received(&notification(2));
+
+        let lines = read_lines(&path);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["id"], 2);
+        assert_eq!(read_lines(&dir.path().join("log.jsonl.1")).len(), 1);
+        Ok(())
+    }
+}
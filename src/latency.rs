@@ -0,0 +1,156 @@
+//! Measures how long notifications take to get through the pipeline: from the moment `Notify` is
+//! called to when the GUI picks the event up (dispatch), and to when its window is shown. The
+//! numbers are available from the control interface's `GetStats` and `demo --stress`.
+
+use crate::lifecycle::Lifecycle;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How many of the most recent samples to keep for each stage.
+pub const MAX_SAMPLES: usize = 1000;
+
+/// One stage's recent samples.
+#[derive(Debug, Default)]
+struct Samples(VecDeque<Duration>);
+
+impl Samples {
+    fn record(&mut self, latency: Duration) {
+        if self.0.len() == MAX_SAMPLES {
+            self.0.pop_front();
+        }
+        self.0.push_back(latency);
+    }
+
+    /// The `p`th percentile (from 0 to 100) of the samples, by nearest rank.
+    fn percentile(&self, p: u32) -> Option<Duration> {
+        let mut sorted: Vec<Duration> = self.0.iter().copied().collect();
+        sorted.sort();
+        let rank = (sorted.len() * p as usize + 99) / 100;
+        sorted.get(rank.max(1) - 1).copied()
+    }
+}
+
+/// The p50 and p95 latencies for each stage, or `None` if there weren't any samples.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// How many notifications have been shown, up to the number of samples kept.
+    pub samples: usize,
+    pub dispatch_p50: Option<Duration>,
+    pub dispatch_p95: Option<Duration>,
+    pub show_p50: Option<Duration>,
+    pub show_p95: Option<Duration>,
+}
+
+impl Summary {
+    /// The summary as `GetStats` returns it, in milliseconds. Stages without samples are left out.
+    pub fn to_map(&self) -> HashMap<String, f64> {
+        let mut map = HashMap::new();
+        map.insert("samples".to_owned(), self.samples as f64);
+        let stages = [
+            ("dispatch_p50_ms", self.dispatch_p50),
+            ("dispatch_p95_ms", self.dispatch_p95),
+            ("show_p50_ms", self.show_p50),
+            ("show_p95_ms", self.show_p95),
+        ];
+        for (key, latency) in stages.iter() {
+            if let Some(latency) = latency {
+                map.insert((*key).to_owned(), latency.as_secs_f64() * 1000.0);
+            }
+        }
+        map
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |latency: Option<Duration>| {
+            latency.map_or_else(
+                || "-".to_owned(),
+                |latency| format!("{:.2}ms", latency.as_secs_f64() * 1000.0),
+            )
+        };
+        write!(
+            f,
+            "{} samples; dispatch p50 {}, p95 {}; show p50 {}, p95 {}",
+            self.samples,
+            ms(self.dispatch_p50),
+            ms(self.dispatch_p95),
+            ms(self.show_p50),
+            ms(self.show_p95)
+        )
+    }
+}
+
+/// Latencies recorded by the GUI thread and read by the server thread.
+#[derive(Debug, Default)]
+pub struct Latencies {
+    /// Receipt to dispatch, and receipt to show.
+    stages: Mutex<(Samples, Samples)>,
+}
+
+impl Latencies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that a notification was picked up by the GUI. Ones that didn't come over DBus, or
+    /// came before a restart, don't count.
+    pub fn dispatched(&self, lifecycle: &Lifecycle) {
+        if let Some(received_at) = lifecycle.received_at {
+            self.stages.lock().unwrap().0.record(received_at.elapsed());
+        }
+    }
+
+    /// Records that a notification has had its window shown.
+    pub fn shown(&self, lifecycle: &Lifecycle) {
+        if let Some(received_at) = lifecycle.received_at {
+            self.stages.lock().unwrap().1.record(received_at.elapsed());
+        }
+    }
+
+    pub fn summary(&self) -> Summary {
+        let stages = self.stages.lock().unwrap();
+        let (dispatch, show) = &*stages;
+        Summary {
+            samples: show.0.len(),
+            dispatch_p50: dispatch.percentile(50),
+            dispatch_p95: dispatch.percentile(95),
+            show_p50: show.percentile(50),
+            show_p95: show.percentile(95),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_percentiles() {
+        let mut samples = Samples::default();
+        assert_eq!(samples.percentile(50), None);
+        for ms in (1..=100).rev() {
+            samples.record(Duration::from_millis(ms));
+        }
+        assert_eq!(samples.percentile(50), Some(Duration::from_millis(50)));
+        assert_eq!(samples.percentile(95), Some(Duration::from_millis(95)));
+        assert_eq!(samples.percentile(0), Some(Duration::from_millis(1)));
+
+        for _ in 0..MAX_SAMPLES {
+            samples.record(Duration::from_millis(7));
+        }
+        assert_eq!(samples.0.len(), MAX_SAMPLES);
+        assert_eq!(samples.percentile(95), Some(Duration::from_millis(7)));
+
+        let summary = Summary {
+            samples: 1,
+            show_p50: Some(Duration::from_micros(2500)),
+            ..Summary::default()
+        };
+        let map = summary.to_map();
+        assert_eq!(map["show_p50_ms"], 2.5);
+        assert!(!map.contains_key("dispatch_p50_ms"));
+    }
+}
@@ -0,0 +1,60 @@
+//! ninomiya, a notification daemon. The binary is a thin layer over this library, which can also
+//! be used to embed the daemon (or just its GUI) in something else.
+
+pub(crate) mod accent;
+pub mod action_command;
+pub mod app_filter;
+pub mod app_icon;
+pub(crate) mod appearance;
+pub mod attention;
+pub(crate) mod bus;
+pub mod calendar;
+pub mod client;
+pub mod config;
+pub(crate) mod control;
+pub(crate) mod css;
+pub(crate) mod curl;
+pub mod dbus_codegen;
+pub mod demo;
+pub mod digest;
+pub(crate) mod docks;
+pub mod doctor;
+pub(crate) mod emoji;
+pub mod error;
+pub mod focus;
+pub mod forward;
+pub mod generate;
+pub mod grouping;
+pub mod gui;
+pub mod hints;
+pub mod history;
+pub mod image;
+pub mod json_log;
+pub mod latency;
+pub mod lifecycle;
+pub mod logging;
+pub mod low_power;
+pub(crate) mod markup;
+pub mod mirror;
+pub(crate) mod mpris;
+pub mod power;
+pub mod processor;
+pub mod push;
+pub mod raise;
+pub mod router;
+pub mod schedule;
+pub mod screencast;
+pub mod script;
+pub mod seat;
+pub mod server;
+pub(crate) mod sound;
+pub mod spam;
+pub mod state;
+pub mod theme_lint;
+pub mod timer;
+pub mod watchdog;
+
+#[cfg(test)]
+mod gtk_test_runner;
+#[cfg(test)]
+mod snapshot;
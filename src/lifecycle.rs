@@ -0,0 +1,143 @@
+//! Where a notification is in its life: when it arrived, when it made it to the screen, and how it
+//! went away. The server keeps one of these for every notification it's tracking, and a copy goes
+//! out with the notification itself, so the GUI, the JSON log and saved state all work from the
+//! same times instead of each keeping their own.
+
+use crate::server::CloseReason;
+use serde::Serialize;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How a notification went away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    Expired,
+    Dismissed,
+    Closed,
+    /// The user invoked one of its actions before it closed.
+    Action,
+}
+
+impl Resolution {
+    /// Its name in the JSON log and `GetStats`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Resolution::Expired => "expired",
+            Resolution::Dismissed => "dismissed",
+            Resolution::Closed => "closed",
+            Resolution::Action => "action",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Lifecycle {
+    /// When `Notify` was called for it, for measuring latency. `None` for notifications that didn't
+    /// come over DBus, or came before a restart.
+    pub received_at: Option<Instant>,
+    /// The same, by the wall clock, for records that outlive us.
+    pub received: Option<SystemTime>,
+    /// When its window was first shown. This survives restarts, so a restored notification only
+    /// stays up for what's left of its timeout.
+    pub shown_at: Option<SystemTime>,
+    /// The key of the action the user invoked, if any.
+    pub action: Option<String>,
+    /// How it went away, once it has.
+    pub resolution: Option<Resolution>,
+}
+
+impl Lifecycle {
+    /// The lifecycle of a notification that just arrived, at `now`.
+    pub fn received(now: Instant) -> Self {
+        Lifecycle {
+            received_at: Some(now),
+            received: Some(SystemTime::now()),
+            ..Lifecycle::default()
+        }
+    }
+
+    /// The lifecycle of a notification saved before a restart, so that its age carries on from
+    /// when it first arrived.
+    pub fn restored(received: Option<SystemTime>, shown_at: Option<SystemTime>) -> Self {
+        Lifecycle {
+            received,
+            shown_at,
+            ..Lifecycle::default()
+        }
+    }
+
+    /// Records that its window was shown. Restored notifications keep the time they were first
+    /// shown.
+    pub fn shown(&mut self) -> SystemTime {
+        *self.shown_at.get_or_insert_with(SystemTime::now)
+    }
+
+    pub fn action_invoked(&mut self, key: &str) {
+        self.action = Some(key.to_owned());
+    }
+
+    /// Records that it was closed, and why.
+    pub fn resolve(&mut self, reason: CloseReason) -> Resolution {
+        let resolution = match (&self.action, reason) {
+            (Some(_), _) => Resolution::Action,
+            (None, CloseReason::Expired) => Resolution::Expired,
+            (None, CloseReason::Dismissed) => Resolution::Dismissed,
+            (None, CloseReason::Closed) => Resolution::Closed,
+        };
+        self.resolution = Some(resolution);
+        resolution
+    }
+
+    /// How long it's been around since it arrived, counting from before a restart. `None` if we
+    /// don't know when that was.
+    pub fn age(&self) -> Option<Duration> {
+        match (self.received_at, self.received) {
+            (Some(received_at), _) => Some(received_at.elapsed()),
+            (None, Some(received)) => SystemTime::now().duration_since(received).ok(),
+            (None, None) => None,
+        }
+    }
+
+    /// How long it's been on screen, counting from before a restart. Zero if it never was.
+    pub fn shown_for(&self) -> Duration {
+        self.shown_at
+            .and_then(|shown_at| SystemTime::now().duration_since(shown_at).ok())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_a_notification() {
+        let mut lifecycle = Lifecycle::received(Instant::now());
+        assert_eq!(lifecycle.shown_for(), Duration::default());
+        let restored = SystemTime::now() - Duration::from_secs(5);
+        lifecycle.shown_at = Some(restored);
+        // Showing it again after a restart doesn't reset the clock.
+        assert_eq!(lifecycle.shown(), restored);
+        assert!(lifecycle.shown_for() >= Duration::from_secs(5));
+
+        assert_eq!(
+            lifecycle.clone().resolve(CloseReason::Expired),
+            Resolution::Expired
+        );
+        lifecycle.action_invoked("reply");
+        assert_eq!(
+            lifecycle.resolve(CloseReason::Dismissed),
+            Resolution::Action
+        );
+        assert_eq!(lifecycle.resolution, Some(Resolution::Action));
+    }
+
+    #[test]
+    fn restores_age() {
+        let received = SystemTime::now() - Duration::from_secs(60);
+        let lifecycle = Lifecycle::restored(Some(received), None);
+        assert_eq!(lifecycle.received_at, None);
+        assert!(lifecycle.age().unwrap() >= Duration::from_secs(60));
+        assert_eq!(Lifecycle::restored(None, None).age(), None);
+    }
+}
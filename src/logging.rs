@@ -0,0 +1,78 @@
+//! Sets up `tracing` based on the command line and the config file.
+
+use crate::config::{Config, LogConfig};
+use anyhow::{Context, Result};
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, EnvFilter};
+
+/// The default level for the given number of `-v` flags.
+fn level_for_verbosity(verbosity: u8) -> &'static str {
+    match verbosity {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    }
+}
+
+/// Builds the filter to use. `RUST_LOG` takes precedence over everything else; otherwise the level
+/// comes from the verbosity, refined by the per-module directives in the config.
+fn filter(verbosity: u8, config: &LogConfig) -> Result<EnvFilter> {
+    if std::env::var_os(EnvFilter::DEFAULT_ENV).is_some() {
+        return Ok(EnvFilter::from_default_env());
+    }
+    let mut directives = level_for_verbosity(verbosity).to_owned();
+    if let Some(filter) = &config.filter {
+        directives.push(',');
+        directives.push_str(filter);
+    }
+    EnvFilter::try_new(&directives).with_context(|| format!("invalid log filter '{}'", directives))
+}
+
+/// Installs the global subscriber. Logs always go to stderr, and additionally to the journal and/or
+/// a file if the config asks for it. `log_file` overrides the config's log file.
+///
+/// Spans are logged when they close, which gives you how long e.g. handling a `Notify` call took.
+pub fn init(verbosity: u8, config: &LogConfig, log_file: Option<&Path>) -> Result<()> {
+    let journald = if config.journald {
+        Some(tracing_journald::layer().context("couldn't connect to journald")?)
+    } else {
+        None
+    };
+    let log_file = match (log_file, &config.file) {
+        (Some(path), _) => Some(path.to_owned()),
+        (None, Some(path)) => Some(Config::config_dir()?.join(path)),
+        (None, None) => None,
+    };
+    let file = match log_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("couldn't open log file {:?}", path))?;
+            Some(
+                fmt::layer()
+                    .with_ansi(false)
+                    .with_span_events(FmtSpan::CLOSE)
+                    .with_writer(Mutex::new(file)),
+            )
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(filter(verbosity, config)?)
+        .with(
+            fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_span_events(FmtSpan::CLOSE),
+        )
+        .with(journald)
+        .with(file)
+        .try_init()
+        .context("failed to install log subscriber")
+}
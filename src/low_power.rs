@@ -0,0 +1,59 @@
+//! Low-power mode, for laptops on battery. While it's on, the GUI skips sounds, body images and
+//! GTK's animations, and the server wakes up less often. It's turned on by `low_power` in the
+//! config, either for good or (with `on_battery`) whenever UPower says we're running on battery.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// How long the server waits for DBus messages before getting on with the rest of its loop.
+pub const LOOP_INTERVAL: Duration = Duration::from_millis(50);
+/// The same, in low-power mode. Signals from the GUI can take this long to reach the apps.
+pub const LOW_POWER_LOOP_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Whether low-power mode is on, shared between the GUI, the server and the battery watcher.
+#[derive(Debug, Default)]
+pub struct LowPower {
+    on: AtomicBool,
+}
+
+impl LowPower {
+    pub fn new(on: bool) -> Self {
+        LowPower {
+            on: AtomicBool::new(on),
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on.load(Ordering::Relaxed)
+    }
+
+    /// Turns low-power mode on or off, returning whether that changed anything.
+    pub fn set(&self, on: bool) -> bool {
+        self.on.swap(on, Ordering::Relaxed) != on
+    }
+
+    /// How long the server should wait for messages on each turn of its loop.
+    pub fn loop_interval(&self) -> Duration {
+        if self.is_on() {
+            LOW_POWER_LOOP_INTERVAL
+        } else {
+            LOOP_INTERVAL
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slows_the_loop_down() {
+        let low_power = LowPower::new(false);
+        assert_eq!(low_power.loop_interval(), LOOP_INTERVAL);
+        assert!(low_power.set(true));
+        assert!(!low_power.set(true));
+        assert_eq!(low_power.loop_interval(), LOW_POWER_LOOP_INTERVAL);
+        assert!(low_power.set(false));
+        assert!(!low_power.is_on());
+    }
+}
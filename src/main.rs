@@ -1,33 +1,63 @@
-mod client;
-mod config;
-mod dbus_codegen;
-mod demo;
-mod gui;
-mod hints;
-mod image;
-mod server;
-
-#[cfg(test)]
-mod gtk_test_runner;
-
-use crate::config::Config;
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use daemonize::Daemonize;
 use dbus::blocking::LocalConnection;
-use log::{info, warn};
-use std::sync::mpsc;
+use ninomiya::config::{Config, LogConfig, LowPowerMode, ScreenShareMode};
+use ninomiya::{
+    action_command, app_filter, app_icon, attention, calendar, client, demo, digest, doctor, focus,
+    forward, generate, grouping, gui, image, json_log, logging, mirror, power, processor, push,
+    raise, router, screencast, script, seat, server, spam, state, theme_lint, timer, watchdog,
+};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::{mpsc, Arc};
 use std::thread;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
+use tracing::{error, info, warn};
 
 static DBUS_NAME: &str = "org.freedesktop.Notifications";
 static DBUS_TESTING_NAME: &str = "org.freedesktop.NotificationsNinomiyaTesting";
+/// How many times in a row the server thread gets restarted before we give up.
+const MAX_SERVER_RESTARTS: u32 = 5;
+/// A server that ran at least this long before dying wasn't crashing in a loop, so its restart
+/// starts the count over.
+const SERVER_RESTART_RESET: Duration = Duration::from_secs(10 * 60);
+/// The system bus refuses to let us own a name unless a policy allows it, so we ship an example.
+static SYSTEM_BUS_POLICY: &str = include_str!("../data/system-bus-policy.conf");
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "example", about = "A beautiful notification daemon.")]
+#[structopt(name = "ninomiya", about = "A beautiful notification daemon.")]
 struct Opt {
+    /// Log more. Pass once for info, twice for debug, and three times for trace. Overridden by
+    /// `RUST_LOG`.
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+
     /// If true, uses a separate DBus name. This is mostly useful for development purposes.
     #[structopt(short, long)]
     testing: bool,
 
+    /// If true, serves notifications on the system bus instead of the session bus. Meant for
+    /// kiosks and embedded devices that don't run a session bus.
+    #[structopt(long)]
+    system: bool,
+
+    /// Also re-sends every notification to the daemon owning this DBus name on the same bus, so
+    /// that ninomiya can run alongside another notification daemon.
+    #[structopt(long)]
+    forward_to: Option<String>,
+
+    /// Also appends logs to this file, overriding the config file's `log.file`.
+    #[structopt(long, parse(from_os_str))]
+    log_file: Option<PathBuf>,
+
+    /// Forks into the background once started, so that ninomiya can be launched from e.g.
+    /// `.xinitrc` without keeping a terminal around. Use with `--log-file` if you want logs.
+    #[structopt(short, long)]
+    daemonize: bool,
+
     #[structopt(subcommand)]
     command: Option<Command>,
 }
@@ -35,68 +65,558 @@ struct Opt {
 #[derive(Debug, StructOpt)]
 enum Command {
     Notify(client::NotifyOpt),
-    Demo,
+    Demo(demo::DemoOpt),
+    /// Lets you pick an action on the newest notification by pressing its number. Bind this to a
+    /// key chord in your window manager; needs `action_shortcuts` in the config.
+    SelectAction,
+    /// Shows the notification you last dismissed again, if it was within `undo_close_timeout`.
+    /// Bind this to a key chord too.
+    UndoClose,
+    /// Pauses notifications, holding them until they're unpaused.
+    Pause(client::PauseOpt),
+    /// Turns on do-not-disturb, dropping notifications until it's turned off.
+    Mute(client::PauseOpt),
+    /// Switches the running daemon's theme, or lists the themes in the config directory.
+    Theme(client::ThemeOpt),
+    /// Shows a progress notification, or updates one, and prints its ID.
+    Progress(client::ProgressOpt),
+    /// Replaces a progress notification with one saying it's done.
+    CompleteProgress(client::CompleteProgressOpt),
+    /// Prints the notifications that came in lately, including the ones that were rolled up,
+    /// collected into digests, or never got a popup.
+    History(client::HistoryOpt),
+    /// Checks that the daemon is running and answering, and prints its version and how many
+    /// seconds it's been up. Exits with an error if it isn't.
+    Ping(client::PingOpt),
+    /// Mirrors every notification to `mirror.send_to` in the config without showing anything. For
+    /// machines without a display, like build servers.
+    Relay,
+    /// Checks the config file and themes for mistakes, without starting the daemon.
+    CheckConfig,
+    /// Checks for common problems with the environment, like another notification daemon running
+    /// or a missing compositor, and suggests fixes.
+    Doctor,
+    /// Lists the images built into ninomiya, which can be used anywhere an image path can as
+    /// `ninomiya:///<name>`.
+    ListAssets,
+    /// Prints a DBus policy that lets the current user run ninomiya with `--system`.
+    SystemPolicy,
+    /// Prints shell completions or a systemd unit.
+    Generate(generate::GenerateOpt),
+}
+
+/// The example system bus policy, filled in with the current user.
+fn system_bus_policy() -> String {
+    let user = std::env::var("USER").unwrap_or_else(|_| "root".to_owned());
+    SYSTEM_BUS_POLICY.replace("{user}", &user)
+}
+
+/// Runs a server that only mirrors notifications to another machine.
+fn relay(config: &Config, dbus_name: &str, system: bool) -> Result<()> {
+    let mirror = mirror::Mirror::new(&config.mirror).context("couldn't set up mirroring")?;
+    let server = server::NotifyServer::new(|_| ()).mirror_to(mirror);
+    // There's no GUI to send signals and nothing else to send notifications, but the server treats
+    // its channels closing as an error, so keep the senders around.
+    let (_signal_tx, signal_rx) = mpsc::channel();
+    let (_local_tx, local_rx) = mpsc::channel();
+    let connection = if system {
+        LocalConnection::new_system()
+    } else {
+        LocalConnection::new_session()
+    }
+    .context("couldn't connect to dbus")?;
+    server.run(dbus_name, connection, &signal_rx, &local_rx)
+}
+
+/// Loads the config and lints the themes it points to, printing any problems. Fails if there were
+/// any, so that it can be used in scripts.
+fn check_config(config: Result<Config>) -> Result<()> {
+    let config = config.context("the config file is invalid")?;
+    let file = Config::file()?;
+    // Not having a config file is fine too; the defaults are used.
+    if !file.exists() {
+        println!(
+            "{}: doesn't exist, so the defaults are used",
+            file.display()
+        );
+    }
+    let mut problems = 0;
+    let themes = std::iter::once(config.full_theme_path()?).chain(config.full_theme_path_dark()?);
+    for theme_path in themes {
+        // Not having a theme is fine; the defaults are used.
+        if !theme_path.exists() {
+            println!(
+                "{}: doesn't exist, so it won't be loaded",
+                theme_path.display()
+            );
+            continue;
+        }
+        for warning in theme_lint::lint_file(&theme_path)? {
+            println!("{}: {}", theme_path.display(), warning);
+            problems += 1;
+        }
+    }
+    if problems > 0 {
+        bail!("found {} problem(s)", problems);
+    }
+    println!("No problems found.");
+    Ok(())
 }
 
 fn main() -> Result<()> {
-    env_logger::builder().format_module_path(true).init();
     let opt = Opt::from_args();
+    // We want the config to decide where logs go, so we can't log anything while loading it.
+    let config = Config::load();
+    // Resolve this before daemonizing, since that changes the working directory.
+    let log_file = opt
+        .log_file
+        .as_ref()
+        .map(|path| std::env::current_dir().map(|cwd| cwd.join(path)))
+        .transpose()?;
+    if opt.daemonize && opt.command.is_none() {
+        Daemonize::new()
+            // We load the default stylesheet relative to the working directory.
+            .working_directory(std::env::current_dir()?)
+            .start()
+            .context("failed to daemonize")?;
+    }
+    logging::init(
+        opt.verbose,
+        config
+            .as_ref()
+            .map(|c| &c.log)
+            .unwrap_or(&LogConfig::default()),
+        log_file.as_deref(),
+    )?;
+    // Only the daemon and the testing name care which session we're in.
+    let session = if opt.testing || matches!(opt.command, None | Some(Command::Demo(_))) {
+        seat::current_session().unwrap_or_else(|err| {
+            warn!("Couldn't find our login session: {:?}", err);
+            None
+        })
+    } else {
+        None
+    };
     let dbus_name = if opt.testing {
-        DBUS_TESTING_NAME
+        seat::testing_name(DBUS_TESTING_NAME, session.as_ref(), seat::several_seats())
     } else {
-        DBUS_NAME
+        DBUS_NAME.to_owned()
     };
+    let dbus_name = dbus_name.as_str();
 
     if let Some(Command::Notify(notify_opt)) = opt.command {
         return client::notify(dbus_name, notify_opt);
     }
+    if let Some(Command::SelectAction) = opt.command {
+        return client::select_action(dbus_name, opt.system);
+    }
+    if let Some(Command::UndoClose) = opt.command {
+        return client::undo_close(dbus_name, opt.system);
+    }
+    if let Some(Command::Pause(pause_opt)) = opt.command {
+        return client::pause(dbus_name, opt.system, pause_opt);
+    }
+    if let Some(Command::Mute(mute_opt)) = opt.command {
+        return client::mute(dbus_name, opt.system, mute_opt);
+    }
+    if let Some(Command::Theme(theme_opt)) = opt.command {
+        return client::theme(dbus_name, opt.system, theme_opt);
+    }
+    if let Some(Command::Progress(progress_opt)) = opt.command {
+        return client::progress(dbus_name, opt.system, progress_opt);
+    }
+    if let Some(Command::CompleteProgress(complete_opt)) = opt.command {
+        return client::complete_progress(dbus_name, opt.system, complete_opt);
+    }
+    if let Some(Command::History(history_opt)) = opt.command {
+        return client::history(dbus_name, opt.system, history_opt);
+    }
+    if let Some(Command::Ping(ping_opt)) = opt.command {
+        return client::ping(dbus_name, opt.system, ping_opt);
+    }
+    if let Some(Command::CheckConfig) = opt.command {
+        return check_config(config);
+    }
+    if let Some(Command::Doctor) = opt.command {
+        return doctor::run(dbus_name, opt.system, &config);
+    }
+    if let Some(Command::ListAssets) = opt.command {
+        for asset in image::ASSETS {
+            println!("{}\t{}", asset.url(), asset.description);
+        }
+        return Ok(());
+    }
+    if let Some(Command::SystemPolicy) = opt.command {
+        print!("{}", system_bus_policy());
+        return Ok(());
+    }
+    if let Some(Command::Generate(generate_opt)) = opt.command {
+        let mut daemon_args = vec![];
+        if opt.verbose > 0 {
+            daemon_args.push(format!("-{}", "v".repeat(opt.verbose.into())));
+        }
+        if opt.testing {
+            daemon_args.push("--testing".to_owned());
+        }
+        if opt.system {
+            daemon_args.push("--system".to_owned());
+        }
+        if let Some(forward_to) = &opt.forward_to {
+            daemon_args.push(format!("--forward-to={}", forward_to));
+        }
+        if let Some(log_file) = &opt.log_file {
+            // The unit doesn't run in this directory.
+            let log_file = std::env::current_dir()?.join(log_file);
+            daemon_args.push(format!("--log-file={}", log_file.display()));
+        }
+        return generate::generate(Opt::clap(), dbus_name, &daemon_args, generate_opt);
+    }
 
     info!("Starting up.");
-    let config = Config::load().unwrap_or_else(|err| {
+    let config = config.unwrap_or_else(|err| {
         warn!("Failed to load config ({:?}); falling back to default", err);
         Config::default()
     });
 
+    if let Some(session) = &session {
+        seat::bind_environment(session);
+    }
+
+    if let Some(Command::Relay) = opt.command {
+        info!("Relaying notifications to {:?}", config.mirror.send_to);
+        return relay(&config, dbus_name, opt.system);
+    }
+    if let Some(session) = &session {
+        seat::check_display(session)?;
+    }
+
     let (tx, rx) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
     let (signal_tx, signal_rx) = mpsc::channel();
-    let theme_path = config.full_theme_path()?;
-    let gui = gui::Gui::new(config, tx.clone(), signal_tx);
-    gui::add_css("data/style.css")?;
-    if theme_path.exists() {
-        gui::add_css(theme_path)?;
+    let json_log_path = config.full_json_log_path()?;
+    let json_log_max_size = config.json_log_max_size;
+    let state_path = if config.restore_notifications {
+        Some(Config::state_path(dbus_name)?)
     } else {
-        warn!("Theme path {:?} doesn't exist, not loading it", theme_path);
-    }
+        None
+    };
+    let mute_path = Config::mute_path(dbus_name)?;
+    let infer_app_icons = config.infer_app_icons;
+    let battery_notifications = config.battery_notifications;
+    let low_power_mode = config.low_power;
+    let screen_share = config.screen_share;
+    let dnd_when_focused = config.dnd_when_focused.clone();
+    let dnd_digest = config.dnd_digest;
+    let pause_digest = config.pause_digest;
+    let calendar_config = config.calendar.clone();
+    let coalesce_close_notify = config.coalesce_close_notify;
+    let advertise_extensions = config.advertise_extensions;
+    let queue_memory_limit = config.queue_memory_limit;
+    let blocked_apps = config.blocked_apps.clone();
+    let allowed_apps = config.allowed_apps.clone();
+    let strict_allowlist = config.strict_allowlist;
+    let redact_body = config.redact_body.clone();
+    let mirror_config = config.mirror.clone();
+    let push_config = config.push.clone();
+    let script_path = config.full_script_path()?;
+    let action_commands = config.action_commands.clone();
+    let raise_apps = config.raise_apps.clone();
+    let attention_apps = config.attention_apps.clone();
+    let processors = config.processors.clone();
+    let processor_timeout = Duration::from_millis(config.processor_timeout);
+    let gui_watchdog_timeout = Duration::from_millis(config.gui_watchdog_timeout);
+    let gui_watchdog_exit = config.gui_watchdog_exit;
+    let group_threshold = config.group_threshold;
+    let digests = config.digests.clone();
+    let group_window = config.group_window;
+    let spam_threshold = config.spam_threshold;
+    let spam_window = config.spam_window;
+    let spam_mute = config.spam_mute;
+    let gui = gui::Gui::new(
+        config,
+        Rc::new(timer::GlibClock::new()),
+        tx.clone(),
+        signal_tx,
+    );
+    gui.add_css_file("data/style.css")?;
+    gui.add_config_css()?;
+    gui.load_theme()?;
+    gui.watch_color_scheme();
 
-    if let Some(Command::Demo) = opt.command {
-        demo::send_notifications(tx.clone()).context("failed sending demo notifications")?;
-        thread::spawn(move || -> Result<()> {
-            loop {
-                // Don't put this inside the info! macro, otherwise if we're not actually logging
-                // then we'll never try to read from the signal queue, resulting in this being an
-                // infinite loop.
-                let gui_signal = signal_rx.recv()?;
-                info!("Received signal from GUI: {:?}", gui_signal);
+    // What's on screen and queued, to be saved once the GUI quits. Demos don't save anything.
+    let mut state_keeper = None;
+    if let Some(Command::Demo(demo_opt)) = opt.command {
+        match demo_opt.stress {
+            Some(count) => {
+                demo::send_stress(tx.clone(), count)
+                    .context("failed sending demo notifications")?;
+                demo::report_stress(gui.latencies(), count);
+            }
+            None => {
+                demo::send_notifications(tx.clone()).context("failed sending demo notifications")?
             }
+        }
+        // There's nobody to send signals to, but they still have to be drained.
+        thread::spawn(move || {
+            let mut signal_router = router::ActionRouter::new();
+            signal_router.add(Rc::new(router::LogSink));
+            signal_router.run(&signal_rx)
         });
     } else {
         // Start off the server thread, which will grab incoming messages from DBus and send them onto
         // the channel.
+        let system = opt.system;
+        if opt.forward_to.as_deref() == Some(dbus_name) {
+            bail!("Can't forward notifications to ourselves ({})", dbus_name);
+        }
+        let forward_to = opt.forward_to;
+        if mirror_config.listen.is_some() {
+            if let Err(err) = mirror::listen(&mirror_config, dbus_name, system) {
+                error!("Failed to listen for mirrored notifications: {:?}", err);
+            }
+        }
+        let latencies = gui.latencies();
+        let heartbeat = gui.heartbeat();
+        let low_power = gui.low_power();
+        if low_power_mode == LowPowerMode::OnBattery {
+            let low_power = Arc::clone(&low_power);
+            thread::spawn(move || {
+                if let Err(err) = power::follow_power_source(low_power) {
+                    error!("Stopped following the power source: {:?}", err);
+                }
+            });
+        }
+        let mut restored = match &state_path {
+            Some(path) => state::load(path).unwrap_or_else(|err| {
+                error!("Failed to restore notifications: {:?}", err);
+                None
+            }),
+            None => None,
+        };
+        state_keeper = state_path
+            .as_ref()
+            .map(|_| Arc::new(state::StateKeeper::new()));
+        let server_state = state_keeper.clone();
+        let (local_tx, local_rx) = mpsc::channel();
+        gui.restore_through(local_tx.clone());
+        if battery_notifications {
+            thread::spawn(move || {
+                if let Err(err) = power::watch(local_tx) {
+                    error!("Stopped watching the battery: {:?}", err);
+                }
+            });
+        }
+        let server_dbus_name = dbus_name.to_owned();
         thread::spawn(move || {
+            let dbus_name = server_dbus_name.as_str();
             info!("Hello from the server thread.");
-            let server =
-                server::NotifyServer::new(move |event| tx.send(event).expect("failed to send"));
-            let connection = LocalConnection::new_session().expect("couldn't connect to dbus");
-            server
-                .run(dbus_name, connection, signal_rx)
-                .expect("Server died unexpectedly");
+            let mut restarts = 0;
+            loop {
+                let server_tx = tx.clone();
+                let mut server = server::NotifyServer::new(move |event| {
+                    server_tx.send(event).expect("failed to send")
+                })
+                .report_latencies(Arc::clone(&latencies))
+                .limit_queue_memory(queue_memory_limit)
+                .follow_low_power(Arc::clone(&low_power));
+                if let Some(state) = &server_state {
+                    server = server.keep_state(Arc::clone(state));
+                }
+                if let Some(saved) = restored.take() {
+                    server = server.restore(saved);
+                }
+                server = server.persist_mute(mute_path.clone());
+                if gui_watchdog_timeout > Duration::from_millis(0) {
+                    server = server.watch_gui(watchdog::Watchdog::new(
+                        Arc::clone(&heartbeat),
+                        gui_watchdog_timeout,
+                        gui_watchdog_exit,
+                    ));
+                }
+                if let Some(forward_to) = &forward_to {
+                    info!("Forwarding notifications to {}", forward_to);
+                    match forward::Forwarder::new(forward_to.clone(), system) {
+                        Ok(forwarder) => server = server.forward_to(forwarder),
+                        Err(err) => error!("Failed to set up forwarding: {:?}", err),
+                    }
+                }
+                if mirror_config.send_to.is_some() {
+                    match mirror::Mirror::new(&mirror_config) {
+                        Ok(mirror) => server = server.mirror_to(mirror),
+                        Err(err) => error!("Failed to set up mirroring: {:?}", err),
+                    }
+                }
+                if push_config.url.is_some() {
+                    match push::Pusher::new(push_config.clone()) {
+                        Ok(pusher) => server = server.push_when_away(pusher),
+                        Err(err) => error!("Failed to set up pushing: {:?}", err),
+                    }
+                }
+                if let Some(path) = &script_path {
+                    match script::Scripts::load(path) {
+                        Ok(scripts) => server = server.run_scripts(scripts),
+                        Err(err) => error!("Failed to load script: {:?}", err),
+                    }
+                }
+                if !action_commands.is_empty() {
+                    server = server.run_action_commands(action_command::ActionCommands::new(
+                        action_commands.clone(),
+                    ));
+                }
+                if !raise_apps.is_empty() {
+                    match raise::Raiser::new(raise_apps.clone(), system) {
+                        Ok(raiser) => server = server.raise_with(raiser),
+                        Err(err) => error!("Failed to set up raising apps: {:?}", err),
+                    }
+                }
+                if !attention_apps.is_empty() {
+                    match attention::AttentionMarker::new(attention_apps.clone(), system) {
+                        Ok(marker) => server = server.mark_for_attention(marker),
+                        Err(err) => error!("Failed to set up marking windows: {:?}", err),
+                    }
+                }
+                if !processors.is_empty() {
+                    match processor::Processors::new(processors.clone(), processor_timeout, system)
+                    {
+                        Ok(processors) => server = server.process_with(processors),
+                        Err(err) => error!("Failed to set up processors: {:?}", err),
+                    }
+                }
+                if let Some(path) = &json_log_path {
+                    match json_log::JsonLog::open(path, json_log_max_size) {
+                        Ok(json_log) => server = server.log_to(json_log),
+                        Err(err) => error!("Failed to open JSON log: {:?}", err),
+                    }
+                }
+                if strict_allowlist || !blocked_apps.is_empty() || !allowed_apps.is_empty() {
+                    let mut app_filter =
+                        app_filter::AppFilter::new(blocked_apps.clone(), allowed_apps.clone());
+                    if strict_allowlist {
+                        app_filter = app_filter.strict();
+                    }
+                    server = server.filter_apps(app_filter);
+                }
+                if !redact_body.is_empty() {
+                    server = server.redact_body_for(redact_body.clone());
+                }
+                if let Some(threshold) = group_threshold {
+                    server = server.group_with(grouping::Grouper::new(threshold, group_window));
+                }
+                if let Some(threshold) = spam_threshold {
+                    server = server.guard_against_spam(spam::SpamGuard::new(
+                        threshold,
+                        spam_window,
+                        spam_mute,
+                    ));
+                }
+                if !digests.is_empty() {
+                    server = server.digest_with(digest::Digester::new(digests.clone()));
+                }
+                if infer_app_icons {
+                    match app_icon::IconInferrer::new(system) {
+                        Ok(icon_inferrer) => server = server.infer_icons_with(icon_inferrer),
+                        Err(err) => error!("Failed to set up icon inference: {:?}", err),
+                    }
+                }
+                // The watcher has to be created here, since connections can't move between threads.
+                if screen_share != ScreenShareMode::Off {
+                    match screencast::ScreenCastWatcher::new() {
+                        Ok(watcher) => server = server.watch_screen_casts(watcher, screen_share),
+                        Err(err) => error!("Failed to watch for screen sharing: {:?}", err),
+                    }
+                }
+                if dnd_digest {
+                    server = server.digest_after_dnd();
+                }
+                if pause_digest {
+                    server = server.digest_after_timed_pause();
+                }
+                if coalesce_close_notify {
+                    server = server.coalesce_close_notify();
+                }
+                if advertise_extensions {
+                    server = server.advertise_extensions();
+                }
+                if !dnd_when_focused.is_empty() {
+                    match focus::FocusWatcher::new() {
+                        Ok(watcher) => {
+                            server = server.watch_focus(watcher, dnd_when_focused.clone())
+                        }
+                        Err(err) => error!("Failed to watch the focused window: {:?}", err),
+                    }
+                }
+                if let Some(url) = calendar_config.url.clone() {
+                    server = server.watch_calendar(calendar::CalendarWatcher::new(
+                        url,
+                        Duration::from_secs(calendar_config.refresh * 60),
+                    ));
+                }
+                let connection = if system {
+                    LocalConnection::new_system()
+                } else {
+                    LocalConnection::new_session()
+                }
+                .expect("couldn't connect to dbus");
+                // A panic takes the server down as surely as an error does, so it gets restarted
+                // the same way.
+                let started = Instant::now();
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    server.run(dbus_name, connection, &signal_rx, &local_rx)
+                }))
+                .unwrap_or_else(|payload| {
+                    Err(anyhow!("it panicked: {}", panic_message(&*payload)))
+                });
+                if let Err(err) = result {
+                    if system && err.downcast_ref::<server::NameUnavailable>().is_some() {
+                        error!(
+                            "Serving on the system bus requires a policy allowing us to own {}. \
+                             Install the output of `ninomiya system-policy` to \
+                             /etc/dbus-1/system.d/ninomiya.conf.",
+                            dbus_name
+                        );
+                    }
+                    if started.elapsed() >= SERVER_RESTART_RESET {
+                        restarts = 0;
+                    }
+                    if restarts == MAX_SERVER_RESTARTS {
+                        panic!("Server died unexpectedly: {:?}", err);
+                    }
+                    restarts += 1;
+                    error!("Server died ({:?}); restarting it", err);
+                    let notification = server::Notification::internal(
+                        "Notification server restarted",
+                        &format!("It died with: {}", err),
+                    );
+                    if let Err(err) = tx.send(server::NinomiyaEvent::Notification(notification)) {
+                        error!("Failed to tell the GUI about the restart: {:?}", err);
+                    }
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
         });
     }
 
     // XXX: We should call with the command-line options here, but GTK wants to do its own argument
     // parsing, and that's annoying.
-    match gui.run(rx, &[]) {
+    let status = gui.run(rx, &[]);
+    if let (Some(state_keeper), Some(path)) = (&state_keeper, &state_path) {
+        match state_keeper.save(path) {
+            Ok(()) => info!("Saved the notifications on screen to {:?}", path),
+            Err(err) => error!("Failed to save the notifications on screen: {:?}", err),
+        }
+    }
+    match status {
         0 => Ok(()),
         _ => Err(anyhow!("error when running application")),
     }
 }
+
+/// The message a panic was raised with, if it was raised with one.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "(no message)"
+    }
+}
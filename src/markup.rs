@@ -0,0 +1,101 @@
+//! Deals with the parts of the body markup that Pango can't handle itself.
+
+use tracing::info;
+
+/// Removes `<img>` tags from the body, returning what's left along with the `src` of each image, in
+/// order. Pango doesn't know about `<img>`, so we have to render those ourselves.
+///
+/// Only local images are kept: absolute paths, and `file:` and `data:` URLs. We don't fetch images
+/// from the network (that would tell the sender when the notification was shown), and there's
+/// nothing sensible to resolve a relative path against.
+pub fn extract_images(body: &str) -> (String, Vec<String>) {
+    let mut text = String::with_capacity(body.len());
+    let mut sources = vec![];
+    let mut rest = body;
+    while let Some(start) = find_img_tag(rest) {
+        let end = match rest[start..].find('>') {
+            Some(index) => start + index + 1,
+            // Not actually a tag; leave it for Pango to complain about.
+            None => break,
+        };
+        text.push_str(&rest[..start]);
+        if let Some(src) = attribute(&rest[start..end], "src") {
+            let src = src.replace("&amp;", "&");
+            if is_local(&src) {
+                sources.push(src);
+            } else {
+                info!("Ignoring body image {}, which isn't a local file", src);
+            }
+        }
+        rest = &rest[end..];
+    }
+    text.push_str(rest);
+    (text.trim_end().to_owned(), sources)
+}
+
+/// Finds the start of the first `<img>` tag, as opposed to some other tag starting with `img`.
+fn find_img_tag(text: &str) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(index) = text[offset..].find("<img") {
+        let start = offset + index;
+        let after = text[start + "<img".len()..].chars().next();
+        if after.map_or(false, |c| c.is_whitespace() || c == '/' || c == '>') {
+            return Some(start);
+        }
+        offset = start + 1;
+    }
+    None
+}
+
+/// Whether `src` refers to an image we can load without going over the network.
+fn is_local(src: &str) -> bool {
+    src.starts_with('/') || src.starts_with("file://") || src.starts_with("data:")
+}
+
+/// Gets the value of a quoted attribute in the given tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let index = tag.find(&format!(" {}=", name))?;
+    let value = &tag[index + name.len() + 2..];
+    let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    let value = &value[1..];
+    Some(&value[..value.find(quote)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_images() {
+        let (text, sources) = extract_images(
+            "New post: <b>hello</b>\n<img src=\"file:///tmp/a.png\" alt=\"a\"/>\
+             <img alt='b' src='/tmp/b.png'>",
+        );
+        assert_eq!(text, "New post: <b>hello</b>");
+        assert_eq!(sources, vec!["file:///tmp/a.png", "/tmp/b.png"]);
+    }
+
+    #[test]
+    fn only_keeps_local_images() {
+        let (text, sources) = extract_images(
+            "<img src='https://example.com/b.png?x=1&amp;y=2'><img src='b.png'>\
+             <img src='data:image/png;base64,AAAA'>",
+        );
+        assert_eq!(text, "");
+        assert_eq!(sources, vec!["data:image/png;base64,AAAA"]);
+    }
+
+    #[test]
+    fn leaves_other_markup_alone() {
+        let body = "<i>no images</i> here <imgur is not a tag";
+        assert_eq!(extract_images(body), (body.to_owned(), vec![]));
+        let body = "<imgur>not a tag either</imgur> <img src='/a.png'>";
+        assert_eq!(
+            extract_images(body),
+            (
+                "<imgur>not a tag either</imgur>".to_owned(),
+                vec!["/a.png".to_owned()]
+            )
+        );
+    }
+}
@@ -0,0 +1,491 @@
+//! Mirrors notifications to ninomiya on another machine, so that a headless box (like a build
+//! server) can show its notifications on your desktop.
+//!
+//! The sending side connects over TLS to `mirror.send_to` and writes one JSON message per line: a
+//! `hello` with the shared token, then `notify` and `close` messages as notifications come and go.
+//! The receiving side listens on `mirror.listen`, checks the token, and re-sends each notification
+//! to the local daemon over DBus, so that filters, the JSON log and so on apply as usual. Nothing
+//! is sent back, so actions and dismissals stay on the receiving machine.
+//!
+//! Notifications are mirrored once they've been through the sending side's app filters, scripts,
+//! processors and `redact_body`, so nothing leaves the machine that it wouldn't have shown.
+
+use crate::bus;
+use crate::config::{Config, MirrorConfig};
+use crate::dbus_codegen::client::OrgFreedesktopNotifications;
+use crate::hints::{OwnedHints, OwnedValue};
+use crate::state::SavedNotification;
+use anyhow::{anyhow, bail, Context, Result};
+use dbus::blocking::Proxy;
+use native_tls::{Certificate, Identity, TlsAcceptor, TlsConnector, TlsStream};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// How long to wait when resolving, connecting or writing. This happens on the mirror's own
+/// thread, but everything behind it waits too.
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(1);
+/// How many messages can be waiting to go out before we start dropping them.
+const QUEUE_LENGTH: usize = 256;
+/// How long a connecting mirror has to finish the TLS handshake and say hello.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+/// The longest hello we'll read. It's only there to carry the token.
+const MAX_HELLO_LENGTH: usize = 4096;
+/// How many mirrors can be connected at once.
+const MAX_CONNECTIONS: usize = 8;
+/// After failing to connect, how long to drop notifications before trying again, so that an
+/// unreachable desktop doesn't slow down every notification.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+/// Hints that hold image data, which is what can make a notification big.
+const IMAGE_DATA_HINTS: &[&str] = &["image-data", "image_data", "icon_data"];
+/// Set on notifications we received from a mirror, so that two machines mirroring to each other
+/// don't bounce notifications back and forth forever.
+const MIRRORED_HINT: &str = "x-ninomiya-mirrored";
+
+/// A notification as it goes over the wire.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MirroredNotification {
+    /// The ID the sending daemon gave it.
+    pub id: u32,
+    pub app_name: String,
+    pub app_icon: String,
+    pub summary: String,
+    pub body: String,
+    /// Keys and labels, alternating, as in the DBus interface.
+    pub actions: Vec<String>,
+    pub hints: OwnedHints,
+    pub expire_timeout: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    Hello { token: String },
+    Notify(MirroredNotification),
+    Close { id: u32 },
+}
+
+/// Compares tokens without bailing out at the first difference, so that how long the check takes
+/// doesn't give away how much of the token was right.
+fn tokens_match(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// How many bytes of data a hint value holds.
+fn data_size(value: &OwnedValue) -> usize {
+    match value {
+        OwnedValue::String(s) => s.len(),
+        OwnedValue::Bytes(bytes) => bytes.len(),
+        OwnedValue::Array(items) | OwnedValue::Struct(items) => items.iter().map(data_size).sum(),
+        _ => 8,
+    }
+}
+
+/// Drops image data bigger than `max_size` bytes. The receiving side will fall back to the icon.
+fn drop_large_images(hints: &mut OwnedHints, max_size: usize) {
+    for key in IMAGE_DATA_HINTS {
+        if let Some(size) = hints.0.get(*key).map(data_size) {
+            if size > max_size {
+                debug!("Not mirroring {} bytes of {}", size, key);
+                hints.0.remove(*key);
+            }
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(Config::config_dir()?.join(path))
+        .with_context(|| format!("couldn't read {:?}", path))
+}
+
+/// The sending side. Connecting and writing happen on a thread of its own, so that a slow or
+/// unreachable desktop doesn't hold up the server.
+pub struct Mirror {
+    messages: SyncSender<Message>,
+    max_image_size: usize,
+}
+
+impl Mirror {
+    /// Sets up mirroring to `config.send_to`. Doesn't connect until there's something to send.
+    pub fn new(config: &MirrorConfig) -> Result<Self> {
+        let address = config
+            .send_to
+            .clone()
+            .ok_or_else(|| anyhow!("mirror.send_to isn't set"))?;
+        let token = config
+            .token
+            .clone()
+            .ok_or_else(|| anyhow!("mirroring needs mirror.token to be set"))?;
+        let mut builder = TlsConnector::builder();
+        if let Some(path) = &config.ca_certificate {
+            let certificate = Certificate::from_pem(&read_file(path)?)
+                .with_context(|| format!("{:?} isn't a PEM certificate", path))?;
+            builder.add_root_certificate(certificate);
+        }
+        let sender = Sender {
+            address,
+            token,
+            connector: builder.build().context("couldn't set up TLS")?,
+            stream: None,
+            retry_at: None,
+        };
+        let (messages, rx) = mpsc::sync_channel(QUEUE_LENGTH);
+        thread::spawn(move || sender.run(rx));
+        Ok(Mirror {
+            messages,
+            max_image_size: config.max_image_size,
+        })
+    }
+
+    fn send(&self, message: Message) {
+        match self.messages.try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("Too many notifications waiting to be mirrored"),
+            Err(TrySendError::Disconnected(_)) => warn!("The mirroring thread is gone"),
+        }
+    }
+
+    /// Mirrors a notification, as it is once it's been through scripts, processors and redaction.
+    pub fn notify(&self, notification: &SavedNotification, expire_timeout: i32) {
+        if notification.hints.0.contains_key(MIRRORED_HINT) {
+            return;
+        }
+        let mut hints = notification.hints.clone();
+        drop_large_images(&mut hints, self.max_image_size);
+        self.send(Message::Notify(MirroredNotification {
+            id: notification.id,
+            app_name: notification.app_name.clone(),
+            app_icon: notification.app_icon.clone(),
+            summary: notification.summary.clone(),
+            body: notification.body.clone(),
+            actions: notification.actions.clone(),
+            hints,
+            expire_timeout,
+        }));
+    }
+
+    /// Closes the mirrored copy of notification `id`.
+    pub fn close_notification(&self, id: u32) {
+        self.send(Message::Close { id });
+    }
+}
+
+/// The mirror's thread, which owns the connection.
+struct Sender {
+    address: String,
+    token: String,
+    connector: TlsConnector,
+    stream: Option<TlsStream<TcpStream>>,
+    /// Set after a failed connection attempt to when we should try again.
+    retry_at: Option<Instant>,
+}
+
+impl Sender {
+    fn run(mut self, rx: Receiver<Message>) {
+        for message in rx {
+            self.send(&message);
+        }
+    }
+
+    fn connect(&self) -> Result<TlsStream<TcpStream>> {
+        let socket_address = resolve(&self.address)?;
+        let tcp = TcpStream::connect_timeout(&socket_address, NETWORK_TIMEOUT)?;
+        tcp.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+        // The certificate has to be for the host name, not the port.
+        let domain = match self.address.rfind(':') {
+            Some(colon) => &self.address[..colon],
+            None => &self.address,
+        };
+        let mut stream = self
+            .connector
+            .connect(domain.trim_start_matches('[').trim_end_matches(']'), tcp)
+            .context("TLS handshake failed")?;
+        write_message(
+            &mut stream,
+            &Message::Hello {
+                token: self.token.clone(),
+            },
+        )?;
+        info!("Mirroring notifications to {}", self.address);
+        Ok(stream)
+    }
+
+    /// Sends `message`, connecting first if we need to. Failures are logged rather than returned,
+    /// since the other machine going away shouldn't stop us from displaying anything.
+    fn send(&mut self, message: &Message) {
+        // Closes only make sense on the connection that got the notification.
+        if let Message::Close { .. } = message {
+            if self.stream.is_none() {
+                return;
+            }
+        }
+        // If the old connection broke, we get one retry on a new one.
+        for _ in 0..2 {
+            if self.stream.is_none() {
+                if self.retry_at.map_or(false, |at| Instant::now() < at) {
+                    return;
+                }
+                match self.connect() {
+                    Ok(new_stream) => self.stream = Some(new_stream),
+                    Err(err) => {
+                        warn!("Failed to connect to {}: {:?}", self.address, err);
+                        self.retry_at = Some(Instant::now() + RETRY_DELAY);
+                        return;
+                    }
+                }
+            }
+            match write_message(self.stream.as_mut().unwrap(), message) {
+                Ok(()) => return,
+                Err(err) => {
+                    debug!("Lost the connection to {}: {:?}", self.address, err);
+                    self.stream = None;
+                }
+            }
+        }
+    }
+}
+
+/// Looks up `address`, giving up after `NETWORK_TIMEOUT`. The standard library can't time out a
+/// lookup, so it happens on a thread of its own that we stop waiting for.
+fn resolve(address: &str) -> Result<SocketAddr> {
+    let (tx, rx) = mpsc::channel();
+    let owned = address.to_owned();
+    thread::spawn(move || {
+        let _ = tx.send(
+            owned
+                .to_socket_addrs()
+                .map(|mut addresses| addresses.next()),
+        );
+    });
+    rx.recv_timeout(NETWORK_TIMEOUT)
+        .map_err(|_| anyhow!("looking up {} took too long", address))?
+        .with_context(|| format!("couldn't resolve {}", address))?
+        .ok_or_else(|| anyhow!("{} didn't resolve to anything", address))
+}
+
+fn write_message<W: Write>(writer: &mut W, message: &Message) -> Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Starts listening on `config.listen` in the background, re-sending everything that comes in to
+/// the daemon owning `dbus_name`.
+pub fn listen(config: &MirrorConfig, dbus_name: &str, system: bool) -> Result<()> {
+    let address = config
+        .listen
+        .as_ref()
+        .ok_or_else(|| anyhow!("mirror.listen isn't set"))?;
+    let token = config
+        .token
+        .clone()
+        .ok_or_else(|| anyhow!("mirroring needs mirror.token to be set"))?;
+    let (certificate, key) = match (&config.certificate, &config.key) {
+        (Some(certificate), Some(key)) => (read_file(certificate)?, read_file(key)?),
+        _ => bail!("listening for mirrored notifications needs mirror.certificate and mirror.key"),
+    };
+    let identity = Identity::from_pkcs8(&certificate, &key)
+        .context("couldn't load the mirroring certificate")?;
+    let acceptor = Arc::new(TlsAcceptor::new(identity).context("couldn't set up TLS")?);
+    let listener =
+        TcpListener::bind(address).with_context(|| format!("couldn't listen on {}", address))?;
+    let max_image_size = config.max_image_size;
+    let connections = Arc::new(AtomicUsize::new(0));
+    let dbus_name: Arc<str> = Arc::from(dbus_name);
+    info!("Listening for mirrored notifications on {}", address);
+    thread::spawn(move || {
+        for tcp in listener.incoming() {
+            let tcp = match tcp {
+                Ok(tcp) => tcp,
+                Err(err) => {
+                    warn!("Failed to accept a mirroring connection: {:?}", err);
+                    continue;
+                }
+            };
+            let peer = tcp
+                .peer_addr()
+                .map_or_else(|_| "unknown".to_owned(), |peer| peer.to_string());
+            if connections.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+                warn!("Turning away mirror {}; too many are connected", peer);
+                continue;
+            }
+            connections.fetch_add(1, Ordering::SeqCst);
+            let connections = Arc::clone(&connections);
+            let acceptor = Arc::clone(&acceptor);
+            let token = token.clone();
+            let dbus_name = Arc::clone(&dbus_name);
+            thread::spawn(move || {
+                // Until it's said hello, it only gets a little while; after that, it's trusted to
+                // stay connected while there's nothing to mirror.
+                let result = tcp
+                    .set_read_timeout(Some(HELLO_TIMEOUT))
+                    .context("couldn't set a timeout")
+                    .and_then(|()| acceptor.accept(tcp).context("TLS handshake failed"))
+                    .and_then(|stream| receive(stream, &token, max_image_size, &dbus_name, system));
+                match result {
+                    Ok(()) => debug!("Mirror {} disconnected", peer),
+                    Err(err) => warn!("Dropped mirror {}: {:?}", peer, err),
+                }
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+    Ok(())
+}
+
+/// How long a message can be once a mirror has said hello. Image data comes as JSON arrays of
+/// numbers, which can take up to four bytes per byte of image; the rest is room for the text and
+/// the other hints.
+fn max_message_length(max_image_size: usize) -> usize {
+    max_image_size.saturating_mul(4).saturating_add(1024 * 1024)
+}
+
+/// Reads a line of at most `max_length` bytes, without the newline. Returns `None` once the other
+/// side hangs up, and fails on longer lines, so that a peer can't make us buffer without end.
+fn read_line<R: BufRead>(reader: &mut R, max_length: usize) -> Result<Option<String>> {
+    let mut line = vec![];
+    reader
+        .by_ref()
+        .take(max_length as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    } else if line.len() > max_length {
+        bail!("got a message longer than {} bytes", max_length);
+    }
+    Ok(Some(
+        String::from_utf8(line).context("got a message that isn't UTF-8")?,
+    ))
+}
+
+/// Handles one connection from a sending daemon until it hangs up.
+fn receive(
+    stream: TlsStream<TcpStream>,
+    token: &str,
+    max_image_size: usize,
+    dbus_name: &str,
+    system: bool,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    match read_line(&mut reader, MAX_HELLO_LENGTH)?.map(|line| serde_json::from_str(&line)) {
+        Some(Ok(Message::Hello { token: theirs })) if tokens_match(token, &theirs) => {}
+        _ => bail!("didn't get the right token"),
+    }
+    reader.get_ref().get_ref().set_read_timeout(None)?;
+    info!("Accepted a mirror");
+    let connection = bus::connect(system)?;
+    let proxy = Proxy::new(
+        dbus_name,
+        "/org/freedesktop/Notifications",
+        NETWORK_TIMEOUT,
+        &connection,
+    );
+    // Maps the sender's IDs to ours, so that replacements and closes go to the right place.
+    let mut ids: HashMap<u32, u32> = HashMap::new();
+    let max_length = max_message_length(max_image_size);
+    while let Some(line) = read_line(&mut reader, max_length)? {
+        let message: Message = serde_json::from_str(&line).context("got a bad message")?;
+        match message {
+            Message::Hello { .. } => bail!("got a second hello"),
+            Message::Notify(mut notification) => {
+                drop_large_images(&mut notification.hints, max_image_size);
+                notification
+                    .hints
+                    .0
+                    .insert(MIRRORED_HINT.to_owned(), OwnedValue::Bool(true));
+                let replaces_id = ids.get(&notification.id).copied().unwrap_or(0);
+                let our_id = proxy.notify(
+                    &notification.app_name,
+                    replaces_id,
+                    &notification.app_icon,
+                    &notification.summary,
+                    &notification.body,
+                    notification.actions.iter().map(String::as_str).collect(),
+                    notification.hints.to_dbus(),
+                    notification.expire_timeout,
+                )?;
+                debug!(
+                    "Showing mirrored notification {} as {}",
+                    notification.id, our_id
+                );
+                ids.insert(notification.id, our_id);
+            }
+            Message::Close { id } => {
+                if let Some(our_id) = ids.remove(&id) {
+                    proxy.close_notification(our_id)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_messages() {
+        let mut hints = OwnedHints::default();
+        hints.0.insert("urgency".to_owned(), OwnedValue::Byte(2));
+        hints.0.insert(
+            "image-data".to_owned(),
+            OwnedValue::Struct(vec![
+                OwnedValue::Int(2),
+                OwnedValue::Int(2),
+                OwnedValue::Int(8),
+                OwnedValue::Bool(true),
+                OwnedValue::Int(8),
+                OwnedValue::Int(4),
+                OwnedValue::Bytes(vec![0; 16]),
+            ]),
+        );
+        let message = Message::Notify(MirroredNotification {
+            id: 3,
+            app_name: "make".to_owned(),
+            app_icon: "".to_owned(),
+            summary: "Build finished".to_owned(),
+            body: "".to_owned(),
+            actions: vec!["default".to_owned(), "Open".to_owned()],
+            hints: hints.clone(),
+            expire_timeout: -1,
+        });
+        let mut line = vec![];
+        write_message(&mut line, &message).unwrap();
+        assert_eq!(line.last(), Some(&b'\n'));
+        assert_eq!(serde_json::from_slice::<Message>(&line).unwrap(), message);
+
+        drop_large_images(&mut hints, 1024);
+        assert!(hints.0.contains_key("image-data"));
+        drop_large_images(&mut hints, 16);
+        assert!(!hints.0.contains_key("image-data"));
+        assert!(hints.0.contains_key("urgency"));
+
+        let mut reader = &b"{}\nmuch too long\n"[..];
+        assert_eq!(read_line(&mut reader, 8).unwrap(), Some("{}".to_owned()));
+        assert!(read_line(&mut reader, 8).is_err());
+        assert_eq!(read_line(&mut &b""[..], 8).unwrap(), None);
+
+        assert!(tokens_match("hunter2", "hunter2"));
+        assert!(!tokens_match("hunter2", "hunter3"));
+        assert!(!tokens_match("hunter2", "hunter"));
+    }
+}
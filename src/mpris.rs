@@ -0,0 +1,200 @@
+//! Turns track-change notifications into a small media controller, by talking to the player over
+//! MPRIS.
+
+use anyhow::{Context, Result};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+/// Players that take longer than this to answer are probably hung.
+const MPRIS_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Whether notifications in this category come from a media player.
+pub fn is_media_category(category: &str) -> bool {
+    category.starts_with("x-gnome.music") || category == "media" || category.starts_with("media.")
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Command {
+    Previous,
+    PlayPause,
+    Next,
+}
+
+impl Command {
+    pub const ALL: [Command; 3] = [Command::Previous, Command::PlayPause, Command::Next];
+
+    fn method(self) -> &'static str {
+        match self {
+            Command::Previous => "Previous",
+            Command::PlayPause => "PlayPause",
+            Command::Next => "Next",
+        }
+    }
+
+    pub fn icon_name(self) -> &'static str {
+        match self {
+            Command::Previous => "media-skip-backward",
+            Command::PlayPause => "media-playback-start",
+            Command::Next => "media-skip-forward",
+        }
+    }
+}
+
+/// What the GUI thread asks the worker to do.
+enum Request {
+    FindPlayer {
+        desktop_entry: Option<String>,
+        is_media: bool,
+        reply: glib::Sender<String>,
+    },
+    Send(String, Command),
+}
+
+/// A handle to a thread that talks to media players. Everything here is a blocking DBus round
+/// trip, so none of it happens on the GUI thread.
+pub struct Mpris {
+    requests: mpsc::Sender<Request>,
+}
+
+impl Mpris {
+    /// Connects to the session bus, which is where media players live.
+    pub fn new() -> Result<Self> {
+        let (requests, rx) = mpsc::channel();
+        let (connected_tx, connected_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let connection = match Connection::new_session() {
+                Ok(connection) => {
+                    let _ = connected_tx.send(Ok(()));
+                    connection
+                }
+                Err(err) => {
+                    let _ = connected_tx.send(Err(err));
+                    return;
+                }
+            };
+            let worker = Worker { connection };
+            for request in rx {
+                worker.handle(request);
+            }
+        });
+        connected_rx
+            .recv()
+            .context("the MPRIS thread died")?
+            .context("couldn't connect to the session bus for MPRIS")?;
+        Ok(Mpris { requests })
+    }
+
+    /// Finds the bus name of the player that a notification came from, and calls `found` with it
+    /// on the GUI thread. If there's no such player, `found` is never called.
+    pub fn find_player<F: FnOnce(String) + 'static>(
+        &self,
+        desktop_entry: Option<&str>,
+        is_media: bool,
+        found: F,
+    ) {
+        let (reply, rx) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        let mut found = Some(found);
+        rx.attach(None, move |player| {
+            if let Some(found) = found.take() {
+                found(player);
+            }
+            glib::Continue(false)
+        });
+        self.request(Request::FindPlayer {
+            desktop_entry: desktop_entry.map(str::to_owned),
+            is_media,
+            reply,
+        });
+    }
+
+    /// Tells the player to do something. Failures are logged, since there's nothing else to do
+    /// about them.
+    pub fn send(&self, player: &str, command: Command) {
+        self.request(Request::Send(player.to_owned(), command));
+    }
+
+    fn request(&self, request: Request) {
+        if self.requests.send(request).is_err() {
+            warn!("The MPRIS thread is gone");
+        }
+    }
+}
+
+struct Worker {
+    connection: Connection,
+}
+
+impl Worker {
+    fn handle(&self, request: Request) {
+        match request {
+            Request::FindPlayer {
+                desktop_entry,
+                is_media,
+                reply,
+            } => {
+                if let Some(player) = self.find_player(desktop_entry.as_deref(), is_media) {
+                    // The window might be gone by now, in which case nobody's listening.
+                    let _ = reply.send(player);
+                }
+            }
+            Request::Send(player, command) => self.send(&player, command),
+        }
+    }
+
+    /// If the notification has a desktop entry, the player has to match it; otherwise, for media
+    /// notifications, we assume it came from whichever player is running.
+    fn find_player(&self, desktop_entry: Option<&str>, is_media: bool) -> Option<String> {
+        let result: Result<(Vec<String>,), dbus::Error> = self
+            .connection
+            .with_proxy(
+                "org.freedesktop.DBus",
+                "/org/freedesktop/DBus",
+                MPRIS_TIMEOUT,
+            )
+            .method_call("org.freedesktop.DBus", "ListNames", ());
+        let names = match result {
+            Ok((names,)) => names,
+            Err(err) => {
+                debug!("Failed to list bus names: {:?}", err);
+                return None;
+            }
+        };
+        let mut players = names
+            .into_iter()
+            .filter(|name| name.starts_with(MPRIS_PREFIX));
+        match desktop_entry {
+            Some(desktop_entry) => players.find(|player| {
+                // Players are named `org.mpris.MediaPlayer2.<name>[.instance<pid>]`, and the name
+                // is usually the desktop entry, but they can also tell us explicitly.
+                player[MPRIS_PREFIX.len()..].split('.').next() == Some(desktop_entry)
+                    || self.desktop_entry(player).as_deref() == Some(desktop_entry)
+            }),
+            None if is_media => players.next(),
+            None => None,
+        }
+    }
+
+    fn desktop_entry(&self, player: &str) -> Option<String> {
+        self.connection
+            .with_proxy(player, MPRIS_PATH, MPRIS_TIMEOUT)
+            .get("org.mpris.MediaPlayer2", "DesktopEntry")
+            .ok()
+    }
+
+    fn send(&self, player: &str, command: Command) {
+        debug!("Sending {:?} to {}", command, player);
+        let result: Result<(), dbus::Error> = self
+            .connection
+            .with_proxy(player, MPRIS_PATH, MPRIS_TIMEOUT)
+            .method_call("org.mpris.MediaPlayer2.Player", command.method(), ());
+        if let Err(err) = result {
+            warn!("Failed to send {:?} to {}: {:?}", command, player, err);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Watches the battery through UPower and sends notifications when it gets low or the charger is
+//! plugged in or out. Meant for minimal setups that don't have a power daemon doing this already.
+//! Also turns low-power mode on and off with the power source, for `low_power = "on_battery"`.
+
+use crate::hints::{ImageRef, Urgency};
+use crate::low_power::LowPower;
+use crate::server::Notification;
+use anyhow::{Context, Result};
+use dbus::blocking::stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged};
+use dbus::blocking::Connection;
+use dbus::Message;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use tracing::{debug, info};
+
+const UPOWER_NAME: &str = "org.freedesktop.UPower";
+/// A composite device that UPower keeps up to date with the overall battery status.
+const DISPLAY_DEVICE_PATH: &str = "/org/freedesktop/UPower/devices/DisplayDevice";
+const DEVICE_INTERFACE: &str = "org.freedesktop.UPower.Device";
+const DBUS_TIMEOUT: Duration = Duration::from_millis(1000);
+/// We re-check the battery at least this often, in case we miss a signal.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+// Values of the device's `State` property.
+const STATE_CHARGING: u32 = 1;
+const STATE_DISCHARGING: u32 = 2;
+const STATE_FULLY_CHARGED: u32 = 4;
+const STATE_PENDING_CHARGE: u32 = 5;
+
+// Values of the device's `WarningLevel` property.
+const WARNING_LOW: u32 = 3;
+const WARNING_CRITICAL: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BatteryState {
+    percentage: f64,
+    state: u32,
+    warning_level: u32,
+}
+
+impl BatteryState {
+    fn on_ac(&self) -> bool {
+        matches!(
+            self.state,
+            STATE_CHARGING | STATE_FULLY_CHARGED | STATE_PENDING_CHARGE
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PowerEvent {
+    ChargerConnected,
+    ChargerDisconnected,
+    Low,
+    Critical,
+}
+
+impl PowerEvent {
+    fn notification(self, percentage: f64) -> Notification {
+        let (summary, icon, urgency) = match self {
+            PowerEvent::ChargerConnected => ("Charger connected", "ac-adapter", Urgency::Low),
+            PowerEvent::ChargerDisconnected => ("Charger disconnected", "ac-adapter", Urgency::Low),
+            PowerEvent::Low => ("Battery low", "battery-low", Urgency::Normal),
+            PowerEvent::Critical => ("Battery critical", "battery-caution", Urgency::Critical),
+        };
+        let mut notification =
+            Notification::internal(summary, &format!("{:.0}% remaining", percentage));
+        notification.application_name = Some("Power".to_owned());
+        notification.icon = Some(ImageRef::IconName(icon.to_owned()));
+        notification.hints.urgency = urgency;
+        // Only a dying battery is important enough to get through do-not-disturb.
+        notification.internal = urgency == Urgency::Critical;
+        notification
+    }
+}
+
+/// The events that happened between two readings of the battery.
+fn changes(old: &BatteryState, new: &BatteryState) -> Vec<PowerEvent> {
+    let mut events = vec![];
+    if !old.on_ac() && new.on_ac() {
+        events.push(PowerEvent::ChargerConnected);
+    } else if old.on_ac() && new.state == STATE_DISCHARGING {
+        events.push(PowerEvent::ChargerDisconnected);
+    }
+    if new.warning_level > old.warning_level {
+        if new.warning_level >= WARNING_CRITICAL {
+            events.push(PowerEvent::Critical);
+        } else if new.warning_level == WARNING_LOW {
+            events.push(PowerEvent::Low);
+        }
+    }
+    events
+}
+
+fn read_state(connection: &Connection) -> Result<BatteryState> {
+    let proxy = connection.with_proxy(UPOWER_NAME, DISPLAY_DEVICE_PATH, DBUS_TIMEOUT);
+    Ok(BatteryState {
+        percentage: proxy.get(DEVICE_INTERFACE, "Percentage")?,
+        state: proxy.get(DEVICE_INTERFACE, "State")?,
+        warning_level: proxy.get(DEVICE_INTERFACE, "WarningLevel")?,
+    })
+}
+
+/// Calls `on_change` with the old and new states of the battery whenever it might have changed,
+/// starting with the state it's in now (with no old state). Returns once `on_change` returns
+/// false.
+fn follow<F>(mut on_change: F) -> Result<()>
+where
+    F: FnMut(Option<&BatteryState>, &BatteryState) -> bool,
+{
+    let mut connection =
+        Connection::new_system().context("couldn't connect to the system bus to watch UPower")?;
+    connection
+        .with_proxy(UPOWER_NAME, DISPLAY_DEVICE_PATH, DBUS_TIMEOUT)
+        .match_signal(|_: PropertiesPropertiesChanged, _: &Connection, _: &Message| true)
+        .context("couldn't listen for battery changes")?;
+    let mut state = read_state(&connection).context("couldn't read the battery state")?;
+    info!("Watching the battery, which is at {:?}", state);
+    if !on_change(None, &state) {
+        return Ok(());
+    }
+    loop {
+        // We re-read everything whenever anything happens, so we don't need to look at the
+        // signal's contents.
+        connection.process(POLL_INTERVAL)?;
+        let new_state = read_state(&connection)?;
+        if !on_change(Some(&state), &new_state) {
+            return Ok(());
+        }
+        state = new_state;
+    }
+}
+
+/// Watches the battery forever, sending notifications through `tx`. Returns once nobody's
+/// listening on the other end.
+pub fn watch(tx: mpsc::Sender<Notification>) -> Result<()> {
+    follow(|old, new| {
+        let events = old.map_or_else(Vec::new, |old| changes(old, new));
+        for event in events {
+            debug!("Power event: {:?}", event);
+            if tx.send(event.notification(new.percentage)).is_err() {
+                return false;
+            }
+        }
+        true
+    })
+}
+
+/// Keeps low-power mode on whenever we're running on battery, forever.
+pub fn follow_power_source(low_power: Arc<LowPower>) -> Result<()> {
+    follow(|_, state| {
+        // Machines without a battery report an unknown state, so only discharging counts.
+        let on_battery = state.state == STATE_DISCHARGING;
+        if low_power.set(on_battery) {
+            info!(
+                "Turning low-power mode {}",
+                if on_battery { "on" } else { "off" }
+            );
+        }
+        true
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn battery(state: u32, warning_level: u32) -> BatteryState {
+        BatteryState {
+            percentage: 50.0,
+            state,
+            warning_level,
+        }
+    }
+
+    #[test]
+    fn charger_events() {
+        assert_eq!(
+            changes(&battery(STATE_DISCHARGING, 1), &battery(STATE_CHARGING, 1)),
+            vec![PowerEvent::ChargerConnected]
+        );
+        assert_eq!(
+            changes(
+                &battery(STATE_FULLY_CHARGED, 1),
+                &battery(STATE_DISCHARGING, 1)
+            ),
+            vec![PowerEvent::ChargerDisconnected]
+        );
+        assert!(changes(
+            &battery(STATE_CHARGING, 1),
+            &battery(STATE_FULLY_CHARGED, 1)
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn warning_events() {
+        let discharging = |level| battery(STATE_DISCHARGING, level);
+        assert_eq!(
+            changes(&discharging(1), &discharging(WARNING_LOW)),
+            vec![PowerEvent::Low]
+        );
+        assert_eq!(
+            changes(&discharging(WARNING_LOW), &discharging(WARNING_CRITICAL)),
+            vec![PowerEvent::Critical]
+        );
+        assert!(changes(&discharging(WARNING_CRITICAL), &discharging(WARNING_LOW)).is_empty());
+    }
+}
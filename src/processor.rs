@@ -0,0 +1,215 @@
+//! Hands notifications to external processors before they're shown, so that plugins can be written
+//! in any language that speaks DBus.
+//!
+//! A processor is a service on the same bus that owns one of the names in `processors` and
+//! implements `ai.deifactor.Ninomiya.Processor` at `/ai/deifactor/Ninomiya/Processor`:
+//!
+//! ```text
+//! Process(a{sv} notification) -> (s decision, a{sv} changes)
+//! ```
+//!
+//! `notification` has `id` (u), `app_name`, `summary`, `body`, `category` and `desktop_entry` (all
+//! s, and left out if unset) and `urgency` (y). `decision` is `keep`, `suppress`, or `modify`, in
+//! which case `changes` holds new values for any of `app_name`, `summary`, `body`, `category` and
+//! `urgency`, with the same types. Processors are called in order, each seeing the last one's
+//! changes. One that fails or takes too long is skipped.
+
+use crate::bus;
+use crate::hints::Urgency;
+use crate::server::Notification;
+use anyhow::{anyhow, bail, Result};
+use dbus::arg::{self, RefArg};
+use dbus::blocking::Connection;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+pub const PROCESSOR_PATH: &str = "/ai/deifactor/Ninomiya/Processor";
+pub const PROCESSOR_INTERFACE: &str = "ai.deifactor.Ninomiya.Processor";
+
+type VariantMap = HashMap<String, arg::Variant<Box<dyn RefArg>>>;
+
+#[derive(Debug)]
+enum Decision {
+    Keep,
+    Suppress,
+    Modify(VariantMap),
+}
+
+fn variant<T: RefArg + 'static>(value: T) -> arg::Variant<Box<dyn RefArg>> {
+    arg::Variant(Box::new(value) as Box<dyn RefArg>)
+}
+
+/// What processors get to see of `notification`.
+fn payload(notification: &Notification) -> VariantMap {
+    let mut map = HashMap::new();
+    map.insert("id".to_owned(), variant(notification.id));
+    map.insert("summary".to_owned(), variant(notification.summary.clone()));
+    map.insert(
+        "urgency".to_owned(),
+        variant(notification.hints.urgency as u8),
+    );
+    let optional = [
+        ("app_name", &notification.application_name),
+        ("body", &notification.body),
+        ("category", &notification.hints.category),
+        ("desktop_entry", &notification.hints.desktop_entry),
+    ];
+    for (key, value) in optional.iter() {
+        if let Some(value) = value {
+            map.insert((*key).to_owned(), variant(value.clone()));
+        }
+    }
+    map
+}
+
+fn parse_decision(decision: &str, changes: VariantMap) -> Result<Decision> {
+    Ok(match decision {
+        "keep" => Decision::Keep,
+        "suppress" => Decision::Suppress,
+        "modify" => Decision::Modify(changes),
+        _ => bail!("unknown decision {:?}", decision),
+    })
+}
+
+/// Applies a processor's changes to `notification`. Everything is checked first, so bad changes
+/// leave the notification alone.
+fn apply(changes: &VariantMap, notification: &mut Notification) -> Result<()> {
+    let string = |key: &str| -> Result<Option<String>> {
+        changes
+            .get(key)
+            .map(|value| {
+                value
+                    .0
+                    .as_str()
+                    .map(str::to_owned)
+                    .ok_or_else(|| anyhow!("{} should be a string", key))
+            })
+            .transpose()
+    };
+    let app_name = string("app_name")?;
+    let summary = string("summary")?;
+    let body = string("body")?;
+    let category = string("category")?;
+    let urgency = changes
+        .get("urgency")
+        .map(|value| {
+            let value = value
+                .0
+                .as_u64()
+                .ok_or_else(|| anyhow!("urgency should be a number"))?;
+            Urgency::from_hint(value)
+        })
+        .transpose()?;
+    if let Some(unknown) = changes.keys().find(|key| {
+        !matches!(
+            &key[..],
+            "app_name" | "summary" | "body" | "category" | "urgency"
+        )
+    }) {
+        bail!("can't change {}", unknown);
+    }
+    if let Some(app_name) = app_name {
+        notification.application_name = Some(app_name);
+    }
+    if let Some(summary) = summary {
+        notification.summary = summary;
+    }
+    if let Some(body) = body {
+        notification.body = Some(body);
+    }
+    if let Some(category) = category {
+        notification.hints.category = Some(category);
+    }
+    if let Some(urgency) = urgency {
+        notification.hints.urgency = urgency;
+    }
+    Ok(())
+}
+
+pub struct Processors {
+    connection: Connection,
+    /// The DBus names of the processors, in the order they're called.
+    names: Vec<String>,
+    /// How long to wait for each processor. This blocks the server thread, so keep it short.
+    timeout: Duration,
+}
+
+impl Processors {
+    pub fn new(names: Vec<String>, timeout: Duration, system: bool) -> Result<Self> {
+        Ok(Processors {
+            connection: bus::connect(system)?,
+            names,
+            timeout,
+        })
+    }
+
+    fn call(&self, name: &str, notification: &Notification) -> Result<Decision> {
+        let (decision, changes): (String, VariantMap) = self
+            .connection
+            .with_proxy(name, PROCESSOR_PATH, self.timeout)
+            .method_call(PROCESSOR_INTERFACE, "Process", (payload(notification),))?;
+        parse_decision(&decision, changes)
+    }
+
+    /// Runs `notification` through every processor. Returns false if one of them suppressed it.
+    pub fn process(&self, notification: &mut Notification) -> bool {
+        for name in &self.names {
+            match self.call(name, notification) {
+                Ok(Decision::Keep) => {}
+                Ok(Decision::Suppress) => {
+                    debug!("{} suppressed notification {}", name, notification.id);
+                    return false;
+                }
+                Ok(Decision::Modify(changes)) => {
+                    debug!("{} changed notification {}", name, notification.id);
+                    if let Err(err) = apply(&changes, notification) {
+                        warn!("Ignoring bad changes from {}: {:?}", name, err);
+                    }
+                }
+                Err(err) => warn!(
+                    "Processor {} failed on notification {}: {:?}",
+                    name, notification.id, err
+                ),
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_changes() {
+        let mut notification = Notification::builder()
+            .id(4)
+            .application_name("make")
+            .summary("build failed")
+            .build();
+        let map = payload(&notification);
+        assert_eq!(map["summary"].0.as_str(), Some("build failed"));
+        assert_eq!(map["urgency"].0.as_u64(), Some(1));
+        assert!(!map.contains_key("body"));
+
+        let mut changes = HashMap::new();
+        changes.insert("summary".to_owned(), variant("Build failed!".to_owned()));
+        changes.insert("urgency".to_owned(), variant(2u8));
+        let decision = parse_decision("modify", changes).unwrap();
+        match decision {
+            Decision::Modify(changes) => apply(&changes, &mut notification).unwrap(),
+            _ => panic!("expected modify, got {:?}", decision),
+        }
+        assert_eq!(notification.summary, "Build failed!");
+        assert_eq!(notification.hints.urgency, Urgency::Critical);
+        assert_eq!(notification.application_name.as_deref(), Some("make"));
+
+        let mut changes = HashMap::new();
+        changes.insert("summary".to_owned(), variant("ignored".to_owned()));
+        changes.insert("actions".to_owned(), variant(3u32));
+        assert!(apply(&changes, &mut notification).is_err());
+        assert_eq!(notification.summary, "Build failed!");
+        assert!(parse_decision("maybe", HashMap::new()).is_err());
+    }
+}
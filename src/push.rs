@@ -0,0 +1,225 @@
+//! Pushes notifications to your phone through ntfy or Gotify while you're away from the computer.
+//!
+//! Whether you're away comes from logind's idle and lock hints for the current session, which most
+//! desktops and screen lockers keep up to date. Pushes are sent with [`crate::curl`].
+
+use crate::app_filter;
+use crate::config::{PushConfig, PushService};
+use crate::curl;
+use crate::hints::Urgency;
+use crate::server::Notification;
+use anyhow::{bail, Context, Result};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+const LOGIND_NAME: &str = "org.freedesktop.login1";
+/// logind resolves `auto` to the session of whoever's asking.
+const SESSION_PATH: &str = "/org/freedesktop/login1/session/auto";
+const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const DBUS_TIMEOUT: Duration = Duration::from_millis(1000);
+/// How often to check whether the session is idle or locked.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Whether the session is idle or locked right now.
+fn session_inactive(connection: &Connection) -> Result<bool> {
+    let session = connection.with_proxy(LOGIND_NAME, SESSION_PATH, DBUS_TIMEOUT);
+    let idle: bool = session.get(SESSION_INTERFACE, "IdleHint")?;
+    let locked: bool = session.get(SESSION_INTERFACE, "LockedHint")?;
+    Ok(idle || locked)
+}
+
+/// Keeps checking whether the session has been idle or locked for `away_after`, and sets `away`
+/// accordingly.
+fn watch_session(away: Arc<AtomicBool>, away_after: Duration) -> Result<()> {
+    let connection =
+        Connection::new_system().context("couldn't connect to the system bus to talk to logind")?;
+    let mut inactive_since: Option<Instant> = None;
+    loop {
+        let inactive = session_inactive(&connection).context("couldn't ask logind")?;
+        inactive_since = match (inactive, inactive_since) {
+            (true, None) => Some(Instant::now()),
+            (true, since) => since,
+            (false, _) => None,
+        };
+        let now_away = inactive_since.map_or(false, |since| since.elapsed() >= away_after);
+        if away.swap(now_away, Ordering::Relaxed) != now_away {
+            info!(
+                "{}",
+                if now_away {
+                    "You're away; pushing notifications"
+                } else {
+                    "You're back; no longer pushing notifications"
+                }
+            );
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+pub struct Pusher {
+    config: PushConfig,
+    away: Arc<AtomicBool>,
+}
+
+impl Pusher {
+    /// Starts watching whether the session is idle or locked in the background.
+    pub fn new(config: PushConfig) -> Result<Self> {
+        if config.url.is_none() {
+            bail!("pushing notifications needs push.url to be set");
+        }
+        let away = Arc::new(AtomicBool::new(false));
+        let away_after = Duration::from_secs(config.away_after);
+        let watcher_away = Arc::clone(&away);
+        thread::spawn(move || {
+            if let Err(err) = watch_session(watcher_away, away_after) {
+                warn!("Stopped watching for idleness: {:?}", err);
+            }
+        });
+        Ok(Pusher { config, away })
+    }
+
+    fn matches(&self, notification: &Notification) -> bool {
+        !notification.internal
+            && notification.hints.urgency >= self.config.min_urgency
+            && (self.config.apps.is_empty()
+                || app_filter::matches_app(
+                    &self.config.apps,
+                    notification.application_name.as_deref(),
+                    notification.hints.desktop_entry.as_deref(),
+                ))
+    }
+
+    /// Pushes `notification` if you're away and it's one we push, without waiting for the request
+    /// to finish.
+    pub fn notify(&self, notification: &Notification) {
+        if !self.away.load(Ordering::Relaxed) || !self.matches(notification) {
+            return;
+        }
+        debug!("Pushing notification {}", notification.id);
+        match self.curl_options(notification).spawn() {
+            Ok(child) => {
+                let id = notification.id;
+                thread::spawn(move || match child.wait_with_output() {
+                    Ok(output) if !output.status.success() => warn!(
+                        "Failed to push notification {}: {}",
+                        id,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ),
+                    Ok(_) => {}
+                    Err(err) => warn!("Failed to push notification {}: {:?}", id, err),
+                });
+            }
+            Err(err) => warn!("Failed to push notification {}: {:?}", notification.id, err),
+        }
+    }
+
+    /// The options to `curl` that push `notification`.
+    fn curl_options(&self, notification: &Notification) -> curl::Options {
+        let url = self.config.url.clone().unwrap_or_default();
+        let title = match &notification.application_name {
+            Some(app_name) => format!("{}: {}", app_name, notification.summary),
+            None => notification.summary.clone(),
+        };
+        // A newline in a header would start another header.
+        let title = title.replace(|c: char| c == '\r' || c == '\n', " ");
+        let message = notification.body.clone().unwrap_or_default();
+        let mut options = curl::Options::new()
+            .flag("silent")
+            .flag("show-error")
+            .flag("fail");
+        match self.config.service {
+            PushService::Ntfy => {
+                options = options.option("header", &format!("Title: {}", title));
+                let priority = match notification.hints.urgency {
+                    Urgency::Low => "low",
+                    Urgency::Normal => "default",
+                    Urgency::Critical => "urgent",
+                };
+                options = options.option("header", &format!("Priority: {}", priority));
+                if let Some(token) = &self.config.token {
+                    options = options.option("header", &format!("Authorization: Bearer {}", token));
+                }
+                // Unlike `data-binary`, this doesn't treat a leading `@` as a file to upload.
+                options.option("data-raw", &message).option("url", &url)
+            }
+            PushService::Gotify => {
+                if let Some(token) = &self.config.token {
+                    options = options.option("header", &format!("X-Gotify-Key: {}", token));
+                }
+                options = options.option("header", "Content-Type: application/json");
+                let priority = match notification.hints.urgency {
+                    Urgency::Low => 2,
+                    Urgency::Normal => 5,
+                    Urgency::Critical => 8,
+                };
+                let body = serde_json::json!({
+                    "title": title,
+                    "message": message,
+                    "priority": priority,
+                });
+                options
+                    .option("data-raw", &body.to_string())
+                    .option("url", &format!("{}/message", url.trim_end_matches('/')))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pusher(service: PushService) -> Pusher {
+        Pusher {
+            config: PushConfig {
+                service,
+                url: Some("https://push.example.com/".to_owned()),
+                token: Some("secret".to_owned()),
+                apps: vec!["Thunderbird".to_owned()],
+                ..PushConfig::default()
+            },
+            away: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    #[test]
+    fn builds_requests() {
+        let notification = Notification::builder()
+            .id(1)
+            .application_name("Thunderbird")
+            .summary("New mail")
+            .body("From: Alice")
+            .build();
+        let ntfy = pusher(PushService::Ntfy);
+        assert!(ntfy.matches(&notification));
+        assert_eq!(
+            ntfy.curl_options(&notification).as_str(),
+            "silent
+show-error
+fail
+header = \"Title: Thunderbird: New mail\"
+header = \"Priority: default\"
+header = \"Authorization: Bearer secret\"
+data-raw = \"From: Alice\"
+url = \"https://push.example.com/\"
+"
+        );
+
+        let options = pusher(PushService::Gotify).curl_options(&notification);
+        let options = options.as_str();
+        assert!(options.contains("header = \"X-Gotify-Key: secret\"\n"));
+        assert!(options.ends_with("url = \"https://push.example.com/message\"\n"));
+
+        let other = Notification::builder()
+            .id(2)
+            .application_name("Slack")
+            .summary("hi")
+            .build();
+        assert!(!ntfy.matches(&other));
+    }
+}
@@ -0,0 +1,264 @@
+//! Raises the app a notification came from when its default action is invoked, for the apps in
+//! `raise_apps`. Plenty of apps don't do this themselves, so clicking their notifications does
+//! nothing visible.
+//!
+//! The window is found with `wmctrl`, first by the sender's PID and then by matching its
+//! `WM_CLASS` against the desktop entry, and activated through EWMH `_NET_ACTIVE_WINDOW`, so this
+//! only works on X11. If the app has no window, it's launched from its desktop entry with
+//! `gtk-launch`.
+
+use crate::app_filter;
+use crate::bus;
+use crate::router::SignalSink;
+use crate::server::{Notification, Signal};
+use anyhow::{bail, Context, Result};
+use dbus::blocking::Connection;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::thread;
+use tracing::{debug, info, warn};
+
+/// The action key that clicking a notification invokes.
+const DEFAULT_KEY: &str = "default";
+
+/// What we know about where a notification came from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Source {
+    pub pid: Option<u32>,
+    pub desktop_entry: Option<String>,
+}
+
+/// A window in `wmctrl -l -p -x` output.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Window<'a> {
+    pub id: &'a str,
+    pid: Option<u32>,
+    /// `instance.class`, like `Navigator.firefox`.
+    wm_class: &'a str,
+}
+
+/// Lists the windows the window manager is managing, in `wmctrl -l -p -x` format.
+pub(crate) fn list_windows() -> Result<String> {
+    let output = Command::new("wmctrl")
+        .args(&["-l", "-p", "-x"])
+        .stderr(Stdio::null())
+        .output()
+        .context("failed to run wmctrl to list windows")?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses `wmctrl -l -p -x` output, whose lines look like
+/// `0x03a00003  0 4242   Navigator.firefox     host Some title`.
+pub(crate) fn parse_windows(output: &str) -> Vec<Window<'_>> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let id = fields.next()?;
+            let _desktop = fields.next()?;
+            let pid = fields.next()?.parse().ok().filter(|&pid| pid != 0);
+            let wm_class = fields.next()?;
+            Some(Window { id, pid, wm_class })
+        })
+        .collect()
+}
+
+/// Last components of reverse-DNS desktop entries that say nothing about which app it is, like the
+/// `desktop` in `org.telegram.desktop`.
+const GENERIC_NAMES: &[&str] = &["app", "application", "client", "desktop", "gui"];
+
+/// Whether a window's `WM_CLASS` looks like it belongs to the app with the given desktop entry,
+/// like `Navigator.firefox` for `firefox` or `org.mozilla.firefox`, or
+/// `telegram-desktop.TelegramDesktop` for `org.telegram.desktop`.
+fn class_matches(wm_class: &str, desktop_entry: &str) -> bool {
+    let desktop_entry = desktop_entry.to_lowercase();
+    let mut names = vec![desktop_entry.clone()];
+    let components: Vec<&str> = desktop_entry.split('.').collect();
+    if components.len() > 2 {
+        // Everything after the domain, like `telegram-desktop`.
+        names.push(components[1..].join("-"));
+        names.push(components[1..].concat());
+    }
+    match components.last() {
+        Some(last) if components.len() > 1 && !GENERIC_NAMES.contains(last) => {
+            names.push((*last).to_owned())
+        }
+        _ => {}
+    }
+    wm_class
+        .to_lowercase()
+        .split('.')
+        .any(|name| names.iter().any(|candidate| candidate == name))
+}
+
+/// The window to raise for `source`: one belonging to its process if there is one, and otherwise
+/// one whose class matches its desktop entry.
+pub(crate) fn find_window<'a>(windows: &'a [Window<'a>], source: &Source) -> Option<&'a str> {
+    let by_pid = source
+        .pid
+        .and_then(|pid| windows.iter().find(|window| window.pid == Some(pid)));
+    let by_class = || {
+        let desktop_entry = source.desktop_entry.as_deref()?;
+        windows
+            .iter()
+            .find(|window| class_matches(window.wm_class, desktop_entry))
+    };
+    by_pid.or_else(by_class).map(|window| window.id)
+}
+
+/// Raises `source`'s window, or launches it if it has none.
+fn raise(source: &Source) -> Result<()> {
+    let output = list_windows()?;
+    let windows = parse_windows(&output);
+    if let Some(id) = find_window(&windows, source) {
+        debug!("Activating window {}", id);
+        let status = Command::new("wmctrl")
+            .args(&["-i", "-a", id])
+            .status()
+            .context("failed to run wmctrl to activate the window")?;
+        if !status.success() {
+            bail!("wmctrl failed to activate {}: {}", id, status);
+        }
+        return Ok(());
+    }
+    let desktop_entry = match &source.desktop_entry {
+        Some(desktop_entry) => desktop_entry,
+        None => bail!("couldn't find a window, and there's no desktop entry to launch"),
+    };
+    info!("{} has no window; launching it", desktop_entry);
+    Command::new("gtk-launch")
+        .arg(desktop_entry)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .context("failed to run gtk-launch")?
+        .wait()?;
+    Ok(())
+}
+
+pub struct Raiser {
+    connection: Connection,
+    /// Which apps to raise, matched like `blocked_apps`.
+    apps: Vec<String>,
+    /// Where each notification on screen that we'd raise came from.
+    sources: HashMap<u32, Source>,
+}
+
+impl Raiser {
+    /// `system` should say which bus we're serving on, so that senders can be looked up there.
+    pub fn new(apps: Vec<String>, system: bool) -> Result<Self> {
+        Ok(Raiser {
+            connection: bus::connect(system)?,
+            apps,
+            sources: HashMap::new(),
+        })
+    }
+
+    /// Remembers where `notification` came from, if it's from one of our apps and has a default
+    /// action. `sender` is the unique bus name that sent it.
+    pub fn add(&mut self, notification: &Notification, sender: Option<&str>) {
+        let has_default = notification
+            .actions
+            .iter()
+            .any(|action| action.key == DEFAULT_KEY);
+        if !has_default
+            || !app_filter::matches_app(
+                &self.apps,
+                notification.application_name.as_deref(),
+                notification.hints.desktop_entry.as_deref(),
+            )
+        {
+            self.sources.remove(&notification.id);
+            return;
+        }
+        let pid = sender.and_then(|sender| bus::sender_pid(&self.connection, sender));
+        self.sources.insert(
+            notification.id,
+            Source {
+                pid,
+                desktop_entry: notification.hints.desktop_entry.clone(),
+            },
+        );
+    }
+
+    /// Raises the app behind notification `id` if `key` is its default action, without waiting.
+    pub fn invoke(&self, id: u32, key: &str) {
+        if key != DEFAULT_KEY {
+            return;
+        }
+        if let Some(source) = self.sources.get(&id).cloned() {
+            thread::spawn(move || {
+                if let Err(err) = raise(&source) {
+                    warn!("Couldn't raise the app for notification {}: {:?}", id, err);
+                }
+            });
+        }
+    }
+
+    /// Forgets about notification `id` once it's closed.
+    pub fn remove(&mut self, id: u32) {
+        self.sources.remove(&id);
+    }
+}
+
+impl SignalSink for RefCell<Raiser> {
+    fn handle(&self, signal: &Signal) {
+        match signal {
+            Signal::ActionInvoked { id, key } => self.borrow().invoke(*id, key),
+            Signal::NotificationClosed { id, .. } => self.borrow_mut().remove(*id),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_windows() {
+        let output = "0x01e00003 -1 0      xfce4-panel.Xfce4-panel  host xfce4-panel\n\
+                      0x03a00003  0 4242   Navigator.firefox     host Mozilla Firefox\n\
+                      0x04400004  0 5151   telegram-desktop.TelegramDesktop  host Telegram\n";
+        let windows = parse_windows(output);
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows[0].pid, None);
+        assert_eq!(
+            windows[1],
+            Window {
+                id: "0x03a00003",
+                pid: Some(4242),
+                wm_class: "Navigator.firefox"
+            }
+        );
+
+        let by_pid = Source {
+            pid: Some(5151),
+            desktop_entry: Some("firefox".to_owned()),
+        };
+        assert_eq!(find_window(&windows, &by_pid), Some("0x04400004"));
+        let by_class = Source {
+            pid: Some(1),
+            desktop_entry: Some("org.mozilla.firefox".to_owned()),
+        };
+        assert_eq!(find_window(&windows, &by_class), Some("0x03a00003"));
+        let neither = Source {
+            pid: None,
+            desktop_entry: Some("thunderbird".to_owned()),
+        };
+        assert_eq!(find_window(&windows, &neither), None);
+    }
+
+    #[test]
+    fn matches_classes() {
+        assert!(class_matches("Navigator.firefox", "firefox"));
+        assert!(class_matches("Navigator.firefox", "org.mozilla.firefox"));
+        assert!(class_matches(
+            "telegram-desktop.TelegramDesktop",
+            "org.telegram.desktop"
+        ));
+        // `desktop` on its own could be anything.
+        assert!(!class_matches("desktop.Desktop", "org.telegram.desktop"));
+    }
+}
@@ -0,0 +1,150 @@
+//! Sends the signals coming back from the GUI everywhere they need to go. The server routes them to
+//! DBus, to the hooks that scripts, action commands and the like have, and to the log; demos, which
+//! have no DBus connection, just log them.
+
+use crate::control;
+use crate::dbus_codegen::server as dbus_server;
+use crate::server::Signal;
+use anyhow::Result;
+use dbus::message::SignalArgs;
+use dbus::Message;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc::Receiver;
+use tracing::debug;
+
+/// Something that wants to know about signals. Sinks get every signal, so they should ignore the
+/// ones they don't care about.
+pub trait SignalSink {
+    fn handle(&self, signal: &Signal);
+}
+
+/// Logs every signal.
+pub struct LogSink;
+
+impl SignalSink for LogSink {
+    fn handle(&self, signal: &Signal) {
+        debug!("Signal from the GUI: {:?}", signal);
+    }
+}
+
+/// Turns signals into DBus messages: the spec's ones on the notifications path, and our own on the
+/// control path. They're held until whoever owns the connection takes them to send.
+#[derive(Default)]
+pub struct DbusEmitter {
+    outgoing: RefCell<Vec<Message>>,
+}
+
+impl DbusEmitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the messages to send.
+    pub fn take(&self) -> Vec<Message> {
+        self.outgoing.borrow_mut().drain(..).collect()
+    }
+}
+
+impl SignalSink for DbusEmitter {
+    fn handle(&self, signal: &Signal) {
+        let path = dbus::strings::Path::new("/org/freedesktop/Notifications")
+            .expect("failed to parse dbus path name; this is really weird!");
+        let control_path = dbus::strings::Path::new(control::CONTROL_PATH)
+            .expect("failed to parse dbus path name; this is really weird!");
+        let message = match signal {
+            Signal::ActionInvoked { id, key } => {
+                dbus_server::OrgFreedesktopNotificationsActionInvoked {
+                    id: *id,
+                    action_key: key.clone(),
+                }
+                .to_emit_message(&path)
+            }
+            Signal::NotificationClosed { id, reason } => {
+                dbus_server::OrgFreedesktopNotificationsNotificationClosed {
+                    id: *id,
+                    reason: *reason as u32,
+                }
+                .to_emit_message(&path)
+            }
+            Signal::NotificationDisplayed { id } => {
+                control::NotificationDisplayed { id: *id }.to_emit_message(&control_path)
+            }
+            Signal::NotificationQueued { id } => {
+                control::NotificationQueued { id: *id }.to_emit_message(&control_path)
+            }
+        };
+        self.outgoing.borrow_mut().push(message);
+    }
+}
+
+/// Hands each signal to every sink, in the order they were added.
+#[derive(Default)]
+pub struct ActionRouter {
+    sinks: Vec<Rc<dyn SignalSink>>,
+}
+
+impl ActionRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, sink: Rc<dyn SignalSink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn route(&self, signal: &Signal) {
+        for sink in &self.sinks {
+            sink.handle(signal);
+        }
+    }
+
+    /// Routes the signals from `signal_rx` until the GUI goes away.
+    pub fn run(&self, signal_rx: &Receiver<Signal>) -> Result<()> {
+        for signal in signal_rx.iter() {
+            self.route(&signal);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::CloseReason;
+
+    #[derive(Default)]
+    struct Recorder(RefCell<Vec<u32>>);
+
+    impl SignalSink for Recorder {
+        fn handle(&self, signal: &Signal) {
+            if let Signal::NotificationClosed { id, .. } = signal {
+                self.0.borrow_mut().push(*id);
+            }
+        }
+    }
+
+    #[test]
+    fn routes_to_every_sink() {
+        let recorder = Rc::new(Recorder::default());
+        let emitter = Rc::new(DbusEmitter::new());
+        let mut router = ActionRouter::new();
+        router.add(Rc::new(LogSink));
+        router.add(recorder.clone());
+        router.add(emitter.clone());
+
+        router.route(&Signal::NotificationDisplayed { id: 1 });
+        router.route(&Signal::NotificationClosed {
+            id: 1,
+            reason: CloseReason::Dismissed,
+        });
+        assert_eq!(*recorder.0.borrow(), vec![1]);
+        let members: Vec<String> = emitter
+            .take()
+            .iter()
+            .filter_map(|message| message.member().map(|member| member.to_string()))
+            .collect();
+        assert_eq!(members, vec!["NotificationDisplayed", "NotificationClosed"]);
+        assert!(emitter.take().is_empty());
+    }
+}
@@ -0,0 +1,75 @@
+//! Keeps track of the things the server has to do at a set time, like ending a timed pause or
+//! do-not-disturb. Times are by the wall clock rather than `Instant`s, so that they can be saved
+//! and still mean the same thing after a restart or a reboot.
+
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+/// Something the server has to do once its time comes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Event {
+    Unpause,
+    EndDoNotDisturb,
+}
+
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    deadlines: HashMap<Event, SystemTime>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Schedules `event` for `at`, replacing any earlier time for it.
+    pub fn set(&mut self, event: Event, at: SystemTime) {
+        self.deadlines.insert(event, at);
+    }
+
+    pub fn cancel(&mut self, event: Event) {
+        self.deadlines.remove(&event);
+    }
+
+    /// When `event` is scheduled for, if it is.
+    pub fn get(&self, event: Event) -> Option<SystemTime> {
+        self.deadlines.get(&event).copied()
+    }
+
+    /// Takes the events whose time has come by `now`, earliest first.
+    pub fn due(&mut self, now: SystemTime) -> Vec<Event> {
+        let mut due: Vec<(SystemTime, Event)> = self
+            .deadlines
+            .iter()
+            .filter(|(_, &at)| at <= now)
+            .map(|(&event, &at)| (at, event))
+            .collect();
+        due.sort_by_key(|&(at, _)| at);
+        for (_, event) in &due {
+            self.deadlines.remove(event);
+        }
+        due.into_iter().map(|(_, event)| event).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn takes_due_events() {
+        let now = SystemTime::now();
+        let minute = Duration::from_secs(60);
+        let mut scheduler = Scheduler::new();
+        scheduler.set(Event::Unpause, now + minute);
+        scheduler.set(Event::EndDoNotDisturb, now + 2 * minute);
+        assert!(scheduler.due(now).is_empty());
+        // Setting it again moves it.
+        scheduler.set(Event::EndDoNotDisturb, now - minute);
+        assert_eq!(scheduler.due(now), vec![Event::EndDoNotDisturb]);
+        assert_eq!(scheduler.get(Event::EndDoNotDisturb), None);
+        scheduler.cancel(Event::Unpause);
+        assert!(scheduler.due(now + 3 * minute).is_empty());
+    }
+}
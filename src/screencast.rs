@@ -0,0 +1,173 @@
+//! Figures out whether the screen is being shared, by watching apps talk to the ScreenCast portal.
+//!
+//! The portal doesn't announce casts to anyone but the app that asked for them, so we monitor the
+//! session bus instead. A cast goes through a few steps:
+//!
+//! 1. The app calls `ScreenCast.Start` with a session handle.
+//! 2. The portal replies with the path of a request object.
+//! 3. Once the user picks what to share, the request emits `Response`; 0 means it worked.
+//! 4. The cast ends when the app calls `Session.Close` or the portal emits `Session.Closed`.
+
+use anyhow::{Context, Result};
+use dbus::blocking::LocalConnection;
+use dbus::channel::MatchingReceiver;
+use dbus::message::{MatchRule, MessageType};
+use dbus::Message;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::time::Duration;
+use tracing::{debug, info};
+
+const MONITOR_RULES: &[&str] = &[
+    "type='method_call',interface='org.freedesktop.portal.ScreenCast',member='Start'",
+    "type='method_return',sender='org.freedesktop.portal.Desktop'",
+    "type='signal',interface='org.freedesktop.portal.Request',member='Response'",
+    "type='signal',interface='org.freedesktop.portal.Session',member='Closed'",
+    "type='method_call',interface='org.freedesktop.portal.Session',member='Close'",
+];
+
+/// Tracks screen casts through the steps described in the module docs.
+#[derive(Debug, Default)]
+struct Casts {
+    /// Session handles of `Start` calls we haven't seen the reply to, keyed by the caller and the
+    /// call's serial.
+    starting: HashMap<(String, u32), String>,
+    /// Session handles of casts waiting on the user, keyed by the request path.
+    requests: HashMap<String, String>,
+    /// Session handles of casts that are running.
+    active: HashSet<String>,
+}
+
+impl Casts {
+    fn start_called(&mut self, caller: String, serial: u32, session: String) {
+        self.starting.insert((caller, serial), session);
+    }
+
+    fn start_returned(&mut self, caller: String, reply_serial: u32, request: String) {
+        if let Some(session) = self.starting.remove(&(caller, reply_serial)) {
+            self.requests.insert(request, session);
+        }
+    }
+
+    fn responded(&mut self, request: &str, response: u32) {
+        if let Some(session) = self.requests.remove(request) {
+            if response == 0 {
+                info!("Screen cast {} started", session);
+                self.active.insert(session);
+            }
+        }
+    }
+
+    fn closed(&mut self, session: &str) {
+        if self.active.remove(session) {
+            info!("Screen cast {} stopped", session);
+        }
+    }
+
+    fn handle(&mut self, msg: &Message) {
+        let path = msg.path().map(|path| path.to_string());
+        match (msg.msg_type(), msg.member().as_deref()) {
+            (MessageType::MethodCall, Some("Start")) => {
+                if let (Some(caller), Some(serial), Ok(session)) =
+                    (msg.sender(), msg.get_serial(), msg.read1::<dbus::Path>())
+                {
+                    self.start_called(caller.to_string(), serial, session.to_string());
+                }
+            }
+            (MessageType::MethodReturn, _) => {
+                if let (Some(caller), Some(reply_serial), Ok(request)) = (
+                    msg.destination(),
+                    msg.get_reply_serial(),
+                    msg.read1::<dbus::Path>(),
+                ) {
+                    self.start_returned(caller.to_string(), reply_serial, request.to_string());
+                }
+            }
+            (MessageType::Signal, Some("Response")) => {
+                if let (Some(path), Ok(response)) = (path, msg.read1::<u32>()) {
+                    self.responded(&path, response);
+                }
+            }
+            (_, Some("Closed")) | (_, Some("Close")) => {
+                if let Some(path) = path {
+                    self.closed(&path);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct ScreenCastWatcher {
+    /// A connection that's been turned into a monitor, so it can't be used for anything else.
+    connection: LocalConnection,
+    casts: Rc<RefCell<Casts>>,
+}
+
+impl ScreenCastWatcher {
+    pub fn new() -> Result<Self> {
+        let connection = LocalConnection::new_session()
+            .context("couldn't connect to the session bus to watch screen casts")?;
+        connection
+            .with_proxy(
+                "org.freedesktop.DBus",
+                "/org/freedesktop/DBus",
+                Duration::from_millis(1000),
+            )
+            .method_call::<(), _, _, _>(
+                "org.freedesktop.DBus.Monitoring",
+                "BecomeMonitor",
+                (MONITOR_RULES.to_vec(), 0u32),
+            )
+            .context("couldn't start monitoring the session bus")?;
+        let casts = Rc::new(RefCell::new(Casts::default()));
+        let callback_casts = casts.clone();
+        connection.start_receive(
+            MatchRule::new(),
+            Box::new(move |msg, _| {
+                callback_casts.borrow_mut().handle(&msg);
+                true
+            }),
+        );
+        debug!("Watching for screen casts");
+        Ok(ScreenCastWatcher { connection, casts })
+    }
+
+    /// Handles everything that's happened since the last call, without blocking. Returns whether
+    /// the screen is being shared.
+    pub fn poll(&mut self) -> Result<bool> {
+        while self.connection.process(Duration::from_millis(0))? {}
+        Ok(!self.casts.borrow().active.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_a_cast() {
+        let mut casts = Casts::default();
+        casts.start_called(":1.42".into(), 7, "/session/1".into());
+        casts.start_returned(":1.42".into(), 7, "/request/1".into());
+        assert!(casts.active.is_empty());
+        casts.responded("/request/1", 0);
+        assert!(casts.active.contains("/session/1"));
+        casts.closed("/session/1");
+        assert!(casts.active.is_empty());
+    }
+
+    #[test]
+    fn ignores_cancelled_casts() {
+        let mut casts = Casts::default();
+        casts.start_called(":1.42".into(), 7, "/session/1".into());
+        // A reply to some other call from the same app shouldn't count.
+        casts.start_returned(":1.42".into(), 8, "/request/2".into());
+        casts.start_returned(":1.42".into(), 7, "/request/1".into());
+        casts.responded("/request/2", 0);
+        // 1 means the user cancelled.
+        casts.responded("/request/1", 1);
+        assert!(casts.active.is_empty());
+    }
+}
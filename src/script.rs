@@ -0,0 +1,241 @@
+//! Runs a user's Rhai script on notifications, for anything the config file can't express. The
+//! script can define any of these functions:
+//!
+//! - `on_notify(n)`: gets the notification as a map, and returns it (changed or not) to show it or
+//!   `()` to drop it.
+//! - `on_close(id, reason)`: `reason` is `"expired"`, `"dismissed"` or `"closed"`.
+//! - `on_action(id, key)`
+//!
+//! Inside them, `this` is a map that's kept between calls, so scripts can remember things.
+
+use crate::hints::Urgency;
+use crate::router::SignalSink;
+use crate::server::{CloseReason, Notification, Signal};
+use anyhow::{anyhow, Context, Result};
+use rhai::{CallFnOptions, Dynamic, Engine, FuncArgs, Map, Scope, AST};
+use std::cell::RefCell;
+use std::path::Path;
+use tracing::{debug, warn};
+
+/// Scripts run on the server thread, so one stuck in a loop would stop every notification. This
+/// is plenty for anything reasonable.
+const MAX_OPERATIONS: u64 = 100_000;
+
+pub struct Scripts {
+    engine: Engine,
+    ast: AST,
+    /// What the script sees as `this`.
+    state: RefCell<Dynamic>,
+}
+
+fn urgency_from_name(name: &str) -> Option<Urgency> {
+    match name {
+        "low" => Some(Urgency::Low),
+        "normal" => Some(Urgency::Normal),
+        "critical" => Some(Urgency::Critical),
+        _ => None,
+    }
+}
+
+fn optional_string(value: &Option<String>) -> Dynamic {
+    value.clone().map_or(Dynamic::UNIT, Dynamic::from)
+}
+
+/// The parts of `notification` that scripts can see.
+fn to_map(notification: &Notification) -> Map {
+    let mut map = Map::new();
+    map.insert("id".into(), (notification.id as i64).into());
+    map.insert(
+        "app_name".into(),
+        optional_string(&notification.application_name),
+    );
+    map.insert("summary".into(), notification.summary.clone().into());
+    map.insert("body".into(), optional_string(&notification.body));
+    map.insert(
+        "urgency".into(),
+        notification.hints.urgency.name().to_owned().into(),
+    );
+    map.insert(
+        "category".into(),
+        optional_string(&notification.hints.category),
+    );
+    map
+}
+
+/// Reads an optional string field back out of a map, where `()` means unset.
+fn read_optional_string(map: &Map, key: &str) -> Result<Option<String>> {
+    match map.get(key) {
+        None => Ok(None),
+        Some(value) if value.is_unit() => Ok(None),
+        Some(value) => value
+            .clone()
+            .into_string()
+            .map(Some)
+            .map_err(|ty| anyhow!("{} should be a string, not {}", key, ty)),
+    }
+}
+
+/// Copies the fields a script can change from `map` back into `notification`. Everything is
+/// checked first, so a bad field leaves the notification alone.
+fn apply_map(map: &Map, notification: &mut Notification) -> Result<()> {
+    let application_name = read_optional_string(map, "app_name")?;
+    let summary = read_optional_string(map, "summary")?.unwrap_or_default();
+    let body = read_optional_string(map, "body")?;
+    let category = read_optional_string(map, "category")?;
+    let urgency = match read_optional_string(map, "urgency")? {
+        Some(name) => {
+            urgency_from_name(&name).ok_or_else(|| anyhow!("unknown urgency {}", name))?
+        }
+        None => notification.hints.urgency,
+    };
+    notification.application_name = application_name;
+    notification.summary = summary;
+    notification.body = body;
+    notification.hints.category = category;
+    notification.hints.urgency = urgency;
+    Ok(())
+}
+
+impl Scripts {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| debug!("Script: {}", text));
+        let ast = engine
+            .compile_file(path.to_owned())
+            .map_err(|err| anyhow!("{}", err))
+            .with_context(|| format!("couldn't load script {:?}", path))?;
+        Ok(Scripts {
+            engine,
+            ast,
+            state: RefCell::new(Map::new().into()),
+        })
+    }
+
+    fn has_hook(&self, name: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|function| function.name == name)
+    }
+
+    /// Calls the hook `name`, if the script has it.
+    fn call(&self, name: &str, args: impl FuncArgs) -> Result<Option<Dynamic>> {
+        if !self.has_hook(name) {
+            return Ok(None);
+        }
+        let mut state = self.state.borrow_mut();
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut *state);
+        self.engine
+            .call_fn_with_options(options, &mut Scope::new(), &self.ast, name, args)
+            .map(Some)
+            .map_err(|err| anyhow!("{} failed: {}", name, err))
+    }
+
+    /// Runs `on_notify`, which can change `notification`. Returns false if the script dropped it.
+    /// If the script fails, the notification is left alone.
+    pub fn on_notify(&self, notification: &mut Notification) -> bool {
+        let result = match self.call("on_notify", (to_map(notification),)) {
+            Ok(Some(result)) => result,
+            Ok(None) => return true,
+            Err(err) => {
+                warn!("{:?}", err);
+                return true;
+            }
+        };
+        if result.is_unit() {
+            debug!("Script dropped notification {}", notification.id);
+            return false;
+        }
+        let map = match result.try_cast::<Map>() {
+            Some(map) => map,
+            None => {
+                warn!("on_notify should return the notification or (), not something else");
+                return true;
+            }
+        };
+        // Fields the script removed keep their old values.
+        let mut changed = to_map(notification);
+        changed.extend(map);
+        if let Err(err) = apply_map(&changed, notification) {
+            warn!("on_notify returned a bad notification: {:?}", err);
+        }
+        true
+    }
+
+    pub fn on_close(&self, id: u32, reason: CloseReason) {
+        let reason = match reason {
+            CloseReason::Expired => "expired",
+            CloseReason::Dismissed => "dismissed",
+            CloseReason::Closed => "closed",
+        };
+        if let Err(err) = self.call("on_close", (id as i64, reason.to_owned())) {
+            warn!("{:?}", err);
+        }
+    }
+
+    pub fn on_action(&self, id: u32, key: &str) {
+        if let Err(err) = self.call("on_action", (id as i64, key.to_owned())) {
+            warn!("{:?}", err);
+        }
+    }
+}
+
+impl SignalSink for Scripts {
+    fn handle(&self, signal: &Signal) {
+        match signal {
+            Signal::ActionInvoked { id, key } => self.on_action(*id, key),
+            Signal::NotificationClosed { id, reason } => self.on_close(*id, *reason),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn scripts(source: &str) -> Scripts {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(source.as_bytes()).unwrap();
+        Scripts::load(file.path()).unwrap()
+    }
+
+    #[test]
+    fn runs_on_notify() {
+        let scripts = scripts(
+            r#"
+            fn on_notify(n) {
+                if this.last == n.summary {
+                    return ();
+                }
+                this.last = n.summary;
+                n.summary = n.summary.to_upper();
+                n.urgency = "critical";
+                n
+            }
+            "#,
+        );
+        let mut notification = Notification::builder()
+            .id(1)
+            .summary("build failed")
+            .build();
+        assert!(scripts.on_notify(&mut notification));
+        assert_eq!(notification.summary, "BUILD FAILED");
+        assert_eq!(notification.hints.urgency, Urgency::Critical);
+
+        let mut repeat = Notification::builder()
+            .id(2)
+            .summary("build failed")
+            .build();
+        assert!(!scripts.on_notify(&mut repeat));
+
+        // Scripts without a hook leave things alone, and broken return values are ignored.
+        let mut notification = Notification::builder().id(3).summary("hi").build();
+        assert!(self::scripts("fn on_close(id, reason) {}").on_notify(&mut notification));
+        assert!(self::scripts("fn on_notify(n) { 3 }").on_notify(&mut notification));
+        assert_eq!(notification.summary, "hi");
+    }
+}
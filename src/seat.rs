@@ -0,0 +1,290 @@
+//! Works out which login session (and seat) ninomiya belongs to by asking logind, so that on a
+//! machine with several seats it uses its own session's bus and display, and doesn't show
+//! notifications in front of whoever is sitting at another seat.
+//!
+//! None of this is needed on an ordinary single-seat machine, and all of it is skipped if logind
+//! isn't around or doesn't know about us.
+
+use anyhow::{bail, Context, Result};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::blocking::Connection;
+use std::env;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{debug, info};
+
+const LOGIND_NAME: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const USER_INTERFACE: &str = "org.freedesktop.login1.User";
+const DBUS_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// A logind session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub id: String,
+    pub uid: u32,
+    /// Empty for sessions that aren't on a seat, like SSH logins.
+    pub seat: String,
+    /// The X11 display, if it's a graphical session that has one.
+    pub display: String,
+}
+
+impl Session {
+    fn seat_name(&self) -> &str {
+        if self.seat.is_empty() {
+            "no seat"
+        } else {
+            &self.seat
+        }
+    }
+}
+
+fn read_session(connection: &Connection, path: dbus::Path<'static>) -> Result<Session> {
+    let proxy = connection.with_proxy(LOGIND_NAME, path, DBUS_TIMEOUT);
+    let (uid, _): (u32, dbus::Path<'static>) = proxy.get(SESSION_INTERFACE, "User")?;
+    let (seat, _): (String, dbus::Path<'static>) = proxy.get(SESSION_INTERFACE, "Seat")?;
+    Ok(Session {
+        id: proxy.get(SESSION_INTERFACE, "Id")?,
+        uid,
+        seat,
+        display: proxy.get(SESSION_INTERFACE, "Display")?,
+    })
+}
+
+/// The path of the session we're running in: the one in `XDG_SESSION_ID`, or the one our process
+/// is in. Failing those (say, for a systemd user service, which isn't in any session), it's our
+/// user's graphical session, if they have one.
+fn session_path(connection: &Connection) -> Result<Option<dbus::Path<'static>>> {
+    let manager = connection.with_proxy(LOGIND_NAME, LOGIND_PATH, DBUS_TIMEOUT);
+    if let Ok(id) = env::var("XDG_SESSION_ID") {
+        let (path,) = manager
+            .method_call(MANAGER_INTERFACE, "GetSession", (id.as_str(),))
+            .with_context(|| format!("logind doesn't know about session {:?}", id))?;
+        return Ok(Some(path));
+    }
+    let by_pid: Result<(dbus::Path<'static>,), _> =
+        manager.method_call(MANAGER_INTERFACE, "GetSessionByPID", (std::process::id(),));
+    if let Ok((path,)) = by_pid {
+        return Ok(Some(path));
+    }
+    let uid = fs::metadata("/proc/self")?.uid();
+    let user: Result<(dbus::Path<'static>,), _> =
+        manager.method_call(MANAGER_INTERFACE, "GetUser", (uid,));
+    let (user,) = match user {
+        Ok(user) => user,
+        Err(err) => {
+            debug!("logind doesn't know about us: {}", err);
+            return Ok(None);
+        }
+    };
+    let (id, path): (String, dbus::Path<'static>) = connection
+        .with_proxy(LOGIND_NAME, user, DBUS_TIMEOUT)
+        .get(USER_INTERFACE, "Display")?;
+    Ok(if id.is_empty() { None } else { Some(path) })
+}
+
+/// The login session we're running in, if logind knows about it.
+pub fn current_session() -> Result<Option<Session>> {
+    let connection = Connection::new_system().context("couldn't connect to the system bus")?;
+    match session_path(&connection)? {
+        Some(path) => {
+            let session = read_session(&connection, path)?;
+            debug!("Running in session {:?}", session);
+            Ok(Some(session))
+        }
+        None => Ok(None),
+    }
+}
+
+/// A session as `ListSessions` lists it: its ID, user ID, user name, seat, and object path.
+type ListedSession = (String, u32, String, String, dbus::Path<'static>);
+
+/// Every session logind knows about.
+fn sessions(connection: &Connection) -> Result<Vec<Session>> {
+    let (listed,): (Vec<ListedSession>,) = connection
+        .with_proxy(LOGIND_NAME, LOGIND_PATH, DBUS_TIMEOUT)
+        .method_call(MANAGER_INTERFACE, "ListSessions", ())
+        .context("couldn't list sessions")?;
+    Ok(listed
+        .into_iter()
+        // Sessions can end while we're looking at them.
+        .filter_map(|(_, _, _, _, path)| read_session(connection, path).ok())
+        .collect())
+}
+
+/// Whether logind knows about more than one seat. If it can't tell us, there's only the one as far
+/// as we're concerned.
+pub fn several_seats() -> bool {
+    let seats = Connection::new_system()
+        .context("couldn't connect to the system bus")
+        .and_then(|connection| {
+            let (seats,): (Vec<(String, dbus::Path<'static>)>,) = connection
+                .with_proxy(LOGIND_NAME, LOGIND_PATH, DBUS_TIMEOUT)
+                .method_call(MANAGER_INTERFACE, "ListSeats", ())
+                .context("couldn't list seats")?;
+            Ok(seats.len())
+        });
+    match seats {
+        Ok(seats) => seats > 1,
+        Err(err) => {
+            debug!("Couldn't count seats: {:?}", err);
+            false
+        }
+    }
+}
+
+/// The DBus name to use with `--testing`. On a machine with several seats, or on a seat other than
+/// `seat0`, it gets the seat on the end (like
+/// `org.freedesktop.NotificationsNinomiyaTesting.seat1`), so that each seat can run its own;
+/// otherwise it's just `base`, so that tools expecting the usual testing name still find it.
+pub fn testing_name(base: &str, session: Option<&Session>, several_seats: bool) -> String {
+    match session
+        .filter(|session| !session.seat.is_empty() && (several_seats || session.seat != "seat0"))
+    {
+        Some(session) => {
+            // Seat names are already fine in bus names, but it doesn't hurt to make sure.
+            let seat: String = session
+                .seat
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            format!("{}.{}", base, seat)
+        }
+        None => base.to_owned(),
+    }
+}
+
+/// Points us at `session`'s bus and display, if we weren't started with them. That happens when
+/// ninomiya is started outside the session, say from a systemd user service.
+pub fn bind_environment(session: &Session) {
+    if env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none() {
+        let bus = Path::new("/run/user")
+            .join(session.uid.to_string())
+            .join("bus");
+        if bus.exists() {
+            info!("Using the session bus at {}", bus.display());
+            env::set_var(
+                "DBUS_SESSION_BUS_ADDRESS",
+                format!("unix:path={}", bus.display()),
+            );
+        }
+    }
+    if env::var_os("DISPLAY").is_none()
+        && env::var_os("WAYLAND_DISPLAY").is_none()
+        && !session.display.is_empty()
+    {
+        info!("Using session {}'s display {}", session.id, session.display);
+        env::set_var("DISPLAY", &session.display);
+    }
+}
+
+/// Strips the screen number off an X11 display, since `:0` and `:0.0` are the same display.
+fn display_without_screen(display: &str) -> &str {
+    let display = display.trim();
+    match display.rfind(':') {
+        Some(colon) => match display[colon..].find('.') {
+            Some(dot) => &display[..colon + dot],
+            None => display,
+        },
+        None => display,
+    }
+}
+
+fn check_display_owner(session: &Session, display: &str, sessions: &[Session]) -> Result<()> {
+    let owners: Vec<&Session> = sessions
+        .iter()
+        .filter(|other| {
+            !other.display.is_empty()
+                && display_without_screen(&other.display) == display_without_screen(display)
+        })
+        .collect();
+    // Nobody owning it is fine too; it might be a nested or headless X server.
+    let ours = owners.iter().any(|owner| {
+        owner.id == session.id
+            || (!session.seat.is_empty() && owner.seat == session.seat && owner.uid == session.uid)
+    });
+    match owners.first() {
+        Some(owner) if !ours => bail!(
+            "display {} belongs to session {} (user {}, {}), but ninomiya was started from \
+             session {} (user {}, {}), so it won't show notifications there; set DISPLAY to \
+             your own session's display",
+            display,
+            owner.id,
+            owner.uid,
+            owner.seat_name(),
+            session.id,
+            session.uid,
+            session.seat_name()
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Fails if the X11 display we're about to show notifications on belongs to another session's
+/// seat or user. On Wayland there's no display name to check.
+pub fn check_display(session: &Session) -> Result<()> {
+    let display = match env::var("DISPLAY") {
+        Ok(display) => display,
+        Err(_) => return Ok(()),
+    };
+    let connection = Connection::new_system().context("couldn't connect to the system bus")?;
+    check_display_owner(session, &display, &sessions(&connection)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(id: &str, uid: u32, seat: &str, display: &str) -> Session {
+        Session {
+            id: id.to_owned(),
+            uid,
+            seat: seat.to_owned(),
+            display: display.to_owned(),
+        }
+    }
+
+    #[test]
+    fn checks_display_owner() {
+        let ours = session("2", 1000, "seat0", ":0");
+        let theirs = session("5", 1001, "seat1", ":1");
+        let sessions = vec![ours.clone(), theirs.clone()];
+        assert!(check_display_owner(&ours, ":0.0", &sessions).is_ok());
+        assert!(check_display_owner(&ours, ":1", &sessions).is_err());
+        // A display nobody owns is fine.
+        assert!(check_display_owner(&ours, ":5", &sessions).is_ok());
+        // So is one from another of our sessions on the same seat, but not one from a session
+        // without a seat.
+        let other = session("3", 1000, "seat0", "");
+        assert!(check_display_owner(&other, ":0", &sessions).is_ok());
+        let ssh = session("4", 1000, "", "");
+        assert!(check_display_owner(&ssh, ":0", &sessions).is_err());
+
+        assert_eq!(
+            testing_name("org.example.Testing", Some(&theirs), false),
+            "org.example.Testing.seat1"
+        );
+        assert_eq!(
+            testing_name("org.example.Testing", Some(&ours), false),
+            "org.example.Testing"
+        );
+        assert_eq!(
+            testing_name("org.example.Testing", Some(&ours), true),
+            "org.example.Testing.seat0"
+        );
+        assert_eq!(
+            testing_name("org.example.Testing", None, true),
+            "org.example.Testing"
+        );
+    }
+}
@@ -1,19 +1,53 @@
+use crate::action_command::ActionCommands;
+use crate::app_filter::{self, AppFilter};
+use crate::app_icon::{self, IconInferrer};
+use crate::attention::AttentionMarker;
+use crate::calendar::CalendarWatcher;
+use crate::config::ScreenShareMode;
+use crate::control::{self, Properties};
 use crate::dbus_codegen::server as dbus_server;
-use crate::hints::{Hints, ImageRef};
-use anyhow::{bail, Context, Result};
+use crate::digest::Digester;
+use crate::error::NotifyError;
+use crate::focus::FocusWatcher;
+use crate::forward::Forwarder;
+use crate::grouping::{Grouped, Grouper};
+use crate::hints::{HintMap, Hints, ImageRef, OwnedHints, Urgency};
+use crate::history::{Entry, History};
+use crate::json_log::JsonLog;
+use crate::latency::Latencies;
+use crate::lifecycle::{Lifecycle, Resolution};
+use crate::low_power::{self, LowPower};
+use crate::markup;
+use crate::mirror::Mirror;
+use crate::processor::Processors;
+use crate::push::Pusher;
+use crate::raise::Raiser;
+use crate::router::{ActionRouter, DbusEmitter, LogSink};
+use crate::schedule::{Event, Scheduler};
+use crate::screencast::ScreenCastWatcher;
+use crate::script::Scripts;
+use crate::spam::{SpamGuard, Verdict};
+use crate::state::{Mute, SavedNotification, SavedState, StateKeeper};
+use crate::watchdog::Watchdog;
+use anyhow::{anyhow, Context, Result};
 use dbus::blocking::stdintf::org_freedesktop_dbus::RequestNameReply;
 use dbus::blocking::LocalConnection;
 use dbus::channel::Sender;
 use dbus::message::SignalArgs;
 use dbus::{self, arg, tree};
-use log::{debug, error, info, trace};
-use std::cell::Cell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::sync::mpsc::{Receiver, TryRecvError};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, debug_span, error, info, trace, warn};
 
 /// Indicates that the notification has some action that the user can take.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Action {
     /// An internal ID, to be used when sending the signal back to the originating application.
     pub key: String,
@@ -21,7 +55,7 @@ pub struct Action {
     pub label: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Notification {
     /// An arbitrary ID number. Generated by `ninomiya`, only used internally.
     pub id: u32,
@@ -34,9 +68,242 @@ pub struct Notification {
     pub application_name: Option<String>,
     /// A brief summary of the notification.
     pub summary: String,
-    /// The notification body.
+    /// The notification body, minus any `<img>` tags.
     pub body: Option<String>,
+    /// Images from `<img>` tags in the body, to be displayed below it.
+    pub body_images: Vec<ImageRef>,
     pub hints: Hints,
+    /// True if this notification comes from ninomiya itself. Internal notifications are shown
+    /// even when we're paused or in do-not-disturb mode.
+    pub internal: bool,
+    /// When it arrived and was shown, as of when it was sent to the GUI.
+    pub lifecycle: Lifecycle,
+    /// The notification whose window it takes over, if it was coalesced with one its app just
+    /// closed.
+    pub replaces: Option<u32>,
+}
+
+/// How long after a `CloseNotification` a `Notify` from the same sender is treated as replacing the
+/// closed notification, when `coalesce_close_notify` is on.
+const COALESCE_WINDOW: Duration = Duration::from_millis(200);
+
+/// How long `SetTheme` waits for the GUI to load the theme.
+const SET_THEME_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The ninomiya extensions clients can look for in `GetCapabilities`: the `value` hint is shown as
+/// a progress bar, and the `x-ninomiya-style-class` hint adds a CSS class to the window.
+const EXTENSION_CAPABILITIES: &[&str] = &["x-ninomiya-progress", "x-ninomiya-style-class"];
+
+/// A `CloseNotification` we're holding off on, in case it's followed by a `Notify` that updates the
+/// notification.
+#[derive(Debug)]
+struct PendingClose {
+    id: u32,
+    sender: String,
+    at: Instant,
+}
+
+/// IDs for internal notifications count down from the top, so they never collide with the ones we
+/// hand out to clients.
+static NEXT_INTERNAL_ID: AtomicU32 = AtomicU32::new(u32::max_value());
+
+impl Notification {
+    /// Starts building a notification. Everything but the summary is optional.
+    pub fn builder() -> NotificationBuilder {
+        NotificationBuilder {
+            notification: Notification {
+                id: 0,
+                actions: vec![],
+                icon: None,
+                application_name: None,
+                summary: String::new(),
+                body: None,
+                body_images: vec![],
+                hints: Hints::new(),
+                internal: false,
+                lifecycle: Lifecycle::default(),
+                replaces: None,
+            },
+        }
+    }
+
+    /// Builds a notification telling the user about something that happened to the daemon.
+    pub fn internal(summary: &str, body: &str) -> Self {
+        Notification::builder()
+            .own_id()
+            .application_name("ninomiya")
+            .summary(summary)
+            .body(body)
+            .internal(true)
+            .build()
+    }
+
+    /// Builds a notification summing up `held`, which isn't empty, with the first one's app and
+    /// icon. It isn't about ninomiya itself, so unlike [`Notification::internal`]'s, it respects
+    /// pausing and so on. Clicking on it invokes its `default` action.
+    pub fn summing_up(held: &[Notification], summary: &str, body: &str) -> Self {
+        let first = &held[0];
+        let mut notification = Notification::builder()
+            .own_id()
+            .summary(summary)
+            .body(body)
+            .action("default", "")
+            .build();
+        notification.application_name = first.application_name.clone();
+        notification.icon = first.icon.clone();
+        notification.hints = Hints {
+            desktop_entry: first.hints.desktop_entry.clone(),
+            category: first.hints.category.clone(),
+            ..Hints::new()
+        };
+        notification
+    }
+
+    /// A notification with just an app and a summary, for tests.
+    #[cfg(test)]
+    pub(crate) fn from_app(app_name: &str, summary: &str) -> Self {
+        Notification::builder()
+            .own_id()
+            .application_name(app_name)
+            .summary(summary)
+            .build()
+    }
+
+    /// Hides everything that might be private, leaving just enough to tell that something arrived.
+    pub fn redact(&mut self) {
+        self.summary = "New notification".to_owned();
+        self.body = None;
+        self.body_images.clear();
+        self.hints.image = None;
+        self.actions.clear();
+    }
+
+    /// Roughly how many bytes the notification takes up, which is mostly its text and images.
+    pub fn approximate_size(&self) -> usize {
+        let image_size = |image: &ImageRef| match image {
+            ImageRef::Image { image_data, .. } => image_data.len(),
+            ImageRef::Url(url) => url.as_str().len(),
+            ImageRef::IconName(name) => name.len(),
+        };
+        let text = [&self.application_name, &self.body]
+            .iter()
+            .map(|text| text.as_deref().map_or(0, str::len))
+            .sum::<usize>()
+            + self.summary.len()
+            + self
+                .actions
+                .iter()
+                .map(|action| action.key.len() + action.label.len())
+                .sum::<usize>();
+        let images: usize = self
+            .icon
+            .iter()
+            .chain(&self.hints.image)
+            .chain(&self.body_images)
+            .map(image_size)
+            .sum();
+        std::mem::size_of::<Notification>() + text + images
+    }
+
+    /// Drops the images that are carried in the notification itself (raw pixels and `data:` URLs)
+    /// rather than referred to. Returns whether there were any.
+    pub fn drop_inline_images(&mut self) -> bool {
+        let inline = |image: &ImageRef| match image {
+            ImageRef::Image { .. } => true,
+            ImageRef::Url(url) => url.scheme() == "data",
+            ImageRef::IconName(_) => false,
+        };
+        let body_images = self.body_images.len();
+        self.body_images.retain(|image| !inline(image));
+        let mut dropped = self.body_images.len() != body_images;
+        for image in &mut [&mut self.icon, &mut self.hints.image] {
+            if image.as_ref().map_or(false, inline) {
+                **image = None;
+                dropped = true;
+            }
+        }
+        dropped
+    }
+
+    /// Hides the body, but leaves the summary (usually who it's from) and the actions alone.
+    pub fn redact_body(&mut self) {
+        self.body = Some("New message".to_owned());
+        self.body_images.clear();
+        self.hints.image = None;
+    }
+}
+
+/// Builds a `Notification` one field at a time, in the style of GTK's widget builders. Get one
+/// from `Notification::builder()`.
+#[derive(Debug)]
+pub struct NotificationBuilder {
+    notification: Notification,
+}
+
+impl NotificationBuilder {
+    pub fn id(mut self, id: u32) -> Self {
+        self.notification.id = id;
+        self
+    }
+
+    /// Gives it an ID of ninomiya's own, for notifications that don't come from a client.
+    pub fn own_id(self) -> Self {
+        self.id(NEXT_INTERNAL_ID.fetch_sub(1, Ordering::Relaxed))
+    }
+
+    pub fn application_name(mut self, application_name: &str) -> Self {
+        self.notification.application_name = owned_if_nonempty(application_name);
+        self
+    }
+
+    pub fn icon(mut self, icon: ImageRef) -> Self {
+        self.notification.icon = Some(icon);
+        self
+    }
+
+    pub fn summary(mut self, summary: &str) -> Self {
+        self.notification.summary = summary.to_owned();
+        self
+    }
+
+    /// An empty body is the same as no body.
+    pub fn body(mut self, body: &str) -> Self {
+        self.notification.body = owned_if_nonempty(body);
+        self
+    }
+
+    pub fn urgency(mut self, urgency: Urgency) -> Self {
+        self.notification.hints.urgency = urgency;
+        self
+    }
+
+    pub fn image(mut self, image: ImageRef) -> Self {
+        self.notification.hints.image = Some(image);
+        self
+    }
+
+    /// Adds an action. Can be called more than once; the buttons are shown in order.
+    pub fn action(mut self, key: &str, label: &str) -> Self {
+        self.notification.actions.push(Action {
+            key: key.to_owned(),
+            label: label.to_owned(),
+        });
+        self
+    }
+
+    pub fn internal(mut self, internal: bool) -> Self {
+        self.notification.internal = internal;
+        self
+    }
+
+    pub fn received_at(mut self, received_at: Instant) -> Self {
+        self.notification.lifecycle = Lifecycle::received(received_at);
+        self
+    }
+
+    pub fn build(self) -> Notification {
+        self.notification
+    }
 }
 
 #[derive(Debug)]
@@ -44,14 +311,109 @@ pub enum NinomiyaEvent {
     /// A notification to be displayed.
     Notification(Notification),
     /// The given notification should be closed.
-    CloseNotification(u32),
+    CloseNotification(u32, CloseReason),
+    /// The user wants to pick an action on the newest notification with the keyboard.
+    SelectAction,
+    /// Show the notification the user last dismissed again.
+    UndoClose,
+    /// Switch to the theme in this file until ninomiya is restarted, and say whether it could be
+    /// loaded.
+    SetTheme(PathBuf, mpsc::Sender<Result<(), String>>),
+    /// Restack the windows on screen. The GUI sends this to itself, say when the pointer moves
+    /// over a deck of notifications.
+    Relayout,
+    /// Show the whole body of the notification, or cut it short again. The GUI sends this to itself
+    /// when the pointer moves over a notification whose body `body_lines` cut short.
+    ExpandBody(u32, bool),
 }
 
-/// Represents all the signals that we can emit, according to the DBus notification specification.
+/// Why a notification was closed. The values are the ones used by the `NotificationClosed` signal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The notification timed out.
+    Expired = 1,
+    /// The user dismissed the notification.
+    Dismissed = 2,
+    /// The notification was closed by a call to `CloseNotification`.
+    Closed = 3,
+}
+
+/// Represents all the signals that we can emit, either from the DBus notification specification
+/// or on our control interface.
 #[derive(Debug)]
 pub enum Signal {
     /// The user invoked an action on the notification.
     ActionInvoked { id: u32, key: String },
+    /// The notification's window went away.
+    NotificationClosed { id: u32, reason: CloseReason },
+    /// The notification's window was shown.
+    NotificationDisplayed { id: u32 },
+    /// The notification is being held until we're unpaused.
+    NotificationQueued { id: u32 },
+}
+
+/// What to save of a notification to bring it back after a restart, or to pass on to another
+/// daemon: what it looks like once scripts, processors and redaction are done with it, so that
+/// hidden bodies never leave us. `app_icon` and `hints` are the ones it came with over DBus.
+fn saved_notification(
+    notification: &Notification,
+    app_icon: &str,
+    mut hints: OwnedHints,
+) -> SavedNotification {
+    hints.follow(&notification.hints);
+    let mut body = notification.body.clone().unwrap_or_default();
+    // Put back the images that were taken out of the body, so they're there once it's restored.
+    for image in &notification.body_images {
+        if let ImageRef::Url(url) = image {
+            body.push_str(&format!(
+                "<img src=\"{}\"/>",
+                url.as_str().replace('&', "&amp;")
+            ));
+        }
+    }
+    SavedNotification {
+        id: notification.id,
+        app_name: notification.application_name.clone().unwrap_or_default(),
+        app_icon: app_icon.to_owned(),
+        summary: notification.summary.clone(),
+        body,
+        actions: notification
+            .actions
+            .iter()
+            .flat_map(|action| vec![action.key.clone(), action.label.clone()])
+            .collect(),
+        hints,
+        received: notification.lifecycle.received,
+        shown_at: notification.lifecycle.shown_at,
+        queued: false,
+    }
+}
+
+/// Parses the `app_icon` argument to `Notify`.
+fn parse_icon(app_icon: &str) -> Option<ImageRef> {
+    if app_icon.is_empty() {
+        return None;
+    }
+    // A bad icon (say, a path that doesn't exist) shouldn't stop the notification from showing up;
+    // the GUI will fall back to something else.
+    app_icon
+        .parse()
+        .map_err(|err| info!("Couldn't parse app icon {}: {:?}", app_icon, err))
+        .ok()
+}
+
+/// Pairs up the `actions` argument to `Notify`, which alternates keys and labels.
+fn parse_actions<S: AsRef<str>>(actions: &[S]) -> Result<Vec<Action>, NotifyError> {
+    if actions.len() % 2 != 0 {
+        return Err(NotifyError::InvalidActionList(actions.len()));
+    }
+    Ok(actions
+        .chunks_exact(2)
+        .map(|c| Action {
+            key: c[0].as_ref().to_owned(),
+            label: c[1].as_ref().to_owned(),
+        })
+        .collect())
 }
 
 fn owned_if_nonempty(s: &str) -> Option<String> {
@@ -62,6 +424,17 @@ fn owned_if_nonempty(s: &str) -> Option<String> {
     }
 }
 
+/// Why [`NotifyServer::run`] failed when it couldn't own the bus name it was asked to serve on.
+/// Holds the name.
+#[derive(Debug)]
+pub struct NameUnavailable(pub String);
+
+impl fmt::Display for NameUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "couldn't own the bus name {}", self.0)
+    }
+}
+
 /// Handles the state of the notification server. This doesn't deal with talking with DBus or
 /// anything.
 pub struct NotifyServer {
@@ -69,6 +442,116 @@ pub struct NotifyServer {
     /// have one NotificationServer at a time.
     next_id: Cell<u32>,
     callback: Box<dyn Fn(NinomiyaEvent) -> ()>,
+    /// If set, every notification is also re-sent to another daemon.
+    forwarder: Option<Rc<Forwarder>>,
+    mirror: Option<Mirror>,
+    pusher: Option<Pusher>,
+    scripts: Option<Rc<Scripts>>,
+    action_commands: Option<Rc<RefCell<ActionCommands>>>,
+    raiser: Option<Rc<RefCell<Raiser>>>,
+    /// Where signals from the GUI go once the server's done with them: the log, `emitter`, and
+    /// whichever of the above want them.
+    router: ActionRouter,
+    emitter: Rc<DbusEmitter>,
+    attention_marker: Option<AttentionMarker>,
+    processors: Option<Processors>,
+    /// If set, every notification and its resolution is recorded here.
+    json_log: Option<RefCell<JsonLog>>,
+    /// If set, notifications from apps it doesn't allow are dropped.
+    app_filter: Option<AppFilter>,
+    /// Notifications from apps matching these patterns have their bodies hidden.
+    redact_body: Vec<String>,
+    /// If set, bursts of notifications from one app are rolled up into one.
+    grouper: Option<RefCell<Grouper>>,
+    /// The notification center, where everything that got past the filters can be looked at,
+    /// popup or not.
+    history: RefCell<History>,
+    /// If set, apps sending notifications too fast are muted for a while.
+    spam_guard: Option<RefCell<SpamGuard>>,
+    /// The notices on screen about apps `spam_guard` muted, along with the app each is about.
+    spam_notices: RefCell<HashMap<u32, String>>,
+    /// If set, notifications matching its rules are collected and shown as periodic digests.
+    digester: Option<RefCell<Digester>>,
+    /// If set, used to guess icons for notifications that don't specify one.
+    icon_inferrer: Option<IconInferrer>,
+    /// If set, reported by `GetStats`.
+    latencies: Option<Arc<Latencies>>,
+    /// Keeps an eye on the GUI thread, if set.
+    watchdog: Option<RefCell<Watchdog>>,
+    /// If set, the loop slows down while low-power mode is on.
+    low_power: Option<Arc<LowPower>>,
+    /// The unique bus name of whoever sent the message we're handling. The generated trait
+    /// doesn't give us the message, so the tree stashes it here before calling us.
+    sender: RefCell<Option<String>>,
+    /// If set, tells us when the screen is being shared, so we can act according to
+    /// `screen_share_mode`.
+    screen_cast_watcher: Option<RefCell<ScreenCastWatcher>>,
+    screen_share_mode: ScreenShareMode,
+    screen_shared: Cell<bool>,
+    /// Whether the current pause is because of screen sharing, so that we only unpause when
+    /// sharing stops if the user didn't pause us themselves.
+    paused_for_sharing: Cell<bool>,
+    paused: Cell<bool>,
+    /// If set, tells us which window has focus, so we can turn on do-not-disturb while one
+    /// matching `dnd_when_focused` is in front.
+    focus_watcher: Option<RefCell<FocusWatcher>>,
+    dnd_when_focused: Vec<String>,
+    /// Whether do-not-disturb is on because of the focused window, so that we only turn it off
+    /// when focus moves if the user didn't turn it on themselves.
+    dnd_for_focus: Cell<bool>,
+    /// If set, do-not-disturb is turned on while the user is busy according to their calendar.
+    calendar: Option<RefCell<CalendarWatcher>>,
+    /// Whether do-not-disturb is on because of the calendar. Critical notifications still get
+    /// through then.
+    dnd_for_calendar: Cell<bool>,
+    /// The start of the calendar event during which the user last set do-not-disturb themselves,
+    /// so that we leave it be for the rest of that event.
+    calendar_override: Cell<Option<i64>>,
+    do_not_disturb: Cell<bool>,
+    /// When timed pauses and do-not-disturb end.
+    schedule: RefCell<Scheduler>,
+    /// If set, a timed pause ends with a digest of what arrived during it, rather than showing it
+    /// all.
+    pause_digest: bool,
+    /// If set, notifications dropped for do-not-disturb are kept in `suppressed`, and summed up in
+    /// a digest once it's turned off.
+    dnd_digest: bool,
+    suppressed: RefCell<Vec<Notification>>,
+    /// If set, how many bytes `suppressed` and `waiting` can take up before we start shedding
+    /// images and notifications from them.
+    queue_memory_limit: Option<usize>,
+    /// The ID of the digest on screen, along with the notifications it's summing up.
+    digest: RefCell<Option<(u32, Vec<Notification>)>>,
+    /// Notifications that arrived while we were paused, in the order they arrived.
+    waiting: RefCell<Vec<Notification>>,
+    /// Whether a `Notify` right after a `CloseNotification` from the same sender replaces the
+    /// closed notification instead of making a new one.
+    coalesce_close_notify: bool,
+    /// Whether `GetCapabilities` lists our extensions.
+    advertise_extensions: bool,
+    pending_closes: RefCell<Vec<PendingClose>>,
+    /// IDs of the notifications the GUI is displaying.
+    displayed: RefCell<HashSet<u32>>,
+    /// IDs of notifications brought back with `undo_close`, whose apps were already told they
+    /// closed.
+    brought_back: RefCell<HashSet<u32>>,
+    /// The lifecycles of the notifications we've received and not yet seen closed.
+    lifecycles: RefCell<HashMap<u32, Lifecycle>>,
+    /// The app that sent each of them, for counting unread notifications by app.
+    app_names: RefCell<HashMap<u32, String>>,
+    /// How many notifications have been resolved each way, for `GetStats`.
+    resolutions: RefCell<HashMap<Resolution, u32>>,
+    /// Signals generated by the server itself, to be sent on the next turn of the loop.
+    outgoing: RefCell<Vec<Signal>>,
+    /// If set, what's on screen and queued is kept here, to be saved when we shut down.
+    state: Option<Arc<StateKeeper>>,
+    /// What was saved the last time we shut down, to be brought back once we're running.
+    restored: RefCell<Option<SavedState>>,
+    /// If set, whether the user paused notifications or turned on do-not-disturb is saved here
+    /// whenever it changes, and brought back when we start.
+    mute_path: Option<PathBuf>,
+    /// When this server was created, for `Ping`. Restarting the server resets it.
+    started_at: Instant,
 }
 
 impl fmt::Debug for NotifyServer {
@@ -79,14 +562,275 @@ impl fmt::Debug for NotifyServer {
 
 impl NotifyServer {
     pub fn new<F: Fn(NinomiyaEvent) -> () + 'static>(callback: F) -> Self {
+        let emitter = Rc::new(DbusEmitter::new());
+        let mut router = ActionRouter::new();
+        router.add(Rc::new(LogSink));
+        router.add(emitter.clone());
         NotifyServer {
             // A lot of client libraries seem to use 0 as the fallback ID for sent notifications,
             // so we shouldn't use 0 as the default.
             next_id: Cell::new(1),
             callback: Box::new(callback),
+            forwarder: None,
+            mirror: None,
+            pusher: None,
+            scripts: None,
+            action_commands: None,
+            raiser: None,
+            router,
+            emitter,
+            attention_marker: None,
+            processors: None,
+            json_log: None,
+            app_filter: None,
+            redact_body: vec![],
+            grouper: None,
+            history: RefCell::new(History::new()),
+            spam_guard: None,
+            spam_notices: RefCell::new(HashMap::new()),
+            digester: None,
+            icon_inferrer: None,
+            latencies: None,
+            watchdog: None,
+            low_power: None,
+            sender: RefCell::new(None),
+            screen_cast_watcher: None,
+            screen_share_mode: ScreenShareMode::Off,
+            screen_shared: Cell::new(false),
+            paused_for_sharing: Cell::new(false),
+            paused: Cell::new(false),
+            focus_watcher: None,
+            dnd_when_focused: vec![],
+            dnd_for_focus: Cell::new(false),
+            calendar: None,
+            dnd_for_calendar: Cell::new(false),
+            calendar_override: Cell::new(None),
+            do_not_disturb: Cell::new(false),
+            schedule: RefCell::new(Scheduler::new()),
+            pause_digest: false,
+            dnd_digest: false,
+            suppressed: RefCell::new(vec![]),
+            queue_memory_limit: None,
+            digest: RefCell::new(None),
+            waiting: RefCell::new(vec![]),
+            coalesce_close_notify: false,
+            advertise_extensions: false,
+            pending_closes: RefCell::new(vec![]),
+            displayed: RefCell::new(HashSet::new()),
+            brought_back: RefCell::new(HashSet::new()),
+            lifecycles: RefCell::new(HashMap::new()),
+            resolutions: RefCell::new(HashMap::new()),
+            app_names: RefCell::new(HashMap::new()),
+            outgoing: RefCell::new(vec![]),
+            state: None,
+            restored: RefCell::new(None),
+            mute_path: None,
+            started_at: Instant::now(),
         }
     }
 
+    /// Makes the server re-send every notification it receives through `forwarder`, in addition
+    /// to displaying it.
+    pub fn forward_to(mut self, forwarder: Forwarder) -> Self {
+        let forwarder = Rc::new(forwarder);
+        self.router.add(forwarder.clone());
+        self.forwarder = Some(forwarder);
+        self
+    }
+
+    /// Makes the server send every notification it receives to another machine through `mirror`.
+    pub fn mirror_to(mut self, mirror: Mirror) -> Self {
+        self.mirror = Some(mirror);
+        self
+    }
+
+    /// Makes the server push notifications to your phone through `pusher` while you're away.
+    pub fn push_when_away(mut self, pusher: Pusher) -> Self {
+        self.pusher = Some(pusher);
+        self
+    }
+
+    /// Makes the server run the hooks in `scripts` as notifications come and go.
+    pub fn run_scripts(mut self, scripts: Scripts) -> Self {
+        let scripts = Rc::new(scripts);
+        self.router.add(scripts.clone());
+        self.scripts = Some(scripts);
+        self
+    }
+
+    /// Makes the server run the shell commands in `action_commands` when actions are invoked.
+    pub fn run_action_commands(mut self, action_commands: ActionCommands) -> Self {
+        let action_commands = Rc::new(RefCell::new(action_commands));
+        self.router.add(action_commands.clone());
+        self.action_commands = Some(action_commands);
+        self
+    }
+
+    /// Makes the server raise the app behind a notification when its default action is invoked,
+    /// for the apps `raiser` covers.
+    pub fn raise_with(mut self, raiser: Raiser) -> Self {
+        let raiser = Rc::new(RefCell::new(raiser));
+        self.router.add(raiser.clone());
+        self.raiser = Some(raiser);
+        self
+    }
+
+    /// Makes the server mark the windows of the apps that `attention_marker` covers as wanting
+    /// attention when their notifications arrive.
+    pub fn mark_for_attention(mut self, attention_marker: AttentionMarker) -> Self {
+        self.attention_marker = Some(attention_marker);
+        self
+    }
+
+    /// Makes the server run every notification through `processors` before showing it.
+    pub fn process_with(mut self, processors: Processors) -> Self {
+        self.processors = Some(processors);
+        self
+    }
+
+    /// Makes the server record every notification it receives (and how it was resolved) to
+    /// `json_log`.
+    pub fn log_to(mut self, json_log: JsonLog) -> Self {
+        self.json_log = Some(RefCell::new(json_log));
+        self
+    }
+
+    /// Makes the server drop notifications from apps that `app_filter` doesn't allow.
+    pub fn filter_apps(mut self, app_filter: AppFilter) -> Self {
+        self.app_filter = Some(app_filter);
+        self
+    }
+
+    /// Hides the bodies of notifications from apps matching `patterns`, which are matched the same
+    /// way as in an `AppFilter`.
+    pub fn redact_body_for(mut self, patterns: Vec<String>) -> Self {
+        self.redact_body = patterns;
+        self
+    }
+
+    /// Makes the server roll up bursts of notifications from the same app, as decided by
+    /// `grouper`.
+    pub fn group_with(mut self, grouper: Grouper) -> Self {
+        self.grouper = Some(RefCell::new(grouper));
+        self
+    }
+
+    /// Makes the server mute apps for a while when `spam_guard` decides they're sending too many
+    /// notifications, telling the user when it does.
+    pub fn guard_against_spam(mut self, spam_guard: SpamGuard) -> Self {
+        self.spam_guard = Some(RefCell::new(spam_guard));
+        self
+    }
+
+    /// Makes the server collect the notifications matching `digester`'s rules, and show digests of
+    /// them instead.
+    pub fn digest_with(mut self, digester: Digester) -> Self {
+        self.digester = Some(RefCell::new(digester));
+        self
+    }
+
+    /// Makes the server guess an icon from the sending process when a notification doesn't come
+    /// with one.
+    pub fn infer_icons_with(mut self, icon_inferrer: IconInferrer) -> Self {
+        self.icon_inferrer = Some(icon_inferrer);
+        self
+    }
+
+    /// Makes the server pause or redact notifications (depending on `mode`) while `watcher` says
+    /// the screen is being shared.
+    pub fn watch_screen_casts(mut self, watcher: ScreenCastWatcher, mode: ScreenShareMode) -> Self {
+        self.screen_cast_watcher = Some(RefCell::new(watcher));
+        self.screen_share_mode = mode;
+        self
+    }
+
+    /// Once do-not-disturb is turned off, shows a digest of what was dropped while it was on.
+    /// Clicking the digest shows the notifications themselves.
+    pub fn digest_after_dnd(mut self) -> Self {
+        self.dnd_digest = true;
+        self
+    }
+
+    /// Ends timed pauses with a digest of what arrived during them, instead of showing it all.
+    pub fn digest_after_timed_pause(mut self) -> Self {
+        self.pause_digest = true;
+        self
+    }
+
+    /// Turns on do-not-disturb while a window whose class matches one of `patterns` is focused.
+    pub fn watch_focus(mut self, watcher: FocusWatcher, patterns: Vec<String>) -> Self {
+        self.focus_watcher = Some(RefCell::new(watcher));
+        self.dnd_when_focused = patterns;
+        self
+    }
+
+    /// Turns on do-not-disturb during events in `watcher`'s calendar that the user is busy for.
+    pub fn watch_calendar(mut self, watcher: CalendarWatcher) -> Self {
+        self.calendar = Some(RefCell::new(watcher));
+        self
+    }
+
+    /// Makes the server hold off on closing notifications for a moment, so that apps that update a
+    /// notification by closing it and sending it again get the same window instead of a flicker.
+    pub fn coalesce_close_notify(mut self) -> Self {
+        self.coalesce_close_notify = true;
+        self
+    }
+
+    /// Lists ninomiya's extensions to the spec in `GetCapabilities`, so that clients can check for
+    /// them instead of going by the server name.
+    pub fn advertise_extensions(mut self) -> Self {
+        self.advertise_extensions = true;
+        self
+    }
+
+    /// Keeps the notifications held for do-not-disturb digests or while paused under `limit`
+    /// bytes, so that a flood of big images can't use up all our memory.
+    pub fn limit_queue_memory(mut self, limit: usize) -> Self {
+        self.queue_memory_limit = Some(limit);
+        self
+    }
+
+    /// Makes `GetStats` on the control interface report the latencies in `latencies`, which the
+    /// GUI records.
+    pub fn report_latencies(mut self, latencies: Arc<Latencies>) -> Self {
+        self.latencies = Some(latencies);
+        self
+    }
+
+    /// Makes the server check that the GUI thread is still handling events, and report it over
+    /// the control interface.
+    pub fn watch_gui(mut self, watchdog: Watchdog) -> Self {
+        self.watchdog = Some(RefCell::new(watchdog));
+        self
+    }
+
+    /// Makes the server wake up less often while `low_power` is on.
+    pub fn follow_low_power(mut self, low_power: Arc<LowPower>) -> Self {
+        self.low_power = Some(low_power);
+        self
+    }
+
+    /// Makes the server keep track of what's on screen and queued in `state`, so that it can be
+    /// saved when ninomiya shuts down.
+    pub fn keep_state(mut self, state: Arc<StateKeeper>) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Makes the server save whether the user paused notifications or turned on do-not-disturb to
+    /// `path`, and pick it back up from there once it's running.
+    pub fn persist_mute(mut self, path: PathBuf) -> Self {
+        self.mute_path = Some(path);
+        self
+    }
+
+    /// Makes the server bring back the notifications in `saved` once it's running.
+    pub fn restore(self, saved: SavedState) -> Self {
+        *self.restored.borrow_mut() = Some(saved);
+        self
+    }
+
     /// Runs the notification server forever.
     ///
     /// The server return if it fails to acquire the given name or if the connectoin closes. Under
@@ -96,119 +840,1247 @@ impl NotifyServer {
         self,
         dbus_name: &str,
         mut connection: LocalConnection,
-        signal_rx: Receiver<Signal>,
+        signal_rx: &Receiver<Signal>,
+        local_rx: &Receiver<Notification>,
     ) -> Result<()> {
         let request_reply = connection
             .request_name(
                 dbus_name, /* allow_replacement */ true, /* replace_existing */ true,
                 /* do_not_queue */ true,
             )
-            .context("requesting the name failed")?;
+            .with_context(|| NameUnavailable(dbus_name.to_owned()))?;
         if request_reply != RequestNameReply::PrimaryOwner {
-            bail!("Failed to get the name we wanted (reason: {:?}), request_reply");
+            return Err(anyhow!("the reply was {:?}", request_reply))
+                .context(NameUnavailable(dbus_name.to_owned()));
         }
-        let tree = create_tree(self);
+        // The tree takes ownership of its data, but we also need the server to handle signals.
+        let server = Rc::new(self);
+        let tree = create_tree(server.clone());
         tree.start_receive(&connection);
+        let control_path = dbus::strings::Path::new(control::CONTROL_PATH)
+            .expect("failed to parse dbus path name; this is really weird!");
+        server.restore_saved();
+        let mut properties = server.properties();
+        let mut mute = server.mute();
         loop {
-            connection.process(std::time::Duration::from_millis(50))?;
-            handle_signal_events(&connection, &signal_rx)?;
+            connection.process(
+                server
+                    .low_power
+                    .as_ref()
+                    .map_or(low_power::LOOP_INTERVAL, |low_power| {
+                        low_power.loop_interval()
+                    }),
+            )?;
+            server.handle_signal_events(&connection, signal_rx)?;
+            server.handle_local_notifications(local_rx);
+            server.flush_pending_closes(Instant::now());
+            server.show_due_digests();
+            server.check_screen_sharing();
+            server.check_focus();
+            server.check_calendar();
+            server.check_schedule();
+            if let Some(watchdog) = &server.watchdog {
+                watchdog.borrow_mut().check();
+            }
+            let new_properties = server.properties();
+            if let Some(sig) = new_properties.changed_since(&properties) {
+                debug!("Control properties changed: {:?}", new_properties);
+                if connection.send(sig.to_emit_message(&control_path)).is_err() {
+                    error!("Failed to send signal over dbus");
+                }
+            }
+            properties = new_properties;
+            let new_mute = server.mute();
+            if new_mute != mute {
+                server.save_mute(&new_mute);
+            }
+            mute = new_mute;
             trace!("Another turn around the loop.");
         }
     }
 
+    /// How many notifications from each app are on screen or waiting to be, by app name, including
+    /// the ones held in rollups and digests. Our own notifications aren't counted.
+    fn unread_counts(&self) -> HashMap<String, u32> {
+        let app_names = self.app_names.borrow();
+        let waiting = self.waiting.borrow();
+        let ids = self
+            .displayed
+            .borrow()
+            .iter()
+            .copied()
+            .chain(waiting.iter().map(|notification| notification.id))
+            .collect::<Vec<u32>>();
+        let mut counted = HashSet::new();
+        let mut counts = HashMap::new();
+        for id in ids {
+            if let Some(app_name) = app_names.get(&id) {
+                counted.insert(id);
+                *counts.entry(app_name.clone()).or_insert(0) += 1;
+            }
+        }
+        let grouper = self.grouper.as_ref().map(|grouper| grouper.borrow());
+        let digester = self.digester.as_ref().map(|digester| digester.borrow());
+        let digest = self.digest.borrow();
+        let held = grouper
+            .iter()
+            .flat_map(|grouper| grouper.held())
+            .chain(digester.iter().flat_map(|digester| digester.held()))
+            .chain(digest.iter().flat_map(|(_, held)| held.iter()));
+        for notification in held {
+            if counted.insert(notification.id) {
+                let app_name = notification.application_name.clone().unwrap_or_default();
+                *counts.entry(app_name).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// The current state, as exposed over the control interface.
+    pub fn properties(&self) -> Properties {
+        Properties {
+            paused: self.paused.get(),
+            do_not_disturb: self.do_not_disturb.get(),
+            displayed_count: self.displayed.borrow().len() as u32,
+            waiting_count: self.waiting.borrow().len() as u32,
+            unread_counts: self.unread_counts(),
+            gui_responsive: self
+                .watchdog
+                .as_ref()
+                .map_or(true, |watchdog| watchdog.borrow().responsive()),
+        }
+    }
+
+    /// What's in the notification center, oldest first, for `GetHistory` on the control interface.
+    pub fn history(&self) -> Vec<Entry> {
+        self.history.borrow().entries().cloned().collect()
+    }
+
+    /// The stats returned by `GetStats` on the control interface.
+    pub fn stats(&self) -> HashMap<String, f64> {
+        let mut stats = self
+            .latencies
+            .as_ref()
+            .map(|latencies| latencies.summary().to_map())
+            .unwrap_or_default();
+        if let Some(watchdog) = &self.watchdog {
+            stats.insert(
+                "gui_silence_ms".to_owned(),
+                watchdog.borrow().silence().as_secs_f64() * 1000.0,
+            );
+        }
+        for (resolution, count) in self.resolutions.borrow().iter() {
+            stats.insert(format!("resolved_{}", resolution.name()), f64::from(*count));
+        }
+        stats
+    }
+
+    /// How long the server has been running, in seconds, and ninomiya's version, for `Ping` on the
+    /// control interface.
+    pub fn ping(&self) -> (u64, String) {
+        (
+            self.started_at.elapsed().as_secs(),
+            env!("CARGO_PKG_VERSION").to_owned(),
+        )
+    }
+
+    /// Pauses or unpauses the server. Unpausing displays everything that arrived in the meantime.
+    pub fn set_paused(&self, paused: bool) {
+        info!("Setting paused to {}", paused);
+        self.paused.set(paused);
+        self.schedule.borrow_mut().cancel(Event::Unpause);
+        self.paused_for_sharing.set(false);
+        if !paused {
+            let waiting: Vec<Notification> = self.waiting.borrow_mut().drain(..).collect();
+            for notification in waiting {
+                self.dispatch(notification);
+            }
+        }
+    }
+
+    /// Turns do-not-disturb on or off. While it's on, incoming notifications are dropped.
+    pub fn set_do_not_disturb(&self, do_not_disturb: bool) {
+        info!("Setting do-not-disturb to {}", do_not_disturb);
+        self.dnd_for_focus.set(false);
+        self.dnd_for_calendar.set(false);
+        if let Some(calendar) = &self.calendar {
+            self.calendar_override
+                .set(calendar.borrow_mut().current_event());
+        }
+        self.schedule.borrow_mut().cancel(Event::EndDoNotDisturb);
+        if self.do_not_disturb.replace(do_not_disturb) == do_not_disturb {
+            return;
+        }
+        if do_not_disturb {
+            self.dispatch(Notification::internal(
+                "Do not disturb is on",
+                "Notifications won't be shown until it's turned off.",
+            ));
+        } else if !self.show_digest() {
+            self.dispatch(Notification::internal("Do not disturb is off", ""));
+        }
+    }
+
+    /// Turns do-not-disturb on for `duration`, after which it turns itself back off.
+    pub fn set_do_not_disturb_for(&self, duration: Duration) {
+        self.set_do_not_disturb(true);
+        self.schedule
+            .borrow_mut()
+            .set(Event::EndDoNotDisturb, SystemTime::now() + duration);
+    }
+
+    /// Pauses for `duration`, after which everything that arrived in the meantime is shown (or
+    /// summed up, with `pause_digest`).
+    pub fn set_paused_for(&self, duration: Duration) {
+        self.set_paused(true);
+        self.schedule
+            .borrow_mut()
+            .set(Event::Unpause, SystemTime::now() + duration);
+    }
+
+    /// Ends timed pauses and do-not-disturb whose time is up.
+    fn check_schedule(&self) {
+        let due = self.schedule.borrow_mut().due(SystemTime::now());
+        for event in due {
+            match event {
+                Event::Unpause => {
+                    info!("The pause's time is up");
+                    self.end_timed_pause();
+                }
+                Event::EndDoNotDisturb => {
+                    info!("Do-not-disturb's time is up");
+                    self.set_do_not_disturb(false);
+                }
+            }
+        }
+    }
+
+    fn end_timed_pause(&self) {
+        // While do-not-disturb is on, what arrived during the pause goes the way of anything else
+        // that arrives then, so there's nothing to sum up.
+        if !self.pause_digest || self.do_not_disturb.get() {
+            self.set_paused(false);
+            return;
+        }
+        let waiting: Vec<Notification> = self.waiting.borrow_mut().drain(..).collect();
+        // Like the ones dropped for do-not-disturb, they're closed as far as their apps know,
+        // unless the user asks to see them.
+        for notification in &waiting {
+            self.close_dropped(notification.id);
+        }
+        self.set_paused(false);
+        self.show_digest_of(waiting);
+    }
+
+    /// Whether the user has paused notifications or turned on do-not-disturb themselves.
+    fn mute(&self) -> Mute {
+        let do_not_disturb =
+            self.do_not_disturb.get() && !self.dnd_for_focus.get() && !self.dnd_for_calendar.get();
+        let paused = self.paused.get() && !self.paused_for_sharing.get();
+        let schedule = self.schedule.borrow();
+        Mute {
+            paused,
+            paused_until: schedule.get(Event::Unpause).filter(|_| paused),
+            do_not_disturb,
+            dnd_until: schedule
+                .get(Event::EndDoNotDisturb)
+                .filter(|_| do_not_disturb),
+        }
+    }
+
+    fn save_mute(&self, mute: &Mute) {
+        if let Some(path) = &self.mute_path {
+            if let Err(err) = mute.save(path) {
+                error!("Failed to save whether notifications are muted: {:?}", err);
+            }
+        }
+    }
+
+    /// Brings back whether the user had paused notifications or turned on do-not-disturb before we
+    /// restarted. Timed pauses and do-not-disturb whose time ran out while we weren't running end
+    /// right away.
+    fn restore_mute(&self) {
+        let path = match &self.mute_path {
+            Some(path) => path,
+            None => return,
+        };
+        let mute = match Mute::load(path) {
+            Ok(mute) => mute,
+            Err(err) => {
+                error!(
+                    "Failed to restore whether notifications were muted: {:?}",
+                    err
+                );
+                return;
+            }
+        };
+        if mute.paused {
+            info!("Staying paused from before we restarted");
+            self.set_paused(true);
+            if let Some(until) = mute.paused_until {
+                self.schedule.borrow_mut().set(Event::Unpause, until);
+            }
+        }
+        let expired = mute
+            .dnd_until
+            .map_or(false, |until| SystemTime::now() >= until);
+        if mute.do_not_disturb && !expired {
+            info!("Staying in do-not-disturb from before we restarted");
+            self.set_do_not_disturb(true);
+            if let Some(until) = mute.dnd_until {
+                self.schedule
+                    .borrow_mut()
+                    .set(Event::EndDoNotDisturb, until);
+            }
+        }
+    }
+
+    /// Shows a digest of the notifications dropped while do-not-disturb was on, if there were any.
+    /// Returns whether it did.
+    fn show_digest(&self) -> bool {
+        let suppressed: Vec<Notification> = self.suppressed.borrow_mut().drain(..).collect();
+        self.show_digest_of(suppressed)
+    }
+
+    /// Shows a digest summing up `held`, whose apps have already been told they closed, unless it's
+    /// empty. Returns whether it did.
+    fn show_digest_of(&self, held: Vec<Notification>) -> bool {
+        if held.is_empty() {
+            return false;
+        }
+        let mut digest = Notification::internal(&digest_text(&held), "");
+        digest.actions = vec![
+            Action {
+                key: "default".to_owned(),
+                label: String::new(),
+            },
+            Action {
+                key: "show".to_owned(),
+                label: "Show".to_owned(),
+            },
+        ];
+        info!("Showing a digest of {} notifications", held.len());
+        // Only the latest digest can be expanded; the notifications in an older one are dropped.
+        self.digest.replace(Some((digest.id, held)));
+        self.dispatch(digest);
+        true
+    }
+
+    /// Shows the digests of collected notifications that are due, in place of any earlier ones for
+    /// the same rules.
+    fn show_due_digests(&self) {
+        let digests = match &self.digester {
+            Some(digester) => digester.borrow_mut().due(Instant::now()),
+            None => return,
+        };
+        for digest in digests {
+            info!("Showing digest {:?}", digest.notification.summary);
+            if let Some(id) = digest.close {
+                (self.callback)(NinomiyaEvent::CloseNotification(id, CloseReason::Closed));
+            }
+            self.dispatch(digest.notification);
+        }
+    }
+
+    /// Asks the screen cast watcher whether the screen is being shared, and reacts if that's
+    /// changed since last time.
+    fn check_screen_sharing(&self) {
+        let watcher = match &self.screen_cast_watcher {
+            Some(watcher) => watcher,
+            None => return,
+        };
+        let shared = match watcher.borrow_mut().poll() {
+            Ok(shared) => shared,
+            Err(err) => {
+                error!("Failed to check for screen sharing: {:?}", err);
+                return;
+            }
+        };
+        if self.screen_shared.replace(shared) == shared {
+            return;
+        }
+        info!(
+            "Screen sharing is now {}",
+            if shared { "on" } else { "off" }
+        );
+        if self.screen_share_mode != ScreenShareMode::Pause {
+            return;
+        }
+        if shared && !self.paused.get() {
+            self.set_paused(true);
+            self.paused_for_sharing.set(true);
+        } else if !shared && self.paused_for_sharing.get() {
+            self.set_paused(false);
+        }
+    }
+
+    /// Asks the focus watcher which window is focused, and turns do-not-disturb on or off if it
+    /// matches `dnd_when_focused`. This doesn't announce itself the way `set_do_not_disturb` does,
+    /// since that would happen every time the user switched windows.
+    fn check_focus(&self) {
+        let watcher = match &self.focus_watcher {
+            Some(watcher) => watcher,
+            None => return,
+        };
+        let mut watcher = watcher.borrow_mut();
+        let matches = watcher.poll().iter().any(|class| {
+            self.dnd_when_focused
+                .iter()
+                .any(|pattern| app_filter::glob_matches(pattern, class))
+        });
+        if matches && !self.do_not_disturb.get() {
+            info!("Turning on do-not-disturb for the focused window");
+            self.do_not_disturb.set(true);
+            self.dnd_for_focus.set(true);
+        } else if !matches && self.dnd_for_focus.get() {
+            info!("Turning off do-not-disturb now that the window isn't focused");
+            self.do_not_disturb.set(false);
+            self.dnd_for_focus.set(false);
+            self.show_digest();
+        }
+    }
+
+    /// Turns do-not-disturb on when a busy calendar event starts, and back off when it ends, unless
+    /// the user set it themselves during the event. Like `check_focus`, this happens quietly.
+    fn check_calendar(&self) {
+        let event = match &self.calendar {
+            Some(calendar) => calendar.borrow_mut().current_event(),
+            None => return,
+        };
+        let busy = event.is_some();
+        if busy && event != self.calendar_override.get() && !self.do_not_disturb.get() {
+            info!("Turning on do-not-disturb for a calendar event");
+            self.do_not_disturb.set(true);
+            self.dnd_for_calendar.set(true);
+        } else if !busy && self.dnd_for_calendar.get() {
+            info!("Turning off do-not-disturb now that the calendar event is over");
+            self.do_not_disturb.set(false);
+            self.dnd_for_calendar.set(false);
+            self.show_digest();
+        }
+    }
+
+    /// Asks the GUI to grab the keyboard so the user can pick one of the newest notification's
+    /// actions by number.
+    pub fn select_action(&self) {
+        info!("Selecting an action with the keyboard");
+        (self.callback)(NinomiyaEvent::SelectAction);
+    }
+
+    /// Asks the GUI to bring back the notification the user last dismissed, if it was recent
+    /// enough.
+    pub fn undo_close(&self) {
+        info!("Undoing the last dismissal");
+        (self.callback)(NinomiyaEvent::UndoClose);
+    }
+
+    /// Asks the GUI to switch to the theme at `path`, and waits to hear whether it could. If it
+    /// can't be loaded, the old theme stays.
+    pub fn set_theme(&self, path: PathBuf) -> Result<(), tree::MethodErr> {
+        info!("Switching to the theme at {:?}", path);
+        let (reply, rx) = mpsc::channel();
+        (self.callback)(NinomiyaEvent::SetTheme(path, reply));
+        match rx.recv_timeout(SET_THEME_TIMEOUT) {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(err)) => Err(NotifyError::InvalidTheme(anyhow!(err)).into()),
+            Err(_) => Err(NotifyError::Internal(anyhow!(
+                "the GUI didn't answer in time; is it hung?"
+            ))
+            .into()),
+        }
+    }
+
+    /// Shows a progress notification at `percent` for `sender`, or updates the one with `id` if
+    /// it's still around. Returns its ID, for the next update. It goes through `Notify` like any
+    /// other notification, so filters, redaction and do-not-disturb apply to it too.
+    pub fn update_progress(
+        &self,
+        sender: Option<String>,
+        id: u32,
+        percent: u32,
+        text: &str,
+    ) -> Result<u32, tree::MethodErr> {
+        let mut hints = HashMap::new();
+        hints.insert(
+            "value",
+            arg::Variant(Box::new(percent.min(100) as i32) as Box<dyn arg::RefArg>),
+        );
+        self.sender.replace(sender);
+        dbus_server::OrgFreedesktopNotifications::notify(
+            self,
+            "",
+            id,
+            "",
+            text,
+            "",
+            vec![],
+            hints,
+            -1,
+        )
+    }
+
+    /// Replaces the progress notification with `id` with one saying it's done.
+    pub fn complete_progress(
+        &self,
+        sender: Option<String>,
+        id: u32,
+        summary: &str,
+    ) -> Result<u32, tree::MethodErr> {
+        self.sender.replace(sender);
+        dbus_server::OrgFreedesktopNotifications::notify(
+            self,
+            "",
+            id,
+            "",
+            summary,
+            "",
+            vec![],
+            HashMap::new(),
+            -1,
+        )
+    }
+
+    /// Sends the notification to the GUI, unless we're paused or in do-not-disturb mode.
+    fn dispatch(&self, notification: Notification) {
+        if let Some(notification) = self.admit(notification) {
+            self.show(notification);
+        }
+    }
+
+    /// Sends notifications that were held in a rollup or a digest to the GUI, the way `dispatch`
+    /// does, but without rolling them up again.
+    fn expand(&self, held: Vec<Notification>) {
+        for notification in held {
+            if let Some(notification) = self.admit(notification) {
+                (self.callback)(NinomiyaEvent::Notification(notification));
+            }
+        }
+    }
+
+    /// Returns the notification if it can go on screen now, redacted if the screen is being shared.
+    /// Otherwise it's held until we're unpaused, or dropped for do-not-disturb.
+    fn admit(&self, mut notification: Notification) -> Option<Notification> {
+        if self.screen_shared.get()
+            && self.screen_share_mode == ScreenShareMode::Redact
+            && !notification.internal
+        {
+            debug!(
+                "Redacting notification {} (screen is shared)",
+                notification.id
+            );
+            notification.redact();
+        }
+        if notification.internal {
+            return Some(notification);
+        }
+        if self.do_not_disturb.get()
+            && !(self.dnd_for_calendar.get() && notification.hints.urgency == Urgency::Critical)
+        {
+            info!("Dropping notification {} (do not disturb)", notification.id);
+            self.close_dropped(notification.id);
+            if self.dnd_digest {
+                self.suppressed.borrow_mut().push(notification);
+                self.enforce_queue_limit();
+            }
+            return None;
+        }
+        if self.paused.get() {
+            info!("Holding notification {} until unpaused", notification.id);
+            self.outgoing.borrow_mut().push(Signal::NotificationQueued {
+                id: notification.id,
+            });
+            self.waiting.borrow_mut().push(notification);
+            self.enforce_queue_limit();
+            return None;
+        }
+        Some(notification)
+    }
+
+    /// Whether a notification from the app with the given name and hints should get through
+    /// `spam_guard`. If it's the one that gets the app muted, the user is told.
+    fn check_spam(&self, app_name: &str, hints: &HintMap) -> bool {
+        let spam_guard = match &self.spam_guard {
+            Some(spam_guard) => spam_guard,
+            None => return true,
+        };
+        let desktop_entry = hints
+            .get("desktop-entry")
+            .and_then(|entry| entry.0.as_str());
+        let app = match desktop_entry.or_else(|| Some(app_name).filter(|name| !name.is_empty())) {
+            Some(app) => app,
+            None => return true,
+        };
+        let verdict = spam_guard.borrow_mut().check(app, Instant::now());
+        match verdict {
+            Verdict::Show => true,
+            Verdict::Drop => {
+                info!("Dropping notification from {} (muted for spamming)", app);
+                false
+            }
+            Verdict::Mute => {
+                warn!("Muting {} for sending too many notifications", app);
+                let name = owned_if_nonempty(app_name).unwrap_or_else(|| app.to_owned());
+                let notice = spam_guard.borrow().notice(&name);
+                self.spam_notices
+                    .borrow_mut()
+                    .insert(notice.id, app.to_owned());
+                self.dispatch(notice);
+                false
+            }
+        }
+    }
+
+    /// Sheds from the queues until they fit in `queue_memory_limit`. Notifications kept for the
+    /// digest go first, since they might never be looked at; held ones that are dropped count as
+    /// expired.
+    fn enforce_queue_limit(&self) {
+        let limit = match self.queue_memory_limit {
+            Some(limit) => limit,
+            None => return,
+        };
+        let mut suppressed = self.suppressed.borrow_mut();
+        let mut waiting = self.waiting.borrow_mut();
+        if queue_size(&suppressed) + queue_size(&waiting) <= limit {
+            return;
+        }
+        warn!(
+            "Queued notifications are using more than {} bytes; shedding some",
+            limit
+        );
+        let budget = limit.saturating_sub(queue_size(&waiting));
+        for notification in shed(&mut suppressed, budget) {
+            warn!(
+                "Dropped notification {} from the digest to save memory",
+                notification.id
+            );
+        }
+        let budget = limit.saturating_sub(queue_size(&suppressed));
+        for notification in shed(&mut waiting, budget) {
+            warn!(
+                "Dropped held notification {} to save memory",
+                notification.id
+            );
+            self.outgoing.borrow_mut().push(Signal::NotificationClosed {
+                id: notification.id,
+                reason: CloseReason::Expired,
+            });
+        }
+    }
+
+    /// Sends the notification to the GUI, rolling it up with others from the same app if there
+    /// have been a lot of them.
+    fn show(&self, notification: Notification) {
+        let grouped = match &self.grouper {
+            // Updates to a notification on screen aren't part of a burst; they take its place.
+            Some(_)
+                if notification.replaces.is_some()
+                    || self.displayed.borrow().contains(&notification.id) =>
+            {
+                Grouped::Show(notification)
+            }
+            Some(grouper) => grouper.borrow_mut().add(notification, Instant::now()),
+            None => Grouped::Show(notification),
+        };
+        match grouped {
+            Grouped::Show(notification) => {
+                (self.callback)(NinomiyaEvent::Notification(notification))
+            }
+            Grouped::Rollup { close, rollup } => {
+                info!(
+                    "Rolling up notifications from {:?}",
+                    rollup.application_name
+                );
+                // Their apps didn't close them, and nobody dismissed them, so they count as
+                // expired.
+                for id in close {
+                    (self.callback)(NinomiyaEvent::CloseNotification(id, CloseReason::Expired));
+                }
+                (self.callback)(NinomiyaEvent::Notification(rollup));
+            }
+        }
+    }
+
+    /// If `id` is the digest of what arrived during do-not-disturb or a timed pause, ends it and
+    /// returns those notifications.
+    fn take_digest(&self, id: u32) -> Option<Vec<Notification>> {
+        let mut digest = self.digest.borrow_mut();
+        if digest.as_ref().map(|(digest_id, _)| *digest_id) != Some(id) {
+            return None;
+        }
+        digest.take().map(|(_, held)| held)
+    }
+
+    /// If `id` is a rollup or a digest collected by `digester`, ends it and returns the
+    /// notifications it was holding.
+    fn take_held(&self, id: u32) -> Option<Vec<Notification>> {
+        if let Some(held) = self
+            .digester
+            .as_ref()
+            .and_then(|digester| digester.borrow_mut().take(id))
+        {
+            return Some(held);
+        }
+        self.grouper.as_ref()?.borrow_mut().take(id)
+    }
+
+    /// Starts keeping track again of a notification whose app was already told it closed, like one
+    /// the user dismissed by accident, so that it can be shown. Its app won't hear about it closing
+    /// a second time.
+    fn bring_back(&self, notification: &mut Notification) {
+        notification.lifecycle.resolution = None;
+        notification.lifecycle.action = None;
+        self.track(notification);
+        self.brought_back.borrow_mut().insert(notification.id);
+    }
+
+    /// Starts keeping track of a notification until it's resolved.
+    fn track(&self, notification: &Notification) {
+        self.lifecycles
+            .borrow_mut()
+            .insert(notification.id, notification.lifecycle.clone());
+        self.app_names.borrow_mut().insert(
+            notification.id,
+            notification.application_name.clone().unwrap_or_default(),
+        );
+    }
+
+    /// Stops tracking a notification that's been closed for `reason`, returning its lifecycle.
+    /// `None` if it wasn't being tracked, say because it was dropped before it got that far.
+    fn resolve(&self, id: u32, reason: CloseReason) -> Option<Lifecycle> {
+        self.app_names.borrow_mut().remove(&id);
+        let mut lifecycle = self.lifecycles.borrow_mut().remove(&id)?;
+        let resolution = lifecycle.resolve(reason);
+        *self.resolutions.borrow_mut().entry(resolution).or_insert(0) += 1;
+        Some(lifecycle)
+    }
+
+    /// Tells the app that sent notification `id` that it's closed, since we've dropped it.
+    fn close_dropped(&self, id: u32) {
+        if self.displayed.borrow().contains(&id) {
+            // It's an update to one on screen, which can't stay up either.
+            (self.callback)(NinomiyaEvent::CloseNotification(id, CloseReason::Expired));
+        } else {
+            self.outgoing.borrow_mut().push(Signal::NotificationClosed {
+                id,
+                reason: CloseReason::Expired,
+            });
+        }
+    }
+
+    /// Picks an icon for a notification that didn't come with one: the icon of its desktop entry
+    /// if it has one, and otherwise whatever we can figure out from the process that sent it.
+    fn fallback_icon(&self, hints: &Hints) -> Option<ImageRef> {
+        if let Some(desktop_entry) = &hints.desktop_entry {
+            return app_icon::desktop_entry_icon(desktop_entry);
+        }
+        let icon_inferrer = self.icon_inferrer.as_ref()?;
+        let sender = self.sender.borrow();
+        let icon = icon_inferrer.infer(sender.as_deref()?);
+        debug!("Inferred icon {:?} from {:?}", icon, sender);
+        icon
+    }
+
+    /// Checks whether `id` is a notification that can be replaced: one that's on screen or waiting
+    /// to be. A waiting one is dropped, since its replacement will wait in its place.
+    fn replace(&self, id: u32) -> bool {
+        if self.displayed.borrow().contains(&id) {
+            return true;
+        }
+        let mut waiting = self.waiting.borrow_mut();
+        match waiting.iter().position(|n| n.id == id) {
+            Some(index) => {
+                waiting.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// If the current sender closed a notification that's still on screen within the last
+    /// `COALESCE_WINDOW` before `now`, returns its ID so that the notification they're sending can
+    /// take its place.
+    fn coalescable_close(&self, now: Instant) -> Option<u32> {
+        if self.paused.get() || self.do_not_disturb.get() {
+            // The new notification wouldn't be shown, so the old one has to go.
+            return None;
+        }
+        let sender = self.sender.borrow();
+        let sender = sender.as_deref()?;
+        self.pending_closes
+            .borrow()
+            .iter()
+            .rev()
+            .find(|close| close.sender == sender && now.duration_since(close.at) < COALESCE_WINDOW)
+            .map(|close| close.id)
+    }
+
+    /// Closes the notifications whose `CloseNotification` wasn't followed by an update in time, as
+    /// of `now`.
+    fn flush_pending_closes(&self, now: Instant) {
+        let expired: Vec<PendingClose> = {
+            let mut pending = self.pending_closes.borrow_mut();
+            let (expired, kept) = pending
+                .drain(..)
+                .partition(|close| now.duration_since(close.at) >= COALESCE_WINDOW);
+            *pending = kept;
+            expired
+        };
+        for close in expired {
+            (self.callback)(NinomiyaEvent::CloseNotification(
+                close.id,
+                CloseReason::Closed,
+            ));
+        }
+    }
+
+    /// Brings back the notifications saved the last time we shut down, if we were asked to, along
+    /// with whether notifications were muted. The ones that were on screen are shown for what's
+    /// left of their timeouts, and the ones that were queued are queued again.
+    fn restore_saved(&self) {
+        let saved = match self.restored.borrow_mut().take() {
+            Some(saved) => saved,
+            None => {
+                self.restore_mute();
+                return;
+            }
+        };
+        info!(
+            "Restoring {} notifications from before we restarted",
+            saved.notifications.len()
+        );
+        if let Some(max_id) = saved.notifications.iter().map(|saved| saved.id).max() {
+            self.next_id.set(self.next_id.get().max(max_id + 1));
+        }
+        // The ones that were on screen go back up whether or not we were paused.
+        let (shown, queued): (Vec<_>, Vec<_>) = saved
+            .notifications
+            .into_iter()
+            .partition(|saved| saved.shown_at.is_some());
+        for saved in shown {
+            self.restore_notification(saved);
+        }
+        self.restore_mute();
+        for saved in queued {
+            self.restore_notification(saved);
+        }
+    }
+
+    fn restore_notification(&self, saved: SavedNotification) {
+        let notification = self.build_notification(
+            saved.id,
+            &saved.app_name,
+            parse_icon(&saved.app_icon),
+            &saved.summary,
+            &saved.body,
+            parse_actions(&saved.actions).unwrap_or_default(),
+            saved.hints.to_dbus(),
+        );
+        let mut notification = match notification {
+            Ok(notification) => notification,
+            Err(err) => {
+                warn!("Couldn't restore notification {}: {}", saved.id, err);
+                return;
+            }
+        };
+        notification.lifecycle = Lifecycle::restored(saved.received, saved.shown_at);
+        self.redact_body_if_private(&mut notification);
+        self.track(&notification);
+        if let Some(action_commands) = &self.action_commands {
+            action_commands.borrow_mut().add(&notification);
+        }
+        // We don't know who sent it anymore, so its window can only be found by its class.
+        if let Some(raiser) = &self.raiser {
+            raiser.borrow_mut().add(&notification, None);
+        }
+        if let Some(state) = &self.state {
+            state.add(saved);
+        }
+        self.dispatch(notification);
+    }
+
+    /// Builds a notification from the arguments to `Notify`, once its ID is known.
+    #[allow(clippy::too_many_arguments)]
+    fn build_notification(
+        &self,
+        id: u32,
+        app_name: &str,
+        icon: Option<ImageRef>,
+        summary: &str,
+        body: &str,
+        actions: Vec<Action>,
+        hints: HintMap,
+    ) -> Result<Notification, NotifyError> {
+        let hints = Hints::from_dbus(hints).map_err(|err| {
+            let err = NotifyError::from_hints(err);
+            error!("Failed to build hints dict: {}", err);
+            err
+        })?;
+        let icon = icon.or_else(|| self.fallback_icon(&hints));
+        let (body, body_images) = markup::extract_images(body);
+        let body_images: Vec<ImageRef> = body_images
+            .iter()
+            .filter_map(|src| {
+                src.parse()
+                    .map_err(|err| info!("Couldn't parse body image {}: {:?}", src, err))
+                    .ok()
+            })
+            .collect();
+        Ok(Notification {
+            id,
+            icon,
+            actions,
+            application_name: owned_if_nonempty(app_name),
+            summary: summary.to_owned(),
+            body: owned_if_nonempty(&body),
+            body_images,
+            hints,
+            internal: false,
+            lifecycle: Lifecycle::default(),
+            replaces: None,
+        })
+    }
+
+    /// Hides the body of the notification if it's from one of the apps in `redact_body`.
+    fn redact_body_if_private(&self, notification: &mut Notification) {
+        if app_filter::matches_app(
+            &self.redact_body,
+            notification.application_name.as_deref(),
+            notification.hints.desktop_entry.as_deref(),
+        ) {
+            debug!("Hiding the body of notification {}", notification.id);
+            notification.redact_body();
+        }
+    }
+
     fn new_id(&self) -> u32 {
         let id = self.next_id.get();
         self.next_id.set(id + 1);
         id
     }
-}
 
-/// Drains the receiver of signals that are queued to be sent, then sends them over the connection.
-fn handle_signal_events(connection: &LocalConnection, signal_rx: &Receiver<Signal>) -> Result<()> {
-    let path = dbus::strings::Path::new("/org/freedesktop/Notifications")
-        .expect("failed to parse dbus path name; this is really weird!");
-    loop {
-        match signal_rx.try_recv() {
-            Ok(Signal::ActionInvoked { id, key }) => {
-                debug!("Sending signal: {} invoked on {}", key, id);
-                let sig = dbus_server::OrgFreedesktopNotificationsActionInvoked {
-                    id,
-                    action_key: key,
-                };
-                if connection.send(sig.to_emit_message(&path)).is_err() {
-                    error!("Failed to send signal over dbus");
+    /// Displays the notifications generated inside ninomiya, such as by the battery monitor. Unlike
+    /// the ones sent straight to the GUI, these respect pausing and do-not-disturb unless they're
+    /// marked internal.
+    fn handle_local_notifications(&self, local_rx: &Receiver<Notification>) {
+        // It's fine if every sender has gone away, since the features that send these are optional.
+        for mut notification in local_rx.try_iter() {
+            // One that's already been resolved is one the user dismissed and then brought back with
+            // `undo_close`. It's tracked again, but its app already heard that it closed.
+            if notification.lifecycle.resolution.is_some() {
+                info!("Got back dismissed notification {}", notification.id);
+                self.bring_back(&mut notification);
+            } else {
+                info!("Got local notification {}", notification.id);
+            }
+            self.dispatch(notification);
+        }
+    }
+
+    /// Drains the signals queued by the server itself and by the GUI, then sends them over the
+    /// connection.
+    fn handle_signal_events(
+        &self,
+        connection: &LocalConnection,
+        signal_rx: &Receiver<Signal>,
+    ) -> Result<()> {
+        self.handle_outgoing();
+        let result = loop {
+            match signal_rx.try_recv() {
+                Ok(signal) => self.handle_signal(signal),
+                Err(TryRecvError::Empty) => break Ok(()),
+                Err(TryRecvError::Disconnected) => break Err(anyhow!("GUI closed its signal tx")),
+            }
+        };
+        for message in self.emitter.take() {
+            if connection.send(message).is_err() {
+                error!("Failed to send signal over dbus");
+            }
+        }
+        result
+    }
+
+    /// Handles the signals the server queued up itself.
+    fn handle_outgoing(&self) {
+        let outgoing: Vec<Signal> = self.outgoing.borrow_mut().drain(..).collect();
+        for signal in outgoing {
+            self.handle_signal(signal);
+        }
+    }
+
+    /// Does the server's own bookkeeping for `signal`, then routes it everywhere else.
+    fn handle_signal(&self, signal: Signal) {
+        match &signal {
+            Signal::ActionInvoked { id, key } => {
+                if let Some(mut held) = self.take_digest(*id) {
+                    debug!("Expanding digest {}", id);
+                    for notification in &mut held {
+                        self.bring_back(notification);
+                    }
+                    self.expand(held);
+                    return;
+                }
+                if let Some(held) = self.take_held(*id) {
+                    debug!("Expanding {}", id);
+                    self.expand(held);
+                    return;
+                }
+                if let Some(lifecycle) = self.lifecycles.borrow_mut().get_mut(id) {
+                    lifecycle.action_invoked(key);
+                }
+                let app = self.spam_notices.borrow().get(id).cloned();
+                if let (Some(app), Some(spam_guard)) = (app, &self.spam_guard) {
+                    if spam_guard.borrow_mut().answer(&app, key, Instant::now()) {
+                        info!("Picked {:?} for {}, which was muted for spamming", key, app);
+                    }
+                }
+            }
+            Signal::NotificationClosed { id, reason } => {
+                self.displayed.borrow_mut().remove(id);
+                self.spam_notices.borrow_mut().remove(id);
+                if self.take_digest(*id).is_some() {
+                    // Their apps already heard that the notifications in it closed.
+                    return;
+                }
+                if let Some(held) = self.take_held(*id) {
+                    // The notifications it was holding go away with it, for the same reason.
+                    for notification in held {
+                        self.outgoing.borrow_mut().push(Signal::NotificationClosed {
+                            id: notification.id,
+                            reason: *reason,
+                        });
+                    }
+                    return;
+                }
+                if let Some(state) = &self.state {
+                    state.remove(*id);
+                }
+                let lifecycle = self.resolve(*id, *reason);
+                if let (Some(json_log), Some(lifecycle)) = (&self.json_log, lifecycle) {
+                    json_log.borrow_mut().resolved(*id, &lifecycle);
+                }
+                if self.brought_back.borrow_mut().remove(id) {
+                    debug!("Not announcing that {} closed again", id);
+                    return;
+                }
+            }
+            Signal::NotificationDisplayed { id } => {
+                self.displayed.borrow_mut().insert(*id);
+                let shown_at = self
+                    .lifecycles
+                    .borrow_mut()
+                    .get_mut(id)
+                    .map(Lifecycle::shown);
+                if let (Some(state), Some(shown_at)) = (&self.state, shown_at) {
+                    state.shown(*id, shown_at);
+                }
+            }
+            Signal::NotificationQueued { id } => {
+                if let Some(state) = &self.state {
+                    state.queued(*id);
                 }
             }
-            Err(TryRecvError::Empty) => return Ok(()),
-            Err(TryRecvError::Disconnected) => bail!("GUI closed its signal tx"),
         }
+        self.router.route(&signal);
     }
 }
 
 impl dbus_server::OrgFreedesktopNotifications for NotifyServer {
     fn get_capabilities(&self) -> Result<Vec<String>, tree::MethodErr> {
-        Ok(vec!["body", "actions", "body-markup"]
-            .into_iter()
-            .map(|s| s.to_string())
-            .collect())
+        let mut capabilities = vec!["body", "actions", "body-markup", "body-images"];
+        if self.advertise_extensions {
+            capabilities.extend_from_slice(EXTENSION_CAPABILITIES);
+        }
+        Ok(capabilities.into_iter().map(|s| s.to_string()).collect())
     }
 
     fn notify(
         &self,
         app_name: &str,
-        _replaces_id: u32,
+        replaces_id: u32,
         app_icon: &str,
         summary: &str,
         body: &str,
         actions: Vec<&str>,
         hints: HashMap<&str, arg::Variant<Box<dyn arg::RefArg>>>,
-        _expire_timeout: i32,
+        expire_timeout: i32,
     ) -> Result<u32, tree::MethodErr> {
-        let icon: Option<ImageRef> = if app_icon.is_empty() {
+        let received_at = Instant::now();
+        let _span = debug_span!("notify", app_name, summary).entered();
+        let icon = parse_icon(app_icon);
+        let raw_actions = actions;
+        let actions = parse_actions(&raw_actions)?;
+
+        let coalesced = if replaces_id == 0 {
+            self.coalescable_close(received_at)
+        } else {
             None
+        };
+        let id = if replaces_id != 0 && self.replace(replaces_id) {
+            replaces_id
         } else {
-            Some(
-                app_icon
-                    .parse()
-                    .map_err(|err| tree::MethodErr::failed(&err))?,
-            )
+            self.new_id()
         };
-
-        if actions.len() % 2 != 0 {
-            return Err(tree::MethodErr::failed(&format!(
-                "Action length {} must be a multiple of 2",
-                actions.len()
-            )));
+        if let Some(old) = coalesced {
+            debug!(
+                "Treating notification {} as an update to {}, which was just closed",
+                id, old
+            );
         }
-        let actions = actions
-            .chunks_exact(2)
-            .map(|c| Action {
-                key: c[0].to_owned(),
-                label: c[1].to_owned(),
-            })
-            .collect::<Vec<_>>();
-
-        let id = self.new_id();
-        let hints = Hints::from_dbus(hints);
-        if let Err(err) = &hints {
-            error!("Failed to build hints dict: {:?}", err);
+        let mut pops_up = true;
+        if let Some(app_filter) = &self.app_filter {
+            let desktop_entry = hints
+                .get("desktop-entry")
+                .and_then(|entry| entry.0.as_str());
+            let app_name = owned_if_nonempty(app_name);
+            if !app_filter.allows(app_name.as_deref(), desktop_entry) {
+                // Blocked apps still get an ID back, so they don't think something went wrong.
+                info!("Dropping notification {} (app is blocked)", id);
+                self.close_dropped(id);
+                return Ok(id);
+            }
+            pops_up = app_filter.pops_up(app_name.as_deref(), desktop_entry);
         }
-        let notification = Notification {
-            id,
-            icon,
-            actions,
-            application_name: owned_if_nonempty(app_name),
-            summary: summary.to_owned(),
-            body: owned_if_nonempty(body),
-            hints: hints.map_err(|err| tree::MethodErr::failed(&err))?,
-        };
+        // Updates to a notification on screen don't count; progress bars send lots of those. Nor do
+        // apps that don't get popups anyway.
+        if pops_up && !self.displayed.borrow().contains(&id) && !self.check_spam(app_name, &hints) {
+            self.close_dropped(id);
+            return Ok(id);
+        }
+        // Only kept to be saved or passed on, once scripts, processors and redaction have had their
+        // way.
+        let owned_hints =
+            if self.state.is_some() || self.forwarder.is_some() || self.mirror.is_some() {
+                Some(OwnedHints::from_dbus(&hints))
+            } else {
+                None
+            };
+        let mut notification =
+            self.build_notification(id, app_name, icon, summary, body, actions, hints)?;
+        notification.lifecycle = Lifecycle::received(received_at);
+        if let Some(scripts) = &self.scripts {
+            if !scripts.on_notify(&mut notification) {
+                info!("Dropping notification {} (the script dropped it)", id);
+                self.close_dropped(id);
+                return Ok(id);
+            }
+        }
+        if let Some(processors) = &self.processors {
+            if !processors.process(&mut notification) {
+                info!("Dropping notification {} (a processor suppressed it)", id);
+                self.close_dropped(id);
+                return Ok(id);
+            }
+        }
+        self.redact_body_if_private(&mut notification);
+        self.history.borrow_mut().add(&notification);
+        let outgoing = owned_hints.map(|hints| saved_notification(&notification, app_icon, hints));
         info!("Got notification {}", notification.id);
-        (self.callback)(NinomiyaEvent::Notification(notification));
+        self.track(&notification);
+        if let Some(pusher) = &self.pusher {
+            pusher.notify(&notification);
+        }
+        if let Some(json_log) = &self.json_log {
+            json_log.borrow_mut().received(&notification);
+        }
+        if !pops_up {
+            // It's in the notification center, and that's as far as it goes.
+            info!("Not showing notification {} (app isn't allowed popups)", id);
+            self.close_dropped(id);
+            return Ok(id);
+        }
+        if let Some(outgoing) = &outgoing {
+            if let Some(forwarder) = &self.forwarder {
+                forwarder.notify(outgoing, expire_timeout);
+            }
+            if let Some(mirror) = &self.mirror {
+                mirror.notify(outgoing, expire_timeout);
+            }
+        }
+        if let Some(action_commands) = &self.action_commands {
+            action_commands.borrow_mut().add(&notification);
+        }
+        if let Some(raiser) = &self.raiser {
+            raiser
+                .borrow_mut()
+                .add(&notification, self.sender.borrow().as_deref());
+        }
+        // Updates to a notification on screen take its place rather than going in a digest.
+        let mut notification = match &self.digester {
+            Some(digester) if !self.displayed.borrow().contains(&coalesced.unwrap_or(id)) => {
+                match digester.borrow_mut().add(notification, received_at) {
+                    Some(notification) => notification,
+                    None => return Ok(id),
+                }
+            }
+            _ => notification,
+        };
+        if let Some(attention_marker) = &self.attention_marker {
+            attention_marker.notify(&notification, self.sender.borrow().as_deref());
+        }
+        if let (Some(state), Some(saved)) = (&self.state, outgoing) {
+            state.add(saved);
+        }
+        // Only now do we know it'll take the old one's place; if it was dropped, the old one still
+        // has to be closed. Either way its app asked for it to be closed, so that's what it's told.
+        if let Some(old) = coalesced {
+            self.pending_closes
+                .borrow_mut()
+                .retain(|close| close.id != old);
+            self.outgoing.borrow_mut().push(Signal::NotificationClosed {
+                id: old,
+                reason: CloseReason::Closed,
+            });
+            notification.replaces = Some(old);
+        }
+        self.dispatch(notification);
         Ok(id)
     }
 
     fn close_notification(&self, id: u32) -> Result<(), tree::MethodErr> {
-        (self.callback)(NinomiyaEvent::CloseNotification(id));
+        if let Some(forwarder) = &self.forwarder {
+            forwarder.close_notification(id);
+        }
+        if let Some(mirror) = &self.mirror {
+            mirror.close_notification(id);
+        }
+        if self.coalesce_close_notify && self.displayed.borrow().contains(&id) {
+            if let Some(sender) = self.sender.borrow().clone() {
+                self.pending_closes.borrow_mut().push(PendingClose {
+                    id,
+                    sender,
+                    at: Instant::now(),
+                });
+                return Ok(());
+            }
+        }
+        // Its app already heard it closed if it was dropped for do-not-disturb, but it shouldn't be
+        // in the digest either.
+        self.suppressed
+            .borrow_mut()
+            .retain(|notification| notification.id != id);
+        let collected = self
+            .digester
+            .as_ref()
+            .map_or(false, |digester| digester.borrow_mut().remove(id));
+        if collected {
+            // It was only ever going to be part of a digest, so there's no window to close.
+            self.outgoing.borrow_mut().push(Signal::NotificationClosed {
+                id,
+                reason: CloseReason::Closed,
+            });
+            return Ok(());
+        }
+        let mut waiting = self.waiting.borrow_mut();
+        if let Some(index) = waiting.iter().position(|n| n.id == id) {
+            // It never made it to the GUI, so there's no window to close.
+            waiting.remove(index);
+            self.outgoing.borrow_mut().push(Signal::NotificationClosed {
+                id,
+                reason: CloseReason::Closed,
+            });
+        } else {
+            (self.callback)(NinomiyaEvent::CloseNotification(id, CloseReason::Closed));
+        }
         Ok(())
     }
 
@@ -223,10 +2095,67 @@ impl dbus_server::OrgFreedesktopNotifications for NotifyServer {
     }
 }
 
+/// How many bytes the notifications in `queue` take up, roughly.
+fn queue_size(queue: &[Notification]) -> usize {
+    queue.iter().map(Notification::approximate_size).sum()
+}
+
+/// Sheds from `queue`, oldest first, until it takes up at most `budget` bytes: first inline
+/// images, then whole low-urgency notifications, then normal ones. Critical notifications are
+/// always kept. Returns the notifications it dropped.
+fn shed(queue: &mut Vec<Notification>, budget: usize) -> Vec<Notification> {
+    let mut size = queue_size(queue);
+    for notification in queue.iter_mut() {
+        if size <= budget {
+            break;
+        }
+        let before = notification.approximate_size();
+        if notification.drop_inline_images() {
+            let saved = before - notification.approximate_size();
+            warn!(
+                "Dropped the images from queued notification {} to save {} bytes",
+                notification.id, saved
+            );
+            size -= saved;
+        }
+    }
+    let mut dropped = vec![];
+    for &urgency in &[Urgency::Low, Urgency::Normal] {
+        let mut index = 0;
+        while size > budget && index < queue.len() {
+            if queue[index].hints.urgency == urgency {
+                let notification = queue.remove(index);
+                size -= notification.approximate_size();
+                dropped.push(notification);
+            } else {
+                index += 1;
+            }
+        }
+    }
+    dropped
+}
+
+/// Sums up the notifications dropped while do-not-disturb was on, like "7 notifications from 3 apps
+/// while you were away".
+fn digest_text(suppressed: &[Notification]) -> String {
+    let apps: HashSet<Option<&str>> = suppressed
+        .iter()
+        .map(|notification| notification.application_name.as_deref())
+        .collect();
+    let plural = |count: usize, noun: &str| {
+        format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+    };
+    format!(
+        "{} from {} while you were away",
+        plural(suppressed.len(), "notification"),
+        plural(apps.len(), "app")
+    )
+}
+
 #[derive(Copy, Clone, Default, Debug)]
 pub struct TData;
 impl tree::DataType for TData {
-    type Tree = NotifyServer;
+    type Tree = Rc<NotifyServer>;
     type ObjectPath = ();
     type Property = ();
     type Interface = ();
@@ -234,15 +2163,552 @@ impl tree::DataType for TData {
     type Signal = ();
 }
 
-pub fn create_tree(server: NotifyServer) -> tree::Tree<tree::MTFn<TData>, TData> {
+pub fn create_tree(server: Rc<NotifyServer>) -> tree::Tree<tree::MTFn<TData>, TData> {
     let f = tree::Factory::new_fn();
-    let iface =
-        dbus_server::org_freedesktop_notifications_server(&f, (), move |_m| _m.tree.get_data());
+    let iface = dbus_server::org_freedesktop_notifications_server(
+        &f,
+        (),
+        move |m: &tree::MethodInfo<tree::MTFn<TData>, TData>| {
+            let server = &**m.tree.get_data();
+            server
+                .sender
+                .replace(m.msg.sender().map(|sender| sender.to_string()));
+            server
+        },
+    );
     let mut tree = f.tree(server);
     tree = tree.add(
         f.object_path("/org/freedesktop/Notifications", ())
             .introspectable()
             .add(iface),
     );
+    tree = tree.add(
+        f.object_path(control::CONTROL_PATH, ())
+            .introspectable()
+            .add(control::control_interface(&f)),
+    );
     tree
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from(app_name: &str) -> Notification {
+        Notification::builder().application_name(app_name).build()
+    }
+
+    #[test]
+    fn summarizes_digest() {
+        assert_eq!(
+            digest_text(&[from("galax")]),
+            "1 notification from 1 app while you were away"
+        );
+        assert_eq!(
+            digest_text(&[from("galax"), from("mail"), from("galax")]),
+            "3 notifications from 2 apps while you were away"
+        );
+    }
+
+    #[test]
+    fn sheds_queued_notifications() {
+        const IMAGE_SIZE: usize = 32 * 32 * 4;
+        let notification = |id, urgency| {
+            Notification::builder()
+                .id(id)
+                .summary("hi")
+                .urgency(urgency)
+                .image(ImageRef::Image {
+                    width: 32,
+                    height: 32,
+                    has_alpha: true,
+                    bits_per_sample: 8,
+                    image_data: vec![0; IMAGE_SIZE],
+                })
+                .build()
+        };
+        let mut queue = vec![
+            notification(1, Urgency::Critical),
+            notification(2, Urgency::Normal),
+            notification(3, Urgency::Low),
+            notification(4, Urgency::Normal),
+        ];
+        let small_size = Notification::builder()
+            .summary("hi")
+            .build()
+            .approximate_size();
+
+        // Dropping the two oldest images is enough.
+        let budget = queue_size(&queue) - 2 * IMAGE_SIZE;
+        assert!(shed(&mut queue, budget).is_empty());
+        assert!(queue[0].hints.image.is_none());
+        assert!(queue[1].hints.image.is_none());
+        assert!(queue[2].hints.image.is_some());
+
+        // Then low urgency goes before normal, and critical stays.
+        let dropped = shed(&mut queue, small_size);
+        assert_eq!(
+            dropped.iter().map(|n| n.id).collect::<Vec<_>>(),
+            vec![3, 2, 4]
+        );
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].id, 1);
+        assert!(shed(&mut queue, 0).is_empty());
+    }
+
+    #[test]
+    fn advertises_extensions() {
+        use dbus_server::OrgFreedesktopNotifications;
+        let plain = NotifyServer::new(|_| ()).get_capabilities().unwrap();
+        assert!(!plain.iter().any(|c| c.starts_with("x-ninomiya-")));
+        let extended = NotifyServer::new(|_| ())
+            .advertise_extensions()
+            .get_capabilities()
+            .unwrap();
+        assert!(extended.contains(&"x-ninomiya-style-class".to_owned()));
+    }
+
+    #[test]
+    fn counts_unread_by_app() {
+        use crate::config::DigestRule;
+        use dbus_server::OrgFreedesktopNotifications;
+        let server = NotifyServer::new(|_| ()).digest_with(Digester::new(vec![DigestRule {
+            name: "CI results".to_owned(),
+            apps: vec!["CI".to_owned()],
+            summary: None,
+            every: 10,
+            count: Default::default(),
+        }]));
+        let notify = |app_name| {
+            server
+                .notify(app_name, 0, "", "hi", "", vec![], HashMap::new(), -1)
+                .unwrap()
+        };
+        for &app_name in &["Mail", "Mail", "Chat"] {
+            let id = notify(app_name);
+            server.handle_signal(Signal::NotificationDisplayed { id });
+        }
+        // Collected for a digest, so it isn't on screen, but it hasn't been read either.
+        notify("CI");
+        server.set_paused(true);
+        notify("Chat");
+        let mut expected = HashMap::new();
+        expected.insert("Mail".to_owned(), 2);
+        expected.insert("Chat".to_owned(), 2);
+        expected.insert("CI".to_owned(), 1);
+        assert_eq!(server.properties().unread_counts, expected);
+    }
+
+    #[test]
+    fn saves_redacted_bodies_redacted() {
+        use dbus_server::OrgFreedesktopNotifications;
+        let state = Arc::new(StateKeeper::new());
+        let server = NotifyServer::new(|_| ())
+            .redact_body_for(vec!["Signal".to_owned()])
+            .keep_state(Arc::clone(&state));
+        let id = server
+            .notify(
+                "Signal",
+                0,
+                "",
+                "Alice",
+                "the password is hunter2",
+                vec![],
+                HashMap::new(),
+                -1,
+            )
+            .unwrap();
+        state.shown(id, SystemTime::now());
+        let saved = state.snapshot().notifications;
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].body, "New message");
+    }
+
+    #[test]
+    fn sums_up_timed_pauses_outside_dnd() {
+        use dbus_server::OrgFreedesktopNotifications;
+        let events = Rc::new(RefCell::new(vec![]));
+        let server = NotifyServer::new({
+            let events = Rc::clone(&events);
+            move |event| events.borrow_mut().push(event)
+        })
+        .digest_after_timed_pause();
+        let digests = || {
+            events
+                .borrow()
+                .iter()
+                .filter(|event| match event {
+                    NinomiyaEvent::Notification(notification) => {
+                        notification.summary.ends_with("while you were away")
+                    }
+                    _ => false,
+                })
+                .count()
+        };
+
+        server.set_paused(true);
+        server
+            .notify("app", 0, "", "hi", "", vec![], HashMap::new(), -1)
+            .unwrap();
+        server.end_timed_pause();
+        assert_eq!(digests(), 1);
+
+        server.set_paused(true);
+        server
+            .notify("app", 0, "", "hi", "", vec![], HashMap::new(), -1)
+            .unwrap();
+        server.set_do_not_disturb(true);
+        server.end_timed_pause();
+        assert_eq!(digests(), 1);
+        assert!(server.suppressed.borrow().is_empty());
+    }
+
+    #[test]
+    fn resolves_notifications_dropped_for_dnd() {
+        use dbus_server::OrgFreedesktopNotifications;
+        let server = NotifyServer::new(|_| ());
+        server.set_do_not_disturb(true);
+        let id = server
+            .notify("app", 0, "", "hi", "", vec![], HashMap::new(), -1)
+            .unwrap();
+        assert!(matches!(
+            server.outgoing.borrow().last(),
+            Some(Signal::NotificationClosed { id: closed, reason: CloseReason::Expired })
+                if *closed == id
+        ));
+        server.handle_outgoing();
+        assert!(server.lifecycles.borrow().is_empty());
+        assert!(server.app_names.borrow().is_empty());
+        assert_eq!(server.stats()["resolved_expired"], 1.0);
+        assert_eq!(server.emitter.take().len(), 1);
+    }
+
+    #[test]
+    fn closes_dropped_notifications() {
+        use dbus_server::OrgFreedesktopNotifications;
+        let server = NotifyServer::new(|_| ())
+            .filter_apps(AppFilter::new(vec!["spammer".to_owned()], vec![]))
+            .guard_against_spam(SpamGuard::new(
+                1,
+                Duration::from_secs(60),
+                Duration::from_secs(60),
+            ));
+        let notify = |app_name| {
+            server
+                .notify(app_name, 0, "", "hi", "", vec![], HashMap::new(), -1)
+                .unwrap()
+        };
+        let blocked = notify("spammer");
+        notify("chatty");
+        let muted = notify("chatty");
+        let closed: Vec<u32> = server
+            .outgoing
+            .borrow()
+            .iter()
+            .filter_map(|signal| match signal {
+                Signal::NotificationClosed {
+                    id,
+                    reason: CloseReason::Expired,
+                } => Some(*id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(closed, vec![blocked, muted]);
+    }
+
+    #[test]
+    fn keeps_notifications_without_popups_in_history() {
+        use dbus_server::OrgFreedesktopNotifications;
+        let displayed = Rc::new(RefCell::new(vec![]));
+        let server = {
+            let displayed = Rc::clone(&displayed);
+            NotifyServer::new(move |event| {
+                if let NinomiyaEvent::Notification(notification) = event {
+                    displayed.borrow_mut().push(notification.id);
+                }
+            })
+            .filter_apps(AppFilter::new(vec![], vec!["Mail".to_owned()]).strict())
+        };
+        let notify = |app_name| {
+            server
+                .notify(app_name, 0, "", "hi", "", vec![], HashMap::new(), -1)
+                .unwrap()
+        };
+        let mail = notify("Mail");
+        let chat = notify("Chat");
+        assert_eq!(*displayed.borrow(), vec![mail]);
+        let history: Vec<u32> = server.history().iter().map(|entry| entry.id).collect();
+        assert_eq!(history, vec![mail, chat]);
+    }
+
+    #[test]
+    fn expands_rollups_through_dispatch() {
+        use dbus_server::OrgFreedesktopNotifications;
+        let events = Rc::new(RefCell::new(vec![]));
+        let server = NotifyServer::new({
+            let events = Rc::clone(&events);
+            move |event| events.borrow_mut().push(event)
+        })
+        .group_with(Grouper::new(1, Duration::from_secs(60)));
+        let notify = |summary| {
+            server
+                .notify("chat", 0, "", summary, "", vec![], HashMap::new(), -1)
+                .unwrap()
+        };
+        let rollup_id = || {
+            events
+                .borrow()
+                .iter()
+                .rev()
+                .find_map(|event| match event {
+                    NinomiyaEvent::Notification(notification)
+                        if notification.actions.len() == 1 =>
+                    {
+                        Some(notification.id)
+                    }
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        let shown = notify("#general");
+        let held = notify("#random");
+        assert!(events.borrow().iter().any(|event| matches!(
+            event,
+            NinomiyaEvent::CloseNotification(id, CloseReason::Expired) if *id == shown
+        )));
+        // Everything is in the notification center, rolled up or not.
+        assert_eq!(server.history().len(), 2);
+
+        // Expanding it while paused holds what it was holding, rather than showing it.
+        server.set_paused(true);
+        server.handle_signal(Signal::ActionInvoked {
+            id: rollup_id(),
+            key: "default".to_owned(),
+        });
+        assert_eq!(server.waiting.borrow()[0].id, held);
+
+        // Closing a rollup closes what it's holding, for the same reason.
+        server.set_paused(false);
+        let held = notify("#dev");
+        let another = notify("#ops");
+        server.outgoing.borrow_mut().clear();
+        server.handle_signal(Signal::NotificationClosed {
+            id: rollup_id(),
+            reason: CloseReason::Dismissed,
+        });
+        let closed: Vec<u32> = server
+            .outgoing
+            .borrow()
+            .iter()
+            .filter_map(|signal| match signal {
+                Signal::NotificationClosed {
+                    id,
+                    reason: CloseReason::Dismissed,
+                } => Some(*id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(closed, vec![held, another]);
+    }
+
+    #[test]
+    fn closes_collected_notifications() {
+        use crate::config::DigestRule;
+        use dbus_server::OrgFreedesktopNotifications;
+        let server = NotifyServer::new(|_| ()).digest_with(Digester::new(vec![DigestRule {
+            name: "CI results".to_owned(),
+            apps: vec!["ci".to_owned()],
+            summary: None,
+            every: 10,
+            count: Default::default(),
+        }]));
+        let id = server
+            .notify("ci", 0, "", "Build passed", "", vec![], HashMap::new(), -1)
+            .unwrap();
+        assert!(server.outgoing.borrow().is_empty());
+        assert_eq!(server.history()[0].id, id);
+
+        server.close_notification(id).unwrap();
+        assert!(matches!(
+            server.outgoing.borrow().last(),
+            Some(Signal::NotificationClosed { id: closed, reason: CloseReason::Closed })
+                if *closed == id
+        ));
+        assert!(!server.digester.as_ref().unwrap().borrow_mut().remove(id));
+    }
+
+    #[test]
+    fn expands_dnd_digests_through_dispatch() {
+        use dbus_server::OrgFreedesktopNotifications;
+        let events = Rc::new(RefCell::new(vec![]));
+        let server = NotifyServer::new({
+            let events = Rc::clone(&events);
+            move |event| events.borrow_mut().push(event)
+        })
+        .digest_after_dnd();
+        server.set_do_not_disturb(true);
+        let id = server
+            .notify("app", 0, "", "hi", "", vec![], HashMap::new(), -1)
+            .unwrap();
+        server.set_do_not_disturb(false);
+        server.handle_outgoing();
+        assert!(server.lifecycles.borrow().is_empty());
+        let digest = server.digest.borrow().as_ref().unwrap().0;
+
+        // Showing them while paused holds them, tracked again, and their apps don't hear about
+        // them closing twice.
+        server.set_paused(true);
+        server.handle_signal(Signal::ActionInvoked {
+            id: digest,
+            key: "show".to_owned(),
+        });
+        assert_eq!(server.waiting.borrow()[0].id, id);
+        assert!(server.lifecycles.borrow().contains_key(&id));
+        server.close_notification(id).unwrap();
+        server.handle_outgoing();
+        assert!(!server.brought_back.borrow().contains(&id));
+        assert!(server.lifecycles.borrow().is_empty());
+        let closes = server
+            .emitter
+            .take()
+            .iter()
+            .filter(|message| message.member().unwrap().to_string() == "NotificationClosed")
+            .count();
+        assert_eq!(closes, 1);
+    }
+
+    #[test]
+    fn brings_back_dismissed_notifications_through_dispatch() {
+        let events = Rc::new(RefCell::new(vec![]));
+        let server = NotifyServer::new({
+            let events = Rc::clone(&events);
+            move |event| events.borrow_mut().push(event)
+        });
+        let mut notification = Notification::builder().id(7).summary("hi").build();
+        notification.lifecycle.resolve(CloseReason::Dismissed);
+        let (local_tx, local_rx) = std::sync::mpsc::channel();
+
+        server.set_paused(true);
+        local_tx.send(notification).unwrap();
+        server.handle_local_notifications(&local_rx);
+        assert!(events.borrow().is_empty());
+        assert!(server.lifecycles.borrow().contains_key(&7));
+        assert!(server.brought_back.borrow().contains(&7));
+
+        server.set_paused(false);
+        assert!(matches!(
+            events.borrow().last(),
+            Some(NinomiyaEvent::Notification(notification))
+                if notification.id == 7 && notification.lifecycle.resolution.is_none()
+        ));
+    }
+
+    #[test]
+    fn coalesces_close_and_notify() {
+        use dbus_server::OrgFreedesktopNotifications;
+        let events = Rc::new(RefCell::new(vec![]));
+        let server = NotifyServer::new({
+            let events = Rc::clone(&events);
+            move |event| events.borrow_mut().push(event)
+        })
+        .coalesce_close_notify();
+        server.sender.replace(Some(":1.42".to_owned()));
+        let notify = |summary| {
+            server
+                .notify("app", 0, "", summary, "", vec![], HashMap::new(), -1)
+                .unwrap()
+        };
+
+        let id = notify("downloading: 10%");
+        server.handle_signal(Signal::NotificationDisplayed { id });
+        server.close_notification(id).unwrap();
+        let update = notify("downloading: 20%");
+        assert_ne!(update, id);
+        server.flush_pending_closes(Instant::now());
+        // The update takes over the old window, but as far as the app's concerned, the old one was
+        // closed like it asked.
+        assert!(matches!(
+            events.borrow().last(),
+            Some(NinomiyaEvent::Notification(notification)) if notification.replaces == Some(id)
+        ));
+        assert!(events
+            .borrow()
+            .iter()
+            .all(|event| matches!(event, NinomiyaEvent::Notification(_))));
+        assert!(server.outgoing.borrow().iter().any(|signal| matches!(
+            signal,
+            Signal::NotificationClosed { id: closed, reason: CloseReason::Closed } if *closed == id
+        )));
+
+        // Without a follow-up, it's closed once the window passes.
+        server.handle_signal(Signal::NotificationDisplayed { id: update });
+        server.close_notification(update).unwrap();
+        server.flush_pending_closes(Instant::now() + COALESCE_WINDOW);
+        assert!(matches!(
+            events.borrow().last(),
+            Some(NinomiyaEvent::CloseNotification(closed, CloseReason::Closed)) if *closed == update
+        ));
+    }
+
+    #[test]
+    fn sends_progress_through_notify() {
+        let events = Rc::new(RefCell::new(vec![]));
+        let server = NotifyServer::new({
+            let events = Rc::clone(&events);
+            move |event| events.borrow_mut().push(event)
+        });
+        let last_value = || match events.borrow().last() {
+            Some(NinomiyaEvent::Notification(notification)) => {
+                (notification.id, notification.hints.value)
+            }
+            event => panic!("expected a notification, got {:?}", event),
+        };
+
+        let id = server
+            .update_progress(None, 0, 40, "Copying photos")
+            .unwrap();
+        assert_eq!(last_value(), (id, Some(40)));
+        server.handle_signal(Signal::NotificationDisplayed { id });
+        assert_eq!(
+            server.update_progress(None, id, 150, "Copying photos"),
+            Ok(id)
+        );
+        assert_eq!(last_value(), (id, Some(100)));
+        assert_eq!(
+            server.complete_progress(None, id, "Copied 200 photos"),
+            Ok(id)
+        );
+        assert_eq!(last_value(), (id, None));
+
+        // Like any other notification, it's kept off screen during do-not-disturb.
+        server.set_do_not_disturb(true);
+        let events_before = events.borrow().len();
+        let muted = server.update_progress(None, 0, 10, "Syncing").unwrap();
+        assert_eq!(events.borrow().len(), events_before);
+        assert!(matches!(
+            server.outgoing.borrow().last(),
+            Some(Signal::NotificationClosed { id: closed, .. }) if *closed == muted
+        ));
+    }
+
+    #[test]
+    fn reports_themes_that_cant_be_loaded() {
+        let server = NotifyServer::new(|event| {
+            if let NinomiyaEvent::SetTheme(path, reply) = event {
+                let loaded = if path.ends_with("dark.css") {
+                    Ok(())
+                } else {
+                    Err("couldn't read it".to_owned())
+                };
+                let _ = reply.send(loaded);
+            }
+        });
+        assert!(server.set_theme(PathBuf::from("dark.css")).is_ok());
+        let err = server.set_theme(PathBuf::from("missing.css")).unwrap_err();
+        assert_eq!(
+            &**err.errorname(),
+            "ai.deifactor.Ninomiya.Error.InvalidTheme"
+        );
+    }
+}
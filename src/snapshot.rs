@@ -0,0 +1,135 @@
+//! Snapshot testing for rendered notifications. Renders are compared against the PNGs in
+//! `data/snapshots`, allowing for small differences (say, from font hinting) so that the tests
+//! don't break from machine to machine.
+//!
+//! Set `NINOMIYA_UPDATE_SNAPSHOTS=1` to write the renders out as the new snapshots instead. Without
+//! it, a missing snapshot is a failure, so that a snapshot that never got committed can't pass
+//! silently.
+
+use gdk_pixbuf::Pixbuf;
+use gtk::prelude::*;
+use std::env;
+use std::path::PathBuf;
+
+/// Two pixels whose channels are all within this much of each other count as the same.
+const CHANNEL_TOLERANCE: u8 = 32;
+/// The fraction of pixels that can differ before a render no longer matches its snapshot.
+const MAX_DIFFERENCE: f64 = 0.01;
+
+/// Draws a widget into a pixbuf, the same way it'd be drawn on screen. It has to have been shown
+/// and laid out already.
+pub fn render(widget: &gtk::Widget) -> Pixbuf {
+    let (width, height) = (widget.get_allocated_width(), widget.get_allocated_height());
+    let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height)
+        .expect("failed to create a surface to render to");
+    widget.draw(&cairo::Context::new(&surface));
+    surface.flush();
+    gdk::pixbuf_get_from_surface(&surface, 0, 0, width, height)
+        .expect("failed to read back the render")
+}
+
+/// The RGBA values of every pixel, row by row. Pixbufs without alpha are treated as opaque.
+fn pixels(pixbuf: &Pixbuf) -> Vec<[u8; 4]> {
+    let channels = pixbuf.get_n_channels() as usize;
+    let rowstride = pixbuf.get_rowstride() as usize;
+    // Safe because we don't hold onto the slice or modify the pixbuf while we're reading it.
+    let bytes = unsafe { pixbuf.get_pixels() };
+    let mut pixels = vec![];
+    for y in 0..pixbuf.get_height() as usize {
+        for x in 0..pixbuf.get_width() as usize {
+            let pixel = &bytes[y * rowstride + x * channels..][..channels];
+            let alpha = if channels == 4 { pixel[3] } else { 255 };
+            pixels.push([pixel[0], pixel[1], pixel[2], alpha]);
+        }
+    }
+    pixels
+}
+
+/// The fraction of pixels that differ noticeably between `a` and `b`. Images of different sizes
+/// are entirely different.
+fn difference(a: &Pixbuf, b: &Pixbuf) -> f64 {
+    if (a.get_width(), a.get_height()) != (b.get_width(), b.get_height()) {
+        return 1.0;
+    }
+    let (a, b) = (pixels(a), pixels(b));
+    if a.is_empty() {
+        return 0.0;
+    }
+    let differing = a
+        .iter()
+        .zip(&b)
+        .filter(|(a, b)| {
+            a.iter()
+                .zip(b.iter())
+                .any(|(a, b)| (*a as i16 - *b as i16).abs() > CHANNEL_TOLERANCE as i16)
+        })
+        .count();
+    differing as f64 / a.len() as f64
+}
+
+fn snapshot_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("data/snapshots")
+        .join(format!("{}.png", name))
+}
+
+/// Checks that `render` matches the snapshot called `name`. On a mismatch, the render is saved
+/// next to the snapshot as `<name>.actual.png` for comparison.
+pub fn assert_matches(name: &str, render: &Pixbuf) {
+    let path = snapshot_path(name);
+    if env::var_os("NINOMIYA_UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).expect("failed to create snapshot dir");
+        render
+            .savev(&path, "png", &[])
+            .unwrap_or_else(|err| panic!("failed to write snapshot {:?}: {}", path, err));
+        eprintln!("Wrote snapshot {:?}", path);
+        return;
+    }
+    if !path.exists() {
+        panic!(
+            "there's no snapshot for {} at {:?}; run with NINOMIYA_UPDATE_SNAPSHOTS=1 to record it",
+            name, path
+        );
+    }
+    let expected = Pixbuf::new_from_file(&path)
+        .unwrap_or_else(|err| panic!("failed to load snapshot {:?}: {}", path, err));
+    let difference = difference(&expected, render);
+    if difference > MAX_DIFFERENCE {
+        let actual_path = path.with_extension("actual.png");
+        let saved = render.savev(&actual_path, "png", &[]).is_ok();
+        panic!(
+            "render of {} differs from its snapshot in {:.1}% of pixels{}",
+            name,
+            difference * 100.0,
+            if saved {
+                format!("; see {:?}", actual_path)
+            } else {
+                String::new()
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(width: i32, height: i32, color: u32) -> Pixbuf {
+        let pixbuf = Pixbuf::new(gdk_pixbuf::Colorspace::Rgb, true, 8, width, height).unwrap();
+        pixbuf.fill(color);
+        pixbuf
+    }
+
+    #[test]
+    fn differences() {
+        let white = filled(10, 10, 0xffffffff);
+        assert_eq!(difference(&white, &white), 0.0);
+        assert_eq!(difference(&white, &filled(10, 10, 0xf0f0f0ff)), 0.0);
+        assert_eq!(difference(&white, &filled(10, 10, 0x000000ff)), 1.0);
+        assert_eq!(difference(&white, &filled(10, 11, 0xffffffff)), 1.0);
+
+        let speck = filled(10, 10, 0xffffffff);
+        filled(1, 1, 0x000000ff).copy_area(0, 0, 1, 1, &speck, 5, 5);
+        assert_eq!(difference(&white, &speck), 0.01);
+    }
+}
@@ -0,0 +1,37 @@
+//! Plays notification sounds. We shell out to `canberra-gtk-play`, which already knows how to look
+//! sounds up in the freedesktop sound theme.
+
+use crate::hints::expand_path;
+use std::process::{Command, Stdio};
+use std::thread;
+use tracing::{debug, warn};
+
+/// Plays the given sound without waiting for it to finish. `sound` is either the name of a sound
+/// in the sound theme (like `message-new-instant`) or a path to a sound file.
+pub fn play(sound: &str) {
+    let mut command = Command::new("canberra-gtk-play");
+    if sound.contains('/') {
+        match expand_path(sound) {
+            Ok(path) => command.arg("--file").arg(path),
+            Err(err) => {
+                warn!("Couldn't play sound {}: {:?}", sound, err);
+                return;
+            }
+        };
+    } else {
+        command.arg("--id").arg(sound);
+    }
+    debug!("Playing sound {}", sound);
+    match command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        // Reap it in the background so we don't leave zombies around.
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+        }
+        Err(err) => warn!("Failed to run canberra-gtk-play: {:?}", err),
+    }
+}
@@ -0,0 +1,197 @@
+//! Notices apps sending notifications faster than anyone could read them, like a script stuck in a
+//! loop, and mutes them for a while. Unlike grouping, which rolls a burst up into one popup,
+//! nothing from a muted app is shown at all; the user just gets a notice saying so, which offers to
+//! keep it muted for longer or to unmute it straight away.
+
+use crate::server::{Action, Notification};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// The action on the notice that keeps the app muted for `KEEP_MUTED_FOR`.
+pub const KEEP_MUTED: &str = "keep-muted";
+/// The action on the notice that unmutes the app.
+pub const UNMUTE: &str = "unmute";
+const KEEP_MUTED_FOR: Duration = Duration::from_secs(60 * 60);
+
+/// What to do with a notification from an app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Show,
+    /// The app is muted.
+    Drop,
+    /// This one pushed the app over the threshold, so it's muted from now on. The user should be
+    /// told.
+    Mute,
+}
+
+#[derive(Debug)]
+pub struct SpamGuard {
+    /// Mutes an app once more than this many notifications arrive from it within `window`.
+    threshold: usize,
+    window: Duration,
+    /// How long the app stays muted for.
+    mute_for: Duration,
+    /// When each recent notification from each app arrived. Keyed by desktop entry, or by app
+    /// name if there isn't one.
+    recent: HashMap<String, VecDeque<Instant>>,
+    /// When each muted app is unmuted.
+    muted: HashMap<String, Instant>,
+}
+
+impl SpamGuard {
+    pub fn new(threshold: usize, window: Duration, mute_for: Duration) -> Self {
+        SpamGuard {
+            threshold,
+            window,
+            mute_for,
+            recent: HashMap::new(),
+            muted: HashMap::new(),
+        }
+    }
+
+    /// Decides what to do with a notification from `app` that arrived at `now`.
+    pub fn check(&mut self, app: &str, now: Instant) -> Verdict {
+        if let Some(&until) = self.muted.get(app) {
+            if now < until {
+                return Verdict::Drop;
+            }
+            self.muted.remove(app);
+        }
+        self.forget_before(now);
+        let recent = self.recent.entry(app.to_owned()).or_default();
+        recent.push_back(now);
+        if recent.len() <= self.threshold {
+            return Verdict::Show;
+        }
+        // It starts from scratch once the mute's over.
+        self.recent.remove(app);
+        self.muted.insert(app.to_owned(), now + self.mute_for);
+        Verdict::Mute
+    }
+
+    /// Forgets the notifications that have fallen out of the window by `now`, and the mutes that
+    /// have run out, so that apps that have gone quiet don't take up room forever.
+    fn forget_before(&mut self, now: Instant) {
+        let window = self.window;
+        self.recent.retain(|_, recent| {
+            while let Some(&arrived) = recent.front() {
+                if now.duration_since(arrived) <= window {
+                    break;
+                }
+                recent.pop_front();
+            }
+            !recent.is_empty()
+        });
+        self.muted.retain(|_, until| now < *until);
+    }
+
+    /// Does what the user picked on the notice about `app`. Returns whether `key` was one of the
+    /// notice's actions.
+    pub fn answer(&mut self, app: &str, key: &str, now: Instant) -> bool {
+        match key {
+            KEEP_MUTED => {
+                self.muted.insert(app.to_owned(), now + KEEP_MUTED_FOR);
+            }
+            UNMUTE => {
+                self.muted.remove(app);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// The notice telling the user that `app_name` was muted.
+    pub fn notice(&self, app_name: &str) -> Notification {
+        let mut notification = Notification::internal(
+            &format!("Muted {}", app_name),
+            &format!(
+                "It sent more than {} notifications in {}, so it's muted for {}.",
+                self.threshold,
+                describe(self.window),
+                describe(self.mute_for)
+            ),
+        );
+        notification.actions = vec![
+            Action {
+                key: KEEP_MUTED.to_owned(),
+                label: "Keep muted for 1 hour".to_owned(),
+            },
+            Action {
+                key: UNMUTE.to_owned(),
+                label: "Unmute".to_owned(),
+            },
+        ];
+        notification
+    }
+}
+
+/// Spells out `duration` in minutes and seconds, like "1 minute and 30 seconds".
+fn describe(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 60, seconds % 60) {
+        (0, seconds) => plural(seconds, "second"),
+        (minutes, 0) => plural(minutes, "minute"),
+        (minutes, seconds) => format!(
+            "{} and {}",
+            plural(minutes, "minute"),
+            plural(seconds, "second")
+        ),
+    }
+}
+
+fn plural(count: u64, unit: &str) -> String {
+    format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mutes_spammers() {
+        let now = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut guard = SpamGuard::new(3, 10 * second, 60 * second);
+        // Slow enough that the earlier ones fall out of the window.
+        for i in 0..5 {
+            assert_eq!(guard.check("slow", now + i * 6 * second), Verdict::Show);
+        }
+        for i in 0..3 {
+            assert_eq!(guard.check("loop", now + i * second), Verdict::Show);
+        }
+        assert_eq!(guard.check("loop", now + 3 * second), Verdict::Mute);
+        assert_eq!(guard.check("loop", now + 30 * second), Verdict::Drop);
+        assert_eq!(guard.check("slow", now + 30 * second), Verdict::Show);
+        // The mute runs out on its own.
+        assert_eq!(guard.check("loop", now + 64 * second), Verdict::Show);
+
+        assert!(guard.answer("loop", KEEP_MUTED, now));
+        assert_eq!(guard.check("loop", now + 30 * 60 * second), Verdict::Drop);
+        assert!(guard.answer("loop", UNMUTE, now));
+        assert_eq!(guard.check("loop", now + 40 * 60 * second), Verdict::Show);
+        assert!(!guard.answer("loop", "default", now));
+
+        assert_eq!(
+            guard.notice("loop").body.as_deref(),
+            Some("It sent more than 3 notifications in 10 seconds, so it's muted for 1 minute.")
+        );
+        assert_eq!(describe(Duration::from_secs(90)), "1 minute and 30 seconds");
+        assert_eq!(describe(Duration::from_secs(120)), "2 minutes");
+    }
+
+    #[test]
+    fn forgets_quiet_apps() {
+        let now = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut guard = SpamGuard::new(1, 10 * second, 60 * second);
+        for i in 0..100 {
+            guard.check(&format!("app {}", i), now);
+        }
+        guard.check("chatty", now);
+        guard.check("chatty", now);
+        assert_eq!(guard.recent.len(), 100);
+        guard.check("latest", now + 100 * second);
+        assert_eq!(guard.recent.keys().collect::<Vec<_>>(), vec!["latest"]);
+        assert!(guard.muted.is_empty());
+    }
+}
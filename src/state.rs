@@ -0,0 +1,239 @@
+//! Saves the notifications on screen, and the ones held while we're paused, when ninomiya shuts
+//! down cleanly, and brings them back when it starts again. Otherwise restarting the daemon (say,
+//! after upgrading it) would quietly lose them.
+//!
+//! Notifications are saved as they're shown, after scripts, processors and `redact_body` are done
+//! with them, and restored without going through those again. That way a hidden body never ends up
+//! on disk. They keep their IDs, so apps that are still running can close them or get their
+//! actions. The ones that were on screen only stay up for what was left of their timeout.
+//!
+//! Whether the user paused notifications or turned on do-not-disturb is saved separately, as soon
+//! as it changes, so that it survives crashes and reboots too. Nobody wants notifications turning
+//! themselves back on halfway through a presentation.
+
+use crate::hints::OwnedHints;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedNotification {
+    pub id: u32,
+    pub app_name: String,
+    pub app_icon: String,
+    pub summary: String,
+    pub body: String,
+    /// Keys and labels, alternating, as in the DBus interface.
+    pub actions: Vec<String>,
+    pub hints: OwnedHints,
+    /// When it arrived, so that how long it's been around counts from before a restart. Missing
+    /// from state saved by older versions.
+    #[serde(default)]
+    pub received: Option<SystemTime>,
+    /// When its window was shown, if it was.
+    pub shown_at: Option<SystemTime>,
+    /// Whether it was being held because we were paused.
+    pub queued: bool,
+}
+
+impl SavedNotification {
+    /// Whether it made it to the screen or the queue. The rest (say, ones dropped by
+    /// do-not-disturb) aren't worth saving.
+    fn settled(&self) -> bool {
+        self.shown_at.is_some() || self.queued
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SavedState {
+    /// In order of ID, which isn't quite the order they arrived in, since our own notifications
+    /// count down from the top.
+    pub notifications: Vec<SavedNotification>,
+}
+
+/// What's on screen and queued right now. The server keeps this up to date, and the main thread
+/// saves it once the GUI has quit.
+#[derive(Debug, Default)]
+pub struct StateKeeper {
+    notifications: Mutex<BTreeMap<u32, SavedNotification>>,
+}
+
+impl StateKeeper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts keeping track of a notification, replacing any with the same ID.
+    pub fn add(&self, notification: SavedNotification) {
+        let mut notifications = self.notifications.lock().unwrap();
+        notifications.insert(notification.id, notification);
+    }
+
+    /// Records that a notification's window was shown at `shown_at`.
+    pub fn shown(&self, id: u32, shown_at: SystemTime) {
+        if let Some(notification) = self.notifications.lock().unwrap().get_mut(&id) {
+            notification.shown_at = Some(shown_at);
+            notification.queued = false;
+        }
+    }
+
+    /// Records that a notification is being held until we're unpaused.
+    pub fn queued(&self, id: u32) {
+        if let Some(notification) = self.notifications.lock().unwrap().get_mut(&id) {
+            notification.queued = true;
+        }
+    }
+
+    pub fn remove(&self, id: u32) {
+        self.notifications.lock().unwrap().remove(&id);
+    }
+
+    pub fn snapshot(&self) -> SavedState {
+        SavedState {
+            notifications: self
+                .notifications
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|notification| notification.settled())
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Writes everything on screen and queued to `path`, replacing whatever was there.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_json(path, &self.snapshot())
+    }
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let dir = path.parent().context("the state file has no directory")?;
+    fs::create_dir_all(dir).with_context(|| format!("couldn't create {:?}", dir))?;
+    // Written to the side first, so that dying halfway through doesn't leave half a file.
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    serde_json::to_writer(&mut file, value)?;
+    file.flush()?;
+    file.persist(path)
+        .with_context(|| format!("couldn't write {:?}", path))?;
+    Ok(())
+}
+
+/// Whether the user has silenced notifications. Only what they chose themselves counts, not
+/// do-not-disturb turned on for the focused window or pausing for screen sharing, since those
+/// sort themselves out again after a restart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mute {
+    pub paused: bool,
+    /// When the pause ends by itself, if it was only for a while.
+    pub paused_until: Option<SystemTime>,
+    pub do_not_disturb: bool,
+    /// When do-not-disturb turns itself off, if it was only turned on for a while.
+    pub dnd_until: Option<SystemTime>,
+}
+
+impl Mute {
+    /// Saves it to `path`, replacing whatever was there.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_json(path, self)
+    }
+
+    /// Reads what was saved at `path`. Nothing having been saved means nothing was muted.
+    pub fn load(path: &Path) -> Result<Mute> {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("{:?} isn't a saved mute", path)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Mute::default()),
+            Err(err) => Err(err).with_context(|| format!("couldn't read {:?}", path)),
+        }
+    }
+}
+
+/// Reads the state saved at `path`, if there is any, and deletes it so that it's only restored
+/// once.
+pub fn load(path: &Path) -> Result<Option<SavedState>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("couldn't read {:?}", path)),
+    };
+    fs::remove_file(path).with_context(|| format!("couldn't remove {:?}", path))?;
+    Ok(Some(serde_json::from_str(&contents).with_context(
+        || format!("{:?} isn't a saved state", path),
+    )?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved(id: u32) -> SavedNotification {
+        SavedNotification {
+            id,
+            app_name: "app".to_owned(),
+            app_icon: String::new(),
+            summary: format!("notification {}", id),
+            body: String::new(),
+            actions: vec!["default".to_owned(), "Open".to_owned()],
+            hints: OwnedHints::default(),
+            received: None,
+            shown_at: None,
+            queued: false,
+        }
+    }
+
+    #[test]
+    fn saves_and_restores() -> Result<()> {
+        let keeper = StateKeeper::new();
+        for id in 1..=4 {
+            keeper.add(saved(id));
+        }
+        keeper.shown(1, SystemTime::now());
+        keeper.queued(2);
+        keeper.shown(3, SystemTime::now());
+        keeper.remove(3);
+        // 4 was never shown or queued, so it's left out.
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("state.json");
+        keeper.save(&path)?;
+        let state = load(&path)?.unwrap();
+        assert_eq!(
+            state
+                .notifications
+                .iter()
+                .map(|notification| notification.id)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert!(state.notifications[0].shown_at.is_some());
+        assert!(state.notifications[1].queued);
+        // It's only restored once.
+        assert_eq!(load(&path)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn saves_mute() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("mute.json");
+        assert_eq!(Mute::load(&path)?, Mute::default());
+        let mute = Mute {
+            paused: true,
+            paused_until: None,
+            do_not_disturb: true,
+            dnd_until: Some(SystemTime::now()),
+        };
+        mute.save(&path)?;
+        // Unlike the rest of the state, it stays around.
+        assert_eq!(Mute::load(&path)?, mute);
+        assert_eq!(Mute::load(&path)?, mute);
+        Ok(())
+    }
+}
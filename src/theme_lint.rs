@@ -0,0 +1,232 @@
+//! Finds selectors in a theme that can never match anything, because they name a widget ID or CSS
+//! node that ninomiya doesn't create. GTK silently ignores them, so a typo like `#sumary` just
+//! looks like the theme not working.
+
+use anyhow::{Context, Result};
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::CharIndices;
+
+/// The names ninomiya gives its widgets, for `#name` selectors. Keep this in sync with the
+/// `.name(...)` calls in `gui`.
+pub const WIDGET_NAMES: &[&str] = &[
+    "container",
+    "image",
+    "text",
+    "summary",
+    "body",
+    "body-images",
+    "body-image",
+    "progress",
+    "icon-and-name",
+    "icon",
+    "application-name",
+    "media-controls",
+    "buttons",
+    "action-number",
+];
+
+/// The CSS nodes of the GTK widgets we use (including the ones GTK creates inside them), for type
+/// selectors.
+const NODE_NAMES: &[&str] = &[
+    "window",
+    "decoration",
+    "box",
+    "label",
+    "selection",
+    "link",
+    "image",
+    "button",
+    "tooltip",
+    "menu",
+    "menuitem",
+    "separator",
+    "progressbar",
+    "trough",
+    "progress",
+    "text",
+    // Only there while a notification is growing or shrinking.
+    "scrolledwindow",
+    "viewport",
+    "scrollbar",
+];
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
+
+/// The number of single-character edits to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous + if a == *b { 0 } else { 1 };
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// A warning for `name` if it isn't one of `known`, suggesting the closest one if it's near enough
+/// to be a typo.
+fn check_name(prefix: &str, name: &str, known: &[&str]) -> Option<String> {
+    if known.contains(&name) {
+        return None;
+    }
+    let suggestion = known
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min();
+    Some(match suggestion {
+        Some((_, candidate)) => format!(
+            "{}{} never matches anything; did you mean {}{}?",
+            prefix, name, prefix, candidate
+        ),
+        None => format!("{}{} never matches anything", prefix, name),
+    })
+}
+
+/// Skips to the end of the name `chars` is in the middle of, returning the index it ends at.
+fn skip_name(selector: &str, chars: &mut Peekable<CharIndices>) -> usize {
+    while let Some(&(index, c)) = chars.peek() {
+        if !is_name_char(c) {
+            return index;
+        }
+        chars.next();
+    }
+    selector.len()
+}
+
+/// Checks one selector (without commas), like `window.center #summary:hover`.
+fn check_selector(selector: &str) -> Vec<String> {
+    let mut warnings = vec![];
+    let mut chars = selector.char_indices().peekable();
+    // Whether the next name would start a new compound selector, and so be a node name.
+    let mut at_start = true;
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '#' => {
+                let end = skip_name(selector, &mut chars);
+                warnings.extend(check_name("#", &selector[start + 1..end], WIDGET_NAMES));
+                at_start = false;
+            }
+            // Classes can come from templates, so any class might match. Pseudo-classes are
+            // GTK's business.
+            '.' | ':' => {
+                skip_name(selector, &mut chars);
+                at_start = false;
+            }
+            '[' => {
+                for (_, c) in &mut chars {
+                    if c == ']' {
+                        break;
+                    }
+                }
+                at_start = false;
+            }
+            // Arguments to pseudo-classes like `:not(...)` are selectors too.
+            '(' | ' ' | '\t' | '\n' | '>' | '+' | '~' => at_start = true,
+            c if is_name_char(c) && at_start && !c.is_ascii_digit() => {
+                let end = skip_name(selector, &mut chars);
+                warnings.extend(check_name("", &selector[start..end], NODE_NAMES));
+                at_start = false;
+            }
+            _ => at_start = false,
+        }
+    }
+    warnings
+}
+
+/// Checks every selector in `css`, returning warnings that start with the line they're on.
+pub fn lint(css: &str) -> Vec<String> {
+    let mut warnings = vec![];
+    let mut depth = 0;
+    let mut line = 1;
+    // The text since the last block or statement, and the line it started on.
+    let mut prelude = String::new();
+    let mut prelude_line = 1;
+    let mut chars = css.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            let mut last = ' ';
+            for c in &mut chars {
+                if c == '\n' {
+                    line += 1;
+                }
+                if last == '*' && c == '/' {
+                    break;
+                }
+                last = c;
+            }
+            continue;
+        }
+        match c {
+            '{' => {
+                // At-rules like `@keyframes` don't have selectors.
+                if depth == 0 && !prelude.trim_start().starts_with('@') {
+                    for selector in prelude.split(',') {
+                        for warning in check_selector(selector.trim()) {
+                            warnings.push(format!("line {}: {}", prelude_line, warning));
+                        }
+                    }
+                }
+                depth += 1;
+                prelude.clear();
+            }
+            '}' => {
+                depth = std::cmp::max(depth, 1) - 1;
+                prelude.clear();
+            }
+            ';' if depth == 0 => prelude.clear(),
+            _ if depth == 0 => {
+                if prelude.trim().is_empty() && !c.is_whitespace() {
+                    prelude_line = line;
+                }
+                prelude.push(c);
+            }
+            _ => {}
+        }
+        if c == '\n' {
+            line += 1;
+        }
+    }
+    warnings
+}
+
+/// Reads the theme at `path` and lints it.
+pub fn lint_file(path: &Path) -> Result<Vec<String>> {
+    let css = std::fs::read_to_string(path).with_context(|| format!("couldn't read {:?}", path))?;
+    Ok(lint(&css))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_bad_selectors() {
+        assert!(lint(include_str!("../data/style.css")).is_empty());
+        let css = "@define-color accent #ff0000;\n\
+                   /* #nope { } */\n\
+                   window.urgency-critical #sumary,\n\
+                   #body:hover, lable {\n\
+                     color: @accent;\n\
+                   }\n\
+                   box > #widget, button:not(#buttons) { }\n\
+                   @keyframes pulse { from { opacity: 0; } to { opacity: 1; } }\n";
+        assert_eq!(
+            lint(css),
+            vec![
+                "line 3: #sumary never matches anything; did you mean #summary?",
+                "line 3: lable never matches anything; did you mean label?",
+                "line 7: #widget never matches anything",
+            ]
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! The timers that close notifications, and the clock they run on. The GUI uses the glib main loop
+//! as its clock, but tests can swap in a fake one and move time forward themselves.
+
+use glib::Continue;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Identifies a callback scheduled on a `Clock`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+/// Tells the time and runs callbacks later.
+pub trait Clock {
+    fn now(&self) -> Instant;
+    /// Runs `callback` once `delay` has passed.
+    fn schedule(&self, delay: Duration, callback: Box<dyn FnOnce()>) -> TimerId;
+    /// Cancels a callback. Does nothing if it's already run.
+    fn cancel(&self, timer: TimerId);
+}
+
+/// The real clock, which schedules callbacks on the glib main loop.
+#[derive(Debug, Default)]
+pub struct GlibClock {
+    next_id: Cell<u64>,
+    /// The sources for the callbacks that haven't run yet. Removing a source that's already gone
+    /// makes glib complain, so callbacks take themselves out of here when they run.
+    sources: Rc<RefCell<HashMap<TimerId, glib::SourceId>>>,
+}
+
+impl GlibClock {
+    pub fn new() -> Self {
+        GlibClock::default()
+    }
+}
+
+impl Clock for GlibClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn schedule(&self, delay: Duration, callback: Box<dyn FnOnce()>) -> TimerId {
+        let id = TimerId(self.next_id.get());
+        self.next_id.set(id.0 + 1);
+        let sources = self.sources.clone();
+        let mut callback = Some(callback);
+        let source = glib::timeout_add_local(delay.as_millis() as u32, move || {
+            sources.borrow_mut().remove(&id);
+            if let Some(callback) = callback.take() {
+                callback();
+            }
+            Continue(false)
+        });
+        self.sources.borrow_mut().insert(id, source);
+        id
+    }
+
+    fn cancel(&self, timer: TimerId) {
+        let source = self.sources.borrow_mut().remove(&timer);
+        if let Some(source) = source {
+            glib::source_remove(source);
+        }
+    }
+}
+
+/// A callback waiting on a `FakeClock`, along with when it's due.
+#[cfg(test)]
+type Pending = (Instant, TimerId, Box<dyn FnOnce()>);
+
+/// A clock that only moves when it's told to.
+#[cfg(test)]
+pub struct FakeClock {
+    now: Cell<Instant>,
+    next_id: Cell<u64>,
+    pending: RefCell<Vec<Pending>>,
+}
+
+#[cfg(test)]
+impl Default for FakeClock {
+    fn default() -> Self {
+        FakeClock::new()
+    }
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new() -> Self {
+        FakeClock {
+            now: Cell::new(Instant::now()),
+            next_id: Cell::new(0),
+            pending: RefCell::new(vec![]),
+        }
+    }
+
+    /// Moves time forward by `by`, running the callbacks that come due in the order they're due.
+    pub fn advance(&self, by: Duration) {
+        let target = self.now.get() + by;
+        loop {
+            let next = {
+                let mut pending = self.pending.borrow_mut();
+                let index = pending
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (due, _, _))| *due <= target)
+                    .min_by_key(|(_, (due, id, _))| (*due, id.0))
+                    .map(|(index, _)| index);
+                index.map(|index| pending.remove(index))
+            };
+            match next {
+                Some((due, _, callback)) => {
+                    self.now.set(due);
+                    callback();
+                }
+                None => break,
+            }
+        }
+        self.now.set(target);
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+
+    fn schedule(&self, delay: Duration, callback: Box<dyn FnOnce()>) -> TimerId {
+        let id = TimerId(self.next_id.get());
+        self.next_id.set(id.0 + 1);
+        self.pending
+            .borrow_mut()
+            .push((self.now.get() + delay, id, callback));
+        id
+    }
+
+    fn cancel(&self, timer: TimerId) {
+        self.pending.borrow_mut().retain(|(_, id, _)| *id != timer);
+    }
+}
+
+/// Closes a notification once it's been on screen long enough. It can be paused (say, while the
+/// pointer is over the window) and cancelled.
+pub struct CloseTimer {
+    clock: Rc<dyn Clock>,
+    on_expire: Rc<dyn Fn()>,
+    /// The pending callback, while the timer is running.
+    timer: Option<TimerId>,
+    /// How long is left, as of `started`.
+    remaining: Duration,
+    started: Instant,
+}
+
+impl CloseTimer {
+    /// Creates a timer that calls `on_expire` after `duration`, and starts it.
+    pub fn start<F: Fn() + 'static>(
+        clock: Rc<dyn Clock>,
+        duration: Duration,
+        on_expire: F,
+    ) -> Rc<RefCell<Self>> {
+        let started = clock.now();
+        let timer = Rc::new(RefCell::new(CloseTimer {
+            clock,
+            on_expire: Rc::new(on_expire),
+            timer: None,
+            remaining: duration,
+            started,
+        }));
+        CloseTimer::resume(&timer);
+        timer
+    }
+
+    /// Starts counting down again, if it's paused.
+    pub fn resume(timer: &Rc<RefCell<Self>>) {
+        let mut this = timer.borrow_mut();
+        if this.timer.is_some() {
+            return;
+        }
+        this.started = this.clock.now();
+        let weak = Rc::downgrade(timer);
+        let id = this.clock.schedule(
+            this.remaining,
+            Box::new(move || {
+                if let Some(timer) = weak.upgrade() {
+                    let on_expire = {
+                        let mut timer = timer.borrow_mut();
+                        // It's already run, so there's nothing left to cancel.
+                        timer.timer = None;
+                        timer.remaining = Duration::default();
+                        timer.on_expire.clone()
+                    };
+                    on_expire();
+                }
+            }),
+        );
+        this.timer = Some(id);
+    }
+
+    /// Stops counting down, remembering how much time was left.
+    pub fn pause(&mut self) {
+        if let Some(id) = self.timer.take() {
+            self.clock.cancel(id);
+            let elapsed = self.clock.now().duration_since(self.started);
+            self.remaining = self.remaining.checked_sub(elapsed).unwrap_or_default();
+        }
+    }
+
+    /// Stops the timer for good, so that it doesn't fire after the window is gone.
+    pub fn cancel(&mut self) {
+        if let Some(id) = self.timer.take() {
+            self.clock.cancel(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer(clock: &Rc<FakeClock>, seconds: u64) -> (Rc<RefCell<CloseTimer>>, Rc<Cell<bool>>) {
+        let expired = Rc::new(Cell::new(false));
+        let flag = expired.clone();
+        let timer = CloseTimer::start(clock.clone(), Duration::from_secs(seconds), move || {
+            flag.set(true)
+        });
+        (timer, expired)
+    }
+
+    #[test]
+    fn expires() {
+        let clock = Rc::new(FakeClock::new());
+        let (_timer, expired) = timer(&clock, 3);
+        clock.advance(Duration::from_secs(2));
+        assert!(!expired.get());
+        clock.advance(Duration::from_secs(1));
+        assert!(expired.get());
+    }
+
+    #[test]
+    fn pauses() {
+        let clock = Rc::new(FakeClock::new());
+        let (timer, expired) = timer(&clock, 3);
+        clock.advance(Duration::from_secs(2));
+        timer.borrow_mut().pause();
+        clock.advance(Duration::from_secs(10));
+        assert!(!expired.get());
+        CloseTimer::resume(&timer);
+        clock.advance(Duration::from_millis(999));
+        assert!(!expired.get());
+        clock.advance(Duration::from_millis(1));
+        assert!(expired.get());
+    }
+
+    #[test]
+    fn cancels() {
+        let clock = Rc::new(FakeClock::new());
+        let (timer, expired) = timer(&clock, 3);
+        timer.borrow_mut().cancel();
+        clock.advance(Duration::from_secs(10));
+        assert!(!expired.get());
+    }
+}
@@ -0,0 +1,134 @@
+//! Notices when the GUI thread stops handling events, for example because it's stuck loading an
+//! image synchronously. The GUI beats a `Heartbeat` from a timer on its main loop, and the server
+//! checks how long ago the last beat was on every turn of its own loop. Without this, the server
+//! would keep sending notifications into a channel nobody reads, and nothing would show up.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+/// How often the GUI beats the heartbeat.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// When the GUI thread's main loop last ran.
+#[derive(Debug)]
+pub struct Heartbeat {
+    last: Mutex<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Heartbeat {
+            last: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Records that the main loop just ran.
+    pub fn beat(&self) {
+        *self.last.lock().unwrap() = Instant::now();
+    }
+
+    fn last(&self) -> Instant {
+        *self.last.lock().unwrap()
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Watches a `Heartbeat` from the server thread.
+#[derive(Debug)]
+pub struct Watchdog {
+    heartbeat: Arc<Heartbeat>,
+    /// How long the GUI can go without a beat before we consider it hung.
+    timeout: Duration,
+    /// Whether to exit once the GUI's been hung for `EXIT_AFTER` timeouts, so that whatever
+    /// started us (systemd, or DBus activation on the next notification) can start us again.
+    exit: bool,
+    /// When we first noticed the GUI was hung, if it still is.
+    hung_since: Option<Instant>,
+}
+
+/// How many timeouts the GUI has to be hung for before we give up on it, if `exit` is set.
+const EXIT_AFTER: u32 = 3;
+
+impl Watchdog {
+    pub fn new(heartbeat: Arc<Heartbeat>, timeout: Duration, exit: bool) -> Self {
+        Watchdog {
+            heartbeat,
+            timeout,
+            exit,
+            hung_since: None,
+        }
+    }
+
+    /// How long it's been since the GUI's main loop last ran.
+    pub fn silence(&self) -> Duration {
+        self.heartbeat.last().elapsed()
+    }
+
+    /// Whether the GUI thread is handling events.
+    pub fn responsive(&self) -> bool {
+        self.hung_since.is_none()
+    }
+
+    /// Checks on the GUI thread, logging and trying to recover if it's hung.
+    pub fn check(&mut self) {
+        self.check_at(Instant::now())
+    }
+
+    fn check_at(&mut self, now: Instant) {
+        let silence = now.saturating_duration_since(self.heartbeat.last());
+        if silence < self.timeout {
+            if let Some(hung_since) = self.hung_since.take() {
+                info!(
+                    "The GUI thread is responding again after {:?}",
+                    now.saturating_duration_since(hung_since)
+                );
+            }
+            return;
+        }
+        if self.hung_since.is_none() {
+            error!(
+                "The GUI thread hasn't handled events for {:?}; notifications won't be shown \
+                 until it does",
+                silence
+            );
+            self.hung_since = Some(now);
+            // In case it's waiting for a wakeup that never came, rather than actually stuck.
+            glib::MainContext::default().wakeup();
+        } else if self.exit && silence >= self.timeout * EXIT_AFTER {
+            error!(
+                "The GUI thread has been hung for {:?}; exiting so that we can be restarted",
+                silence
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notices_hangs() {
+        let heartbeat = Arc::new(Heartbeat::new());
+        let timeout = Duration::from_secs(5);
+        let mut watchdog = Watchdog::new(Arc::clone(&heartbeat), timeout, false);
+        let start = heartbeat.last();
+        watchdog.check_at(start + Duration::from_secs(1));
+        assert!(watchdog.responsive());
+        watchdog.check_at(start + timeout);
+        assert!(!watchdog.responsive());
+        watchdog.check_at(start + timeout * 4);
+        assert!(!watchdog.responsive());
+
+        heartbeat.beat();
+        watchdog.check_at(heartbeat.last());
+        assert!(watchdog.responsive());
+    }
+}